@@ -1,13 +1,59 @@
-use super::Opt;
+use super::{BatchOpt, CompareOpt, NodeOpt, Opt, TaskOpt};
+use crate::audio::{self, BitDepth, Clip, Layout};
+use crate::batch::{self, Job};
+use crate::cache::{self, Size};
+use crate::combine::Combiner;
+use crate::compress;
+use crate::config::{
+    AudioFilterConfig, Config, FilterConfig, Limits, SessionConfig, TelemetryConfig,
+};
+use crate::currency::Rate;
+use crate::encode::{self, Chapter, Encoder, Format, Quality};
+use crate::encoding::Encoding;
+use crate::epub;
+use crate::events::{EventLog, EventMonitor};
+use crate::failure::Stage;
+use crate::filter::{self, Chain, TextOptions};
+use crate::golem::{self, Golemcli};
+use crate::html;
+use crate::lang;
+use crate::markdown;
+use crate::notify::{self, Notification, Notifier, Outcome, Summary};
+use crate::postprocess::{self, Loudness, Remix, Resample, Trim};
+use crate::preprocess;
+use crate::prompt;
+use crate::qa::QaAnalyzer;
+use crate::quarantine::{self, Placeholder, Quarantine};
+use crate::run::{Run, RunManifest, RunOutput};
+use crate::session;
+use crate::split::{self, Chunk, InputFormat, Interleave, LineNames, SplitBy, Subtasks};
+use crate::ssml;
+use crate::task::{self, SubtaskDescriptor, SubtaskInput, TaskBuilder, TaskManifest, TaskOptions};
+use crate::telemetry::{self, Telemetry};
+use crate::timing::{self, Timeline};
+use crate::toc::{self, TocMode};
+use crate::usage;
+use crate::voice::{self, Voice, VoiceRoute};
+use crate::watchdog::{Neighbours, Quarantined, Stall, Timeouts, Watchdog};
 use anyhow::{anyhow, bail, Context, Result};
+use chrono::Local;
 use console::{style, Emoji};
 use gwasm_api::prelude::*;
 use hound;
 use indicatif::ProgressBar;
-use std::cell::Cell;
+use regex::Regex;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 use std::{fmt, fs};
+use std::{iter, slice};
 use tempfile::{Builder, TempDir};
 
 static TRUCK: Emoji = Emoji("🚚  ", "");
@@ -15,8 +61,27 @@ static CLIP: Emoji = Emoji("🔗  ", "");
 static PAPER: Emoji = Emoji("📃  ", "");
 static HOURGLASS: Emoji = Emoji("⌛  ", "");
 
-const FLITE_JS: &[u8] = include_bytes!("../assets/flite.js");
-const FLITE_WASM: &[u8] = include_bytes!("../assets/flite.wasm");
+/// Prints progress message to stdout, or to stderr when the audio is
+/// streamed to stdout, so that the two never mix
+macro_rules! status {
+    ($app:expr, $($arg:tt)*) => {
+        if $app.streams_output() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+/// Maximum number of redirects followed when downloading input
+const INPUT_REDIRECTS: u32 = 5;
+/// How long to wait for testnet faucet funds before giving up
+const FUNDS_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+/// How often the node's balance is checked while waiting for funds
+const FUNDS_POLL_INTERVAL: Duration = Duration::from_secs(15);
+/// How far apart voices are panned in stereo dialogue, 1 putting the
+/// outermost ones hard left and right
+const PAN_WIDTH: f32 = 0.6;
 
 #[derive(Debug)]
 enum Workspace {
@@ -42,20 +107,29 @@ impl AsRef<Path> for Workspace {
     }
 }
 
-struct ProgressUpdater {
+pub struct ProgressUpdater {
     bar: ProgressBar,
     progress: Cell<f64>,
     num_subtasks: u64,
+    /// When the node reported the task finished
+    finished: Arc<Mutex<Option<Instant>>>,
 }
 
 impl ProgressUpdater {
-    fn new(num_subtasks: u64) -> Self {
+    pub fn new(num_subtasks: u64) -> Self {
         Self {
             bar: ProgressBar::new(num_subtasks),
             progress: Cell::new(0.0),
             num_subtasks,
+            finished: Arc::new(Mutex::new(None)),
         }
     }
+
+    /// Returns handle to when the task finished, which is set once the
+    /// updater is stopped
+    pub fn finished(&self) -> Arc<Mutex<Option<Instant>>> {
+        self.finished.clone()
+    }
 }
 
 impl ProgressUpdate for ProgressUpdater {
@@ -74,275 +148,2692 @@ impl ProgressUpdate for ProgressUpdater {
     }
 
     fn stop(&self) {
+        if let Ok(mut finished) = self.finished.lock() {
+            finished.get_or_insert_with(Instant::now);
+        }
         self.bar.finish_and_clear()
     }
 }
 
+/// Input text file synthesized into an output file of its own
 #[derive(Debug)]
-pub struct App {
+struct Document {
     input: PathBuf,
-    output_dir: PathBuf,
     output_filename: PathBuf,
+    /// Maximum cost of the document's own subtasks in GNT, set per job in a
+    /// batch manifest
+    budget: Option<f64>,
+}
+
+impl Document {
+    fn read(&self, encoding: Option<Encoding>, format: InputFormat) -> Result<String> {
+        read_input(&self.input, encoding, format)
+    }
+
+    /// Name of the input as shown to the user
+    fn name(&self) -> String {
+        if is_stdin(&self.input) {
+            "stdin".to_owned()
+        } else {
+            self.input.display().to_string()
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct App {
+    documents: Vec<Document>,
+    output_dir: PathBuf,
     datadir: PathBuf,
     address: String,
     port: u16,
-    num_subtasks: u64,
+    subtasks: Subtasks,
     bid: f64,
     budget: Option<f64>,
+    shuffle: bool,
+    scatter: bool,
     task_timeout: Timeout,
     subtask_timeout: Timeout,
     workspace: Workspace,
     net: Net,
+    voices: Vec<Voice>,
+    default_voice: Option<Voice>,
+    formats: Vec<Format>,
+    quality: Option<Quality>,
+    bit_depth: BitDepth,
+    crossfade: Option<Duration>,
+    max_output_duration: Option<Duration>,
+    /// Channel layout chunks are converted to, otherwise the synthesized one
+    layout: Option<Layout>,
+    preview: Option<Duration>,
+    preview_offset: Duration,
+    lrc: Option<PathBuf>,
+    lrc_words: bool,
+    timings: Option<PathBuf>,
+    srt: Option<PathBuf>,
+    qa_report: Option<PathBuf>,
+    qa_max_silence: Duration,
+    split_by: SplitBy,
+    chapter_pattern: Option<Regex>,
+    chapter_pad: Option<Duration>,
+    detect_language: bool,
+    voice_routes: Vec<VoiceRoute>,
+    toc: Option<TocMode>,
+    /// Text preprocessing applied to the inputs
+    filters: Chain,
+    /// Text filters configured in the config file
+    filter_config: Vec<FilterConfig>,
+    /// Audio postprocessing applied to each chunk
+    audio_filters: postprocess::Chain,
+    /// Audio filters configured in the config file
+    audio_filter_config: Vec<AudioFilterConfig>,
+    dash_pause: Option<Duration>,
+    gap: Option<Duration>,
+    per_line: Option<LineNames>,
+    per_chapter: bool,
+    /// Times each sentence is read, along with the silence following each
+    /// reading
+    repeat: Option<(u32, Duration)>,
+    interleave: Option<Interleave>,
+    /// Format of all the inputs, otherwise detected for each one
+    input_format: Option<InputFormat>,
+    /// Reuses chunks synthesized before rather than submitting them
+    use_cache: bool,
+    compress_workspace: bool,
+    /// Encoding of all the inputs, otherwise UTF-8 or the charset they're
+    /// served with
+    encoding: Option<Encoding>,
+    max_duration: Option<Duration>,
+    append: bool,
+    dry_run: bool,
+    event_log: bool,
+    wait_for_funds: bool,
+    currency: Option<String>,
+    limits: Limits,
+    /// Size the state dir is pruned to after each run
+    cache_max_size: Size,
+    timeouts: Timeouts,
+    placeholder: Placeholder,
+    session: SessionConfig,
+    usage_metrics: TelemetryConfig,
+    golemcli: Golemcli,
+    notifiers: Vec<Box<dyn Notifier>>,
+    /// SHA-256 checksums the node reported for result files, checked as
+    /// the files are read
+    checksums: RefCell<HashMap<PathBuf, String>>,
 }
 
-impl App {
-    fn split_input(&self) -> Result<Vec<String>> {
-        let contents = fs::read(&self.input)
-            .with_context(|| format!("reading from '{}'", self.input.display()))?;
-        let contents = String::from_utf8(contents).context("converting read bytes to string")?;
-        let word_count = contents.split_whitespace().count();
-
-        if (word_count as u64) < self.num_subtasks {
-            bail!(
-                "splitting input into Golem subtasks: cannot split input of {} words into {} subtasks",
-                word_count, self.num_subtasks
-            );
+/// Chunks of a document along with titles of its chapters
+type SplitDocument = (Vec<Chunk>, Vec<String>);
+
+/// Place of a subtask in the task, i.e. its voice, the index of its
+/// document, the index of its chunk within the document and the chunk
+type Slot<'a> = (Option<Voice>, usize, usize, &'a Chunk);
+
+/// Lays subtasks out voice after voice, document after document
+fn slots<'a>(documents: &'a [Vec<Chunk>], voices: &[Option<Voice>]) -> Vec<Slot<'a>> {
+    voices
+        .iter()
+        .flat_map(|voice| {
+            documents
+                .iter()
+                .enumerate()
+                .flat_map(move |(document, chunks)| {
+                    chunks
+                        .iter()
+                        .enumerate()
+                        .map(move |(i, chunk)| (*voice, document, i, chunk))
+                })
+        })
+        .collect()
+}
+
+/// Returns where each chapter starts in the combined output, i.e. the part
+/// of the output it starts in, its index and its offset in the part
+fn chapter_marks(timelines: &[&Timeline], chunks: &[Chunk]) -> Vec<(usize, usize, Duration)> {
+    let mut marks = Vec::new();
+    let mut next_chapter = 0;
+    for (part, timeline) in timelines.iter().enumerate() {
+        for span in timeline.spans() {
+            let chapter = chunks.get(span.chunk).map_or(0, |chunk| chunk.chapter);
+            if chapter >= next_chapter {
+                next_chapter = chapter + 1;
+                marks.push((part, chapter, timeline.frames_to_duration(span.start)));
+            }
         }
+    }
+    marks
+}
+
+/// Returns chapter markers of the part of the output, each lasting until
+/// the next one or the end of the last chunk
+///
+/// A part starting in the middle of a chapter opens with a marker of it.
+fn part_chapters(
+    marks: &[(usize, usize, Duration)],
+    part: usize,
+    timeline: &Timeline,
+    titles: &[String],
+) -> Vec<Chapter> {
+    let mut starts: Vec<_> = marks
+        .iter()
+        .filter(|&&(at, _, _)| at == part)
+        .map(|&(_, chapter, start)| (chapter, start))
+        .collect();
+    let opens_chapter = match starts.first() {
+        Some(&(_, start)) => start == Duration::from_secs(0),
+        None => false,
+    };
+    let carried = marks.iter().rev().find(|&&(at, _, _)| at < part);
+    if let (Some(&(_, chapter, _)), false) = (carried, opens_chapter) {
+        starts.insert(0, (chapter, Duration::from_secs(0)));
+    }
+
+    let end = timeline.frames_to_duration(timeline.spans().last().map_or(0, |span| span.end));
+    starts
+        .iter()
+        .enumerate()
+        .map(|(i, &(chapter, start))| Chapter {
+            title: titles.get(chapter).cloned().unwrap_or_default(),
+            start,
+            end: starts.get(i + 1).map_or(end, |&(_, next)| next),
+        })
+        .collect()
+}
+
+/// Returns key the chunk synthesized with the voice is cached under
+fn chunk_key(voice: Option<Voice>, chunk: &Chunk) -> String {
+    cache::chunk_key(
+        voice.or(chunk.voice),
+        &chunk.args,
+        &cache::text_hash(&chunk.text),
+    )
+}
 
-        log::info!("Input text file has {} words", word_count);
+impl App {
+    /// Checks whether the output is `-`, standing for stdout
+    fn streams_output(&self) -> bool {
+        self.documents
+            .iter()
+            .any(|document| document.output_filename == Path::new("-"))
+    }
+
+    /// Names of all the inputs as shown to the user
+    fn input_name(&self) -> String {
+        self.documents
+            .iter()
+            .map(Document::name)
+            .collect::<Vec<_>>()
+            .join("', '")
+    }
 
-        println!(
+    /// Normalizes input text before splitting it into chunks
+    fn preprocess(&self, contents: String) -> Result<String> {
+        let text = self.filters.apply(contents)?;
+        Ok(preprocess::join_pause_markers(&text))
+    }
+
+    /// Announces splitting the document of given word count, returning the
+    /// number of subtasks it's split into
+    fn announce_split(&self, document: &Document, word_count: usize) -> u64 {
+        let num_subtasks = self.subtasks.count(word_count);
+        status!(
+            self,
             "{} {}Splitting '{}' into {} Golem subtasks...",
             style("[1/4]").bold().dim(),
             PAPER,
-            self.input.to_string_lossy(),
-            self.num_subtasks,
+            document.name(),
+            num_subtasks,
         );
+        num_subtasks
+    }
 
-        let mut chunks = Vec::with_capacity(self.num_subtasks as usize);
-        let num_words = (word_count as f64 / self.num_subtasks as f64).ceil() as usize;
+    fn split_text(&self, contents: &str, document: &Document) -> Result<Vec<Chunk>> {
+        let num_subtasks = self.announce_split(document, split::word_count(&[contents]));
+        split::split(
+            contents,
+            num_subtasks,
+            self.chapter_pattern.as_ref(),
+            self.split_by,
+        )
+    }
+
+    /// Splits sections of the document, e.g. its chapters, into chunks
+    fn split_sections(&self, sections: &[String], document: &Document) -> Result<Vec<Chunk>> {
+        if self.repeat.is_some() {
+            self.announce_sentences(document);
+            let sections: Vec<_> = sections.iter().map(String::as_str).collect();
+            return split::split_sentences(&sections);
+        }
+        let num_subtasks = self.announce_split(document, split::word_count(sections));
+        split::split_sections(sections, num_subtasks, self.split_by)
+    }
+
+    fn announce_sentences(&self, document: &Document) {
+        status!(
+            self,
+            "{} {}Splitting '{}' into sentences...",
+            style("[1/4]").bold().dim(),
+            PAPER,
+            document.name(),
+        );
+    }
 
-        log::info!("Each chunk will have max {} words", num_words);
+    fn split_input(&self, contents: &str, document: &Document) -> Result<Vec<Chunk>> {
+        let mut chunks = if self.per_line.is_some() {
+            status!(
+                self,
+                "{} {}Splitting '{}' into lines...",
+                style("[1/4]").bold().dim(),
+                PAPER,
+                document.name(),
+            );
+            split::split_lines(contents)?
+        } else if self.repeat.is_some() {
+            self.announce_sentences(document);
+            split::split_text_sentences(contents, self.chapter_pattern.as_ref())?
+        } else {
+            self.split_text(contents, document)?
+        };
 
-        let mut acc = Vec::with_capacity(num_words);
-        for word in contents.split_whitespace() {
-            acc.push(word);
+        self.assign_voices(&mut chunks);
+        Ok(chunks)
+    }
 
-            if acc.len() == num_words {
-                chunks.push(acc);
-                acc = Vec::with_capacity(num_words);
-                continue;
+    /// Pairs lines of the input with their translations, normalizing both,
+    /// and splits them into chunks of one line each, alternating between
+    /// the two
+    fn split_pairs(
+        &self,
+        contents: String,
+        interleave: &Interleave,
+        document: &Document,
+    ) -> Result<Vec<Chunk>> {
+        status!(
+            self,
+            "{} {}Pairing '{}' with its translation...",
+            style("[1/4]").bold().dim(),
+            PAPER,
+            document.name(),
+        );
+        let (sources, translations) = match &interleave.translation {
+            Some(path) => (
+                contents,
+                read_input(path, self.encoding, InputFormat::Text)?,
+            ),
+            None => {
+                let (sources, translations): (Vec<_>, Vec<_>) =
+                    split::tsv_pairs(&contents)?.into_iter().unzip();
+                (sources.join("\n"), translations.join("\n"))
             }
+        };
+
+        let pairs =
+            split::parallel_pairs(&self.preprocess(sources)?, &self.preprocess(translations)?)?;
+        let mut chunks = split::interleave(&pairs, interleave)?;
+        self.assign_voices(&mut chunks);
+        Ok(chunks)
+    }
+
+    /// Normalizes plain text, e.g. one extracted from HTML, and splits it into chunks
+    fn split_plain(&self, contents: String, document: &Document) -> Result<SplitDocument> {
+        if let Some(interleave) = &self.interleave {
+            return Ok((
+                self.split_pairs(contents, interleave, document)?,
+                Vec::new(),
+            ));
+        }
+
+        let text = self.preprocess(contents)?;
+        let chunks = self.split_input(&text, document)?;
+        let titles = match (&self.chapter_pattern, self.per_line) {
+            (Some(pattern), None) => split::chapter_titles(&text, Some(pattern)),
+            _ => Vec::new(),
+        };
+        Ok((chunks, titles))
+    }
+
+    /// Parses SSML document, normalizing the text of its segments, and splits
+    /// it into chunks on element boundaries
+    fn split_ssml(&self, document: &Document) -> Result<SplitDocument> {
+        let mut segments = ssml::parse(&document.read(self.encoding, InputFormat::Ssml)?)
+            .with_context(|| format!("parsing SSML in '{}'", document.name()))?;
+        for segment in &mut segments {
+            segment.text = self.preprocess(segment.text.clone())?;
         }
 
-        if !acc.is_empty() {
-            chunks.push(acc);
+        let texts: Vec<_> = segments
+            .iter()
+            .map(|segment| segment.text.as_str())
+            .collect();
+        let num_subtasks = self.announce_split(document, split::word_count(&texts));
+        let mut chunks = ssml::split(&segments, num_subtasks)?;
+        self.assign_voices(&mut chunks);
+
+        Ok((chunks, Vec::new()))
+    }
+
+    /// Strips Markdown syntax from the document and splits it into chunks,
+    /// each section under a header forming a chapter of its own
+    fn split_markdown(&self, document: &Document) -> Result<SplitDocument> {
+        let sections: Vec<_> =
+            markdown::sections(&document.read(self.encoding, InputFormat::Markdown)?)
+                .into_iter()
+                .map(|section| self.preprocess(section))
+                .collect::<Result<_>>()?;
+
+        let mut chunks = self.split_sections(&sections, document)?;
+        self.assign_voices(&mut chunks);
+
+        Ok((chunks, split::section_titles(&sections)))
+    }
+
+    /// Reads chapters of EPUB book and splits them into chunks, each chapter
+    /// on its own
+    fn split_epub(&self, document: &Document) -> Result<SplitDocument> {
+        if is_stdin(&document.input) || as_url(&document.input).is_some() {
+            bail!("EPUB input has to be a local file");
+        }
+        let mut titles = Vec::new();
+        let mut sections = Vec::new();
+        for chapter in epub::chapters(&document.input)? {
+            let text = self.preprocess(chapter.text)?;
+            if text.split_whitespace().next().is_some() {
+                titles.push(chapter.title);
+                sections.push(text);
+            }
         }
 
-        if log::log_enabled!(log::Level::Info) {
-            for (i, chunk) in chunks.iter().enumerate() {
-                log::info!("Chunk {} has {} words", i, chunk.len(),);
+        let mut chunks = self.split_sections(&sections, document)?;
+        self.assign_voices(&mut chunks);
+
+        Ok((chunks, titles))
+    }
+
+    /// Routes chunks to voices based on their detected language, if enabled,
+    /// unless their voice is set by a directive, falling back to the default
+    /// voice
+    fn assign_voices(&self, chunks: &mut [Chunk]) {
+        for (i, chunk) in chunks.iter_mut().enumerate() {
+            if chunk.voice.is_some() {
+                continue;
+            }
+            if self.detect_language {
+                let language = lang::detect(&chunk.text);
+                chunk.voice =
+                    language.and_then(|language| voice::route(&self.voice_routes, language));
+                log::info!(
+                    "Chunk {} detected as {}, will use {} voice",
+                    i,
+                    language.unwrap_or("unknown language"),
+                    chunk
+                        .voice
+                        .or(self.default_voice)
+                        .map_or("default", Voice::name)
+                );
             }
+            chunk.voice = chunk.voice.or(self.default_voice);
         }
+    }
 
-        Ok(chunks.into_iter().map(|chunk| chunk.join(" ")).collect())
+    /// Returns voices to synthesize the input with, `None` being flite's default
+    fn output_voices(&self) -> Vec<Option<Voice>> {
+        if self.voices.is_empty() {
+            vec![None]
+        } else {
+            self.voices.iter().cloned().map(Some).collect()
+        }
     }
 
-    fn prepare_task(&self, chunks: impl IntoIterator<Item = String>) -> Result<Task> {
-        log::info!("Will prepare task in '{}'", self.workspace);
+    /// Prepares task synthesizing chunks of all the documents with all the
+    /// voices, laid out voice after voice and document after document
+    fn prepare_task(
+        &self,
+        name: &str,
+        attempt_dir: &Path,
+        slots: &[Slot],
+    ) -> Result<(Task, Vec<SubtaskDescriptor>)> {
+        log::info!("Will prepare task in '{}'", attempt_dir.display());
 
-        // prepare Golem task
-        let binary = GWasmBinary {
-            js: FLITE_JS,
-            wasm: FLITE_WASM,
-        };
-        // get expected output dir (if any)
-        let mut task_builder = TaskBuilder::new(&self.workspace, binary)
-            .name("g_flite")
-            .bid(self.bid)
-            .timeout(self.task_timeout)
-            .subtask_timeout(self.subtask_timeout)
-            .output_path(&self.output_dir);
+        let mut task_builder =
+            TaskBuilder::new(attempt_dir, self.task_timeout, self.subtask_timeout)
+                .name(name)
+                .bid(self.bid)
+                .budget(self.budget)
+                .shuffle(self.shuffle);
 
-        if let Some(budget) = self.budget {
-            task_builder = task_builder.budget(budget);
+        for &(voice, _, i, chunk) in slots {
+            let mut args = voice
+                .or(chunk.voice)
+                .map(Voice::exec_args)
+                .unwrap_or_default();
+            args.extend(chunk.args.iter().cloned());
+            task_builder = task_builder.push_subtask(SubtaskInput {
+                text: preprocess::to_flite(&chunk.text),
+                chunk: Some(i),
+                args,
+            });
         }
 
-        for chunk in chunks {
-            task_builder = task_builder.push_subtask_data(chunk.as_bytes());
+        let (task, subtasks) = task_builder.build().context("building gWasm task")?;
+
+        TaskManifest {
+            name: name.to_owned(),
+            bid: self.bid,
+            budget: self.budget,
+            options: TaskOptions {
+                subtasks: subtasks.len() as u64,
+                timeout: self.task_timeout.to_string(),
+                subtask_timeout: self.subtask_timeout.to_string(),
+            },
+            subtasks: subtasks.clone(),
         }
+        .save(attempt_dir)?;
 
-        task_builder.build().context("building gWasm task")
+        Ok((task, subtasks))
     }
 
-    fn combine_output(&self, task: ComputedTask) -> Result<()> {
-        let output = self.output_dir.join(&self.output_filename);
-        println!(
-            "{} {}Combining output into '{}'...",
-            style("[4/4]").bold().dim(),
-            CLIP,
-            output.display()
-        );
+    /// Warns if the longest chunk is estimated to take longer to synthesize
+    /// than the subtask timeout allows
+    fn check_subtask_timeout(&self, slots: &[Slot]) {
+        let timeout = match timeout_duration(&self.subtask_timeout) {
+            Some(timeout) if timeout > Duration::from_secs(0) => timeout,
+            _ => return,
+        };
+        let longest = slots
+            .iter()
+            .map(|&(_, _, _, chunk)| chunk.text.split_whitespace().count())
+            .max()
+            .unwrap_or(0);
+        let estimate = timing::estimate_synthesis(longest);
+        if estimate > timeout {
+            status!(
+                self,
+                "{} Subtasks of up to {} words may take about {} to synthesize, longer than \
+                 the subtask timeout of {}; raise --subtask_timeout or split the input into \
+                 more subtasks",
+                style("!").bold().yellow(),
+                longest,
+                humantime::format_duration(estimate),
+                self.subtask_timeout,
+            );
+        }
+    }
 
-        let mut writer: Option<hound::WavWriter<_>> = None;
+    /// Waits until the node has at least `gnt` and some ETH to pay for the task with
+    fn wait_for_funds(&self, gnt: f64) -> Result<()> {
+        let started = Instant::now();
+        let mut balance = self.golemcli.balance()?;
+        log::info!("Node balance is {}", balance);
+        if balance.gnt >= gnt && balance.eth > 0.0 {
+            return Ok(());
+        }
 
-        log::info!("Computed task = {:?}", task);
+        status!(
+            self,
+            "{} Node has {} but the task needs {} GNT; waiting for testnet faucet funds...",
+            style("!").bold().yellow(),
+            balance,
+            gnt
+        );
+        while balance.gnt < gnt || balance.eth <= 0.0 {
+            if started.elapsed() > FUNDS_TIMEOUT {
+                bail!(
+                    "node still has only {} after {}; make sure it's running on testnet and check its logs for faucet errors",
+                    balance,
+                    humantime::format_duration(FUNDS_TIMEOUT)
+                );
+            }
+            thread::sleep(FUNDS_POLL_INTERVAL);
+            balance = self.golemcli.balance()?;
+            log::info!("Node balance is {}", balance);
+        }
+        Ok(())
+    }
 
-        for (i, subtask) in task.subtasks.into_iter().enumerate() {
-            for (_, reader) in subtask.data.into_iter() {
-                let reader = hound::WavReader::new(reader).context("parsing WAVE input")?;
+    /// Checks that no document's subtasks cost more than its own budget, so
+    /// that one job can't take up what the task may spend
+    fn check_job_budgets(&self, submitted: &[Slot]) -> Result<()> {
+        for (i, document) in self.documents.iter().enumerate() {
+            let budget = match document.budget {
+                Some(budget) => budget,
+                None => continue,
+            };
+            let subtasks = submitted
+                .iter()
+                .filter(|&&(_, document, _, _)| document == i)
+                .count();
+            let cost = self.bid * subtasks as f64;
+            if cost > budget {
+                bail!(
+                    "'{}' would cost up to {} GNT for {} subtasks, exceeding its budget of {} GNT; lower its bid or --subtasks, or raise its budget in the manifest",
+                    document.name(),
+                    cost,
+                    subtasks,
+                    budget
+                );
+            }
+        }
+        Ok(())
+    }
 
-                if writer.is_none() {
-                    writer = Some(
-                        hound::WavWriter::create(&output, reader.spec()).with_context(|| {
-                            format!("creating output WAVE file '{}'", output.display())
-                        })?,
+    /// Checks the cost of the task against the limits, waiting for funds if
+    /// asked to, and shows it; on mainnet the user has to confirm it first
+    ///
+    /// Returns the exchange rate the cost was shown in, along with estimated
+    /// transaction fees in ETH on mainnet.
+    fn confirm_cost(&self, subtasks: u64) -> Result<(Option<Rate>, Option<f64>)> {
+        let cost = self.budget.unwrap_or(self.bid * subtasks as f64);
+        if let Some(max_budget) = self.limits.max_budget {
+            if cost > max_budget {
+                bail!(
+                    "task would cost up to {} GNT, exceeding the maximum of {} GNT set in the config file; lower --bid or --subtasks, or set a smaller --budget",
+                    cost,
+                    max_budget
+                );
+            }
+        }
+        if self.wait_for_funds {
+            self.wait_for_funds(cost)?;
+        }
+        let rate = self.exchange_rate();
+        let estimated_fee_eth = match self.net {
+            Net::MainNet => self.confirm_spend(cost, subtasks, rate.as_ref())?,
+            Net::TestNet => {
+                if rate.is_some() {
+                    status!(
+                        self,
+                        "Task will cost at most {} for {} subtasks",
+                        format_gnt(cost, rate.as_ref()),
+                        subtasks
                     );
                 }
+                None
+            }
+        };
+        Ok((rate, estimated_fee_eth))
+    }
 
-                let mut wrt = writer.as_mut().unwrap().get_i16_writer(reader.len());
-                for sample in reader.into_samples::<i16>() {
-                    sample
-                        .map(|sample| unsafe { wrt.write_sample_unchecked(sample) })
-                        .with_context(|| format!("reading audio sample from subtask '{}'", i))?;
-                }
-                wrt.flush().with_context(|| {
-                    format!("writing audio samples to file '{}'", output.display(),)
-                })?;
+    /// Shows expected cost of the task and asks whether to submit it on mainnet
+    ///
+    /// Returns estimated transaction fees in ETH, unless the node's gas price
+    /// couldn't be queried.
+    fn confirm_spend(&self, cost: f64, subtasks: u64, rate: Option<&Rate>) -> Result<Option<f64>> {
+        let fee = match self.golemcli.gas_price() {
+            Ok(gas_price) => Some(golem::estimate_fee(gas_price, subtasks)),
+            Err(e) => {
+                log::warn!("Couldn't query gas price: {:#}", e);
+                None
+            }
+        };
+
+        status!(
+            self,
+            "Task will cost at most {} for {} subtasks",
+            format_gnt(cost, rate),
+            subtasks
+        );
+        match fee {
+            Some(fee) => status!(
+                self,
+                "plus at most {} in transaction fees",
+                format_eth(fee, rate)
+            ),
+            None => status!(self, "plus transaction fees, which couldn't be estimated"),
+        }
+        if !prompt::confirm("Submit the task on mainnet?")? {
+            bail!("task submission cancelled");
+        }
+        Ok(fee)
+    }
+
+    /// Describes why the watchdog aborted the task and what to do about it
+    fn stall_error(&self, stall: Stall) -> anyhow::Error {
+        match stall {
+            Stall::Unassigned { timeout, peers } => {
+                let market = match peers {
+                    Some(0) => "the node isn't connected to any peers, so check its network connectivity".to_owned(),
+                    Some(peers) => format!(
+                        "the node is connected to {} peers, none of which took the task at the bid of {} GNT; try a higher --bid, e.g. {}",
+                        peers,
+                        self.bid,
+                        self.bid * 2.0
+                    ),
+                    None => format!(
+                        "providers may not accept the bid of {} GNT; try a higher --bid, e.g. {}",
+                        self.bid,
+                        self.bid * 2.0
+                    ),
+                };
+                anyhow!(
+                    "task aborted as no provider picked up any subtask within {}; {}",
+                    humantime::format_duration(timeout),
+                    market
+                )
             }
+            Stall::Quarantined(quarantined) => anyhow!(
+                "task aborted as {} subtask(s) kept failing",
+                quarantined.len()
+            ),
+            Stall::Understaffed {
+                timeout,
+                required,
+                reached,
+            } => anyhow!(
+                "task aborted as at most {} of the {} providers required were computing it at once within {}; try a higher --bid, e.g. {}, or a lower --min-providers",
+                reached,
+                required,
+                humantime::format_duration(timeout),
+                self.bid * 2.0
+            ),
+            Stall::Unscattered {
+                subtask_id,
+                provider,
+            } => anyhow!(
+                "task aborted as subtask {} kept getting assigned to providers holding an adjacent chunk, lastly {}; too few providers seem to be available to keep adjacent chunks apart, so try a higher --bid or run without --scatter",
+                subtask_id,
+                provider
+            ),
         }
+    }
 
-        Ok(())
+    /// Warns about adjacent chunks sent to the same provider despite
+    /// --scatter, listing which provider got to see the text of which
+    fn warn_exposed(
+        &self,
+        descriptors: &[SubtaskDescriptor],
+        exposed: &[(String, String, String)],
+    ) {
+        if exposed.is_empty() {
+            return;
+        }
+        let chunk = |name: &str| {
+            descriptors
+                .iter()
+                .find(|descriptor| descriptor.id == name)
+                .and_then(|descriptor| descriptor.chunk)
+                .map_or_else(|| "?".to_owned(), |chunk| (chunk + 1).to_string())
+        };
+        status!(
+            self,
+            "{} WARNING: {} pair(s) of adjacent chunks reached the same provider despite --scatter, which got to see the text of both:",
+            style("!").bold().red(),
+            exposed.len()
+        );
+        for (a, b, provider) in exposed {
+            status!(self, "  chunks {} and {}: {}", chunk(a), chunk(b), provider);
+        }
     }
 
-    pub fn run(&self) -> Result<()> {
-        let chunks = self.split_input()?;
-        let task = self.prepare_task(chunks)?;
+    /// Salvages results of the finished subtasks, substituting silence for
+    /// the quarantined ones, and saves the chunks left out to the workspace
+    fn salvage(
+        &self,
+        task_name: &str,
+        descriptors: &[SubtaskDescriptor],
+        slots: &[Slot],
+        quarantined: Vec<Quarantined>,
+    ) -> Result<Vec<ComputedSubtask>> {
+        let mut placeholders = BTreeMap::new();
+        let mut entries = Vec::with_capacity(quarantined.len());
+        for subtask in quarantined {
+            let index = descriptors
+                .iter()
+                .position(|descriptor| descriptor.id == subtask.subtask)
+                .ok_or_else(|| anyhow!("node reported unexpected subtask '{}'", subtask.subtask))?;
+            let (voice, document, i, chunk) = slots[index];
+            let duration = timing::estimate_duration(slice::from_ref(chunk));
+            placeholders.insert(index, duration);
+            entries.push(quarantine::Entry {
+                subtask: descriptors[index].id.clone(),
+                subtask_id: subtask.subtask_id,
+                input: self.documents[document].input.clone(),
+                chunk: i,
+                voice,
+                text: chunk.text.clone(),
+                failures: subtask.failures,
+                providers: subtask.providers,
+                placeholder_secs: duration.as_secs_f64(),
+            });
+        }
+
+        let subtasks = quarantine::salvage(descriptors, &placeholders, self.placeholder)?;
+        let path = Quarantine {
+            task_name: task_name.to_owned(),
+            entries,
+        }
+        .save(&self.workspace)?;
+        status!(
+            self,
+            "{} chunk(s) kept failing and were replaced with {}; their text and providers are listed in '{}'. Check the text for anything flite may choke on and synthesize it separately, or rerun with a higher --bid",
+            placeholders.len(),
+            self.placeholder,
+            path.display()
+        );
+        Ok(subtasks)
+    }
+
+    /// Notes checksums the node reported for the subtasks' result files
+    ///
+    /// Checksums are matched with the results by their paths, so ones which
+    /// don't lead to a subtask's output dir are left out rather than checked
+    /// against the wrong file.
+    fn expect_checksums(
+        &self,
+        attempt_dir: &Path,
+        descriptors: &[SubtaskDescriptor],
+        reported: BTreeMap<String, String>,
+    ) {
+        let output_dir = attempt_dir.join(task::OUTPUT_DIR);
+        let mut checksums = self.checksums.borrow_mut();
+        for (name, checksum) in reported {
+            let path = output_dir.join(&name);
+            let known = descriptors
+                .iter()
+                .any(|descriptor| descriptor.output.parent() == path.parent());
+            if known {
+                checksums.insert(path, checksum);
+            } else {
+                log::debug!(
+                    "Ignoring checksum of '{}', which isn't a result of any subtask",
+                    name
+                );
+            }
+        }
+    }
+
+    /// Computes the subtasks of the slots in a Golem task, returning their
+    /// results in the slots' order and whether any had to be salvaged
+    fn compute_subtasks(
+        &self,
+        task_name: &str,
+        attempt_dir: &Path,
+        slots: &[Slot],
+        telemetry: &mut Telemetry,
+    ) -> Result<(Vec<ComputedSubtask>, bool)> {
+        let stage = Instant::now();
+        let (task, descriptors) = self
+            .prepare_task(task_name, attempt_dir, slots)
+            .context(Stage::Prepare)?;
+        telemetry.uploaded_bytes = telemetry::dir_size(attempt_dir.join(task::INPUT_DIR));
+        telemetry.record_stage("prepare", stage);
 
         log::debug!("g_flite run task = {:?}", task);
 
-        println!(
+        status!(
+            self,
             "{} {}Sending task to Golem...",
             style("[2/4]").bold().dim(),
             TRUCK
         );
 
-        println!(
+        status!(
+            self,
             "{} {}Waiting on compute to finish...",
             style("[3/4]").bold().dim(),
             HOURGLASS
         );
 
-        let progress_updater = ProgressUpdater::new(self.num_subtasks);
-        let computed_task = compute(
+        let stage = Instant::now();
+        let progress_updater = ProgressUpdater::new(slots.len() as u64);
+        let finished = progress_updater.finished();
+        let monitor = if self.event_log {
+            let log = EventLog::create(&self.workspace)?;
+            Some(EventMonitor::spawn(
+                log,
+                self.golemcli.clone(),
+                task_name.to_owned(),
+            ))
+        } else {
+            None
+        };
+        let scatter = if self.scatter {
+            Some(Neighbours::new(&descriptors))
+        } else {
+            None
+        };
+        // a task can't keep busy more providers than it has subtasks
+        let mut timeouts = self.timeouts;
+        timeouts.min_providers = timeouts.min_providers.map(|min| min.min(slots.len()));
+        // runs even without timeouts, to note when the task gets assigned
+        let watchdog = Watchdog::spawn(
+            self.golemcli.clone(),
+            task_name.to_owned(),
+            timeouts,
+            scatter,
+        );
+        let computed_task = session::compute(
             self.datadir.clone(),
             self.address.clone(),
             self.port,
             self.net.clone(),
             task,
             progress_updater,
-        )?;
+            self.session,
+        );
+        if let Some(mut log) = monitor.and_then(EventMonitor::stop) {
+            if let Err(e) = &computed_task {
+                log.log(&format!("task failed: {:#}", e))?;
+            }
+            status!(self, "Task event log written to '{}'", log.path().display());
+        }
+        let returned = Instant::now();
+        let mut watched = watchdog.stop();
+        self.expect_checksums(
+            attempt_dir,
+            &descriptors,
+            std::mem::take(&mut watched.checksums),
+        );
+        self.warn_exposed(&descriptors, &watched.exposed);
+        if let (Some(required), None) = (timeouts.min_providers, watched.healthy) {
+            if computed_task.is_ok() {
+                status!(
+                    self,
+                    "Task was computed by at most {} of the {} providers required at once",
+                    watched.peak_providers,
+                    required
+                );
+            }
+        }
+        let quarantined = match watched.stall {
+            Some(Stall::Quarantined(quarantined)) => Some(quarantined),
+            Some(stall) => return Err(self.stall_error(stall).context(Stage::Compute)),
+            None => None,
+        };
+
+        let expected: Vec<usize> = slots.iter().map(|&(_, _, i, _)| i).collect();
+        task::verify_chunk_order(&descriptors, &expected)?;
+        let salvaged = quarantined.is_some();
+        let computed = match quarantined {
+            Some(quarantined) => self
+                .salvage(task_name, &descriptors, slots, quarantined)
+                .context(Stage::Compute)?,
+            None => {
+                let computed_task = computed_task.context(Stage::Compute)?;
+                log::info!("Computed task = {:?}", computed_task);
+                task::order_subtasks(computed_task.subtasks, &descriptors)?
+            }
+        };
+        telemetry.downloaded_bytes = telemetry::dir_size(attempt_dir.join(task::OUTPUT_DIR));
 
-        self.combine_output(computed_task)
+        // results are fetched after the node reports the task finished
+        let finished = finished
+            .lock()
+            .ok()
+            .and_then(|finished| *finished)
+            .unwrap_or(returned);
+        let computing = match watched.assigned {
+            Some(assigned) => {
+                telemetry.record_span("assign", stage, assigned);
+                assigned
+            }
+            None => stage,
+        };
+        telemetry.record_span("compute", computing, finished);
+        telemetry.record_stage("download", finished);
+
+        Ok((computed, salvaged))
     }
-}
 
-impl TryFrom<Opt> for App {
-    type Error = anyhow::Error;
+    /// Returns exchange rate of the currency to show costs in, if any
+    fn exchange_rate(&self) -> Option<Rate> {
+        let currency = self.currency.as_ref()?;
+        match Rate::get(currency) {
+            Ok(rate) => Some(rate),
+            Err(e) => {
+                eprintln!(
+                    "Couldn't get {} exchange rate, showing costs in GNT only: {:#}",
+                    currency.to_uppercase(),
+                    e
+                );
+                None
+            }
+        }
+    }
 
-    fn try_from(opt: Opt) -> std::result::Result<Self, Self::Error> {
-        // verify input exists
-        let input = opt.input;
-        if !input.is_file() {
-            bail!(
-                "Input file '{}' doesn't exist. Did you make a typo anywhere?",
-                input.display()
-            );
+    /// Returns name of the output file, tagged with the voice if comparing voices
+    fn output_filename(&self, document: &Document, voice: Option<Voice>) -> PathBuf {
+        self.tag_filename(&document.output_filename, voice)
+    }
+
+    /// Tags the file name with the voice if comparing voices
+    fn tag_filename(&self, filename: &Path, voice: Option<Voice>) -> PathBuf {
+        match voice {
+            Some(voice) if self.voices.len() > 1 => {
+                let mut tagged: OsString =
+                    filename.file_stem().unwrap_or(filename.as_os_str()).into();
+                tagged.push(".");
+                tagged.push(voice.name());
+                if let Some(extension) = filename.extension() {
+                    tagged.push(".");
+                    tagged.push(extension);
+                }
+                tagged.into()
+            }
+            _ => filename.to_path_buf(),
         }
+    }
 
-        // verify output path excluding topmost file exists
-        let output = if opt.output.is_relative() {
-            Path::new(".").join(opt.output)
-        } else {
-            opt.output.into()
-        };
-        let (output_dir, output_filename) = {
-            let parent = output.parent().unwrap(); // guaranteed not to fail
-            let filename = output.file_name().ok_or(anyhow!(
-                "working out the expected output filename from '{}'",
-                output.display()
-            ))?;
-            (parent.to_path_buf(), PathBuf::from(filename))
-        };
-        let output_dir = output_dir.canonicalize().with_context(|| {
-            format!(
-                "working out absolute path for the expected output path '{}'",
-                output.display(),
-            )
-        })?;
+    /// Returns path of the output file of a single line in per-line mode
+    fn line_path(&self, name: &str, voice: Option<Voice>, format: Format) -> PathBuf {
+        let filename = PathBuf::from(format!("{}.{}", name, format.extension()));
+        self.output_dir.join(self.tag_filename(&filename, voice))
+    }
 
-        let datadir = match opt.datadir {
-            Some(datadir) => datadir.canonicalize().with_context(|| {
-                format!(
-                    "working out absolute path for the provided datadir '{}'",
-                    datadir.display(),
-                )
-            })?,
-            None => match appdirs::user_data_dir(Some("golem"), Some("golem"), false) {
-                Ok(datadir) => datadir.join("default"),
-                Err(_) => bail!(
-                    "
-                    No standard project app datadirs available.
-                    You'll need to specify path to your Golem datadir manually.
-                    "
-                ),
-            },
-        };
+    fn output_path(&self, document: &Document, voice: Option<Voice>, format: Format) -> PathBuf {
+        let filename = self.output_filename(document, voice);
+        match format {
+            Format::Wav => self.output_dir.join(filename),
+            _ => self
+                .output_dir
+                .join(filename.with_extension(format.extension())),
+        }
+    }
 
-        let address = opt.address;
-        let port = opt.port;
-        let num_subtasks = opt.subtasks;
-        let bid = opt.bid;
-        let budget = opt.budget;
-        let task_timeout = opt.task_timeout;
-        let subtask_timeout = opt.subtask_timeout;
-        let net = if opt.mainnet {
-            Net::MainNet
-        } else {
-            Net::TestNet
-        };
+    /// Returns path of the part of the output, numbered if the output is
+    /// split by duration, e.g. `output_001.wav`
+    fn part_path(
+        &self,
+        document: &Document,
+        voice: Option<Voice>,
+        format: Format,
+        part: usize,
+    ) -> PathBuf {
+        let path = self.output_path(document, voice, format);
+        if self.max_output_duration.is_none() {
+            return path;
+        }
+        let mut filename: OsString = path.file_stem().unwrap_or_else(|| path.as_os_str()).into();
+        filename.push(format!("_{:03}", part + 1));
+        if let Some(extension) = path.extension() {
+            filename.push(".");
+            filename.push(extension);
+        }
+        path.with_file_name(filename)
+    }
 
-        let workspace = match opt.workspace {
-            Some(workspace) => {
-                Workspace::UserSpecified(workspace.canonicalize().with_context(|| {
-                    format!(
-                        "working out absolute path for provided workspace dir '{}'",
-                        workspace.display(),
-                    )
-                })?)
+    fn preview_path(&self, document: &Document, voice: Option<Voice>) -> PathBuf {
+        let filename = self.output_filename(document, voice);
+        let mut preview: OsString = filename
+            .file_stem()
+            .unwrap_or_else(|| filename.as_os_str())
+            .into();
+        preview.push(".preview.wav");
+        self.output_dir.join(preview)
+    }
+
+    fn toc_path(&self, document: &Document, voice: Option<Voice>, format: Format) -> PathBuf {
+        let filename = self.output_filename(document, voice);
+        let mut toc: OsString = filename
+            .file_stem()
+            .unwrap_or_else(|| filename.as_os_str())
+            .into();
+        toc.push(".toc.");
+        toc.push(format.extension());
+        self.output_dir.join(toc)
+    }
+
+    /// Returns path of the output file of a single chapter in per-chapter mode
+    fn chapter_path(
+        &self,
+        document: &Document,
+        voice: Option<Voice>,
+        name: &str,
+        format: Format,
+    ) -> PathBuf {
+        let filename = &document.output_filename;
+        let mut chapter: OsString = filename
+            .file_stem()
+            .unwrap_or_else(|| filename.as_os_str())
+            .into();
+        chapter.push("_");
+        chapter.push(name);
+        chapter.push(".");
+        chapter.push(format.extension());
+        self.output_dir
+            .join(self.tag_filename(Path::new(&chapter), voice))
+    }
+
+    fn chapters_path(&self, document: &Document, voice: Option<Voice>) -> PathBuf {
+        let filename = self.output_filename(document, voice);
+        let mut chapters: OsString = filename
+            .file_stem()
+            .unwrap_or_else(|| filename.as_os_str())
+            .into();
+        chapters.push(".chapters.json");
+        self.output_dir.join(chapters)
+    }
+
+    /// Works out where each chapter starts in the combined audio without
+    /// decoding it, leaving the subtask outputs rewound for combining
+    fn chapter_starts(
+        &self,
+        subtasks: &mut [ComputedSubtask],
+        chunks: &[Chunk],
+    ) -> Result<Vec<Duration>> {
+        let mut starts = Vec::new();
+        let mut position = 0.0;
+        let mut chapter_start = 0.0;
+
+        for (i, subtask) in subtasks.iter_mut().enumerate() {
+            let chapter = chunks.get(i).map(|chunk| chunk.chapter);
+            // first chunk of the next chapter
+            if chapter >= Some(starts.len()) {
+                starts.push(Duration::from_millis((position * 1000.0) as u64));
             }
-            None => Workspace::Temp(
-                Builder::new()
-                    .prefix("g_flite")
-                    .tempdir()
-                    .context("creating workspace dir in your tmp files")?,
-            ),
-        };
 
-        Ok(Self {
-            input,
-            output_dir,
-            output_filename,
-            datadir,
-            address,
-            port,
-            num_subtasks,
-            bid,
-            budget,
-            task_timeout,
-            subtask_timeout,
-            workspace,
-            net,
-        })
+            for reader in subtask.data.values_mut() {
+                {
+                    let header = hound::WavReader::new(&mut *reader)
+                        .with_context(|| format!("parsing WAVE header of subtask '{}'", i))?;
+                    position += f64::from(header.duration()) / f64::from(header.spec().sample_rate);
+                }
+                reader
+                    .seek(SeekFrom::Start(0))
+                    .with_context(|| format!("rewinding output of subtask '{}'", i))?;
+            }
+
+            if let Some(pad) = self.chapter_pad {
+                let next_chapter = chunks.get(i + 1).map(|chunk| chunk.chapter);
+                if chapter != next_chapter {
+                    let boundary = pad.as_millis() as f64 / 1000.0;
+                    if boundary > 0.0 {
+                        let length = position - chapter_start;
+                        position = chapter_start + (length / boundary).ceil() * boundary;
+                    }
+                    chapter_start = position;
+                }
+            }
+        }
+
+        Ok(starts)
+    }
+
+    /// Synthesizes table of contents script for each voice in a separate task
+    fn synthesize_toc(
+        &self,
+        scripts: Vec<(Option<Voice>, String)>,
+    ) -> Result<Vec<ComputedSubtask>> {
+        status!(
+            self,
+            "{} {}Synthesizing table of contents...",
+            style("[3/4]").bold().dim(),
+            HOURGLASS
+        );
+
+        let workspace = task::create_attempt_dir(self.workspace.as_ref().join("toc"))
+            .context("creating table of contents workspace")?;
+
+        let num_subtasks = scripts.len() as u64;
+        let mut task_builder =
+            TaskBuilder::new(&workspace, self.task_timeout, self.subtask_timeout)
+                .name("g_flite_toc")
+                .bid(self.bid)
+                .budget(self.budget);
+        for (voice, text) in scripts {
+            log::info!(
+                "Table of contents for {} voice: {}",
+                voice.map_or("default", Voice::name),
+                text
+            );
+            task_builder = task_builder.push_subtask(SubtaskInput {
+                text,
+                chunk: None,
+                args: voice.map(Voice::exec_args).unwrap_or_default(),
+            });
+        }
+        let (task, subtasks) = task_builder
+            .build()
+            .context("building table of contents gWasm task")?;
+
+        let computed_task = session::compute(
+            self.datadir.clone(),
+            self.address.clone(),
+            self.port,
+            self.net.clone(),
+            task,
+            ProgressUpdater::new(num_subtasks),
+            self.session,
+        )?;
+
+        task::order_subtasks(computed_task.subtasks, &subtasks)
+    }
+
+    /// Reads output of a subtask, running it through the chunk audio filters
+    ///
+    /// Fails if the output doesn't match the checksum the node reported for
+    /// it, e.g. having got corrupted in transfer.
+    fn read_chunk(
+        &self,
+        path: &Path,
+        reader: BufReader<File>,
+        name: &str,
+    ) -> Result<(hound::WavSpec, Vec<i16>)> {
+        let checksum = self.checksums.borrow().get(path).cloned();
+        if checksum.is_none() {
+            log::debug!("No checksum reported for '{}'", path.display());
+        }
+        let (spec, samples) = audio::read_wav_file(reader, name, checksum.as_deref())?;
+        self.audio_filters.apply(spec, samples)
+    }
+
+    /// Returns audio filters run on each output file
+    fn output_filters(&self) -> Result<postprocess::Chain> {
+        postprocess::chain(&self.audio_filter_config, postprocess::Stage::Output)
+    }
+
+    /// Reads output of a subtask, converting it to the spec of the combined
+    /// audio and creating the combiner on first use
+    fn read_output(
+        &self,
+        path: &Path,
+        reader: BufReader<File>,
+        combiner: &mut Option<Combiner>,
+        document: &Document,
+        voice: Option<Voice>,
+        name: &str,
+    ) -> Result<Vec<i16>> {
+        let (spec, samples) = self.read_chunk(path, reader, name)?;
+
+        if combiner.is_none() {
+            *combiner = Some(self.create_combiner(spec, document, voice, 0)?);
+        }
+        let combiner = combiner.as_mut().unwrap();
+
+        // chunks synthesized with different voices may differ in spec
+        if spec != combiner.spec() {
+            log::info!(
+                "Converting {} output from {:?} to {:?}",
+                name,
+                spec,
+                combiner.spec()
+            );
+            return Ok(combiner.convert(samples, spec));
+        }
+
+        Ok(samples)
+    }
+
+    /// Writes table of contents as a separate track in all requested formats
+    fn write_toc_track(
+        &self,
+        toc: ComputedSubtask,
+        document: &Document,
+        voice: Option<Voice>,
+    ) -> Result<Vec<PathBuf>> {
+        let mut paths = Vec::with_capacity(self.formats.len());
+
+        for (path, reader) in toc.data.into_iter() {
+            let (spec, samples) = self.read_chunk(&path, reader, "table of contents")?;
+            paths.extend(self.write_track(spec, &samples, |format| {
+                self.toc_path(document, voice, format)
+            })?);
+        }
+
+        Ok(paths)
+    }
+
+    /// Writes samples as a standalone track in all requested formats
+    fn write_track<F>(&self, spec: hound::WavSpec, samples: &[i16], path: F) -> Result<Vec<PathBuf>>
+    where
+        F: Fn(Format) -> PathBuf,
+    {
+        let mut paths = Vec::with_capacity(self.formats.len());
+        let mut combiner = Combiner::new(spec);
+        combiner.set_filters(self.output_filters()?);
+        combiner.set_bit_depth(self.bit_depth);
+        for &format in &self.formats {
+            let path = path(format);
+            match format {
+                Format::Wav => combiner.set_output(&path)?,
+                _ => combiner.add_encoder(Encoder::spawn(format, &path, spec, self.quality)?),
+            }
+            paths.push(path);
+        }
+        combiner.write_samples(samples)?;
+        combiner.finish()?;
+
+        Ok(paths)
+    }
+
+    /// Writes output of each line into a file of its own in per-line mode
+    fn write_lines(
+        &self,
+        subtasks: Vec<ComputedSubtask>,
+        chunks: &[Chunk],
+        voice: Option<Voice>,
+        names: LineNames,
+    ) -> Result<RunOutput> {
+        status!(
+            self,
+            "{} {}Writing {} lines into '{}'...",
+            style("[4/4]").bold().dim(),
+            CLIP,
+            chunks.len(),
+            self.output_dir.display()
+        );
+
+        let names = split::line_names(chunks, names);
+        let mut files = Vec::with_capacity(chunks.len() * self.formats.len());
+
+        for ((i, subtask), name) in subtasks.into_iter().enumerate().zip(&names) {
+            for (path, reader) in subtask.data.into_iter() {
+                let (spec, samples) =
+                    self.read_chunk(&path, reader, &format!("subtask '{}'", i))?;
+                files.extend(
+                    self.write_track(spec, &samples, |format| self.line_path(name, voice, format))?,
+                );
+            }
+        }
+
+        if files.is_empty() {
+            bail!("writing lines: no subtask results to write");
+        }
+
+        RunOutput::new(voice, files)
+    }
+
+    /// Writes output of each chapter into a file of its own in per-chapter
+    /// mode, listing them in the chapter manifest
+    fn write_chapters(
+        &self,
+        subtasks: Vec<ComputedSubtask>,
+        chunks: &[Chunk],
+        document: &Document,
+        voice: Option<Voice>,
+        titles: &[String],
+    ) -> Result<RunOutput> {
+        status!(
+            self,
+            "{} {}Writing {} chapters into '{}'...",
+            style("[4/4]").bold().dim(),
+            CLIP,
+            titles.len(),
+            self.output_dir.display()
+        );
+
+        let names = split::chapter_names(titles);
+        let mut entries: Vec<_> = titles
+            .iter()
+            .enumerate()
+            .map(|(i, title)| toc::ManifestEntry {
+                index: i + 1,
+                title: title.clone(),
+                start_secs: None,
+                files: Vec::new(),
+            })
+            .collect();
+        // spec of the first chunk, which all chapters are written in
+        let mut chapter_spec: Option<hound::WavSpec> = None;
+        let mut chapter: Option<(usize, Vec<i16>)> = None;
+
+        let mut subtasks = subtasks.into_iter().enumerate().peekable();
+        while let Some((i, subtask)) = subtasks.next() {
+            let index = chunks.get(i).map_or(0, |chunk| chunk.chapter);
+            for (path, reader) in subtask.data.into_iter() {
+                let (spec, samples) =
+                    self.read_chunk(&path, reader, &format!("subtask '{}'", i))?;
+                let chapter_spec = *chapter_spec.get_or_insert(spec);
+                // chunks synthesized with different voices may differ in spec
+                let samples = if spec == chapter_spec {
+                    samples
+                } else {
+                    audio::convert(&samples, spec, chapter_spec)
+                };
+                chapter
+                    .get_or_insert_with(|| (index, Vec::new()))
+                    .1
+                    .extend_from_slice(&samples);
+            }
+
+            if let (Some(pause), Some((_, samples)), Some(spec)) = (
+                chunks.get(i).and_then(|chunk| chunk.pause),
+                chapter.as_mut(),
+                chapter_spec,
+            ) {
+                let frames = audio::duration_to_frames(pause, spec.sample_rate);
+                let silence = frames as usize * usize::from(spec.channels);
+                samples.resize(samples.len() + silence, 0);
+            }
+
+            let next = subtasks
+                .peek()
+                .and_then(|(i, _)| chunks.get(*i))
+                .map(|chunk| chunk.chapter);
+            if next == Some(index) {
+                continue;
+            }
+            if let (Some((index, samples)), Some(spec)) = (chapter.take(), chapter_spec) {
+                let name = names
+                    .get(index)
+                    .ok_or_else(|| anyhow!("writing chapters: chapter {} has no title", index))?;
+                let files = self.write_track(spec, &samples, |format| {
+                    self.chapter_path(document, voice, name, format)
+                })?;
+                entries[index].files = files;
+            }
+        }
+
+        if chapter_spec.is_none() {
+            bail!("writing chapters: no subtask results to write");
+        }
+
+        let mut files: Vec<_> = entries
+            .iter()
+            .flat_map(|entry| entry.files.iter().cloned())
+            .collect();
+        let path = self.chapters_path(document, voice);
+        log::info!("Writing chapter manifest to '{}'", path.display());
+        toc::ChapterManifest {
+            input: document.input.clone(),
+            chapters: entries,
+        }
+        .save(&path)?;
+        files.push(path);
+
+        RunOutput::new(voice, files)
+    }
+
+    /// Finishes the current part of the output and starts the next one if
+    /// writing given number of frames would make it longer than
+    /// --max-output-duration, returning whether it did
+    fn roll_over(
+        &self,
+        combiner: &mut Option<Combiner>,
+        parts: &mut Vec<Timeline>,
+        frames: u64,
+        document: &Document,
+        voice: Option<Voice>,
+    ) -> Result<bool> {
+        let (max, current) = match (self.max_output_duration, combiner.as_ref()) {
+            (Some(max), Some(current)) => (max, current),
+            _ => return Ok(false),
+        };
+        let limit = audio::duration_to_frames(max, current.spec().sample_rate);
+        if current.position() == 0 || current.position() + frames <= limit {
+            return Ok(false);
+        }
+        if frames > limit {
+            log::warn!(
+                "Chunk of {} frames is longer than --max-output-duration, writing it into a part of its own",
+                frames
+            );
+        }
+
+        let next = self.create_combiner(current.spec(), document, voice, parts.len() + 1)?;
+        if let Some(previous) = combiner.take() {
+            parts.push(previous.roll_over()?);
+        }
+        *combiner = Some(next);
+        Ok(true)
+    }
+
+    /// Creates combiner of the part of the output, the first one unless
+    /// it's split by duration
+    fn create_combiner(
+        &self,
+        spec: hound::WavSpec,
+        document: &Document,
+        voice: Option<Voice>,
+        part: usize,
+    ) -> Result<Combiner> {
+        let mut combiner = Combiner::new(spec);
+        combiner.set_filters(self.output_filters()?);
+        combiner.set_bit_depth(self.bit_depth);
+        if let Some(crossfade) = self.crossfade {
+            combiner.set_crossfade(audio::duration_to_frames(crossfade, spec.sample_rate));
+        }
+
+        for &format in &self.formats {
+            let path = self.part_path(document, voice, format, part);
+            match format {
+                Format::Wav if self.streams_output() => combiner.stream_output(io::stdout())?,
+                Format::Wav if self.append && path.is_file() => combiner.append_output(path)?,
+                Format::Wav => combiner.set_output(path)?,
+                _ => combiner.add_encoder(Encoder::spawn(format, path, spec, self.quality)?),
+            }
+        }
+
+        if let Some(length) = self.preview.filter(|_| part == 0) {
+            let path = self.preview_path(document, voice);
+            log::info!("Will write preview clip to '{}'", path.display());
+            combiner.set_preview(Clip::create(path, spec, self.preview_offset, length)?);
+        }
+
+        if self.qa_report.is_some() {
+            combiner.set_qa(QaAnalyzer::new(spec, self.qa_max_silence));
+        }
+
+        Ok(combiner)
+    }
+
+    /// Returns stereo positions of the voices of the chunks, spread evenly
+    /// in the order they first speak, if the output is stereo and there's
+    /// more than one voice to tell apart
+    fn voice_pans(&self, chunks: &[Chunk]) -> Vec<(Option<Voice>, f32)> {
+        if self.layout != Some(Layout::Stereo) {
+            return Vec::new();
+        }
+        let mut voices = Vec::new();
+        for chunk in chunks {
+            if !voices.contains(&chunk.voice) {
+                voices.push(chunk.voice);
+            }
+        }
+        if voices.len() < 2 {
+            return Vec::new();
+        }
+
+        let last = (voices.len() - 1) as f32;
+        voices
+            .into_iter()
+            .enumerate()
+            .map(|(i, voice)| (voice, PAN_WIDTH * (2.0 * i as f32 / last - 1.0)))
+            .collect()
+    }
+
+    fn combine_output(
+        &self,
+        subtasks: Vec<ComputedSubtask>,
+        chunks: &[Chunk],
+        document: &Document,
+        voice: Option<Voice>,
+        toc: Option<ComputedSubtask>,
+        titles: &[String],
+    ) -> Result<RunOutput> {
+        let mut outputs: Vec<_> = self
+            .formats
+            .iter()
+            .map(|&format| self.part_path(document, voice, format, 0))
+            .collect();
+        status!(
+            self,
+            "{} {}Combining output into {}...",
+            style("[4/4]").bold().dim(),
+            CLIP,
+            outputs
+                .iter()
+                .map(|output| format!("'{}'", output.display()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        let mut combiner: Option<Combiner> = None;
+
+        match (self.toc, toc) {
+            (Some(TocMode::Prepend), Some(toc)) => {
+                for (path, reader) in toc.data.into_iter() {
+                    let samples = self.read_output(
+                        &path,
+                        reader,
+                        &mut combiner,
+                        document,
+                        voice,
+                        "table of contents",
+                    )?;
+                    combiner.as_mut().unwrap().write_samples(&samples)?;
+                }
+            }
+            (Some(TocMode::Track), Some(toc)) => {
+                outputs.extend(self.write_toc_track(toc, document, voice)?);
+            }
+            _ => {}
+        }
+
+        let mut chapter_start = combiner.as_ref().map_or(0, Combiner::position);
+        let pans = self.voice_pans(chunks);
+        // timelines of the parts written before the current one
+        let mut parts = Vec::new();
+
+        for (i, subtask) in subtasks.into_iter().enumerate() {
+            for (path, reader) in subtask.data.into_iter() {
+                let name = format!("subtask '{}'", i);
+                let mut samples =
+                    self.read_output(&path, reader, &mut combiner, document, voice, &name)?;
+                let spec = combiner.as_ref().unwrap().spec();
+
+                if let Some(chunk) = chunks.get(i) {
+                    if let Some(&(_, position)) =
+                        pans.iter().find(|(voice, _)| *voice == chunk.voice)
+                    {
+                        audio::pan(&mut samples, position);
+                    }
+
+                    let mut pauses = preprocess::pause_positions(&chunk.text);
+                    if let Some(pause) = self.dash_pause {
+                        let text = preprocess::strip_pause_markers(&chunk.text);
+                        pauses.extend(
+                            preprocess::dash_positions(&text)
+                                .into_iter()
+                                .map(|position| (position, pause)),
+                        );
+                    }
+                    if !pauses.is_empty() {
+                        log::info!("Inserting {} pauses into subtask '{}'", pauses.len(), i);
+                        samples = audio::insert_pauses(&samples, spec, &pauses);
+                    }
+                }
+
+                let mut frames = samples.len() as u64 / u64::from(spec.channels);
+                if let Some((times, gap)) = self.repeat {
+                    frames = (frames + audio::duration_to_frames(gap, spec.sample_rate))
+                        * u64::from(times);
+                }
+                if let Some(pause) = chunks.get(i).and_then(|chunk| chunk.pause) {
+                    frames += audio::duration_to_frames(pause, spec.sample_rate);
+                }
+                if self.roll_over(&mut combiner, &mut parts, frames, document, voice)? {
+                    chapter_start = 0;
+                }
+                let combiner = combiner.as_mut().unwrap();
+
+                match self.repeat {
+                    Some((times, gap)) => {
+                        let gap = audio::duration_to_frames(gap, spec.sample_rate);
+                        for _ in 0..times {
+                            combiner.write_chunk(i, &samples)?;
+                            combiner.write_silence(gap)?;
+                        }
+                    }
+                    None => combiner.write_chunk(i, &samples)?,
+                }
+            }
+
+            if let (Some(pause), Some(combiner)) = (
+                chunks.get(i).and_then(|chunk| chunk.pause),
+                combiner.as_mut(),
+            ) {
+                log::info!("Inserting {:?} break after subtask '{}'", pause, i);
+                combiner.write_silence(audio::duration_to_frames(
+                    pause,
+                    combiner.spec().sample_rate,
+                ))?;
+            }
+
+            if let (Some(pad), Some(combiner)) = (self.chapter_pad, combiner.as_mut()) {
+                let chapter = chunks.get(i).map(|chunk| chunk.chapter);
+                let next_chapter = chunks.get(i + 1).map(|chunk| chunk.chapter);
+                if chapter != next_chapter {
+                    let boundary = audio::duration_to_frames(pad, combiner.spec().sample_rate);
+                    let remainder = (combiner.position() - chapter_start) % boundary.max(1);
+                    if remainder > 0 {
+                        log::info!(
+                            "Padding chapter {:?} with {} frames of silence",
+                            chapter,
+                            boundary - remainder
+                        );
+                        combiner.write_silence(boundary - remainder)?;
+                    }
+                    chapter_start = combiner.position();
+                }
+            }
+        }
+
+        let combined = combiner
+            .ok_or_else(|| anyhow!("combining output: no subtask results to combine"))?
+            .finish()?;
+
+        let part_paths = |part| -> Vec<PathBuf> {
+            self.formats
+                .iter()
+                .map(|&format| self.part_path(document, voice, format, part))
+                .collect()
+        };
+        if !parts.is_empty() {
+            status!(
+                self,
+                "Output split into {} parts of at most {}",
+                parts.len() + 1,
+                humantime::format_duration(self.max_output_duration.unwrap_or_default())
+            );
+            for part in 1..=parts.len() {
+                outputs.extend(part_paths(part));
+            }
+        }
+
+        let timelines: Vec<_> = parts.iter().chain(iter::once(&combined.timeline)).collect();
+        let marks = chapter_marks(&timelines, chunks);
+        if !titles.is_empty() {
+            for (part, timeline) in timelines.iter().enumerate() {
+                let chapters = part_chapters(&marks, part, timeline, titles);
+                for &format in self.formats.iter().filter(|format| format.has_chapters()) {
+                    let path = self.part_path(document, voice, format, part);
+                    log::info!(
+                        "Embedding {} chapters into '{}'",
+                        chapters.len(),
+                        path.display()
+                    );
+                    encode::embed_chapters(&path, &chapters)?;
+                }
+            }
+        }
+
+        // books get their chapters listed even when combined
+        let listed = self.input_format(document) == InputFormat::Epub;
+        if listed && !self.streams_output() {
+            let chapters = marks
+                .iter()
+                .map(|&(part, chapter, start)| toc::ManifestEntry {
+                    index: chapter + 1,
+                    title: titles.get(chapter).cloned().unwrap_or_default(),
+                    start_secs: Some(start.as_secs_f64()),
+                    files: if self.max_output_duration.is_some() {
+                        part_paths(part)
+                    } else {
+                        Vec::new()
+                    },
+                })
+                .collect();
+
+            let path = self.chapters_path(document, voice);
+            log::info!("Writing chapter manifest to '{}'", path.display());
+            toc::ChapterManifest {
+                input: document.input.clone(),
+                chapters,
+            }
+            .save(&path)?;
+            outputs.push(path);
+        }
+
+        if let Some(lrc) = &self.lrc {
+            log::info!("Writing LRC file to '{}'", lrc.display());
+            timing::write_lrc(lrc, &combined.timeline, chunks, self.lrc_words)?;
+        }
+        if let Some(timings) = &self.timings {
+            log::info!("Writing timing map to '{}'", timings.display());
+            timing::write_timings(timings, &combined.timeline, chunks)?;
+        }
+        if let Some(srt) = &self.srt {
+            log::info!("Writing subtitles to '{}'", srt.display());
+            timing::write_srt(srt, &combined.timeline, chunks)?;
+        }
+
+        if let (Some(path), Some(qa)) = (&self.qa_report, combined.qa) {
+            let report = qa.finish(&combined.timeline);
+            if report.issues.is_empty() {
+                log::info!("QA found no suspicious sections in the output");
+            } else {
+                status!(
+                    self,
+                    "QA found {} suspicious section(s) in the output; see '{}' for details",
+                    report.issues.len(),
+                    path.display()
+                );
+            }
+            report.save(path)?;
+        }
+
+        RunOutput::new(voice, outputs)
+    }
+
+    pub fn run(&self) -> Result<()> {
+        if self.dry_run {
+            return self.prepare();
+        }
+
+        let run = Run::create()?;
+        log::info!("Run '{}' stored in '{}'", run.id(), run.dir().display());
+
+        let started = Instant::now();
+        let result = self.synthesize(&run);
+        if let Err(e) = &result {
+            if let Err(e) = run.save_error(e) {
+                log::warn!("Couldn't record the error in the run history: {:#}", e);
+            }
+        }
+
+        match cache::prune(self.cache_max_size, Some(run.dir()), false) {
+            Ok(evicted) if !evicted.is_empty() => log::info!(
+                "Evicted {} entries from the state dir to keep it under {}",
+                evicted.len(),
+                self.cache_max_size
+            ),
+            Ok(_) => {}
+            Err(e) => log::warn!("Couldn't prune the state dir: {:#}", e),
+        }
+
+        if !self.notifiers.is_empty() {
+            let mut summary = Summary {
+                duration_secs: started.elapsed().as_secs(),
+                bid: self.bid,
+                budget: self.budget,
+                subtasks: 0,
+                estimated_fee_eth: None,
+                rate: None,
+                output_dir: self.output_dir.clone(),
+                outputs: Vec::new(),
+            };
+            let notification = match &result {
+                Ok(manifest) => {
+                    summary.subtasks = manifest.subtasks * manifest.outputs.len() as u64;
+                    summary.estimated_fee_eth = manifest.estimated_fee_eth;
+                    summary.rate = manifest.rate.clone();
+                    summary.outputs = manifest
+                        .outputs
+                        .iter()
+                        .flat_map(|output| output.files.iter().cloned())
+                        .collect();
+                    Notification {
+                        outcome: Outcome::Success,
+                        run_id: run.id().to_owned(),
+                        message: format!("Synthesized '{}'", self.input_name()),
+                        summary,
+                    }
+                }
+                Err(e) => Notification {
+                    outcome: Outcome::Failure,
+                    run_id: run.id().to_owned(),
+                    message: format!("Synthesizing '{}' failed: {:#}", self.input_name(), e),
+                    summary,
+                },
+            };
+            notify::notify_all(&self.notifiers, &notification);
+        }
+
+        let subtasks = result
+            .as_ref()
+            .map(|manifest| manifest.subtasks * manifest.outputs.len() as u64);
+        usage::send(
+            &self.usage_metrics,
+            &usage::Report::new(
+                subtasks,
+                self.documents.len(),
+                self.output_voices().len(),
+                started.elapsed(),
+            ),
+        );
+
+        result.map(|_| ())
+    }
+
+    /// Returns format of the document, unless set detected from its name
+    fn input_format(&self, document: &Document) -> InputFormat {
+        self.input_format
+            .unwrap_or_else(|| InputFormat::detect(&document.input))
+    }
+
+    /// Reads, normalizes and splits all the documents, returning their
+    /// chunks along with titles of their chapters, if they have any
+    fn split_documents(&self) -> Result<Vec<SplitDocument>> {
+        let mut documents = Vec::with_capacity(self.documents.len());
+        for document in &self.documents {
+            let (mut chunks, titles) = match self.input_format(document) {
+                InputFormat::Text => {
+                    self.split_plain(document.read(self.encoding, InputFormat::Text)?, document)?
+                }
+                InputFormat::Html => {
+                    log::info!("Extracting text from HTML in '{}'", document.name());
+                    let contents = document.read(self.encoding, InputFormat::Html)?;
+                    self.split_plain(html::extract(&contents), document)?
+                }
+                InputFormat::Ssml => self.split_ssml(document)?,
+                InputFormat::Markdown => self.split_markdown(document)?,
+                InputFormat::Epub => self.split_epub(document)?,
+            };
+            if let Some(gap) = self.gap {
+                split::separate(&mut chunks, gap);
+            }
+            documents.push((chunks, titles));
+        }
+        Ok(documents)
+    }
+
+    /// Prepares the task in the workspace without submitting it, so that it
+    /// can be reviewed and submitted later on with `g_flite submit`
+    fn prepare(&self) -> Result<()> {
+        let (chunks, _): (Vec<_>, Vec<_>) = self
+            .split_documents()
+            .context(Stage::Split)?
+            .into_iter()
+            .unzip();
+        let task_name = format!("g_flite_{}", Local::now().format("%Y%m%d-%H%M%S"));
+        let attempt_dir = task::create_attempt_dir(&self.workspace).context(Stage::Prepare)?;
+        let slots = slots(&chunks, &self.output_voices());
+        self.check_subtask_timeout(&slots);
+        let (_, descriptors) = self
+            .prepare_task(&task_name, &attempt_dir, &slots)
+            .context(Stage::Prepare)?;
+
+        status!(
+            self,
+            "Task of {} subtasks prepared in '{}'; submit it with `g_flite submit {} <output>`",
+            descriptors.len(),
+            attempt_dir.display(),
+            attempt_dir.display()
+        );
+        Ok(())
+    }
+
+    fn synthesize(&self, run: &Run) -> Result<RunManifest> {
+        let mut telemetry = Telemetry::default();
+
+        let stage = Instant::now();
+        let (chunks, titles): (Vec<_>, Vec<_>) = self
+            .split_documents()
+            .context(Stage::Split)?
+            .into_iter()
+            .unzip();
+        telemetry.record_stage("split", stage);
+
+        let estimate: Duration = chunks
+            .iter()
+            .map(|chunks| timing::estimate_duration(chunks))
+            .sum();
+        log::info!(
+            "Estimated spoken duration is {}",
+            humantime::format_duration(estimate)
+        );
+        if let Some(max_duration) = self.max_duration {
+            if estimate > max_duration {
+                bail!(
+                    "estimated spoken duration of {} exceeds the maximum of {}; make sure '{}' is the text you meant to synthesize, or raise --max-duration",
+                    humantime::format_duration(estimate),
+                    humantime::format_duration(max_duration),
+                    self.input_name()
+                );
+            }
+        }
+
+        let voices = self.output_voices();
+        let num_chunks: usize = chunks.iter().map(Vec::len).sum();
+        let slots = slots(&chunks, &voices);
+        let cached: Vec<_> = slots
+            .iter()
+            .map(|&(voice, _, _, chunk)| {
+                if self.use_cache {
+                    cache::cached_chunk(&chunk_key(voice, chunk))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let submitted: Vec<_> = slots
+            .iter()
+            .zip(&cached)
+            .filter(|(_, cached)| cached.is_none())
+            .map(|(slot, _)| *slot)
+            .collect();
+        if submitted.len() < slots.len() {
+            status!(
+                self,
+                "Reusing {} of {} chunks synthesized before",
+                slots.len() - submitted.len(),
+                slots.len()
+            );
+        }
+
+        let subtasks = submitted.len() as u64;
+        let (rate, estimated_fee_eth) = if submitted.is_empty() {
+            (None, None)
+        } else {
+            self.check_subtask_timeout(&submitted);
+            self.check_job_budgets(&submitted)?;
+            self.confirm_cost(subtasks)?
+        };
+
+        // unique name lets the task be looked up on the node
+        let task_name = format!("g_flite_{}", run.id());
+        let (task_dir, computed) = if submitted.is_empty() {
+            (self.workspace.as_ref().to_path_buf(), Vec::new())
+        } else {
+            let attempt_dir = task::create_attempt_dir(&self.workspace).context(Stage::Prepare)?;
+            let (computed, salvaged) =
+                self.compute_subtasks(&task_name, &attempt_dir, &submitted, &mut telemetry)?;
+
+            // chunks replaced with placeholders would stand in for the real
+            // ones in later runs
+            if self.use_cache && !salvaged {
+                for (&(voice, _, _, chunk), subtask) in submitted.iter().zip(&computed) {
+                    let key = chunk_key(voice, chunk);
+                    let meta = cache::ChunkMeta {
+                        voice: voice.or(chunk.voice),
+                        args: chunk.args.clone(),
+                        text_hash: cache::text_hash(&chunk.text),
+                        source: format!("run {}", run.id()),
+                    };
+                    for path in subtask.data.keys() {
+                        // a corrupted result would be reused by later runs
+                        if let Some(checksum) = self.checksums.borrow().get(path) {
+                            if let Err(e) = audio::verify_file_checksum(path, checksum) {
+                                log::warn!("Not caching chunk: {:#}", e);
+                                continue;
+                            }
+                        }
+                        if let Err(e) = cache::store_chunk(&key, path, &meta) {
+                            log::warn!("Couldn't cache chunk '{}': {:#}", path.display(), e);
+                        }
+                    }
+                }
+            }
+            (attempt_dir, computed)
+        };
+
+        // cached chunks are put back in place of the ones not submitted
+        let mut computed = computed.into_iter();
+        let mut ordered = Vec::with_capacity(slots.len());
+        for path in &cached {
+            ordered.push(match path {
+                Some(path) => cache::open_chunk(path)?,
+                None => computed
+                    .next()
+                    .ok_or_else(|| anyhow!("results of some subtasks are missing"))?,
+            });
+        }
+        let computed = ordered;
+
+        let mut subtasks = computed.into_iter();
+        let mut groups: Vec<(Option<Voice>, usize, Vec<ComputedSubtask>)> = Vec::new();
+        for voice in &voices {
+            for (document, chunks) in chunks.iter().enumerate() {
+                let subtasks = subtasks.by_ref().take(chunks.len()).collect();
+                groups.push((*voice, document, subtasks));
+            }
+        }
+
+        let mut tocs: Vec<Option<ComputedSubtask>> = groups.iter().map(|_| None).collect();
+        if let Some(mode) = self.toc {
+            let stage = Instant::now();
+            let mut scripts = Vec::with_capacity(groups.len());
+            for (voice, document, subtasks) in groups.iter_mut() {
+                let entries: Vec<_> = self
+                    .chapter_starts(subtasks, &chunks[*document])?
+                    .into_iter()
+                    .zip(titles[*document].iter())
+                    .map(|(start, title)| toc::Entry {
+                        title: title.clone(),
+                        start,
+                    })
+                    .collect();
+                scripts.push((voice.or(self.default_voice), toc::script(&entries, mode)));
+            }
+            tocs = self
+                .synthesize_toc(scripts)
+                .context(Stage::Toc)?
+                .into_iter()
+                .map(Some)
+                .collect();
+            telemetry.record_stage("toc", stage);
+        }
+
+        let stage = Instant::now();
+        telemetry::reset_peak_memory();
+
+        let mut outputs = Vec::with_capacity(groups.len());
+        for ((voice, document, subtasks), toc) in groups.into_iter().zip(tocs) {
+            let chunks = &chunks[document];
+            let titles = &titles[document];
+            let document = &self.documents[document];
+            outputs.push(match self.per_line {
+                Some(names) => self
+                    .write_lines(subtasks, chunks, voice, names)
+                    .context(Stage::Combine)?,
+                None if self.per_chapter => self
+                    .write_chapters(subtasks, chunks, document, voice, titles)
+                    .context(Stage::Combine)?,
+                None => self
+                    .combine_output(subtasks, chunks, document, voice, toc, titles)
+                    .context(Stage::Combine)?,
+            });
+        }
+        telemetry.combine_peak_memory_bytes = telemetry::peak_memory();
+        telemetry.record_stage("combine", stage);
+
+        if self.compress_workspace {
+            match compress::compress_workspace(self.workspace.as_ref()) {
+                Ok((before, after)) => log::info!(
+                    "Compressed chunks in workspace '{}' from {} to {}",
+                    self.workspace,
+                    Size(before),
+                    Size(after)
+                ),
+                Err(e) => log::warn!("Couldn't compress workspace: {:#}", e),
+            }
+        }
+        telemetry.workspace_bytes = telemetry::dir_size(&self.workspace);
+
+        let manifest = RunManifest {
+            id: run.id().to_owned(),
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            started: run.started().to_owned(),
+            finished: Local::now().to_rfc3339(),
+            inputs: self
+                .documents
+                .iter()
+                .map(|document| document.input.clone())
+                .collect(),
+            task_name,
+            task_dir,
+            outputs,
+            subtasks: num_chunks as u64,
+            estimated_fee_eth,
+            rate,
+            telemetry,
+        };
+        let path = manifest.save(run)?;
+        log::info!("Run manifest saved to '{}'", path.display());
+        status!(self, "Time spent: {}", manifest.telemetry.breakdown());
+
+        Ok(manifest)
+    }
+}
+
+impl App {
+    /// Creates app synthesizing `input` into `output` with all the remaining
+    /// settings left at their defaults
+    fn new(input: PathBuf, output: PathBuf, task: TaskOpt, node: NodeOpt) -> Result<Self> {
+        verify_input(&input)?;
+
+        // verify output path excluding topmost file exists
+        let output = if output.is_relative() {
+            Path::new(".").join(output)
+        } else {
+            output
+        };
+        let (output_dir, output_filename) = {
+            let parent = output.parent().unwrap(); // guaranteed not to fail
+            let filename = output.file_name().ok_or(anyhow!(
+                "working out the expected output filename from '{}'",
+                output.display()
+            ))?;
+            (parent.to_path_buf(), PathBuf::from(filename))
+        };
+        let output_dir = output_dir.canonicalize().with_context(|| {
+            format!(
+                "working out absolute path for the expected output path '{}'",
+                output.display(),
+            )
+        })?;
+
+        let datadir = golem_datadir(node.datadir)?;
+
+        let address = node.address;
+        let port = node.port;
+        let wait_for_funds = node.wait_for_funds;
+        let auto_words = timing::auto_words_per_subtask(timeout_duration(&task.subtask_timeout));
+        let subtasks = Subtasks::new(task.subtasks, task.words_per_subtask, auto_words)?;
+        let bid = task.bid;
+        let budget = task.budget;
+        let currency = task.currency;
+        let timeouts = Timeouts {
+            assignment: task.assignment_timeout,
+            heartbeat: task.heartbeat,
+            max_failures: task.max_failures,
+            min_providers: task.min_providers,
+            providers: task.providers_timeout,
+        };
+        let placeholder = task.placeholder;
+        let shuffle = task.shuffle;
+        let scatter = task.scatter;
+        let task_timeout = task.task_timeout;
+        let subtask_timeout = task.subtask_timeout;
+        let net = if node.mainnet {
+            Net::MainNet
+        } else {
+            Net::TestNet
+        };
+
+        let workspace = match task.workspace {
+            Some(workspace) => {
+                Workspace::UserSpecified(workspace.canonicalize().with_context(|| {
+                    format!(
+                        "working out absolute path for provided workspace dir '{}'",
+                        workspace.display(),
+                    )
+                })?)
+            }
+            None => Workspace::Temp(
+                Builder::new()
+                    .prefix("g_flite")
+                    .tempdir()
+                    .context("creating workspace dir in your tmp files")?,
+            ),
+        };
+
+        let config = Config::load()?;
+        if let Some(max_bid) = config.limits.max_bid {
+            if bid > max_bid {
+                bail!(
+                    "bid of {} GNT exceeds the maximum of {} GNT set in the config file",
+                    bid,
+                    max_bid
+                );
+            }
+        }
+        let golemcli = Golemcli::new(
+            config.golemcli,
+            &datadir,
+            address.clone(),
+            port,
+            node.mainnet,
+        )
+        .call_timeout(config.session.call_timeout);
+        let filters = filter::chain(&TextOptions::default(), &config.filters)?;
+        let audio_filters = postprocess::chain(&config.audio_filters, postprocess::Stage::Chunk)?;
+        let notifiers = config
+            .notifiers
+            .into_iter()
+            .map(notify::from_config)
+            .collect();
+
+        Ok(Self {
+            documents: vec![Document {
+                input,
+                output_filename,
+                budget: None,
+            }],
+            output_dir,
+            datadir,
+            address,
+            port,
+            subtasks,
+            bid,
+            budget,
+            shuffle,
+            scatter,
+            task_timeout,
+            subtask_timeout,
+            workspace,
+            net,
+            voices: Vec::new(),
+            default_voice: task.voice,
+            formats: vec![Format::Wav],
+            quality: None,
+            bit_depth: BitDepth::default(),
+            crossfade: None,
+            max_output_duration: None,
+            layout: None,
+            preview: None,
+            preview_offset: Duration::from_secs(0),
+            lrc: None,
+            lrc_words: false,
+            timings: None,
+            srt: None,
+            qa_report: None,
+            qa_max_silence: Duration::from_secs(2),
+            split_by: SplitBy::Words,
+            chapter_pattern: None,
+            chapter_pad: None,
+            detect_language: false,
+            voice_routes: Vec::new(),
+            toc: None,
+            filters,
+            filter_config: config.filters,
+            audio_filters,
+            audio_filter_config: config.audio_filters,
+            dash_pause: None,
+            gap: None,
+            per_line: None,
+            per_chapter: false,
+            repeat: None,
+            interleave: None,
+            input_format: None,
+            use_cache: !task.no_cache,
+            compress_workspace: task.compress_workspace,
+            encoding: None,
+            max_duration: None,
+            append: false,
+            dry_run: false,
+            event_log: false,
+            wait_for_funds,
+            currency,
+            limits: config.limits,
+            cache_max_size: config.cache.max_size(),
+            timeouts,
+            placeholder,
+            session: config.session,
+            usage_metrics: config.telemetry,
+            golemcli,
+            notifiers,
+            checksums: RefCell::new(HashMap::new()),
+        })
+    }
+}
+
+/// Converts the `HH:MM:SS` timeout into duration, unless it's malformed
+fn timeout_duration(timeout: &Timeout) -> Option<Duration> {
+    let parts = timeout
+        .to_string()
+        .split(':')
+        .map(|part| part.trim().parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    match parts.as_slice() {
+        [hours, minutes, seconds] => {
+            Some(Duration::from_secs(hours * 3600 + minutes * 60 + seconds))
+        }
+        _ => None,
+    }
+}
+
+/// Returns absolute path to Golem datadir, the default one unless provided
+pub fn golem_datadir(datadir: Option<PathBuf>) -> Result<PathBuf> {
+    match datadir {
+        Some(datadir) => datadir.canonicalize().with_context(|| {
+            format!(
+                "working out absolute path for the provided datadir '{}'",
+                datadir.display(),
+            )
+        }),
+        None => match appdirs::user_data_dir(Some("golem"), Some("golem"), false) {
+            Ok(datadir) => Ok(datadir.join("default")),
+            Err(_) => bail!(
+                "
+                No standard project app datadirs available.
+                You'll need to specify path to your Golem datadir manually.
+                "
+            ),
+        },
+    }
+}
+
+/// Formats GNT amount, followed by its value in fiat if the rate is known
+fn format_gnt(amount: f64, rate: Option<&Rate>) -> String {
+    match rate {
+        Some(rate) => format!("{} GNT ({})", amount, rate.gnt(amount)),
+        None => format!("{} GNT", amount),
+    }
+}
+
+/// Formats ETH amount, followed by its value in fiat if the rate is known
+fn format_eth(amount: f64, rate: Option<&Rate>) -> String {
+    match rate {
+        Some(rate) => format!("{:.6} ETH ({})", amount, rate.eth(amount)),
+        None => format!("{:.6} ETH", amount),
+    }
+}
+
+fn dedup<T: PartialEq>(items: Vec<T>) -> Vec<T> {
+    let mut deduped = Vec::with_capacity(items.len());
+    for item in items {
+        if !deduped.contains(&item) {
+            deduped.push(item);
+        }
+    }
+    deduped
+}
+
+/// Checks whether the input path is `-`, standing for stdin
+fn is_stdin(input: &Path) -> bool {
+    input == Path::new("-")
+}
+
+/// Reads the input, decoding it as UTF-8 unless given another encoding or,
+/// when downloaded, served with another charset
+///
+/// Downloads have to be served as input of the given format.
+pub fn read_input(input: &Path, encoding: Option<Encoding>, format: InputFormat) -> Result<String> {
+    let mut charset = None;
+    let contents = if let Some(url) = as_url(input) {
+        let (contents, served) = download_input(url, encoding, format)?;
+        charset = served;
+        contents
+    } else if is_stdin(input) {
+        let mut contents = Vec::new();
+        io::stdin()
+            .lock()
+            .read_to_end(&mut contents)
+            .context("reading from stdin")?;
+        contents
+    } else {
+        fs::read(input).with_context(|| format!("reading from '{}'", input.display()))?
+    };
+    encoding
+        .or(charset)
+        .unwrap_or(Encoding::Utf8)
+        .decode(contents)
+}
+
+/// Returns the input path as URL if it's one
+fn as_url(input: &Path) -> Option<&str> {
+    input
+        .to_str()
+        .filter(|input| input.starts_with("http://") || input.starts_with("https://"))
+}
+
+/// Downloads the input, returning it along with the charset it's served with
+///
+/// The charset is only looked at unless the encoding is given, so that the
+/// encoding can stand in for a charset which isn't supported.
+fn download_input(
+    url: &str,
+    encoding: Option<Encoding>,
+    format: InputFormat,
+) -> Result<(Vec<u8>, Option<Encoding>)> {
+    log::info!("Downloading input from '{}'", url);
+    let response = ureq::get(url)
+        .timeout_connect(10_000)
+        .redirects(INPUT_REDIRECTS)
+        .call();
+    if let Some(e) = response.synthetic_error() {
+        bail!("downloading '{}': {}", url, e);
+    }
+    if !response.ok() {
+        bail!(
+            "downloading '{}': {} {}",
+            url,
+            response.status(),
+            response.status_text()
+        );
+    }
+
+    let content_type = response.content_type().to_lowercase();
+    if !format.content_types().contains(&content_type.as_str()) {
+        match format {
+            InputFormat::Text => bail!(
+                "'{}' is {} rather than plain text; make sure the URL points to the text itself rather than a page about it, or pass --input-format if it's in another format",
+                response.get_url(),
+                content_type
+            ),
+            _ => bail!(
+                "'{}' is {} rather than {} input, which is served as {}; pass --input-format if it's in another format",
+                response.get_url(),
+                content_type,
+                format,
+                format.content_types().join(", ")
+            ),
+        }
+    }
+    let charset = match encoding {
+        Some(_) => None,
+        None => match response.charset().parse::<Encoding>() {
+            Ok(Encoding::Auto) | Err(_) => bail!(
+                "'{}' is encoded as {}, while only UTF-8, Latin-1, Windows-1252 and UTF-16 are supported; pass --encoding to decode it as one of them anyway",
+                response.get_url(),
+                response.charset()
+            ),
+            Ok(charset) => Some(charset),
+        },
+    };
+
+    let mut contents = Vec::new();
+    response
+        .into_reader()
+        .read_to_end(&mut contents)
+        .with_context(|| format!("downloading '{}'", url))?;
+    Ok((contents, charset))
+}
+
+fn verify_input(input: &Path) -> Result<()> {
+    if as_url(input).is_none() && !is_stdin(input) && !input.is_file() {
+        bail!(
+            "Input file '{}' doesn't exist. Did you make a typo anywhere?",
+            input.display()
+        );
+    }
+    Ok(())
+}
+
+/// Works out name of the WAVE file the input is synthesized into when only
+/// the output dir is given
+fn wav_filename(input: &Path) -> Result<OsString> {
+    let mut filename: OsString = if is_stdin(input) {
+        "stdin".into()
+    } else {
+        input
+            .file_stem()
+            .ok_or_else(|| anyhow!("working out the output filename from '{}'", input.display()))?
+            .into()
+    };
+    filename.push(".wav");
+    Ok(filename)
+}
+
+impl TryFrom<Opt> for App {
+    type Error = anyhow::Error;
+
+    fn try_from(opt: Opt) -> std::result::Result<Self, Self::Error> {
+        // both are required unless running a subcommand
+        let input = opt
+            .input
+            .ok_or_else(|| anyhow!("no input file specified; see --help for usage"))?;
+        let output = opt
+            .output
+            .ok_or_else(|| anyhow!("no output file specified; see --help for usage"))?;
+
+        if output == Path::new("-") {
+            if opt.per_line {
+                bail!("--per-line writes a file per line, so its output can't be stdout");
+            }
+            if opt.formats.iter().any(|&format| format != Format::Wav) {
+                bail!("only WAV output can be streamed to stdout");
+            }
+            if opt.append
+                || opt.preview.is_some()
+                || opt.toc == Some(TocMode::Track)
+                || opt.max_output_duration.is_some()
+            {
+                bail!(
+                    "--append, --preview, --toc track and --max-output-duration need the output to be a file, not stdout"
+                );
+            }
+        }
+
+        encode::check_formats(&output, &opt.formats)?;
+
+        if output == Path::new("-") && opt.per_chapter {
+            bail!("--per-chapter writes a file per chapter, so its output can't be stdout");
+        }
+
+        match opt.input_format {
+            Some(format @ InputFormat::Ssml)
+            | Some(format @ InputFormat::Markdown)
+            | Some(format @ InputFormat::Epub)
+                if opt.per_line || opt.chapter_pattern.is_some() =>
+            {
+                bail!(
+                    "--per-line and --chapter-pattern can't be used with {} input",
+                    format
+                );
+            }
+            _ => {}
+        }
+
+        if opt.per_chapter && opt.chapter_pattern.is_none() {
+            let format = opt
+                .input_format
+                .unwrap_or_else(|| InputFormat::detect(&input));
+            if format != InputFormat::Epub && format != InputFormat::Markdown {
+                bail!(
+                    "--per-chapter needs {} input to be split into chapters with --chapter-pattern",
+                    format
+                );
+            }
+        }
+
+        // in per-line mode the output is a dir, the file name being a mere placeholder
+        let output = if opt.per_line {
+            fs::create_dir_all(&output)
+                .with_context(|| format!("creating output dir '{}'", output.display()))?;
+            output.join("lines.wav")
+        } else {
+            output
+        };
+
+        let mut app = Self::new(input, output, opt.task, opt.node)?;
+
+        app.formats = dedup(opt.formats);
+        if opt.append && app.formats != [Format::Wav] {
+            bail!("--append only supports WAV output");
+        }
+        app.quality = opt.quality;
+        if opt.trim {
+            app.audio_filters.push(Trim::default());
+        }
+        app.crossfade = opt.crossfade;
+        app.max_output_duration = opt.max_output_duration;
+        if let Some(sample_rate) = opt.sample_rate {
+            app.audio_filters.push(Resample::new(sample_rate)?);
+        }
+        app.bit_depth = opt.bit_depth.unwrap_or_default();
+        match opt.loudness {
+            Some(target) if target >= 0.0 => {
+                bail!("--loudness needs a target below 0 dBFS, e.g. -20")
+            }
+            Some(target) => app.audio_filters.push(Loudness(target)),
+            None => {}
+        }
+        if let Some(layout) = opt.channels {
+            app.audio_filters.push(Remix(layout.channels()));
+        }
+        app.layout = opt.channels;
+        app.append = opt.append;
+        app.preview = opt.preview;
+        app.preview_offset = opt.preview_offset;
+        app.lrc = opt.lrc;
+        app.lrc_words = opt.lrc_words;
+        app.timings = opt.timings;
+        app.srt = opt.srt;
+        app.qa_report = opt.qa_report;
+        app.qa_max_silence = opt.qa_max_silence;
+        app.split_by = opt.split_by;
+        app.chapter_pattern = match opt.chapter_pattern {
+            Some(pattern) => Some(split::chapter_pattern(&pattern)?),
+            None => None,
+        };
+        app.chapter_pad = opt.chapter_pad;
+        app.detect_language = opt.detect_language;
+        app.voice_routes = opt.voice_routes;
+        app.toc = opt.toc;
+        app.filters = filter::chain(
+            &TextOptions {
+                normalize: opt.normalize,
+                dehyphenate: !opt.keep_hyphenation,
+                strip_citations: !opt.keep_citations,
+                rules: opt.rules,
+                expand_numerals: opt.expand_numerals,
+                locale: opt.locale,
+                speak_locale: opt.speak_locale,
+                acronyms: opt.acronyms,
+                spell_acronyms: opt.spell_acronyms,
+                spell_patterns: opt.spell,
+            },
+            &app.filter_config,
+        )?;
+        app.dash_pause = opt.dash_pause;
+        app.gap = opt.gap;
+        app.max_duration = opt.max_duration;
+        app.event_log = opt.event_log;
+        app.dry_run = opt.dry_run;
+        app.input_format = opt.input_format;
+        app.encoding = opt.encoding;
+        app.per_line = if opt.per_line {
+            Some(opt.line_names)
+        } else {
+            None
+        };
+        app.per_chapter = opt.per_chapter;
+        app.repeat = match opt.repeat {
+            Some(0) => bail!("--repeat needs each sentence to be read at least once"),
+            Some(times) => Some((times, opt.repeat_gap)),
+            None => None,
+        };
+        if opt.interleave.is_some() || opt.pairs {
+            app.interleave = Some(Interleave {
+                translation: opt.interleave,
+                voice: opt.translation_voice,
+                translation_gap: opt.translation_gap,
+                pair_gap: opt.pair_gap,
+            });
+        }
+
+        Ok(app)
+    }
+}
+
+impl TryFrom<CompareOpt> for App {
+    type Error = anyhow::Error;
+
+    fn try_from(opt: CompareOpt) -> std::result::Result<Self, Self::Error> {
+        let output = opt.out_dir.join(wav_filename(&opt.input)?);
+
+        let mut app = Self::new(opt.input, output, opt.task, opt.node)?;
+        app.voices = dedup(opt.voices);
+
+        Ok(app)
+    }
+}
+
+impl App {
+    /// Creates the apps synthesizing the batch, one for each Golem task
+    ///
+    /// Inputs listed in a manifest with different bids need tasks of their
+    /// own, otherwise all of them are computed in a single task.
+    pub fn batch(opt: BatchOpt) -> Result<Vec<Self>> {
+        let jobs = match &opt.manifest {
+            Some(manifest) => batch::load(manifest)?,
+            None => batch::from_inputs(opt.inputs),
+        };
+
+        let mut output_filenames: Vec<(PathBuf, PathBuf)> = Vec::with_capacity(jobs.len());
+        for job in &jobs {
+            verify_input(&job.input)?;
+            let output_filename = PathBuf::from(wav_filename(&job.input)?);
+            if let Some((other, _)) = output_filenames
+                .iter()
+                .find(|(_, filename)| *filename == output_filename)
+            {
+                bail!(
+                    "both '{}' and '{}' would be synthesized into '{}'; rename one of them",
+                    other.display(),
+                    job.input.display(),
+                    output_filename.display()
+                );
+            }
+            output_filenames.push((job.input.clone(), output_filename));
+        }
+
+        let out_dir = opt.out_dir;
+        fs::create_dir_all(&out_dir)
+            .with_context(|| format!("creating output dir '{}'", out_dir.display()))?;
+        let (task, node) = (opt.task, opt.node);
+        batch::by_bid(jobs, task.bid)
+            .into_iter()
+            .map(|(bid, jobs)| {
+                let mut task = task.clone();
+                task.bid = bid;
+                Self::batch_task(jobs, &out_dir, task, node.clone())
+            })
+            .collect()
+    }
+
+    /// Creates the app computing the jobs in a single Golem task
+    fn batch_task(jobs: Vec<Job>, out_dir: &Path, task: TaskOpt, node: NodeOpt) -> Result<Self> {
+        let mut documents: Vec<Document> = Vec::with_capacity(jobs.len());
+        for job in jobs {
+            let output_filename = PathBuf::from(wav_filename(&job.input)?);
+            documents.push(Document {
+                input: job.input,
+                output_filename,
+                budget: job.budget,
+            });
+        }
+
+        let first = &documents[0];
+        let mut app = Self::new(
+            first.input.clone(),
+            out_dir.join(&first.output_filename),
+            task,
+            node,
+        )?;
+        for document in &documents {
+            if let (Some(budget), Some(max_budget)) = (document.budget, app.limits.max_budget) {
+                if budget > max_budget {
+                    bail!(
+                        "budget of {} GNT for '{}' exceeds the maximum of {} GNT set in the config file",
+                        budget,
+                        document.name(),
+                        max_budget
+                    );
+                }
+            }
+        }
+        // the task may spend what its jobs may, once each of them is capped
+        let budgets: Option<Vec<f64>> = documents.iter().map(|document| document.budget).collect();
+        if let Some(budgets) = budgets {
+            let total = budgets.iter().sum();
+            app.budget = Some(app.budget.map_or(total, |budget: f64| budget.min(total)));
+        }
+        app.documents = documents;
+
+        Ok(app)
     }
 }
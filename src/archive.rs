@@ -0,0 +1,174 @@
+use super::ArchiveOpt;
+use crate::compress;
+use crate::events::EventLog;
+use crate::quarantine::Quarantine;
+use crate::run::{self, Run};
+use crate::task::{self, TaskManifest};
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// zstd compression level of the archive
+const LEVEL: i32 = 19;
+/// Permissions of all files in the archive
+const MODE: u32 = 0o644;
+
+/// Bundles a finished run into a single zstd-compressed tar archive, e.g.
+/// to attach it to a bug report or hand over the result
+///
+/// The archive holds the run manifest, the task manifest and event log
+/// along with the text of each chunk, optionally its audio as well, and the
+/// run's output files, all under a dir named after the run. Files are added
+/// in a fixed order with their timestamps and owners cleared, so archiving
+/// the same run twice gives the same archive.
+pub fn archive(opt: ArchiveOpt) -> Result<()> {
+    let run_dir = run::runs_dir()?.join(&opt.run_id);
+    let manifest_path = run_dir.join(Run::MANIFEST);
+    let contents = fs::read(&manifest_path).with_context(|| {
+        format!(
+            "reading manifest of run '{}' from '{}'; did the run finish?",
+            opt.run_id,
+            manifest_path.display()
+        )
+    })?;
+    let manifest: serde_json::Value =
+        serde_json::from_slice(&contents).context("parsing run manifest")?;
+
+    let mut archive = Archive::create(&opt.output, PathBuf::from(&opt.run_id))?;
+
+    archive.add_data(Path::new(Run::MANIFEST), &contents)?;
+
+    let task_dir = manifest["task_dir"].as_str().map(Path::new);
+    match task_dir {
+        Some(task_dir) if task_dir.is_dir() => {
+            archive.add_task(task_dir, opt.audio)?;
+        }
+        _ => log::warn!(
+            "Task dir of run '{}' is gone, so its chunks aren't archived; pass --workspace \
+             to keep it",
+            opt.run_id
+        ),
+    }
+
+    let outputs = manifest["outputs"].as_array().into_iter().flatten();
+    for file in outputs.flat_map(|output| output["files"].as_array().into_iter().flatten()) {
+        let file = file.as_str().map(PathBuf::from).unwrap_or_default();
+        match file.file_name() {
+            Some(name) if file.is_file() => {
+                archive.add_file(&Path::new("output").join(name), &file)?
+            }
+            _ => log::warn!("Output '{}' is missing, not archiving it", file.display()),
+        }
+    }
+
+    let files = archive.finish(&opt.output)?;
+
+    println!(
+        "Archived {} files of run '{}' into '{}'",
+        files,
+        opt.run_id,
+        opt.output.display()
+    );
+    Ok(())
+}
+
+/// zstd-compressed tar archive with all files under a single dir
+pub struct Archive<W: io::Write> {
+    builder: tar::Builder<W>,
+    /// Dir all files are archived under
+    root: PathBuf,
+    files: usize,
+}
+
+impl Archive<zstd::Encoder<File>> {
+    pub fn create(path: &Path, root: PathBuf) -> Result<Self> {
+        let file =
+            File::create(path).with_context(|| format!("creating archive '{}'", path.display()))?;
+        let encoder = zstd::Encoder::new(file, LEVEL).context("creating archive encoder")?;
+        Ok(Self {
+            builder: tar::Builder::new(encoder),
+            root,
+            files: 0,
+        })
+    }
+
+    /// Finishes writing the archive, returning the number of files in it
+    pub fn finish(self, path: &Path) -> Result<usize> {
+        self.builder
+            .into_inner()
+            .and_then(|encoder| encoder.finish())
+            .with_context(|| format!("writing archive '{}'", path.display()))?;
+        Ok(self.files)
+    }
+}
+
+impl<W: io::Write> Archive<W> {
+    /// Adds the task manifest, event log and quarantine list, returning
+    /// whether the task was submitted at all
+    pub fn add_task_records(&mut self, task_dir: &Path) -> Result<bool> {
+        let workspace = task::attempt_workspace(task_dir);
+        for name in &[EventLog::FILENAME, Quarantine::FILENAME] {
+            let path = workspace.join(name);
+            if path.is_file() {
+                self.add_file(Path::new(name), &path)?;
+            }
+        }
+
+        // all chunks were reused from the cache, so no task was submitted
+        if !task_dir.join(TaskManifest::FILENAME).is_file() {
+            return Ok(false);
+        }
+        self.add_file(
+            Path::new(TaskManifest::FILENAME),
+            &task_dir.join(TaskManifest::FILENAME),
+        )?;
+        Ok(true)
+    }
+
+    /// Adds the task's records along with text of each chunk and optionally
+    /// its audio
+    fn add_task(&mut self, task_dir: &Path, audio: bool) -> Result<()> {
+        if !self.add_task_records(task_dir)? {
+            return Ok(());
+        }
+
+        let manifest = TaskManifest::load(task_dir)?;
+        for descriptor in &manifest.subtasks {
+            let dir = Path::new("chunks").join(&descriptor.id);
+            if compress::exists(&descriptor.input) {
+                self.add_file(&dir.join(task::INPUT_NAME), &descriptor.input)?;
+            }
+            if audio && compress::exists(&descriptor.output) {
+                self.add_file(&dir.join(task::OUTPUT_NAME), &descriptor.output)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the file, decompressing it if only its compressed copy is left
+    pub fn add_file(&mut self, name: &Path, path: &Path) -> Result<()> {
+        let mut data = Vec::new();
+        compress::open(path)?
+            .read_to_end(&mut data)
+            .with_context(|| format!("reading '{}'", path.display()))?;
+        self.add_data(name, &data)
+    }
+
+    pub fn add_data(&mut self, name: &Path, data: &[u8]) -> Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(data.len() as u64);
+        header.set_mode(MODE);
+        header.set_mtime(0);
+        header.set_uid(0);
+        header.set_gid(0);
+
+        let path = self.root.join(name);
+        self.builder
+            .append_data(&mut header, &path, data)
+            .with_context(|| format!("archiving '{}'", path.display()))?;
+        self.files += 1;
+        Ok(())
+    }
+}
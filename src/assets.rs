@@ -0,0 +1,54 @@
+use crate::cache;
+use crate::run;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Returns path to the content-addressed copy of the asset in the state dir
+///
+/// The copy is written on first use, so that workspaces can link to it
+/// rather than each getting a copy of its own.
+fn stored(name: &str, contents: &[u8]) -> Result<PathBuf> {
+    let hash = format!("{:x}", Sha256::digest(contents));
+    let dir = run::state_dir()?.join("assets").join(hash);
+    let path = dir.join(name);
+    if path.is_file() {
+        cache::touch(&dir);
+        return Ok(path);
+    }
+
+    fs::create_dir_all(&dir).with_context(|| format!("creating dir '{}'", dir.display()))?;
+    // write under a temporary name first, so that a concurrent run never
+    // links to a partially written asset
+    let partial = dir.join(format!("{}.partial-{}", name, std::process::id()));
+    fs::write(&partial, contents)
+        .with_context(|| format!("writing asset to '{}'", partial.display()))?;
+    fs::rename(&partial, &path).with_context(|| format!("moving asset to '{}'", path.display()))?;
+    cache::touch(&dir);
+    Ok(path)
+}
+
+/// Places the asset at `path`, hard-linking it to the stored copy if possible
+///
+/// Falls back to copying the stored copy when hard links aren't supported,
+/// e.g. across filesystems, which reflinks it on filesystems supporting that,
+/// and to writing the asset out when the state dir isn't available.
+pub fn install<P: AsRef<Path>>(name: &str, contents: &[u8], path: P) -> Result<()> {
+    let path = path.as_ref();
+    let source = match stored(name, contents) {
+        Ok(source) => source,
+        Err(e) => {
+            log::debug!("Couldn't store asset '{}': {:#}", name, e);
+            return fs::write(path, contents)
+                .with_context(|| format!("writing asset to '{}'", path.display()));
+        }
+    };
+
+    if let Err(e) = fs::hard_link(&source, path) {
+        log::debug!("Couldn't hard-link asset '{}': {}", name, e);
+        fs::copy(&source, path)
+            .with_context(|| format!("copying asset to '{}'", path.display()))?;
+    }
+    Ok(())
+}
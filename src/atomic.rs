@@ -0,0 +1,97 @@
+use anyhow::{Context, Result};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Partial files of outputs still being written, removed if the run is
+/// interrupted
+static PENDING: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Returns path of the temporary file output is written to until it's complete
+///
+/// The file lives next to the output, so that it can be atomically renamed
+/// into place, and keeps the output's extension, which encoders rely on.
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".partial-");
+    name.push(path.file_name().unwrap_or_default());
+    path.with_file_name(name)
+}
+
+/// Temporary file output is written to until it's complete
+///
+/// Unless persisted, the file is removed once dropped, e.g. when writing the
+/// output fails, so that no partial files are left next to the outputs.
+#[derive(Debug)]
+pub struct Partial {
+    target: PathBuf,
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl Partial {
+    /// Reserves the partial file of the output, which is up to the caller
+    /// to create
+    pub fn new<P: AsRef<Path>>(target: P) -> Self {
+        let target = target.as_ref().to_path_buf();
+        let path = partial_path(&target);
+        if let Ok(mut pending) = PENDING.lock() {
+            pending.push(path.clone());
+        }
+        Self {
+            target,
+            path,
+            persisted: false,
+        }
+    }
+
+    /// Returns path of the partial file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns path of the complete output
+    pub fn target(&self) -> &Path {
+        &self.target
+    }
+
+    /// Moves complete output from its partial path into place
+    pub fn persist(mut self) -> Result<()> {
+        fs::rename(&self.path, &self.target).with_context(|| {
+            format!(
+                "moving complete output from '{}' to '{}'",
+                self.path.display(),
+                self.target.display()
+            )
+        })?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for Partial {
+    fn drop(&mut self) {
+        if let Ok(mut pending) = PENDING.lock() {
+            pending.retain(|path| *path != self.path);
+        }
+        if !self.persisted && self.path.exists() {
+            if let Err(e) = fs::remove_file(&self.path) {
+                log::warn!(
+                    "Couldn't remove partial output '{}': {}",
+                    self.path.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// Removes partial files of all the outputs still being written, e.g. when
+/// the run is interrupted with Ctrl-C and nothing gets dropped
+pub fn remove_pending() {
+    if let Ok(pending) = PENDING.lock() {
+        for path in pending.iter() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
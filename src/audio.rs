@@ -0,0 +1,487 @@
+use crate::atomic::Partial;
+use anyhow::{bail, Context, Result};
+use memmap2::Mmap;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Size from which subtask outputs are memory-mapped rather than read
+/// through a buffer
+const MMAP_THRESHOLD: u64 = 1 << 20;
+
+/// Number of samples the sample loops process at once, fixed so that the
+/// compiler unrolls and vectorizes them
+pub const BLOCK_SAMPLES: usize = 256;
+
+/// Converts duration into number of frames at the given sample rate
+pub fn duration_to_frames(duration: Duration, sample_rate: u32) -> u64 {
+    let sample_rate = u64::from(sample_rate);
+    duration.as_secs() * sample_rate + u64::from(duration.subsec_millis()) * sample_rate / 1000
+}
+
+/// Bit depth of WAVE output, whose integer samples may hold 8, 16, 24 or 32
+/// bits
+///
+/// Audio is processed in 16 bits and only requantized when written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BitDepth(u16);
+
+impl BitDepth {
+    pub fn bits(self) -> u16 {
+        self.0
+    }
+
+    /// Requantizes block of 16-bit samples to the bit depth into the buffer,
+    /// replacing what it held, and rounding samples to the nearest value when
+    /// reducing them
+    ///
+    /// The bit depth is matched once per block rather than per sample, so
+    /// that the loops vectorize.
+    pub fn requantize_into(self, samples: &[i16], requantized: &mut Vec<i32>) {
+        requantized.clear();
+        match self.0 {
+            8 => requantized.extend(
+                samples
+                    .iter()
+                    .map(|&sample| ((i32::from(sample) + 128) >> 8).min(127)),
+            ),
+            bits => {
+                let shift = bits - 16;
+                requantized.extend(samples.iter().map(|&sample| i32::from(sample) << shift));
+            }
+        }
+    }
+}
+
+impl Default for BitDepth {
+    fn default() -> Self {
+        BitDepth(16)
+    }
+}
+
+impl FromStr for BitDepth {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.parse() {
+            Ok(bits @ 8) | Ok(bits @ 16) | Ok(bits @ 24) | Ok(bits @ 32) => Ok(BitDepth(bits)),
+            _ => bail!(
+                "unsupported bit depth '{}'; expected one of: 8, 16, 24, 32",
+                s
+            ),
+        }
+    }
+}
+
+/// Channel layout of the output
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout {
+    Mono,
+    Stereo,
+}
+
+impl Layout {
+    pub fn channels(self) -> u16 {
+        match self {
+            Layout::Mono => 1,
+            Layout::Stereo => 2,
+        }
+    }
+}
+
+impl FromStr for Layout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "mono" | "1" => Ok(Layout::Mono),
+            "stereo" | "2" => Ok(Layout::Stereo),
+            _ => bail!(
+                "unknown channel layout '{}'; expected one of: mono, stereo",
+                s
+            ),
+        }
+    }
+}
+
+/// Reads all samples of subtask output WAVE
+pub fn read_wav<R: Read>(reader: R, name: &str) -> Result<(hound::WavSpec, Vec<i16>)> {
+    let reader = hound::WavReader::new(reader).context("parsing WAVE input")?;
+    let spec = reader.spec();
+    let samples = reader
+        .into_samples::<i16>()
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .with_context(|| format!("reading audio sample from {}", name))?;
+    Ok((spec, samples))
+}
+
+/// Reads all samples of subtask output WAVE file, memory-mapping it if
+/// it's large so that its bytes aren't copied through the read buffer
+///
+/// Given the SHA-256 checksum reported for the file, the file is checked
+/// against it as it's read. Mapped files are read from their start,
+/// whatever was read of them already.
+pub fn read_wav_file(
+    reader: BufReader<File>,
+    name: &str,
+    checksum: Option<&str>,
+) -> Result<(hound::WavSpec, Vec<i16>)> {
+    let len = reader
+        .get_ref()
+        .metadata()
+        .with_context(|| format!("reading size of {}", name))?
+        .len();
+    if len < MMAP_THRESHOLD {
+        let expected = match checksum {
+            Some(expected) => expected,
+            None => return read_wav(reader, name),
+        };
+        let mut reader = Checksummed {
+            inner: reader,
+            hasher: Sha256::new(),
+        };
+        let read = read_wav(&mut reader, name)?;
+        // the reader may stop short of chunks following the samples
+        io::copy(&mut reader, &mut io::sink()).with_context(|| format!("reading {}", name))?;
+        verify_checksum(&format!("{:x}", reader.hasher.result()), expected, name)?;
+        return Ok(read);
+    }
+
+    let file = reader.into_inner();
+    // subtask outputs are left alone once the task is computed, so the map
+    // can't change under the reader
+    let map = unsafe { Mmap::map(&file) }.with_context(|| format!("mapping {}", name))?;
+    if let Some(expected) = checksum {
+        verify_checksum(&format!("{:x}", Sha256::digest(&map)), expected, name)?;
+    }
+    read_wav(&map[..], name)
+}
+
+/// Checks the file against the SHA-256 checksum reported for it
+pub fn verify_file_checksum(path: &Path, checksum: &str) -> Result<()> {
+    verify_checksum(
+        &file_checksum(path)?,
+        checksum,
+        &format!("'{}'", path.display()),
+    )
+}
+
+/// Computes SHA-256 checksum of the file
+pub fn file_checksum(path: &Path) -> Result<String> {
+    let mut file = File::open(path).with_context(|| format!("opening '{}'", path.display()))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).with_context(|| format!("reading '{}'", path.display()))?;
+    Ok(format!("{:x}", hasher.result()))
+}
+
+fn verify_checksum(actual: &str, expected: &str, name: &str) -> Result<()> {
+    if !actual.eq_ignore_ascii_case(expected) {
+        bail!(
+            "{} is corrupted: its SHA-256 checksum is {} where the node reported {}",
+            name,
+            actual,
+            expected
+        );
+    }
+    log::debug!("Verified checksum of {}", name);
+    Ok(())
+}
+
+/// Reader computing SHA-256 of the bytes read through it
+struct Checksummed<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> Read for Checksummed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.input(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// Writer of a clip covering a fixed window of the combined audio stream
+pub struct Clip {
+    writer: hound::WavWriter<BufWriter<File>>,
+    /// Dropped after the writer, so that the file is closed before removed
+    partial: Partial,
+    start: u64,
+    end: u64,
+}
+
+impl Clip {
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        spec: hound::WavSpec,
+        offset: Duration,
+        length: Duration,
+    ) -> Result<Self> {
+        let partial = Partial::new(path);
+        let writer = hound::WavWriter::create(partial.path(), spec)
+            .with_context(|| format!("creating clip WAVE file '{}'", partial.target().display()))?;
+        let channels = u64::from(spec.channels);
+        let start = duration_to_frames(offset, spec.sample_rate) * channels;
+        let end = start + duration_to_frames(length, spec.sample_rate) * channels;
+
+        Ok(Self {
+            writer,
+            partial,
+            start,
+            end,
+        })
+    }
+
+    /// Writes the part of `samples` which falls within the clip's window
+    ///
+    /// `position` is the index of the first of `samples` within the entire
+    /// combined audio stream.
+    pub fn write_samples(&mut self, position: u64, samples: &[i16]) -> Result<()> {
+        let from = self.start.max(position);
+        let to = self.end.min(position + samples.len() as u64);
+        if from >= to {
+            return Ok(());
+        }
+
+        let samples = &samples[(from - position) as usize..(to - position) as usize];
+        let mut wrt = self.writer.get_i16_writer(samples.len() as u32);
+        for &sample in samples {
+            unsafe { wrt.write_sample_unchecked(sample) };
+        }
+        wrt.flush().with_context(|| {
+            format!(
+                "writing audio samples to clip '{}'",
+                self.partial.target().display()
+            )
+        })
+    }
+
+    pub fn finalize(self) -> Result<()> {
+        let partial = self.partial;
+        self.writer.finalize().with_context(|| {
+            format!("finalizing clip WAVE file '{}'", partial.target().display())
+        })?;
+        partial.persist()
+    }
+}
+
+/// Converts 16-bit samples between channel layouts and sample rates
+///
+/// Channels are mixed down by averaging or duplicated when going up, while
+/// sample rate is converted with linear interpolation, which is good enough
+/// for speech.
+pub fn convert(samples: &[i16], from: hound::WavSpec, to: hound::WavSpec) -> Vec<i16> {
+    let mut converted = Vec::new();
+    convert_into(samples, from, to, &mut converted);
+    converted
+}
+
+/// Converts 16-bit samples as [`convert`] does into the buffer, replacing
+/// what it held, so that a buffer can be reused across chunks
+pub fn convert_into(
+    samples: &[i16],
+    from: hound::WavSpec,
+    to: hound::WavSpec,
+    converted: &mut Vec<i16>,
+) {
+    let from_channels = usize::from(from.channels.max(1));
+    let to_channels = usize::from(to.channels.max(1));
+    converted.clear();
+
+    // most outputs only differ in channels from the mono flite chunks
+    if from.sample_rate == to.sample_rate {
+        if from_channels == to_channels {
+            converted.extend_from_slice(samples);
+            return;
+        }
+        if from_channels == 1 {
+            duplicate_channels(samples, to_channels, converted);
+            return;
+        }
+    }
+
+    // mix down to mono frames first
+    let mono: Vec<f64> = samples
+        .chunks(from_channels)
+        .map(|frame| frame.iter().map(|&s| f64::from(s)).sum::<f64>() / frame.len() as f64)
+        .collect();
+
+    let mono = if from.sample_rate == to.sample_rate || mono.is_empty() {
+        mono
+    } else {
+        let ratio = f64::from(from.sample_rate) / f64::from(to.sample_rate);
+        let len = (mono.len() as f64 / ratio).round() as usize;
+        (0..len)
+            .map(|i| {
+                let pos = i as f64 * ratio;
+                let idx = pos.floor() as usize;
+                let frac = pos - idx as f64;
+                let a = mono[idx.min(mono.len() - 1)];
+                let b = mono[(idx + 1).min(mono.len() - 1)];
+                a + (b - a) * frac
+            })
+            .collect()
+    };
+
+    converted.reserve(mono.len() * to_channels);
+    for sample in mono {
+        let sample = sample.round().clamp(-32768.0, 32767.0) as i16;
+        for _ in 0..to_channels {
+            converted.push(sample);
+        }
+    }
+}
+
+/// Duplicates mono samples into each of the channels of the buffer, which
+/// is empty to begin with
+fn duplicate_channels(samples: &[i16], channels: usize, duplicated: &mut Vec<i16>) {
+    duplicated.resize(samples.len() * channels, 0);
+    match channels {
+        // stereo is by far the most common, so it gets a loop of its own
+        // with a fixed frame size
+        2 => {
+            for (frame, &sample) in duplicated.chunks_exact_mut(2).zip(samples) {
+                frame[0] = sample;
+                frame[1] = sample;
+            }
+        }
+        _ => {
+            for (frame, &sample) in duplicated.chunks_exact_mut(channels).zip(samples) {
+                frame.fill(sample);
+            }
+        }
+    }
+}
+
+/// Length of the blocks loudness is measured over
+const LOUDNESS_BLOCK: Duration = Duration::from_millis(50);
+/// Level of blocks left out of the loudness measurement as silence
+const LOUDNESS_GATE_DB: f64 = -50.0;
+
+/// Measures loudness of the samples as RMS level of the blocks above the
+/// silence gate, in dBFS, so that pauses don't count
+///
+/// Returns `None` for audio silent throughout.
+pub fn loudness(samples: &[i16], spec: hound::WavSpec) -> Option<f64> {
+    let block = (duration_to_frames(LOUDNESS_BLOCK, spec.sample_rate) as usize
+        * usize::from(spec.channels.max(1)))
+    .max(1);
+    let gate = 10f64.powf(LOUDNESS_GATE_DB / 10.0);
+
+    let (mut sum, mut count) = (0.0, 0);
+    for block in samples.chunks(block) {
+        let power = block
+            .iter()
+            .map(|&sample| (f64::from(sample) / 32768.0).powi(2))
+            .sum::<f64>()
+            / block.len() as f64;
+        if power > gate {
+            sum += power * block.len() as f64;
+            count += block.len();
+        }
+    }
+    if count == 0 {
+        None
+    } else {
+        Some(10.0 * (sum / count as f64).log10())
+    }
+}
+
+/// Returns level of the loudest sample in dBFS
+pub fn peak(samples: &[i16]) -> f64 {
+    let peak = samples
+        .iter()
+        .map(|&sample| i32::from(sample).abs())
+        .max()
+        .unwrap_or(0);
+    20.0 * (f64::from(peak.max(1)) / 32768.0).log10()
+}
+
+/// Pans stereo samples in place to the position from -1, left, through 0,
+/// center, to 1, right
+///
+/// Only the channel away from the position is attenuated, so that audio
+/// panned to the center is left as is.
+pub fn pan(samples: &mut [i16], position: f32) {
+    let gains = [(1.0 - position).min(1.0), (1.0 + position).min(1.0)];
+    for frame in samples.chunks_exact_mut(2) {
+        for (sample, gain) in frame.iter_mut().zip(&gains) {
+            *sample = (f32::from(*sample) * gain).round() as i16;
+        }
+    }
+}
+
+/// Amplifies or attenuates the samples in place by the factor, clipping what
+/// overflows
+pub fn apply_gain(samples: &mut [i16], factor: f32) {
+    let mut blocks = samples.chunks_exact_mut(BLOCK_SAMPLES);
+    for block in &mut blocks {
+        gain_block(block, factor);
+    }
+    gain_block(blocks.into_remainder(), factor);
+}
+
+#[inline(always)]
+fn gain_block(block: &mut [i16], factor: f32) {
+    for sample in block {
+        let amplified = f32::from(*sample) * factor;
+        // rounds half away from zero without calling into libm, while the
+        // cast saturates what overflows
+        *sample = (amplified + 0.5f32.copysign(amplified)) as i16;
+    }
+}
+
+/// Inserts silent pauses of given durations into the samples near the given
+/// relative positions
+///
+/// The positions are only estimates within `0.0..=1.0` of the samples'
+/// length, so each pause is snapped to the quietest 10ms window within 250ms
+/// of its estimate, which is where the speech most likely pauses already.
+pub fn insert_pauses(
+    samples: &[i16],
+    spec: hound::WavSpec,
+    pauses: &[(f64, Duration)],
+) -> Vec<i16> {
+    let channels = usize::from(spec.channels).max(1);
+    let frames = samples.len() / channels;
+    let window = (duration_to_frames(Duration::from_millis(10), spec.sample_rate) as usize).max(1);
+    let reach = duration_to_frames(Duration::from_millis(250), spec.sample_rate) as usize;
+    let energy = |frame: usize| -> u64 {
+        samples[frame * channels..(frame + window).min(frames) * channels]
+            .iter()
+            .map(|&sample| u64::from(sample.unsigned_abs()))
+            .sum()
+    };
+
+    let mut cuts: Vec<(usize, Duration)> = pauses
+        .iter()
+        .map(|&(position, pause)| {
+            let estimate = (position.clamp(0.0, 1.0) * frames as f64) as usize;
+            let from = estimate.saturating_sub(reach);
+            let to = (estimate + reach).min(frames.saturating_sub(window));
+            let cut = (from..=to.max(from))
+                .step_by(window)
+                .min_by_key(|&frame| energy(frame))
+                .unwrap_or(estimate);
+            (cut, pause)
+        })
+        .collect();
+    cuts.sort();
+
+    let total: Duration = pauses.iter().map(|&(_, pause)| pause).sum();
+    let silence = duration_to_frames(total, spec.sample_rate) as usize * channels;
+    let mut paused = Vec::with_capacity(samples.len() + silence);
+    let mut last = 0;
+    for (cut, pause) in cuts {
+        let silence = duration_to_frames(pause, spec.sample_rate) as usize * channels;
+        let cut = (cut * channels).min(samples.len());
+        paused.extend_from_slice(&samples[last..cut]);
+        paused.resize(paused.len() + silence, 0);
+        last = cut;
+    }
+    paused.extend_from_slice(&samples[last..]);
+    paused
+}
@@ -0,0 +1,92 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Input of a batch along with what it may spend, as listed in a batch
+/// manifest, e.g.
+///
+/// ```json
+/// [
+///   { "input": "chapter1.txt" },
+///   { "input": "appendix.txt", "bid": 0.5, "budget": 3.0 }
+/// ]
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    /// Input text file, relative to the manifest's dir
+    pub input: PathBuf,
+    /// Bid in GNT overriding --bid for the job
+    pub bid: Option<f64>,
+    /// Maximum cost of the job's own subtasks in GNT
+    pub budget: Option<f64>,
+}
+
+impl Job {
+    fn from_input(input: PathBuf) -> Self {
+        Self {
+            input,
+            bid: None,
+            budget: None,
+        }
+    }
+}
+
+/// Reads jobs listed in the batch manifest
+pub fn load(path: &Path) -> Result<Vec<Job>> {
+    let contents =
+        fs::read(path).with_context(|| format!("reading batch manifest '{}'", path.display()))?;
+    let mut jobs: Vec<Job> = serde_json::from_slice(&contents)
+        .with_context(|| format!("parsing batch manifest '{}'", path.display()))?;
+    if jobs.is_empty() {
+        bail!("batch manifest '{}' lists no jobs", path.display());
+    }
+
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    for job in &mut jobs {
+        if let Some(bid) = job.bid.filter(|&bid| bid <= 0.0) {
+            bail!(
+                "bid of {} GNT for '{}' must be positive",
+                bid,
+                job.input.display()
+            );
+        }
+        if let Some(budget) = job.budget.filter(|&budget| budget <= 0.0) {
+            bail!(
+                "budget of {} GNT for '{}' must be positive",
+                budget,
+                job.input.display()
+            );
+        }
+        if job.input.is_relative() && job.input != Path::new("-") {
+            job.input = dir.join(&job.input);
+        }
+    }
+    Ok(jobs)
+}
+
+/// Returns jobs of the inputs given on the command line, which spend as
+/// the options say
+pub fn from_inputs(inputs: Vec<PathBuf>) -> Vec<Job> {
+    inputs.into_iter().map(Job::from_input).collect()
+}
+
+/// Groups the jobs by their bid, defaulting to the given one, as a Golem
+/// task has a single bid for all its subtasks
+///
+/// Groups keep the order the jobs are listed in.
+pub fn by_bid(jobs: Vec<Job>, bid: f64) -> Vec<(f64, Vec<Job>)> {
+    let mut groups: Vec<(f64, Vec<Job>)> = Vec::new();
+    for job in jobs {
+        let job_bid = job.bid.unwrap_or(bid);
+        match groups
+            .iter_mut()
+            .find(|(group_bid, _)| *group_bid == job_bid)
+        {
+            Some((_, group)) => group.push(job),
+            None => groups.push((job_bid, vec![job])),
+        }
+    }
+    groups
+}
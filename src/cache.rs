@@ -0,0 +1,594 @@
+use super::{CacheListOpt, ImportOpt, PruneOpt};
+use crate::config::Config;
+use crate::run::{self, Run};
+use crate::tasks;
+use crate::telemetry;
+use crate::voice::Voice;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Local};
+use gwasm_api::prelude::ComputedSubtask;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::{fmt, str::FromStr};
+
+/// Size the state dir is capped at unless set in the config file
+pub const DEFAULT_MAX_SIZE: Size = Size(1 << 30);
+
+/// Name of the file marking when a stored asset was last used
+const LAST_USED: &str = ".last-used";
+/// Name of the synthesized audio of a cached chunk
+const CHUNK_AUDIO: &str = "chunk.wav";
+/// Name of the metadata of a cached chunk
+const CHUNK_META: &str = "chunk.json";
+
+/// Size in bytes, read with an optional binary unit, e.g. `512M` or `2G`
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Size(pub u64);
+
+impl FromStr for Size {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let number: u64 = number
+            .parse()
+            .with_context(|| format!("invalid size '{}'", s))?;
+        let shift = match unit.trim().to_uppercase().trim_end_matches("IB") {
+            "" | "B" => 0,
+            "K" => 10,
+            "M" => 20,
+            "G" => 30,
+            "T" => 40,
+            _ => bail!("unknown unit of size '{}'; expected one of: K, M, G, T", s),
+        };
+        number
+            .checked_mul(1 << shift)
+            .map(Size)
+            .ok_or_else(|| anyhow!("size '{}' is too large", s))
+    }
+}
+
+impl TryFrom<String> for Size {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+        let mut size = self.0 as f64;
+        let mut unit = 0;
+        while size >= 1024.0 && unit + 1 < UNITS.len() {
+            size /= 1024.0;
+            unit += 1;
+        }
+        if unit == 0 {
+            write!(f, "{} {}", self.0, UNITS[0])
+        } else {
+            write!(f, "{:.1} {}", size, UNITS[unit])
+        }
+    }
+}
+
+/// Kind of the entries kept in the state dir
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    /// Asset shared by task workspaces, e.g. flite's WASM binary
+    Asset,
+    /// Run in the run history store
+    Run,
+    /// Synthesized chunk, reused by runs synthesizing the same text
+    Chunk,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kind::Asset => write!(f, "asset"),
+            Kind::Run => write!(f, "run"),
+            Kind::Chunk => write!(f, "chunk"),
+        }
+    }
+}
+
+/// Single dir in the state dir, evicted as a whole
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub kind: Kind,
+    pub path: PathBuf,
+    pub size: u64,
+    pub last_used: SystemTime,
+}
+
+/// Marks the stored asset as used, so that it's evicted last
+pub fn touch(dir: &Path) {
+    if let Err(e) = fs::write(dir.join(LAST_USED), b"") {
+        log::debug!("Couldn't mark '{}' as used: {}", dir.display(), e);
+    }
+}
+
+/// Lists the stored assets and runs, least recently used first
+pub fn entries() -> Result<Vec<Entry>> {
+    let state_dir = run::state_dir()?;
+    let mut entries = Vec::new();
+    for (kind, root) in &[
+        (Kind::Asset, state_dir.join("assets")),
+        (Kind::Run, run::runs_dir()?),
+        (Kind::Chunk, state_dir.join("chunks")),
+    ] {
+        let dirs = match fs::read_dir(root) {
+            Ok(dirs) => dirs,
+            Err(_) => continue,
+        };
+        for dir in dirs {
+            let path = dir
+                .with_context(|| format!("listing '{}'", root.display()))?
+                .path();
+            if !path.is_dir() {
+                continue;
+            }
+            entries.push(Entry {
+                kind: *kind,
+                size: telemetry::dir_size(&path),
+                last_used: last_modified(&path),
+                path,
+            });
+        }
+    }
+    entries.sort_by_key(|entry| entry.last_used);
+    Ok(entries)
+}
+
+/// Returns when anything in the dir was last modified
+fn last_modified(dir: &Path) -> SystemTime {
+    let modified = |path: &Path| {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .unwrap_or(SystemTime::UNIX_EPOCH)
+    };
+    fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| modified(&entry.path()))
+        .chain(Some(modified(dir)))
+        .max()
+        .unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+/// Evicts least recently used entries until the state dir fits in
+/// `max_size`, never touching `keep`, e.g. the current run
+///
+/// Returns the evicted entries, which are only listed with `dry_run`.
+pub fn prune(max_size: Size, keep: Option<&Path>, dry_run: bool) -> Result<Vec<Entry>> {
+    let entries = entries()?;
+    let mut total: u64 = entries.iter().map(|entry| entry.size).sum();
+    let mut evicted = Vec::new();
+
+    for entry in entries {
+        if total <= max_size.0 {
+            break;
+        }
+        if Some(entry.path.as_path()) == keep {
+            continue;
+        }
+        if !dry_run {
+            fs::remove_dir_all(&entry.path)
+                .with_context(|| format!("removing '{}'", entry.path.display()))?;
+            log::info!(
+                "Evicted {} '{}' of {}",
+                entry.kind,
+                entry.path.display(),
+                Size(entry.size)
+            );
+        }
+        total -= entry.size;
+        evicted.push(entry);
+    }
+    Ok(evicted)
+}
+
+/// Evicts least recently used assets and runs over the configured size
+pub fn prune_cmd(opt: PruneOpt) -> Result<()> {
+    let max_size = match opt.max_size {
+        Some(max_size) => max_size,
+        None => Config::load()?.cache.max_size(),
+    };
+    let evicted = prune(max_size, None, opt.dry_run)?;
+
+    let freed = Size(evicted.iter().map(|entry| entry.size).sum());
+    let verb = if opt.dry_run {
+        "Would remove"
+    } else {
+        "Removed"
+    };
+    for entry in &evicted {
+        println!(
+            "{} {} '{}' ({})",
+            verb,
+            entry.kind,
+            entry.path.display(),
+            Size(entry.size)
+        );
+    }
+    if evicted.is_empty() {
+        println!("State dir already fits in {}", max_size);
+    } else {
+        println!("{} {} in total", verb, freed);
+    }
+    Ok(())
+}
+
+/// Metadata stored along with a cached chunk
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChunkMeta {
+    pub voice: Option<Voice>,
+    /// Extra flite arguments the chunk was synthesized with
+    pub args: Vec<String>,
+    /// SHA-256 of the chunk's text
+    pub text_hash: String,
+    /// Where the audio came from, i.e. the run or the imported file
+    pub source: String,
+}
+
+/// Returns SHA-256 of the chunk's text, the way `g_flite split` lists it
+pub fn text_hash(text: &str) -> String {
+    format!("{:x}", Sha256::digest(text.as_bytes()))
+}
+
+/// Returns key the chunk is cached under, telling apart both its text and
+/// how it was synthesized
+pub fn chunk_key(voice: Option<Voice>, args: &[String], text_hash: &str) -> String {
+    let key = format!(
+        "{}\n{}\n{}",
+        voice.map_or("default", Voice::name),
+        args.join(" "),
+        text_hash
+    );
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+fn chunk_dir(key: &str) -> Result<PathBuf> {
+    Ok(run::state_dir()?.join("chunks").join(key))
+}
+
+/// Returns path to the cached audio of the chunk, if there's any
+pub fn cached_chunk(key: &str) -> Option<PathBuf> {
+    let dir = chunk_dir(key).ok()?;
+    let path = dir.join(CHUNK_AUDIO);
+    if !path.is_file() {
+        return None;
+    }
+    touch(&dir);
+    Some(path)
+}
+
+/// Stores the chunk's audio in the cache, hard-linking it if possible
+pub fn store_chunk(key: &str, audio: &Path, meta: &ChunkMeta) -> Result<()> {
+    let dir = chunk_dir(key)?;
+    fs::create_dir_all(&dir).with_context(|| format!("creating dir '{}'", dir.display()))?;
+
+    // never let a concurrent run pick up a partially written chunk
+    let partial = dir.join(format!("{}.partial-{}", CHUNK_AUDIO, std::process::id()));
+    if let Err(e) = fs::hard_link(audio, &partial) {
+        log::debug!("Couldn't hard-link chunk '{}': {}", audio.display(), e);
+        fs::copy(audio, &partial)
+            .with_context(|| format!("copying chunk to '{}'", partial.display()))?;
+    }
+    let json = serde_json::to_vec_pretty(meta).context("serializing chunk metadata")?;
+    let meta_path = dir.join(CHUNK_META);
+    fs::write(&meta_path, json)
+        .with_context(|| format!("writing chunk metadata '{}'", meta_path.display()))?;
+    let path = dir.join(CHUNK_AUDIO);
+    fs::rename(&partial, &path).with_context(|| format!("moving chunk to '{}'", path.display()))
+}
+
+/// Opens the cached audio the way Golem hands over subtask results
+pub fn open_chunk(path: &Path) -> Result<ComputedSubtask> {
+    let file =
+        File::open(path).with_context(|| format!("opening cached chunk '{}'", path.display()))?;
+    let mut data = BTreeMap::new();
+    data.insert(path.to_path_buf(), BufReader::new(file));
+    Ok(ComputedSubtask { data })
+}
+
+/// Seeds the cache with audio synthesized elsewhere, matched with the chunks
+/// listed in the manifest written by `g_flite split`
+///
+/// Files are matched with the chunks of the same name, e.g. `chunk_00003.wav`
+/// with `chunk_00003.txt`, otherwise in order if there are as many of them.
+/// Chunks whose voice is set by a directive are imported with that voice.
+pub fn import(opt: ImportOpt) -> Result<()> {
+    let contents = fs::read(&opt.manifest)
+        .with_context(|| format!("reading chunks manifest '{}'", opt.manifest.display()))?;
+    let manifest: serde_json::Value =
+        serde_json::from_slice(&contents).context("parsing chunks manifest")?;
+    let chunks: Vec<(String, String, Option<Voice>)> = manifest["chunks"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|chunk| {
+            let stem = Path::new(chunk["file"].as_str()?)
+                .file_stem()?
+                .to_string_lossy()
+                .into_owned();
+            let voice = chunk["voice"].as_str().and_then(|voice| voice.parse().ok());
+            Some((stem, chunk["text_hash"].as_str()?.to_owned(), voice))
+        })
+        .collect();
+    if chunks.is_empty() {
+        bail!("'{}' lists no chunks", opt.manifest.display());
+    }
+
+    let stem = |path: &Path| {
+        path.file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+    let by_name = opt
+        .files
+        .iter()
+        .any(|file| chunks.iter().any(|(name, _, _)| *name == stem(file)));
+    if !by_name && opt.files.len() != chunks.len() {
+        bail!(
+            "none of the {} files is named after a chunk, and the manifest lists {} chunks, so they can't be matched in order either",
+            opt.files.len(),
+            chunks.len()
+        );
+    }
+
+    for (i, file) in opt.files.iter().enumerate() {
+        let (_, text_hash, chunk_voice) = if by_name {
+            match chunks.iter().find(|(name, _, _)| *name == stem(file)) {
+                Some(chunk) => chunk,
+                None => {
+                    println!("Skipped '{}' matching no chunk", file.display());
+                    continue;
+                }
+            }
+        } else {
+            &chunks[i]
+        };
+        let voice = opt.voice.or(*chunk_voice);
+
+        hound::WavReader::open(file)
+            .with_context(|| format!("reading '{}' as WAVE", file.display()))?;
+        let meta = ChunkMeta {
+            voice,
+            args: Vec::new(),
+            text_hash: text_hash.clone(),
+            source: file.display().to_string(),
+        };
+        store_chunk(&chunk_key(voice, &[], text_hash), file, &meta)?;
+        println!("Imported '{}'", file.display());
+    }
+    Ok(())
+}
+
+/// Entry as listed by `g_flite cache ls`
+#[derive(Debug, Serialize)]
+struct Listed {
+    kind: Kind,
+    name: String,
+    path: PathBuf,
+    size_bytes: u64,
+    last_used: String,
+    /// Voices the run synthesized with, `default` standing for flite's own
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    voices: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subtasks: Option<u64>,
+}
+
+impl Listed {
+    fn new(entry: &Entry) -> Self {
+        let name = match entry.kind {
+            // assets are stored under their hash, holding a single file
+            Kind::Asset => fs::read_dir(&entry.path)
+                .into_iter()
+                .flatten()
+                .filter_map(|file| file.ok())
+                .map(|file| file.file_name().to_string_lossy().into_owned())
+                .find(|name| name != LAST_USED),
+            // chunks are told apart by their key, which is long
+            Kind::Chunk => entry
+                .path
+                .file_name()
+                .map(|key| key.to_string_lossy().chars().take(12).collect()),
+            Kind::Run => None,
+        };
+        let name = name.unwrap_or_else(|| {
+            entry
+                .path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned()
+        });
+
+        let manifest: Option<serde_json::Value> = match entry.kind {
+            Kind::Run => fs::read(entry.path.join(Run::MANIFEST))
+                .ok()
+                .and_then(|contents| serde_json::from_slice(&contents).ok()),
+            Kind::Asset | Kind::Chunk => None,
+        };
+        let mut voices: Vec<_> = manifest
+            .iter()
+            .flat_map(|manifest| manifest["outputs"].as_array().into_iter().flatten())
+            .map(|output| output["voice"].as_str().unwrap_or("default").to_owned())
+            .collect();
+        if entry.kind == Kind::Chunk {
+            let meta: Option<ChunkMeta> = fs::read(entry.path.join(CHUNK_META))
+                .ok()
+                .and_then(|contents| serde_json::from_slice(&contents).ok());
+            if let Some(meta) = meta {
+                voices.push(meta.voice.map_or("default", Voice::name).to_owned());
+            }
+        }
+
+        Self {
+            kind: entry.kind,
+            name,
+            path: entry.path.clone(),
+            size_bytes: entry.size,
+            last_used: DateTime::<Local>::from(entry.last_used).to_rfc3339(),
+            voices,
+            subtasks: manifest.and_then(|manifest| manifest["subtasks"].as_u64()),
+        }
+    }
+}
+
+/// Number and total size of the entries of a single kind
+#[derive(Debug, Default, Serialize)]
+struct KindStats {
+    count: usize,
+    size_bytes: u64,
+}
+
+/// Summary of the state dir as shown by `g_flite cache stats`
+#[derive(Debug, Serialize)]
+struct Stats {
+    state_dir: PathBuf,
+    max_size_bytes: u64,
+    size_bytes: u64,
+    assets: KindStats,
+    runs: KindStats,
+    chunks: KindStats,
+    /// Runs which finished, i.e. have their manifest written
+    finished_runs: usize,
+    /// Subtasks computed by the finished runs
+    subtasks: u64,
+    /// Number of finished runs synthesizing with each voice
+    voices: BTreeMap<String, usize>,
+}
+
+/// Lists the stored assets and runs, least recently used first
+pub fn list(opt: CacheListOpt) -> Result<()> {
+    let listed: Vec<_> = entries()?.iter().map(Listed::new).collect();
+    if opt.json {
+        let json = serde_json::to_string_pretty(&listed).context("serializing cache entries")?;
+        println!("{}", json);
+        return Ok(());
+    }
+    if listed.is_empty() {
+        println!("State dir is empty");
+        return Ok(());
+    }
+
+    let rows: Vec<Vec<String>> = listed
+        .iter()
+        .map(|listed| {
+            let last_used = DateTime::parse_from_rfc3339(&listed.last_used)
+                .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default();
+            vec![
+                listed.kind.to_string(),
+                listed.name.clone(),
+                Size(listed.size_bytes).to_string(),
+                last_used,
+                listed.voices.join(","),
+                listed
+                    .subtasks
+                    .map_or_else(|| "-".to_owned(), |subtasks| subtasks.to_string()),
+            ]
+        })
+        .collect();
+    tasks::print_table(
+        &["KIND", "NAME", "SIZE", "LAST USED", "VOICES", "SUBTASKS"],
+        &rows,
+    );
+    Ok(())
+}
+
+/// Summarizes what's stored in the state dir against its size cap
+pub fn stats(opt: CacheListOpt) -> Result<()> {
+    let mut stats = Stats {
+        state_dir: run::state_dir()?,
+        max_size_bytes: Config::load()?.cache.max_size().0,
+        size_bytes: 0,
+        assets: KindStats::default(),
+        runs: KindStats::default(),
+        chunks: KindStats::default(),
+        finished_runs: 0,
+        subtasks: 0,
+        voices: BTreeMap::new(),
+    };
+    for entry in entries()? {
+        let listed = Listed::new(&entry);
+        let kind_stats = match entry.kind {
+            Kind::Asset => &mut stats.assets,
+            Kind::Run => &mut stats.runs,
+            Kind::Chunk => &mut stats.chunks,
+        };
+        kind_stats.count += 1;
+        kind_stats.size_bytes += entry.size;
+        stats.size_bytes += entry.size;
+
+        if entry.kind != Kind::Run {
+            continue;
+        }
+        if let Some(subtasks) = listed.subtasks {
+            stats.finished_runs += 1;
+            stats.subtasks += subtasks * listed.voices.len().max(1) as u64;
+        }
+        for voice in listed.voices {
+            *stats.voices.entry(voice).or_insert(0) += 1;
+        }
+    }
+
+    if opt.json {
+        let json = serde_json::to_string_pretty(&stats).context("serializing cache stats")?;
+        println!("{}", json);
+        return Ok(());
+    }
+
+    println!(
+        "State dir '{}' holds {} of {} allowed",
+        stats.state_dir.display(),
+        Size(stats.size_bytes),
+        Size(stats.max_size_bytes)
+    );
+    println!();
+    let rows: Vec<Vec<String>> = [
+        (Kind::Asset, &stats.assets),
+        (Kind::Run, &stats.runs),
+        (Kind::Chunk, &stats.chunks),
+    ]
+    .iter()
+    .map(|(kind, kind_stats)| {
+        vec![
+            kind.to_string(),
+            kind_stats.count.to_string(),
+            Size(kind_stats.size_bytes).to_string(),
+        ]
+    })
+    .collect();
+    tasks::print_table(&["KIND", "COUNT", "SIZE"], &rows);
+    println!();
+    println!(
+        "{} finished runs computed {} subtasks",
+        stats.finished_runs, stats.subtasks
+    );
+    if !stats.voices.is_empty() {
+        let voices: Vec<_> = stats
+            .voices
+            .iter()
+            .map(|(voice, runs)| format!("{} ({})", voice, runs))
+            .collect();
+        println!("Runs by voice: {}", voices.join(", "));
+    }
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use super::SplitOpt;
+use crate::app;
+use crate::config::Config;
+use crate::filter::{self, TextOptions};
+use crate::preprocess;
+use crate::split::{self, InputFormat, Subtasks};
+use crate::timing;
+use crate::voice::Voice;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Manifest describing the chunks written by the split subcommand
+#[derive(Debug, Serialize)]
+struct ChunksManifest<'a> {
+    input: &'a Path,
+    subtasks: u64,
+    chunks: Vec<ChunkDescriptor>,
+}
+
+#[derive(Debug, Serialize)]
+struct ChunkDescriptor {
+    file: PathBuf,
+    /// Index of the chapter the chunk belongs to
+    chapter: usize,
+    words: usize,
+    /// SHA-256 of the chunk's text
+    text_hash: String,
+    /// Voice set by a directive in the input
+    #[serde(skip_serializing_if = "Option::is_none")]
+    voice: Option<Voice>,
+}
+
+impl<'a> ChunksManifest<'a> {
+    const FILENAME: &'static str = "chunks.json";
+}
+
+/// Splits the input into chunks as synthesized by g_flite, writing each
+/// into a text file of its own along with a manifest
+///
+/// The input is normalized the same way as before synthesis, including the
+/// text filters set in the config file, so the chunks match what g_flite
+/// would send to the providers.
+pub fn split(opt: SplitOpt) -> Result<()> {
+    let options = TextOptions {
+        dehyphenate: !opt.keep_hyphenation,
+        strip_citations: !opt.keep_citations,
+        ..TextOptions::default()
+    };
+    let filters = filter::chain(&options, &Config::load()?.filters)?;
+    let contents = filters.apply(app::read_input(
+        &opt.input,
+        opt.encoding,
+        InputFormat::Text,
+    )?)?;
+    let contents = preprocess::join_pause_markers(&contents);
+
+    let pattern = match &opt.chapter_pattern {
+        Some(pattern) => Some(split::chapter_pattern(pattern)?),
+        None => None,
+    };
+    let auto_words = timing::auto_words_per_subtask(None);
+    let num_subtasks = Subtasks::new(opt.subtasks, opt.words_per_subtask, auto_words)?
+        .count(split::word_count(&[&contents]));
+    let chunks = split::split(&contents, num_subtasks, pattern.as_ref(), opt.split_by)?;
+
+    fs::create_dir_all(&opt.out_dir)
+        .with_context(|| format!("creating dir '{}'", opt.out_dir.display()))?;
+
+    let mut descriptors = Vec::with_capacity(chunks.len());
+    for (i, chunk) in chunks.iter().enumerate() {
+        let file = PathBuf::from(format!("chunk_{:05}.txt", i));
+        let path = opt.out_dir.join(&file);
+        fs::write(&path, chunk.text.as_bytes())
+            .with_context(|| format!("writing chunk to '{}'", path.display()))?;
+        descriptors.push(ChunkDescriptor {
+            file,
+            chapter: chunk.chapter,
+            words: chunk.text.split_whitespace().count(),
+            text_hash: format!("{:x}", Sha256::digest(chunk.text.as_bytes())),
+            voice: chunk.voice,
+        });
+    }
+
+    let manifest = ChunksManifest {
+        input: &opt.input,
+        subtasks: num_subtasks,
+        chunks: descriptors,
+    };
+    let path = opt.out_dir.join(ChunksManifest::FILENAME);
+    let contents = serde_json::to_vec_pretty(&manifest).context("serializing chunks manifest")?;
+    fs::write(&path, contents)
+        .with_context(|| format!("writing chunks manifest to '{}'", path.display()))?;
+
+    println!(
+        "Split '{}' into {} chunks in '{}'",
+        opt.input.display(),
+        chunks.len(),
+        opt.out_dir.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,648 @@
+use crate::atomic::Partial;
+use crate::audio::{self, BitDepth, Clip, BLOCK_SAMPLES};
+use crate::encode::Encoder;
+use crate::postprocess;
+use crate::qa::QaAnalyzer;
+use crate::timing::Timeline;
+use anyhow::{bail, Context, Result};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::mem;
+use std::path::Path;
+
+/// Result of combining subtask outputs
+pub struct Combined {
+    pub timeline: Timeline,
+    pub qa: Option<QaAnalyzer>,
+}
+
+/// Combiner of subtask outputs into the final audio
+///
+/// The combined samples are streamed into every configured sink (output
+/// files, encoders, analyzers) at once, so that the audio is only decoded a
+/// single time.
+pub struct Combiner {
+    spec: hound::WavSpec,
+    /// Bit depth the WAVE outputs are written in
+    bit_depth: BitDepth,
+    /// Block of samples requantized to the bit depth, reused across writes
+    requantized: Vec<i32>,
+    /// Samples converted from another spec, reused across chunks
+    converted: Vec<i16>,
+    /// Output WAVE file, dropped before its partial file is removed
+    output: Option<(hound::WavWriter<BufWriter<File>>, Partial)>,
+    stream: Option<WavStream>,
+    encoders: Vec<Encoder>,
+    preview: Option<Clip>,
+    qa: Option<QaAnalyzer>,
+    timeline: Timeline,
+    /// Audio filters run on the whole combined audio, which is then held
+    /// back until finished
+    filters: postprocess::Chain,
+    held: Vec<i16>,
+    /// Frames each chunk's end is mixed with the start of the next one
+    crossfade: u64,
+    /// End of the last chunk held back to be mixed with the next one, along
+    /// with the chunk and the frame its span starts at
+    tail: Option<(usize, u64, Vec<i16>)>,
+    /// Samples written so far
+    position: u64,
+    /// Frames of silence inserted between chunks
+    gaps: u64,
+    /// Frames written outside of chunks and gaps, e.g. the table of contents
+    untimed: u64,
+    /// Samples already in the output file being appended to
+    appended: u64,
+}
+
+impl Combiner {
+    pub fn new(spec: hound::WavSpec) -> Self {
+        let mut timeline = Timeline::default();
+        timeline.set_sample_rate(spec.sample_rate);
+
+        Self {
+            spec,
+            bit_depth: BitDepth::default(),
+            requantized: Vec::with_capacity(BLOCK_SAMPLES),
+            converted: Vec::new(),
+            output: None,
+            stream: None,
+            encoders: Vec::new(),
+            preview: None,
+            qa: None,
+            timeline,
+            filters: postprocess::Chain::default(),
+            held: Vec::new(),
+            crossfade: 0,
+            tail: None,
+            position: 0,
+            gaps: 0,
+            untimed: 0,
+            appended: 0,
+        }
+    }
+
+    pub fn spec(&self) -> hound::WavSpec {
+        self.spec
+    }
+
+    /// Converts samples of another spec to the combined audio's
+    ///
+    /// Samples already in the combined audio's spec are returned as they are,
+    /// while converted ones trade places with the buffer they were converted
+    /// into, so that the next conversion reuses the chunk's allocation.
+    pub fn convert(&mut self, mut samples: Vec<i16>, spec: hound::WavSpec) -> Vec<i16> {
+        if spec != self.spec {
+            audio::convert_into(&samples, spec, self.spec, &mut self.converted);
+            mem::swap(&mut samples, &mut self.converted);
+        }
+        samples
+    }
+
+    /// Sets bit depth of the WAVE outputs, which must be set before them
+    pub fn set_bit_depth(&mut self, bit_depth: BitDepth) {
+        self.bit_depth = bit_depth;
+    }
+
+    /// Returns spec of the WAVE outputs, which only differ from the combined
+    /// audio in bit depth
+    fn output_spec(&self) -> hound::WavSpec {
+        hound::WavSpec {
+            bits_per_sample: self.bit_depth.bits(),
+            ..self.spec
+        }
+    }
+
+    /// Sets output WAVE file, written under a temporary name until finished
+    pub fn set_output<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let partial = Partial::new(path);
+        let writer =
+            hound::WavWriter::create(partial.path(), self.output_spec()).with_context(|| {
+                format!("creating output WAVE file '{}'", partial.target().display())
+            })?;
+        self.output = Some((writer, partial));
+        Ok(())
+    }
+
+    /// Sets existing output WAVE file to append to, which must be of the
+    /// same spec as the combined audio
+    pub fn append_output<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref().to_path_buf();
+        let reader = hound::WavReader::open(&path)
+            .with_context(|| format!("reading output WAVE file '{}'", path.display()))?;
+        let spec = reader.spec();
+        let output_spec = self.output_spec();
+        if spec != output_spec {
+            bail!(
+                "can't append to '{}': its audio is {} Hz, {} channel(s), {} bit, while the synthesized one is {} Hz, {} channel(s), {} bit",
+                path.display(),
+                spec.sample_rate,
+                spec.channels,
+                spec.bits_per_sample,
+                output_spec.sample_rate,
+                output_spec.channels,
+                output_spec.bits_per_sample
+            );
+        }
+
+        // append to a copy, so that the original stays intact until finished
+        let partial = Partial::new(&path);
+        fs::copy(&path, partial.path())
+            .with_context(|| format!("copying output WAVE file '{}'", path.display()))?;
+        let writer = hound::WavWriter::append(partial.path())
+            .with_context(|| format!("opening output WAVE file '{}'", path.display()))?;
+        self.appended = u64::from(reader.len());
+        self.output = Some((writer, partial));
+        Ok(())
+    }
+
+    /// Sets writer the combined audio is streamed into as WAVE, e.g. stdout
+    pub fn stream_output<W: Write + 'static>(&mut self, writer: W) -> Result<()> {
+        self.stream = Some(WavStream::new(Box::new(writer), self.spec, self.bit_depth)?);
+        Ok(())
+    }
+
+    pub fn add_encoder(&mut self, encoder: Encoder) {
+        self.encoders.push(encoder);
+    }
+
+    pub fn set_preview(&mut self, preview: Clip) {
+        self.preview = Some(preview);
+    }
+
+    pub fn set_qa(&mut self, qa: QaAnalyzer) {
+        self.qa = Some(qa);
+    }
+
+    /// Sets audio filters run on the combined audio once all of it is
+    /// written; they must keep its length
+    pub fn set_filters(&mut self, filters: postprocess::Chain) {
+        self.filters = filters;
+    }
+
+    /// Crossfades consecutive chunks over given number of frames
+    ///
+    /// Chunks adjoining silence, or the start or the end of the audio, are
+    /// faded in or out instead, so that the joins don't click.
+    pub fn set_crossfade(&mut self, frames: u64) {
+        self.crossfade = frames;
+    }
+
+    /// Returns current position in the combined audio, in frames
+    pub fn position(&self) -> u64 {
+        let held = self.tail.as_ref().map_or(0, |(_, _, tail)| tail.len());
+        (self.position + held as u64) / u64::from(self.spec.channels)
+    }
+
+    /// Writes samples which don't belong to any chunk
+    pub fn write_samples(&mut self, samples: &[i16]) -> Result<()> {
+        self.flush_tail()?;
+        self.write(samples)?;
+        self.untimed += samples.len() as u64 / u64::from(self.spec.channels);
+        Ok(())
+    }
+
+    fn check_frames(&self, samples: &[i16]) -> Result<()> {
+        let channels = usize::from(self.spec.channels);
+        if samples.len() / channels * channels != samples.len() {
+            bail!(
+                "can't write {} samples of {}-channel audio: not a whole number of frames",
+                samples.len(),
+                self.spec.channels
+            );
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, samples: &[i16]) -> Result<()> {
+        self.check_frames(samples)?;
+
+        if self.filters.is_empty() {
+            self.sink(self.position, samples)?;
+        } else {
+            self.held.extend_from_slice(samples);
+        }
+        self.position += samples.len() as u64;
+
+        Ok(())
+    }
+
+    /// Streams samples starting at `position` into all the sinks
+    fn sink(&mut self, position: u64, samples: &[i16]) -> Result<()> {
+        if let Some((writer, partial)) = self.output.as_mut() {
+            let path = partial.target();
+            if self.bit_depth == BitDepth::default() {
+                let mut wrt = writer.get_i16_writer(samples.len() as u32);
+                for &sample in samples {
+                    unsafe { wrt.write_sample_unchecked(sample) };
+                }
+                wrt.flush().with_context(|| {
+                    format!("writing audio samples to file '{}'", path.display())
+                })?;
+            } else {
+                for block in samples.chunks(BLOCK_SAMPLES) {
+                    self.bit_depth.requantize_into(block, &mut self.requantized);
+                    self.requantized
+                        .iter()
+                        .try_for_each(|&sample| writer.write_sample(sample))
+                        .with_context(|| {
+                            format!("writing audio samples to file '{}'", path.display())
+                        })?;
+                }
+            }
+        }
+
+        if let Some(stream) = self.stream.as_mut() {
+            stream.write_samples(samples)?;
+        }
+
+        for encoder in &mut self.encoders {
+            encoder.write_samples(samples)?;
+        }
+
+        if let Some(preview) = self.preview.as_mut() {
+            preview.write_samples(position, samples)?;
+        }
+
+        if let Some(qa) = self.qa.as_mut() {
+            qa.write_samples(samples);
+        }
+
+        Ok(())
+    }
+
+    /// Writes chunk's samples recording its span in the timeline
+    ///
+    /// With crossfading, the overlap of two chunks counts towards the first
+    /// one's span.
+    pub fn write_chunk(&mut self, chunk: usize, samples: &[i16]) -> Result<()> {
+        if self.crossfade == 0 {
+            let start = self.position();
+            self.write(samples)?;
+            self.timeline.push(chunk, start, self.position());
+            return Ok(());
+        }
+        self.check_frames(samples)?;
+
+        let channels = usize::from(self.spec.channels);
+        let overlap = (self.crossfade as usize * channels).min(samples.len());
+        let (head, rest) = samples.split_at(overlap);
+        let tail = match self.tail.take() {
+            // a chunk no longer than its fade-in leaves nothing to mix with,
+            // so the next one's start isn't counted towards it
+            Some((previous, start, tail)) if tail.is_empty() => {
+                self.timeline.push(previous, start, self.position());
+                None
+            }
+            tail => tail,
+        };
+        let start = match tail {
+            Some((previous, start, tail)) => {
+                let (unmixed, end) = tail.split_at(tail.len().saturating_sub(overlap));
+                self.write(unmixed)?;
+                self.write(&crossfade(end, head, channels))?;
+                self.timeline.push(previous, start, self.position());
+                self.position()
+            }
+            None => {
+                let start = self.position();
+                self.write(&crossfade(&[], head, channels))?;
+                start
+            }
+        };
+
+        let held = (self.crossfade as usize * channels).min(rest.len());
+        let (body, tail) = rest.split_at(rest.len() - held);
+        self.write(body)?;
+        self.tail = Some((chunk, start, tail.to_vec()));
+        Ok(())
+    }
+
+    /// Writes out the held back end of the last chunk, fading it out
+    fn flush_tail(&mut self) -> Result<()> {
+        if let Some((chunk, start, tail)) = self.tail.take() {
+            let silence = vec![0; tail.len()];
+            self.write(&crossfade(&tail, &silence, usize::from(self.spec.channels)))?;
+            self.timeline.push(chunk, start, self.position());
+        }
+        Ok(())
+    }
+
+    pub fn write_silence(&mut self, frames: u64) -> Result<()> {
+        self.flush_tail()?;
+        let silence = vec![0; (frames * u64::from(self.spec.channels)) as usize];
+        self.write(&silence)?;
+        self.gaps += frames;
+        Ok(())
+    }
+
+    /// Checks that every written frame is accounted for by a chunk, a gap
+    /// or untimed audio
+    fn verify_accounting(&self) -> Result<()> {
+        let chunks: u64 = self
+            .timeline
+            .spans()
+            .iter()
+            .map(|span| span.end - span.start)
+            .sum();
+        let accounted = chunks + self.gaps + self.untimed;
+        if accounted != self.position() {
+            bail!(
+                "combined audio is {} frames long, but its chunks, gaps and table of contents add up to {} frames",
+                self.position(),
+                accounted
+            );
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<Combined> {
+        let (timeline, qa) = self.close()?;
+        Ok(Combined { timeline, qa })
+    }
+
+    /// Finishes the outputs, leaving the rest of the audio to the combiner of
+    /// the next part, and returns the timeline of this part
+    pub fn roll_over(self) -> Result<Timeline> {
+        let (timeline, _) = self.close()?;
+        Ok(timeline)
+    }
+
+    fn close(mut self) -> Result<(Timeline, Option<QaAnalyzer>)> {
+        self.flush_tail()?;
+        self.verify_accounting()?;
+
+        if !self.filters.is_empty() {
+            let held = std::mem::take(&mut self.held);
+            let (spec, samples) = self.filters.apply(self.spec, held)?;
+            if spec != self.spec || samples.len() as u64 != self.position {
+                bail!(
+                    "output audio filters changed the combined audio from {} samples of {:?} to {} samples of {:?}",
+                    self.position,
+                    self.spec,
+                    samples.len(),
+                    spec
+                );
+            }
+            self.sink(0, &samples)?;
+        }
+
+        let output_spec = self.output_spec();
+        if let Some((writer, partial)) = self.output {
+            writer.finalize().with_context(|| {
+                format!(
+                    "finalizing output WAVE file '{}'",
+                    partial.target().display()
+                )
+            })?;
+            verify_output(&partial, output_spec, self.appended + self.position)?;
+            partial.persist()?;
+        }
+
+        if let Some(stream) = self.stream {
+            stream.finish()?;
+        }
+
+        for encoder in self.encoders {
+            encoder.finish()?;
+        }
+
+        if let Some(preview) = self.preview {
+            preview.finalize()?;
+        }
+
+        Ok((self.timeline, self.qa))
+    }
+}
+
+/// Checks that the finalized output's header matches the audio written to it
+///
+/// A mismatch means the file would play truncated or padded with garbage, so
+/// it's never moved into place.
+fn verify_output(partial: &Partial, spec: hound::WavSpec, samples: u64) -> Result<()> {
+    let path = partial.target();
+    let reader = hound::WavReader::open(partial.path())
+        .with_context(|| format!("verifying output WAVE file '{}'", path.display()))?;
+    if reader.spec() != spec {
+        bail!(
+            "output WAVE file '{}' declares {:?}, while {:?} was written",
+            path.display(),
+            reader.spec(),
+            spec
+        );
+    }
+
+    let declared = u64::from(reader.len());
+    if declared != samples {
+        bail!(
+            "output WAVE file '{}' declares {} samples, while {} were written",
+            path.display(),
+            declared,
+            samples
+        );
+    }
+
+    let data = declared * u64::from(spec.bits_per_sample / 8);
+    let size = fs::metadata(partial.path())
+        .with_context(|| format!("verifying output WAVE file '{}'", path.display()))?
+        .len();
+    if size < data {
+        bail!(
+            "output WAVE file '{}' is {} bytes long, too short to hold the {} bytes of audio it declares",
+            path.display(),
+            size,
+            data
+        );
+    }
+    Ok(())
+}
+
+/// Mixes the end of one chunk into the start of the next, fading the
+/// former out while fading the latter in
+///
+/// The end may be shorter than the start, in which case it's aligned with
+/// the start's end, or empty to fade the start in from silence.
+fn crossfade(end: &[i16], start: &[i16], channels: usize) -> Vec<i16> {
+    let frames = start.len() / channels;
+    let offset = start.len() - end.len().min(start.len());
+    start
+        .iter()
+        .enumerate()
+        .map(|(i, &sample)| {
+            let t = (i / channels + 1) as f64 / (frames + 1) as f64;
+            let faded = i
+                .checked_sub(offset)
+                .and_then(|i| end.get(i))
+                .map_or(0.0, |&sample| f64::from(sample));
+            (faded * (1.0 - t) + f64::from(sample) * t).round() as i16
+        })
+        .collect()
+}
+
+/// Writer of WAVE into a sink which can't be seeked, e.g. a pipe
+///
+/// As the length of the audio isn't known upfront, the header declares the
+/// maximum length instead, which players and ffmpeg read as "until the end
+/// of the stream".
+struct WavStream {
+    writer: Box<dyn Write>,
+    bit_depth: BitDepth,
+    /// Encoded samples of a write, reused across writes
+    bytes: Vec<u8>,
+    /// Block of samples requantized to the bit depth, reused across writes
+    requantized: Vec<i32>,
+}
+
+impl WavStream {
+    fn new(mut writer: Box<dyn Write>, spec: hound::WavSpec, bit_depth: BitDepth) -> Result<Self> {
+        if spec.bits_per_sample != 16 || spec.sample_format != hound::SampleFormat::Int {
+            bail!(
+                "only 16-bit integer audio can be streamed, while the synthesized one is {}-bit",
+                spec.bits_per_sample
+            );
+        }
+
+        let block_align = spec.channels * (bit_depth.bits() / 8);
+        let mut header = Vec::with_capacity(44);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&u32::MAX.to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        header.extend_from_slice(&spec.channels.to_le_bytes());
+        header.extend_from_slice(&spec.sample_rate.to_le_bytes());
+        header.extend_from_slice(&(spec.sample_rate * u32::from(block_align)).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&bit_depth.bits().to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&u32::MAX.to_le_bytes());
+        writer
+            .write_all(&header)
+            .context("writing WAVE header to output stream")?;
+
+        Ok(Self {
+            writer,
+            bit_depth,
+            bytes: Vec::new(),
+            requantized: Vec::with_capacity(BLOCK_SAMPLES),
+        })
+    }
+
+    fn write_samples(&mut self, samples: &[i16]) -> Result<()> {
+        let width = usize::from(self.bit_depth.bits() / 8);
+        self.bytes.clear();
+        self.bytes.reserve(samples.len() * width);
+        if self.bit_depth == BitDepth::default() {
+            for &sample in samples {
+                self.bytes.extend_from_slice(&sample.to_le_bytes());
+            }
+        } else {
+            for block in samples.chunks(BLOCK_SAMPLES) {
+                self.bit_depth.requantize_into(block, &mut self.requantized);
+                if width == 1 {
+                    // 8-bit WAVE samples are unsigned
+                    self.bytes
+                        .extend(self.requantized.iter().map(|&sample| (sample + 128) as u8));
+                } else {
+                    for &sample in &self.requantized {
+                        self.bytes.extend_from_slice(&sample.to_le_bytes()[..width]);
+                    }
+                }
+            }
+        }
+        self.writer
+            .write_all(&self.bytes)
+            .context("writing audio samples to output stream")
+    }
+
+    fn finish(mut self) -> Result<()> {
+        self.writer.flush().context("flushing output stream")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What's written into the combiner, in frames
+    #[derive(Clone, Copy)]
+    enum Part {
+        Chunk(u64),
+        Silence(u64),
+    }
+
+    #[test]
+    fn accounts_for_crossfaded_samples() {
+        use Part::{Chunk, Silence};
+        // channels, crossfade, writes, expected spans and length in frames
+        type Case = (u16, u64, &'static [Part], &'static [(u64, u64)], u64);
+        let cases: &[Case] = &[
+            (1, 0, &[Chunk(100), Chunk(50)], &[(0, 100), (100, 150)], 150),
+            (1, 10, &[Chunk(100)], &[(0, 100)], 100),
+            (
+                1,
+                10,
+                &[Chunk(100), Chunk(50)],
+                &[(0, 100), (100, 140)],
+                140,
+            ),
+            (
+                2,
+                10,
+                &[Chunk(100), Chunk(50)],
+                &[(0, 100), (100, 140)],
+                140,
+            ),
+            (
+                1,
+                10,
+                &[Chunk(100), Silence(20), Chunk(50)],
+                &[(0, 100), (120, 170)],
+                170,
+            ),
+            // the short chunk is mixed whole into the end of the previous
+            // one, which the overlap counts towards
+            (
+                1,
+                10,
+                &[Chunk(100), Chunk(5), Chunk(100)],
+                &[(0, 100), (100, 100), (100, 200)],
+                200,
+            ),
+            (1, 10, &[Chunk(5), Chunk(5)], &[(0, 5), (5, 10)], 10),
+        ];
+
+        for &(channels, crossfade, writes, spans, frames) in cases {
+            let mut combiner = Combiner::new(hound::WavSpec {
+                channels,
+                sample_rate: 16000,
+                bits_per_sample: 16,
+                sample_format: hound::SampleFormat::Int,
+            });
+            combiner.set_crossfade(crossfade);
+            let mut chunk = 0;
+            for &write in writes {
+                match write {
+                    Chunk(len) => {
+                        let samples = vec![1000; (len * u64::from(channels)) as usize];
+                        combiner.write_chunk(chunk, &samples).unwrap();
+                        chunk += 1;
+                    }
+                    Silence(len) => combiner.write_silence(len).unwrap(),
+                }
+            }
+            combiner.flush_tail().unwrap();
+
+            let case = format!("{} channels, crossfade {}", channels, crossfade);
+            combiner.verify_accounting().expect(&case);
+            assert_eq!(combiner.position(), frames, "{}", case);
+            let actual: Vec<_> = combiner
+                .timeline
+                .spans()
+                .iter()
+                .map(|span| (span.start, span.end))
+                .collect();
+            assert_eq!(actual, spans, "{}", case);
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use crate::task;
+use anyhow::{Context, Result};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// Extension appended to names of the compressed files
+const EXTENSION: &str = "zst";
+/// zstd compression level; higher ones barely shrink PCM any further
+const LEVEL: i32 = 3;
+/// Names of the workspace files compressed, i.e. chunk inputs and outputs
+const COMPRESSED: &[&str] = &[task::INPUT_NAME, task::OUTPUT_NAME];
+
+/// Returns path of the file's compressed copy
+fn compressed_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".");
+    name.push(EXTENSION);
+    PathBuf::from(name)
+}
+
+/// Checks whether the file exists, either as is or compressed
+pub fn exists(path: &Path) -> bool {
+    path.is_file() || compressed_path(path).is_file()
+}
+
+/// Opens the file, transparently decompressing its compressed copy if
+/// only that one is left
+pub fn open(path: &Path) -> Result<Box<dyn Read>> {
+    match File::open(path) {
+        Ok(file) => Ok(Box::new(BufReader::new(file))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound && compressed_path(path).is_file() => {
+            let compressed = compressed_path(path);
+            let file = File::open(&compressed)
+                .with_context(|| format!("opening '{}'", compressed.display()))?;
+            let decoder = zstd::Decoder::new(file)
+                .with_context(|| format!("decompressing '{}'", compressed.display()))?;
+            Ok(Box::new(decoder))
+        }
+        Err(e) => Err(e).with_context(|| format!("opening '{}'", path.display())),
+    }
+}
+
+/// Replaces the file with its compressed copy
+fn compress_file(path: &Path) -> Result<u64> {
+    let compressed = compressed_path(path);
+    let partial = compressed.with_extension(format!("{}.partial", EXTENSION));
+
+    let mut input = File::open(path).with_context(|| format!("opening '{}'", path.display()))?;
+    let output =
+        File::create(&partial).with_context(|| format!("creating '{}'", partial.display()))?;
+    let mut encoder = zstd::Encoder::new(output, LEVEL)
+        .with_context(|| format!("compressing '{}'", path.display()))?;
+    io::copy(&mut input, &mut encoder)
+        .and_then(|_| encoder.finish())
+        .with_context(|| {
+            format!(
+                "compressing '{}' into '{}'",
+                path.display(),
+                partial.display()
+            )
+        })?;
+
+    fs::rename(&partial, &compressed)
+        .with_context(|| format!("renaming '{}'", partial.display()))?;
+    fs::remove_file(path).with_context(|| format!("removing '{}'", path.display()))?;
+
+    let size = fs::metadata(&compressed)
+        .with_context(|| format!("reading metadata of '{}'", compressed.display()))?
+        .len();
+    Ok(size)
+}
+
+/// Compresses the chunk inputs and outputs kept in the workspace, replacing
+/// them with their zstd-compressed copies
+///
+/// Returns total size of the files before and after compression.
+pub fn compress_workspace(workspace: &Path) -> Result<(u64, u64)> {
+    let mut sizes = (0, 0);
+    for entry in
+        fs::read_dir(workspace).with_context(|| format!("reading dir '{}'", workspace.display()))?
+    {
+        let entry = entry.with_context(|| format!("reading dir '{}'", workspace.display()))?;
+        let path = entry.path();
+        let metadata = entry
+            .metadata()
+            .with_context(|| format!("reading metadata of '{}'", path.display()))?;
+
+        if metadata.is_dir() {
+            let (before, after) = compress_workspace(&path)?;
+            sizes.0 += before;
+            sizes.1 += after;
+        } else if COMPRESSED.iter().any(|&name| entry.file_name() == name) {
+            sizes.0 += metadata.len();
+            sizes.1 += compress_file(&path)?;
+        }
+    }
+    Ok(sizes)
+}
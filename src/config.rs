@@ -0,0 +1,303 @@
+use crate::cache::{self, Size};
+use crate::postprocess::Stage;
+use crate::preprocess::{Locale, SpeakLocale};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Deserializer};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Environment variable overriding path to the config file
+const CONFIG_ENV: &str = "G_FLITE_CONFIG";
+
+/// Returns path to g_flite's config file
+pub fn config_path() -> Result<PathBuf> {
+    if let Some(path) = env::var_os(CONFIG_ENV) {
+        return Ok(PathBuf::from(path));
+    }
+
+    appdirs::user_config_dir(Some("g_flite"), Some("golem"), false)
+        .map(|dir| dir.join("config.toml"))
+        .map_err(|_| anyhow!("no standard project app config dirs available"))
+}
+
+/// User configuration read from `config.toml`
+///
+/// All settings are optional, and a missing config file is the same as an
+/// empty one.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Path to the `golemcli` executable, unless it's on `PATH`
+    pub golemcli: Option<PathBuf>,
+    /// Checks for a newer release on startup unless set to false
+    pub version_check: Option<bool>,
+    pub limits: Limits,
+    pub cache: CacheConfig,
+    pub session: SessionConfig,
+    pub telemetry: TelemetryConfig,
+    pub notifiers: Vec<NotifierConfig>,
+    pub filters: Vec<FilterConfig>,
+    pub audio_filters: Vec<AudioFilterConfig>,
+}
+
+/// Ceilings of what a single job may spend, whatever its own bid and budget
+///
+/// Useful when several users or scripts share the node's funds, so that one
+/// expensive job can't use them all up.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Maximum bid in GNT
+    pub max_bid: Option<f64>,
+    /// Maximum total cost of a task in GNT
+    pub max_budget: Option<f64>,
+}
+
+/// Limits on how long talking to the node may take, e.g.
+///
+/// ```toml
+/// [session]
+/// handshake_timeout = "10s"
+/// call_timeout = "1m"
+/// idle_timeout = "5m"
+/// ```
+///
+/// None are set by default, in which case a slow or wedged node is waited on
+/// for as long as it takes.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    /// Time for the node's RPC endpoint to accept a TLS connection before
+    /// a task is sent to it
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub handshake_timeout: Option<Duration>,
+    /// Time for each `golemcli` call to return
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub call_timeout: Option<Duration>,
+    /// Time the RPC session computing a task may go without reporting its
+    /// progress
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub idle_timeout: Option<Duration>,
+}
+
+/// Opt-in reporting of anonymous usage metrics after each run, e.g.
+///
+/// ```toml
+/// [telemetry]
+/// enabled = true
+/// endpoint = "https://metrics.example.com/g_flite"
+/// ```
+///
+/// Off unless enabled with an endpoint to report to.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryConfig {
+    pub enabled: bool,
+    /// URL the reports are POSTed to as JSON
+    pub endpoint: Option<String>,
+}
+
+/// Reads duration such as `30s` or `5m`
+fn deserialize_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Duration>, D::Error> {
+    let duration = String::deserialize(deserializer)?;
+    humantime::parse_duration(&duration)
+        .map(Some)
+        .map_err(|e| serde::de::Error::custom(format!("invalid duration '{}': {}", duration, e)))
+}
+
+/// Cap on the assets and run history kept in g_flite's state dir, e.g.
+///
+/// ```toml
+/// [cache]
+/// max_size = "512M"
+/// ```
+///
+/// Least recently used entries are evicted after each run once the cap is
+/// exceeded.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Defaults to 1G
+    pub max_size: Option<Size>,
+}
+
+impl CacheConfig {
+    pub fn max_size(&self) -> Size {
+        self.max_size.unwrap_or(cache::DEFAULT_MAX_SIZE)
+    }
+}
+
+/// Single text filter entry of the `[[filters]]` array, e.g.
+///
+/// ```toml
+/// [[filters]]
+/// kind = "lexicon"
+/// path = "/home/user/lexicon.txt"
+///
+/// [[filters]]
+/// kind = "regex"
+/// pattern = '(\d+) ?km\b'
+/// replacement = "$1 kilometers"
+/// ```
+///
+/// Filters run in the order they're listed, after the ones enabled on the
+/// command line.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+pub enum FilterConfig {
+    /// Strips control characters, applies Unicode NFC and expands
+    /// abbreviations and, for English, numbers and dates
+    Normalize {
+        locale: Option<Locale>,
+        speak_locale: Option<SpeakLocale>,
+    },
+    /// Expands roman numerals, ordinals and year ranges
+    Numerals { locale: Option<Locale> },
+    /// Applies normalization rules from file, same as `--rules`
+    Rules { path: PathBuf },
+    /// Applies a single normalization rule
+    Regex {
+        pattern: String,
+        replacement: String,
+    },
+    /// Replaces words with their pronunciations from file
+    Lexicon { path: PathBuf },
+    /// Expands acronyms from dictionary file, or spells them out
+    Acronyms {
+        path: Option<PathBuf>,
+        #[serde(default)]
+        spell: bool,
+    },
+    /// Strips Markdown syntax
+    Markdown,
+    /// Reads out common emoji in words and drops the rest
+    Emoji,
+    /// Pipes the text through external executable
+    Command {
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+}
+
+/// Single audio filter entry of the `[[audio_filters]]` array, e.g.
+///
+/// ```toml
+/// [[audio_filters]]
+/// kind = "trim"
+///
+/// [[audio_filters]]
+/// kind = "fade"
+/// in_ms = 200
+/// out_ms = 500
+/// stage = "output"
+/// ```
+///
+/// Filters run in the order they're listed, on each chunk's audio or, with
+/// `stage = "output"`, on each combined output.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+pub enum AudioFilterConfig {
+    /// Trims silence at the chunk's start and end
+    Trim {
+        /// Level in dBFS below which audio is silence, -50 unless set
+        threshold: Option<f64>,
+        /// Silence kept around the speech, 50ms unless set
+        pad_ms: Option<u64>,
+    },
+    /// Amplifies the audio by the dB, or attenuates it if negative
+    Gain {
+        db: f64,
+        #[serde(default)]
+        stage: Stage,
+    },
+    /// Converts the chunk's audio to the sample rate
+    Resample { sample_rate: u32 },
+    /// Fades the audio in and out
+    Fade {
+        #[serde(default)]
+        in_ms: u64,
+        #[serde(default)]
+        out_ms: u64,
+        #[serde(default)]
+        stage: Stage,
+    },
+    /// Pipes the audio as WAVE through external executable
+    Command {
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default)]
+        stage: Stage,
+    },
+}
+
+/// Single notifier entry of the `[[notifiers]]` array, e.g.
+///
+/// ```toml
+/// [[notifiers]]
+/// kind = "webhook"
+/// url = "https://example.com/hooks/g_flite"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+pub enum NotifierConfig {
+    /// POSTs the notification as JSON to the URL
+    Webhook {
+        url: String,
+        #[serde(default)]
+        format: WebhookFormat,
+        /// Message template overriding the format's default one
+        template: Option<String>,
+    },
+    /// Shows the notification on the desktop
+    Desktop,
+    /// Runs the command with the notification passed in environment variables
+    Command {
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+    /// Emails the notification over SMTP
+    Email {
+        host: String,
+        port: Option<u16>,
+        from: String,
+        to: Vec<String>,
+    },
+}
+
+/// Shape of the JSON payload posted by webhook notifiers
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /// The notification as is, including the run summary
+    #[default]
+    Json,
+    /// Slack-compatible incoming webhook, also understood by Mattermost or Rocket.Chat
+    Slack,
+    /// Matrix webhook bridge such as matrix-hookshot
+    Matrix,
+}
+
+impl Config {
+    pub fn load() -> Result<Self> {
+        let path = config_path()?;
+        if !path.is_file() {
+            log::debug!("No config file found at '{}'", path.display());
+            return Ok(Self::default());
+        }
+
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("reading config file '{}'", path.display()))?;
+        let config = toml::from_str(&contents)
+            .with_context(|| format!("parsing config file '{}'", path.display()))?;
+        log::info!("Loaded config from '{}'", path.display());
+
+        Ok(config)
+    }
+}
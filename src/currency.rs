@@ -0,0 +1,127 @@
+use crate::run;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Exchange rates are fetched from CoinGecko's public API
+const RATES_URL: &str = "https://api.coingecko.com/api/v3/simple/price?ids=golem,ethereum";
+/// How long a cached rate is used before fetching it again
+const RATES_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Prices of GNT and ETH in a fiat currency
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rate {
+    pub currency: String,
+    pub gnt: f64,
+    pub eth: f64,
+    /// When the rate was fetched, in RFC 3339
+    pub fetched: String,
+}
+
+impl Rate {
+    /// Returns the rate for the currency, from the cache if it's recent enough
+    ///
+    /// If the rate can't be fetched, e.g. when offline, the cached one is
+    /// used regardless of its age.
+    pub fn get(currency: &str) -> Result<Self> {
+        let currency = currency.to_lowercase();
+        let mut cache = load_cache();
+        if let Some(rate) = cache.get(&currency) {
+            if rate.age().filter(|age| *age < RATES_MAX_AGE).is_some() {
+                return Ok(rate.clone());
+            }
+        }
+
+        match fetch(&currency) {
+            Ok(rate) => {
+                cache.insert(currency, rate.clone());
+                if let Err(e) = save_cache(&cache) {
+                    log::warn!("Couldn't cache exchange rates: {:#}", e);
+                }
+                Ok(rate)
+            }
+            Err(e) => match cache.remove(&currency) {
+                Some(rate) => {
+                    eprintln!(
+                        "Couldn't fetch {} exchange rate ({:#}), using the one from {}",
+                        currency.to_uppercase(),
+                        e,
+                        rate.fetched
+                    );
+                    Ok(rate)
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    fn age(&self) -> Option<Duration> {
+        let fetched = DateTime::parse_from_rfc3339(&self.fetched).ok()?;
+        Local::now().signed_duration_since(fetched).to_std().ok()
+    }
+
+    /// Formats GNT amount in the currency
+    pub fn gnt(&self, amount: f64) -> String {
+        format!("{:.2} {}", amount * self.gnt, self.currency.to_uppercase())
+    }
+
+    /// Formats ETH amount in the currency
+    pub fn eth(&self, amount: f64) -> String {
+        format!("{:.2} {}", amount * self.eth, self.currency.to_uppercase())
+    }
+}
+
+fn fetch(currency: &str) -> Result<Rate> {
+    let url = format!("{}&vs_currencies={}", RATES_URL, currency);
+    let response = ureq::get(&url).timeout(Duration::from_secs(10)).call();
+    if let Some(e) = response.synthetic_error() {
+        bail!("fetching exchange rates: {}", e);
+    }
+    if !response.ok() {
+        bail!(
+            "fetching exchange rates: {} {}",
+            response.status(),
+            response.status_text()
+        );
+    }
+
+    let prices = response
+        .into_json()
+        .context("parsing exchange rates response")?;
+    let price = |id: &str| {
+        prices[id][currency]
+            .as_f64()
+            .ok_or_else(|| anyhow!("no {} price in {}", id, currency.to_uppercase()))
+    };
+    Ok(Rate {
+        currency: currency.to_owned(),
+        gnt: price("golem")?,
+        eth: price("ethereum")?,
+        fetched: Local::now().to_rfc3339(),
+    })
+}
+
+fn cache_path() -> Result<PathBuf> {
+    Ok(run::state_dir()?.join("rates.json"))
+}
+
+/// Loads cached rates, keyed by currency, ignoring a missing or broken cache
+fn load_cache() -> BTreeMap<String, Rate> {
+    cache_path()
+        .and_then(|path| Ok(fs::read(path)?))
+        .ok()
+        .and_then(|contents| serde_json::from_slice(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn save_cache(cache: &BTreeMap<String, Rate>) -> Result<()> {
+    let path = cache_path()?;
+    let contents = serde_json::to_vec_pretty(cache).context("serializing exchange rates")?;
+    fs::create_dir_all(run::state_dir()?).context("creating state dir")?;
+    fs::write(&path, contents)
+        .with_context(|| format!("writing exchange rates to '{}'", path.display()))
+}
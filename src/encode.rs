@@ -0,0 +1,322 @@
+use crate::atomic::Partial;
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::time::Duration;
+use std::{fmt, str::FromStr};
+
+/// External encoder binary used for all compressed output formats
+const FFMPEG: &str = "ffmpeg";
+
+/// Bitrates of Opus output by quality, in kbit/s
+const OPUS_BITRATES: [u32; 11] = [8, 12, 16, 20, 24, 32, 40, 48, 64, 80, 96];
+/// Bitrates of AAC output by quality, in kbit/s
+const AAC_BITRATES: [u32; 11] = [16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128];
+
+/// Output audio format
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Wav,
+    Mp3,
+    /// Vorbis in an Ogg container
+    Ogg,
+    /// Opus in an Ogg container
+    Opus,
+    /// AAC in an MP4 container, i.e. an audiobook with chapter markers
+    M4b,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Wav => "wav",
+            Format::Mp3 => "mp3",
+            Format::Ogg => "ogg",
+            Format::Opus => "opus",
+            Format::M4b => "m4b",
+        }
+    }
+
+    /// Checks whether the format can hold chapter markers
+    pub fn has_chapters(self) -> bool {
+        self == Format::M4b
+    }
+
+    fn codec(self) -> &'static str {
+        match self {
+            Format::Wav => "pcm_s16le",
+            Format::Mp3 => "libmp3lame",
+            Format::Ogg => "libvorbis",
+            Format::Opus => "libopus",
+            Format::M4b => "aac",
+        }
+    }
+
+    /// Encoder arguments setting the quality, on the encoder's own scale
+    fn quality_args(self, quality: Quality) -> Vec<String> {
+        match self {
+            Format::Wav => vec![],
+            // LAME's VBR presets run from V9, the smallest, to V0
+            Format::Mp3 => vec!["-q:a".to_owned(), (10 - quality.0).min(9).to_string()],
+            Format::Ogg => vec!["-q:a".to_owned(), quality.0.to_string()],
+            Format::Opus => vec![
+                "-b:a".to_owned(),
+                format!("{}k", OPUS_BITRATES[usize::from(quality.0)]),
+            ],
+            Format::M4b => vec![
+                "-b:a".to_owned(),
+                format!("{}k", AAC_BITRATES[usize::from(quality.0)]),
+            ],
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "wav" => Ok(Format::Wav),
+            "mp3" => Ok(Format::Mp3),
+            "ogg" | "vorbis" => Ok(Format::Ogg),
+            "opus" => Ok(Format::Opus),
+            "m4b" => Ok(Format::M4b),
+            _ => bail!(
+                "unsupported output format '{}'; expected one of: wav, mp3, ogg, opus, m4b",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+/// Checks that no two of the formats would be written to the same file
+///
+/// WAV output keeps the output's name as given while the other formats swap
+/// its extension for their own, so e.g. `out.mp3` asked for as WAV and MP3
+/// would be written twice.
+pub fn check_formats(output: &Path, formats: &[Format]) -> Result<()> {
+    for (i, format) in formats.iter().enumerate() {
+        if formats[..i].contains(format) {
+            bail!("format {} is requested more than once", format);
+        }
+    }
+    let extension = output
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_lowercase);
+    if let (true, Some(extension)) = (formats.contains(&Format::Wav), extension) {
+        if let Some(format) = formats
+            .iter()
+            .find(|&&format| format != Format::Wav && format.extension() == extension)
+        {
+            bail!(
+                "both WAV and {} output would be written to '{}'; name the output with a .wav extension",
+                format,
+                output.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Quality of the lossy output formats, from 0, the smallest files, to 10,
+/// the best sounding ones
+///
+/// It's mapped onto each encoder's own scale: Vorbis quality as is, LAME's
+/// VBR presets for MP3, bitrates from 8 to 96 kbit/s for Opus and from 16 to
+/// 128 kbit/s for AAC.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quality(u8);
+
+impl FromStr for Quality {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.parse() {
+            Ok(quality) if quality <= 10 => Ok(Quality(quality)),
+            _ => bail!("invalid quality '{}'; expected a number from 0 to 10", s),
+        }
+    }
+}
+
+/// Encoder feeding raw PCM samples into an `ffmpeg` subprocess
+///
+/// Samples are streamed to the encoder while the subtask outputs are being
+/// combined, so that all requested formats are produced in one pass.
+pub struct Encoder {
+    format: Format,
+    child: Child,
+    stdin: BufWriter<ChildStdin>,
+    /// File the encoder writes to, removed unless the encoding succeeds
+    partial: Partial,
+}
+
+impl Encoder {
+    /// Spawns the encoder, leaving the quality to its defaults unless set
+    pub fn spawn<P: AsRef<Path>>(
+        format: Format,
+        path: P,
+        spec: hound::WavSpec,
+        quality: Option<Quality>,
+    ) -> Result<Self> {
+        let partial = Partial::new(path);
+        let mut args = vec![
+            "-loglevel".to_owned(),
+            "error".to_owned(),
+            "-y".to_owned(),
+            "-f".to_owned(),
+            "s16le".to_owned(),
+            "-ar".to_owned(),
+            spec.sample_rate.to_string(),
+            "-ac".to_owned(),
+            spec.channels.to_string(),
+            "-i".to_owned(),
+            "-".to_owned(),
+            "-c:a".to_owned(),
+            format.codec().to_owned(),
+        ];
+        if let Some(quality) = quality {
+            args.extend(format.quality_args(quality));
+        }
+        let mut child = Command::new(FFMPEG)
+            .args(args)
+            .arg(partial.path())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .with_context(|| {
+                format!(
+                    "spawning '{}' to encode {} output; is it installed and in your PATH?",
+                    FFMPEG, format
+                )
+            })?;
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow!("opening stdin of {} encoder", format))?;
+
+        Ok(Self {
+            format,
+            child,
+            stdin: BufWriter::new(stdin),
+            partial,
+        })
+    }
+
+    pub fn write_samples(&mut self, samples: &[i16]) -> Result<()> {
+        for sample in samples {
+            self.stdin
+                .write_all(&sample.to_le_bytes())
+                .with_context(|| format!("streaming audio samples to {} encoder", self.format))?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<()> {
+        let Self {
+            format,
+            mut child,
+            mut stdin,
+            partial,
+        } = self;
+
+        stdin
+            .flush()
+            .with_context(|| format!("streaming audio samples to {} encoder", format))?;
+        drop(stdin);
+
+        let status = child
+            .wait()
+            .with_context(|| format!("waiting for {} encoder to finish", format))?;
+        if !status.success() {
+            bail!(
+                "encoding {} output '{}' failed with {}",
+                format,
+                partial.target().display(),
+                status
+            );
+        }
+
+        partial.persist()
+    }
+}
+
+/// Chapter marker embedded into the formats which can hold them
+#[derive(Debug, Clone)]
+pub struct Chapter {
+    pub title: String,
+    pub start: Duration,
+    pub end: Duration,
+}
+
+/// Embeds chapter markers into the encoded output
+///
+/// Chapters are only known once the whole audio is combined, by when the
+/// encoder is done with the file, so it's remuxed with them without
+/// encoding the audio again.
+pub fn embed_chapters(path: &Path, chapters: &[Chapter]) -> Result<()> {
+    let partial = Partial::new(path);
+    // removed along with the partial output once dropped
+    let metadata = Partial::new(path.with_extension("ffmetadata"));
+    fs::write(metadata.path(), ffmetadata(chapters)).with_context(|| {
+        format!(
+            "writing chapter metadata to '{}'",
+            metadata.path().display()
+        )
+    })?;
+
+    let status = Command::new(FFMPEG)
+        .args(["-loglevel", "error", "-y", "-i"])
+        .arg(path)
+        .arg("-i")
+        .arg(metadata.path())
+        .args(["-map", "0", "-map_chapters", "1", "-c", "copy"])
+        .arg(partial.path())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .with_context(|| format!("spawning '{}' to embed chapters", FFMPEG))?;
+    if !status.success() {
+        bail!(
+            "embedding chapters into '{}' failed with {}",
+            path.display(),
+            status
+        );
+    }
+
+    partial.persist()
+}
+
+/// Writes the chapters in ffmpeg's metadata format, in milliseconds
+fn ffmetadata(chapters: &[Chapter]) -> String {
+    let mut metadata = ";FFMETADATA1\n".to_owned();
+    for chapter in chapters {
+        metadata.push_str(&format!(
+            "\n[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+            chapter.start.as_millis(),
+            chapter.end.as_millis(),
+            escape_metadata(&chapter.title)
+        ));
+    }
+    metadata
+}
+
+/// Escapes characters special to ffmpeg's metadata format
+fn escape_metadata(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if let '=' | ';' | '#' | '\\' | '\n' = c {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -0,0 +1,129 @@
+use anyhow::{anyhow, bail, Result};
+use std::{fmt, str::FromStr};
+
+/// Characters encoded as 0x80 to 0x9f in Windows-1252, which Latin-1 leaves
+/// to control codes; undefined ones are kept as such
+const WINDOWS_1252: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+];
+
+/// Encoding of the input text
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    /// ISO 8859-1
+    Latin1,
+    Windows1252,
+    /// UTF-16 of the byte order given by its BOM, otherwise guessed
+    Utf16,
+    /// Detected from the BOM, otherwise UTF-8 if the text is valid one,
+    /// falling back to Windows-1252
+    Auto,
+}
+
+impl Encoding {
+    /// Decodes the text, dropping its BOM
+    pub fn decode(self, bytes: Vec<u8>) -> Result<String> {
+        match self {
+            Encoding::Utf8 => {
+                let bytes = match bytes.get(..3) {
+                    Some([0xef, 0xbb, 0xbf]) => bytes[3..].to_vec(),
+                    _ => bytes,
+                };
+                String::from_utf8(bytes).map_err(|e| {
+                    anyhow!(
+                        "converting read bytes to string: {}; set the input's encoding with --encoding",
+                        e
+                    )
+                })
+            }
+            Encoding::Latin1 => Ok(bytes.into_iter().map(char::from).collect()),
+            Encoding::Windows1252 => Ok(bytes
+                .into_iter()
+                .map(|byte| match byte {
+                    0x80..=0x9f => WINDOWS_1252[usize::from(byte - 0x80)],
+                    _ => char::from(byte),
+                })
+                .collect()),
+            Encoding::Utf16 => decode_utf16(&bytes),
+            Encoding::Auto => {
+                let encoding = detect(&bytes);
+                log::info!("Input detected to be encoded as {}", encoding);
+                encoding.decode(bytes)
+            }
+        }
+    }
+}
+
+/// Guesses encoding of the text
+fn detect(bytes: &[u8]) -> Encoding {
+    match bytes.get(..2) {
+        Some([0xff, 0xfe]) | Some([0xfe, 0xff]) => return Encoding::Utf16,
+        _ => {}
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return Encoding::Utf8;
+    }
+    // text in UTF-16 without BOM is full of zero bytes, which other
+    // encodings only use for NUL
+    let zeros = bytes.iter().filter(|&&byte| byte == 0).count();
+    if bytes.len() >= 2 && zeros * 4 >= bytes.len() {
+        return Encoding::Utf16;
+    }
+    Encoding::Windows1252
+}
+
+fn decode_utf16(bytes: &[u8]) -> Result<String> {
+    let (big_endian, bytes) = match bytes.get(..2) {
+        Some([0xfe, 0xff]) => (true, &bytes[2..]),
+        Some([0xff, 0xfe]) => (false, &bytes[2..]),
+        // otherwise the order is told by which byte of ASCII characters is zero
+        Some([0, byte]) if *byte != 0 => (true, bytes),
+        _ => (false, bytes),
+    };
+    let pairs = bytes.chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        bail!("converting read bytes to string: UTF-16 input has odd length");
+    }
+    let units = pairs.map(|pair| {
+        if big_endian {
+            u16::from_be_bytes([pair[0], pair[1]])
+        } else {
+            u16::from_le_bytes([pair[0], pair[1]])
+        }
+    });
+    std::char::decode_utf16(units)
+        .collect::<std::result::Result<String, _>>()
+        .map_err(|e| anyhow!("converting read bytes to string: {}", e))
+}
+
+impl FromStr for Encoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "utf-8" | "utf8" | "us-ascii" | "ascii" => Ok(Encoding::Utf8),
+            "latin-1" | "latin1" | "iso-8859-1" => Ok(Encoding::Latin1),
+            "windows-1252" | "cp1252" => Ok(Encoding::Windows1252),
+            "utf-16" | "utf16" | "utf-16le" | "utf-16be" => Ok(Encoding::Utf16),
+            "auto" => Ok(Encoding::Auto),
+            _ => bail!(
+                "unknown encoding '{}'; expected one of: utf-8, latin-1, windows-1252, utf-16, auto",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Encoding::Utf8 => write!(f, "utf-8"),
+            Encoding::Latin1 => write!(f, "latin-1"),
+            Encoding::Windows1252 => write!(f, "windows-1252"),
+            Encoding::Utf16 => write!(f, "utf-16"),
+            Encoding::Auto => write!(f, "auto"),
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use crate::html;
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Path of the container file pointing at the package document
+const CONTAINER: &str = "META-INF/container.xml";
+
+/// Chapter of an EPUB book, i.e. a single document of its spine
+#[derive(Debug, Clone)]
+pub struct Chapter {
+    pub title: String,
+    pub text: String,
+}
+
+/// Reads chapters of the EPUB book in reading order
+///
+/// Titles are taken from the book's navigation document or NCX table of
+/// contents, falling back to the chapter's first heading. Documents marked as
+/// non-linear, such as footnotes, are skipped, as are ones without any text.
+pub fn chapters(path: &Path) -> Result<Vec<Chapter>> {
+    let file = File::open(path).with_context(|| format!("opening '{}'", path.display()))?;
+    let mut book = Book {
+        archive: zip::ZipArchive::new(file)
+            .with_context(|| format!("reading '{}' as EPUB", path.display()))?,
+    };
+
+    let container = book.read(CONTAINER)?;
+    let package_path = attribute(&find_tag(&container, "rootfile")?, "full-path")
+        .ok_or_else(|| anyhow!("'{}' names no package document", CONTAINER))?;
+    let package = book.read(&package_path)?;
+
+    // manifest items by their ID, with paths resolved within the archive
+    let mut items = HashMap::new();
+    let mut nav = None;
+    let mut ncx = None;
+    for item in tags(&package, "item") {
+        let (id, href) = match (attribute(&item, "id"), attribute(&item, "href")) {
+            (Some(id), Some(href)) => (id, resolve(&package_path, &href)),
+            _ => continue,
+        };
+        let media_type = attribute(&item, "media-type").unwrap_or_default();
+        let properties = attribute(&item, "properties").unwrap_or_default();
+        if properties
+            .split_whitespace()
+            .any(|property| property == "nav")
+        {
+            nav = Some(href.clone());
+        }
+        if media_type == "application/x-dtbncx+xml" {
+            ncx = Some(href.clone());
+        }
+        items.insert(id, (href, media_type));
+    }
+
+    let titles = match (nav, ncx) {
+        (Some(nav), _) => nav_titles(&book.read(&nav)?, &nav),
+        (None, Some(ncx)) => ncx_titles(&book.read(&ncx)?, &ncx),
+        (None, None) => HashMap::new(),
+    };
+
+    let mut chapters = Vec::new();
+    for itemref in tags(&package, "itemref") {
+        if attribute(&itemref, "linear").as_deref() == Some("no") {
+            continue;
+        }
+        let (href, media_type) = match attribute(&itemref, "idref").and_then(|id| items.get(&id)) {
+            Some(item) => item,
+            None => continue,
+        };
+        if !media_type.contains("html") {
+            continue;
+        }
+
+        let document = book.read(href)?;
+        let text = html::extract_chapter(&document);
+        if text.split_whitespace().next().is_none() {
+            log::info!("Skipping '{}' without any text", href);
+            continue;
+        }
+        let title = titles
+            .get(href)
+            .cloned()
+            .or_else(|| first_heading(&text))
+            .unwrap_or_else(|| format!("Chapter {}", chapters.len() + 1));
+        chapters.push(Chapter { title, text });
+    }
+
+    if chapters.is_empty() {
+        return Err(anyhow!("'{}' has no chapters with text", path.display()));
+    }
+    log::info!("EPUB book has {} chapters", chapters.len());
+
+    Ok(chapters)
+}
+
+struct Book {
+    archive: zip::ZipArchive<File>,
+}
+
+impl Book {
+    fn read(&mut self, name: &str) -> Result<String> {
+        let mut file = self
+            .archive
+            .by_name(name)
+            .with_context(|| format!("finding '{}' in EPUB", name))?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)
+            .with_context(|| format!("reading '{}' from EPUB", name))?;
+        Ok(contents)
+    }
+}
+
+/// Returns all start tags of the element in the XML document
+fn tags(xml: &str, name: &str) -> Vec<String> {
+    let tag = Regex::new(&format!(r"(?is)<(?:\w+:)?{}\b[^>]*>", regex::escape(name)))
+        .expect("valid tag regex");
+    tag.find_iter(xml).map(|m| m.as_str().to_owned()).collect()
+}
+
+fn find_tag(xml: &str, name: &str) -> Result<String> {
+    tags(xml, name)
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("no <{}> element found in EPUB", name))
+}
+
+/// Returns value of the attribute in the start tag
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let attribute = Regex::new(&format!(
+        r#"(?is)\s{}\s*=\s*(?:"([^"]*)"|'([^']*)')"#,
+        regex::escape(name)
+    ))
+    .expect("valid attribute regex");
+    let caps = attribute.captures(tag)?;
+    caps.get(1).or(caps.get(2)).map(|m| m.as_str().to_owned())
+}
+
+/// Reads chapter titles from EPUB 3 navigation document, by document path
+fn nav_titles(nav: &str, nav_path: &str) -> HashMap<String, String> {
+    let toc = Regex::new(r#"(?is)<nav\b[^>]*type\s*=\s*["']toc["'][^>]*>(.*?)</nav\s*>"#)
+        .expect("valid nav regex");
+    let nav = toc
+        .captures(nav)
+        .map_or(nav.to_owned(), |caps| caps[1].to_owned());
+    let links = Regex::new(r"(?is)<a\b([^>]*)>(.*?)</a\s*>").expect("valid link regex");
+
+    let mut titles = HashMap::new();
+    for caps in links.captures_iter(&nav) {
+        if let Some(href) = attribute(&caps[1], "href") {
+            titles
+                .entry(resolve(nav_path, &href))
+                .or_insert_with(|| html::extract_chapter(&caps[2]));
+        }
+    }
+    titles
+}
+
+/// Reads chapter titles from EPUB 2 NCX table of contents, by document path
+fn ncx_titles(ncx: &str, ncx_path: &str) -> HashMap<String, String> {
+    let points = Regex::new(
+        r"(?is)<navLabel\b[^>]*>\s*<text\b[^>]*>(.*?)</text\s*>\s*</navLabel\s*>\s*(<content\b[^>]*>)",
+    )
+    .expect("valid navPoint regex");
+
+    let mut titles = HashMap::new();
+    for caps in points.captures_iter(ncx) {
+        if let Some(src) = attribute(&caps[2], "src") {
+            titles
+                .entry(resolve(ncx_path, &src))
+                .or_insert_with(|| html::extract_chapter(&caps[1]));
+        }
+    }
+    titles
+}
+
+fn first_heading(text: &str) -> Option<String> {
+    text.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(|line| line.trim_end_matches('.').to_owned())
+}
+
+/// Resolves link relative to the document within the archive, dropping the
+/// fragment
+fn resolve(document: &str, href: &str) -> String {
+    let href = href.split('#').next().unwrap_or_default();
+    let mut segments: Vec<_> = document.split('/').collect();
+    segments.pop();
+    for segment in href.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    percent_decode(&segments.join("/"))
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .filter(|_| bytes[i] == b'%');
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,139 @@
+use crate::golem::{Golemcli, SubtaskInfo, TaskInfo};
+use anyhow::{Context, Result};
+use chrono::Local;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How often the node is polled for task and subtask changes
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Chronological log of what happened to the task on the node
+///
+/// The node's event stream isn't reachable through gwasm-api, so the events
+/// are reconstructed by polling the task's subtasks via golemcli and logging
+/// every change of their status or provider.
+pub struct EventLog {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    task_status: Option<String>,
+    subtasks: HashMap<String, (String, Option<String>)>,
+}
+
+impl EventLog {
+    pub const FILENAME: &'static str = "events.log";
+
+    pub fn create<P: AsRef<Path>>(workspace: P) -> Result<Self> {
+        let path = workspace.as_ref().join(Self::FILENAME);
+        let file = File::create(&path)
+            .with_context(|| format!("creating event log '{}'", path.display()))?;
+        Ok(Self {
+            path,
+            writer: BufWriter::new(file),
+            task_status: None,
+            subtasks: HashMap::new(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn log(&mut self, event: &str) -> Result<()> {
+        writeln!(self.writer, "{} {}", Local::now().to_rfc3339(), event)
+            .and_then(|_| self.writer.flush())
+            .with_context(|| format!("writing to event log '{}'", self.path.display()))
+    }
+
+    /// Logs changes since the task was last seen
+    fn update_task(&mut self, task: &TaskInfo) -> Result<()> {
+        if self.task_status.as_ref() == Some(&task.status) {
+            return Ok(());
+        }
+        self.log(&format!("task {} {}", task.id, task.status))?;
+        self.task_status = Some(task.status.clone());
+        Ok(())
+    }
+
+    /// Logs changes since the subtasks were last seen
+    fn update_subtasks(&mut self, subtasks: &[SubtaskInfo]) -> Result<()> {
+        for subtask in subtasks {
+            let provider = subtask.provider().map(str::to_owned);
+            let current = (subtask.status.clone(), provider.clone());
+            if self.subtasks.get(&subtask.subtask_id) == Some(&current) {
+                continue;
+            }
+
+            let event = match &provider {
+                Some(provider) => format!(
+                    "subtask {} {} (provider {})",
+                    subtask.subtask_id, subtask.status, provider
+                ),
+                None => format!("subtask {} {}", subtask.subtask_id, subtask.status),
+            };
+            self.log(&event)?;
+            self.subtasks.insert(subtask.subtask_id.clone(), current);
+        }
+        Ok(())
+    }
+
+    /// Logs payments made for the task's subtasks
+    fn log_payments(&mut self, golemcli: &Golemcli) -> Result<()> {
+        for payment in golemcli.payments()? {
+            let subtask = payment["subtask"].as_str().unwrap_or_default();
+            if self.subtasks.contains_key(subtask) {
+                self.log(&format!("payment {}", payment))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Background thread recording the event log while the task computes
+pub struct EventMonitor {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<EventLog>,
+}
+
+impl EventMonitor {
+    pub fn spawn(mut log: EventLog, golemcli: Golemcli, task_name: String) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+
+        let handle = thread::spawn(move || {
+            while !stopped.load(Ordering::SeqCst) {
+                if let Err(e) = poll(&mut log, &golemcli, &task_name) {
+                    log::debug!("Polling task events failed: {:#}", e);
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+
+            // catch the final state and whatever got paid by now
+            if let Err(e) =
+                poll(&mut log, &golemcli, &task_name).and_then(|_| log.log_payments(&golemcli))
+            {
+                log::debug!("Polling task events failed: {:#}", e);
+            }
+            log
+        });
+
+        Self { stop, handle }
+    }
+
+    /// Stops monitoring, returning the complete event log
+    pub fn stop(self) -> Option<EventLog> {
+        self.stop.store(true, Ordering::SeqCst);
+        self.handle.join().ok()
+    }
+}
+
+fn poll(log: &mut EventLog, golemcli: &Golemcli, task_name: &str) -> Result<()> {
+    let task = golemcli.find_task(task_name)?;
+    log.update_task(&task)?;
+    log.update_subtasks(&golemcli.subtasks(&task.id)?)
+}
@@ -0,0 +1,188 @@
+use colored::Colorize;
+use serde::Serialize;
+use std::fmt;
+use std::io;
+
+/// Broad cause of a failed run, telling the user where to look
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Failure {
+    /// Nobody computed the task, or not in time
+    NoProviders,
+    /// Providers' results were rejected
+    Verification,
+    /// Node couldn't pay for the task
+    Payment,
+    /// Connection to the node failed or dropped
+    Rpc,
+    /// Reading or writing local files failed
+    Io,
+    Other,
+}
+
+/// Message fragments identifying each kind of failure, checked in order
+const PATTERNS: &[(Failure, &[&str])] = &[
+    (
+        Failure::Payment,
+        &["insufficient", "funds", "balance", "payment", "deposit"],
+    ),
+    (Failure::Verification, &["verif"]),
+    (
+        Failure::NoProviders,
+        &["no provider", "timeout", "timed out", "aborted"],
+    ),
+    (
+        Failure::Rpc,
+        &[
+            "rpc",
+            "wamp",
+            "connection",
+            "connect",
+            "websocket",
+            "golemcli",
+        ],
+    ),
+];
+
+impl Failure {
+    /// Classifies the error based on its chain of causes
+    pub fn classify(error: &anyhow::Error) -> Self {
+        // mistakes in the config file are what they are, whatever setting
+        // they mention
+        if error.chain().any(|cause| cause.is::<toml::de::Error>()) {
+            return Failure::Other;
+        }
+
+        let message = format!("{:#}", error).to_lowercase();
+        for (failure, fragments) in PATTERNS {
+            if fragments.iter().any(|fragment| message.contains(fragment)) {
+                return *failure;
+            }
+        }
+
+        if error.chain().any(|cause| cause.is::<io::Error>()) {
+            Failure::Io
+        } else {
+            Failure::Other
+        }
+    }
+
+    /// Returns troubleshooting advice for the failure, if there's any to give
+    pub fn advice(self) -> Option<&'static str> {
+        match self {
+            Failure::NoProviders => Some(
+                "No provider computed the task in time. Providers pick tasks by their bid, so \
+                 try raising --bid, or the subtask timeout if the subtasks are large. Check \
+                 that your node is connected to the network with `golemcli network show`.",
+            ),
+            Failure::Verification => Some(
+                "The results returned by providers failed verification. This is usually a \
+                 transient problem with a single provider, so try running the task again. \
+                 Run it with --event-log --workspace to keep a record of which provider \
+                 computed what, should you want to dispute the results.",
+            ),
+            Failure::Payment => Some(
+                "Your node couldn't pay for the task. Check its balance with `golemcli \
+                 account info`; on testnet --wait-for-funds waits for the faucet to top it \
+                 up, on mainnet you need to deposit GNT and ETH yourself. Lowering --bid, \
+                 --budget or --subtasks makes the task cheaper.",
+            ),
+            Failure::Rpc => Some(
+                "g_flite couldn't talk to your Golem node. Make sure the node is running, \
+                 and that --datadir, --address and --port (and --mainnet) match its \
+                 settings. If it crashed mid-task, check its logs before retrying.",
+            ),
+            Failure::Io => Some(
+                "Reading or writing local files failed. Check that the input file is \
+                 readable, that the output and workspace dirs are writable, and that there's \
+                 enough free disk space.",
+            ),
+            Failure::Other => None,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Failure::NoProviders => write!(f, "no providers"),
+            Failure::Verification => write!(f, "verification failed"),
+            Failure::Payment => write!(f, "payment issue"),
+            Failure::Rpc => write!(f, "connection to Golem node failed"),
+            Failure::Io => write!(f, "local IO error"),
+            Failure::Other => write!(f, "error"),
+        }
+    }
+}
+
+/// Stage of the run which failed, attached to errors as their context
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Split,
+    Prepare,
+    Compute,
+    Toc,
+    Combine,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stage::Split => write!(f, "splitting input"),
+            Stage::Prepare => write!(f, "preparing task"),
+            Stage::Compute => write!(f, "computing task"),
+            Stage::Toc => write!(f, "synthesizing table of contents"),
+            Stage::Combine => write!(f, "combining output"),
+        }
+    }
+}
+
+/// Machine-readable description of an error
+#[derive(Debug, Serialize)]
+pub struct Report {
+    code: Failure,
+    stage: Option<Stage>,
+    message: String,
+    /// Messages of the error and all its causes, outermost first
+    context: Vec<String>,
+    advice: Option<&'static str>,
+}
+
+impl Report {
+    pub fn new(error: &anyhow::Error) -> Self {
+        let failure = Failure::classify(error);
+        Self {
+            code: failure,
+            stage: error.downcast_ref::<Stage>().cloned(),
+            message: format!("{:#}", error),
+            context: error.chain().map(|cause| cause.to_string()).collect(),
+            advice: failure.advice(),
+        }
+    }
+}
+
+/// Prints the error on stderr, either as prose with advice on what to do
+/// about it, or as a single line JSON object
+pub fn report(error: &anyhow::Error, json: bool) {
+    let failure = Failure::classify(error);
+
+    if json {
+        match serde_json::to_string(&Report::new(error)) {
+            Ok(report) => eprintln!("{}", report),
+            Err(e) => eprintln!(
+                "An error occurred: {:#} (serializing it failed: {})",
+                error, e
+            ),
+        }
+        return;
+    }
+
+    match failure {
+        Failure::Other => eprintln!("{}", format!("An error occurred: {:#}", error).red()),
+        _ => eprintln!("{}", format!("Run failed, {}: {:#}", failure, error).red()),
+    }
+    if let Some(advice) = failure.advice() {
+        eprintln!("\n{}", advice);
+    }
+}
@@ -0,0 +1,398 @@
+use crate::config::FilterConfig;
+use crate::markdown;
+use crate::plugin::Plugin;
+use crate::preprocess::{self, Acronyms, Locale, Rules, SpeakLocale};
+use anyhow::{Context, Result};
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Stage of the preprocessing chain rewriting the input text before it's
+/// split into chunks
+pub trait TextFilter: fmt::Debug {
+    /// Name of the filter as logged
+    fn name(&self) -> &str;
+
+    fn apply(&self, text: &str) -> Result<String>;
+}
+
+/// Ordered chain of text filters, each fed the output of the previous one
+#[derive(Debug, Default)]
+pub struct Chain {
+    filters: Vec<Box<dyn TextFilter>>,
+}
+
+impl Chain {
+    pub fn push<F: TextFilter + 'static>(&mut self, filter: F) {
+        self.filters.push(Box::new(filter));
+    }
+
+    pub fn apply(&self, text: String) -> Result<String> {
+        self.filters.iter().try_fold(text, |text, filter| {
+            log::debug!("Applying text filter '{}'", filter.name());
+            filter.apply(&text)
+        })
+    }
+}
+
+/// Preprocessing enabled by command line options
+#[derive(Debug, Clone)]
+pub struct TextOptions {
+    pub normalize: bool,
+    pub dehyphenate: bool,
+    pub strip_citations: bool,
+    pub rules: Option<PathBuf>,
+    pub expand_numerals: bool,
+    pub locale: Locale,
+    pub speak_locale: Option<SpeakLocale>,
+    pub acronyms: Option<PathBuf>,
+    pub spell_acronyms: bool,
+    pub spell_patterns: Vec<String>,
+}
+
+impl Default for TextOptions {
+    fn default() -> Self {
+        Self {
+            normalize: false,
+            dehyphenate: true,
+            strip_citations: true,
+            rules: None,
+            expand_numerals: false,
+            locale: Locale::En,
+            speak_locale: None,
+            acronyms: None,
+            spell_acronyms: false,
+            spell_patterns: Vec::new(),
+        }
+    }
+}
+
+/// Builds the chain of filters enabled by the options, followed by the ones
+/// configured in the config file, in the order they're listed
+///
+/// Configured filters without a locale or a speak locale of their own use
+/// the ones of the options.
+pub fn chain(options: &TextOptions, configured: &[FilterConfig]) -> Result<Chain> {
+    let mut chain = Chain::default();
+    if options.normalize {
+        chain.push(Unicode);
+    }
+    if options.dehyphenate {
+        chain.push(Dehyphenate);
+    }
+    if options.strip_citations {
+        chain.push(StripCitations);
+    }
+    chain.push(Typography);
+    if let Some(path) = &options.rules {
+        chain.push(Rules::load(path)?);
+    }
+    // spelled before anything rewrites the digits and letters of the tokens
+    chain.push(Spell::new(&options.spell_patterns)?);
+    // read before numerals, so that dates are read whole rather than by parts
+    if let (true, Locale::En, Some(speak_locale)) =
+        (options.normalize, options.locale, options.speak_locale)
+    {
+        chain.push(Dates(speak_locale));
+    }
+    if options.expand_numerals || options.normalize {
+        chain.push(Numerals(options.locale));
+    }
+    if options.normalize {
+        chain.push(Normalize {
+            locale: options.locale,
+            speak_locale: options.speak_locale.unwrap_or_default(),
+        });
+    }
+    if let Some(acronyms) = load_acronyms(options.acronyms.as_deref(), options.spell_acronyms)? {
+        chain.push(acronyms);
+    }
+
+    for filter in configured {
+        match filter {
+            FilterConfig::Normalize {
+                locale,
+                speak_locale,
+            } => {
+                let locale = locale.unwrap_or(options.locale);
+                let speak_locale = speak_locale.or(options.speak_locale);
+                chain.push(Unicode);
+                if let (Locale::En, Some(speak_locale)) = (locale, speak_locale) {
+                    chain.push(Dates(speak_locale));
+                }
+                chain.push(Normalize {
+                    locale,
+                    speak_locale: speak_locale.unwrap_or_default(),
+                });
+            }
+            FilterConfig::Numerals { locale } => {
+                chain.push(Numerals(locale.unwrap_or(options.locale)))
+            }
+            FilterConfig::Rules { path } => chain.push(Rules::load(path)?),
+            FilterConfig::Regex {
+                pattern,
+                replacement,
+            } => chain.push(Rules::from_rule(pattern, replacement)?),
+            FilterConfig::Lexicon { path } => chain.push(Lexicon::load(path)?),
+            FilterConfig::Acronyms { path, spell } => {
+                if let Some(acronyms) = load_acronyms(path.as_deref(), *spell)? {
+                    chain.push(acronyms);
+                }
+            }
+            FilterConfig::Markdown => chain.push(Markdown),
+            FilterConfig::Emoji => chain.push(Emoji),
+            FilterConfig::Command { command, args } => {
+                chain.push(Plugin::new(command.clone(), args.clone()))
+            }
+        }
+    }
+    Ok(chain)
+}
+
+fn load_acronyms(path: Option<&Path>, spell: bool) -> Result<Option<Acronyms>> {
+    let mut acronyms = match path {
+        Some(path) => Acronyms::load(path)?,
+        None if spell => Acronyms::default(),
+        None => return Ok(None),
+    };
+    acronyms.set_spell(spell);
+    Ok(Some(acronyms))
+}
+
+#[derive(Debug)]
+struct Unicode;
+
+impl TextFilter for Unicode {
+    fn name(&self) -> &str {
+        "unicode"
+    }
+
+    fn apply(&self, text: &str) -> Result<String> {
+        Ok(preprocess::normalize_unicode(text))
+    }
+}
+
+#[derive(Debug)]
+struct Dehyphenate;
+
+impl TextFilter for Dehyphenate {
+    fn name(&self) -> &str {
+        "dehyphenate"
+    }
+
+    fn apply(&self, text: &str) -> Result<String> {
+        Ok(preprocess::dehyphenate(text))
+    }
+}
+
+#[derive(Debug)]
+struct StripCitations;
+
+impl TextFilter for StripCitations {
+    fn name(&self) -> &str {
+        "citations"
+    }
+
+    fn apply(&self, text: &str) -> Result<String> {
+        Ok(preprocess::strip_citations(text))
+    }
+}
+
+#[derive(Debug)]
+struct Typography;
+
+impl TextFilter for Typography {
+    fn name(&self) -> &str {
+        "typography"
+    }
+
+    fn apply(&self, text: &str) -> Result<String> {
+        Ok(preprocess::normalize_typography(text))
+    }
+}
+
+#[derive(Debug)]
+struct Numerals(Locale);
+
+impl TextFilter for Numerals {
+    fn name(&self) -> &str {
+        "numerals"
+    }
+
+    fn apply(&self, text: &str) -> Result<String> {
+        Ok(preprocess::expand_numerals(text, self.0))
+    }
+}
+
+/// Reads out numeric dates, for English text
+#[derive(Debug)]
+struct Dates(SpeakLocale);
+
+impl TextFilter for Dates {
+    fn name(&self) -> &str {
+        "dates"
+    }
+
+    fn apply(&self, text: &str) -> Result<String> {
+        Ok(preprocess::expand_dates(text, self.0))
+    }
+}
+
+/// Expands abbreviations, as well as numbers for English text
+#[derive(Debug)]
+struct Normalize {
+    locale: Locale,
+    speak_locale: SpeakLocale,
+}
+
+impl TextFilter for Normalize {
+    fn name(&self) -> &str {
+        "normalize"
+    }
+
+    fn apply(&self, text: &str) -> Result<String> {
+        let text = preprocess::expand_abbreviations(text, self.locale);
+        Ok(match self.locale {
+            Locale::En => preprocess::expand_numbers(&text, self.speak_locale),
+            _ => text,
+        })
+    }
+}
+
+impl TextFilter for Rules {
+    fn name(&self) -> &str {
+        "rules"
+    }
+
+    fn apply(&self, text: &str) -> Result<String> {
+        Ok(Rules::apply(self, text))
+    }
+}
+
+impl TextFilter for Acronyms {
+    fn name(&self) -> &str {
+        "acronyms"
+    }
+
+    fn apply(&self, text: &str) -> Result<String> {
+        Ok(Acronyms::apply(self, text))
+    }
+}
+
+/// Spells out tokens character by character, both ones marked with a
+/// `[spell:<token>]` directive and ones matching any of the patterns
+#[derive(Debug)]
+struct Spell {
+    patterns: Vec<Regex>,
+}
+
+impl Spell {
+    fn new(patterns: &[String]) -> Result<Self> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern).with_context(|| format!("parsing spell pattern '{}'", pattern))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { patterns })
+    }
+}
+
+impl TextFilter for Spell {
+    fn name(&self) -> &str {
+        "spell"
+    }
+
+    fn apply(&self, text: &str) -> Result<String> {
+        let mut text = preprocess::expand_spell_directives(text);
+        for pattern in &self.patterns {
+            text = pattern
+                .replace_all(&text, |caps: &Captures| preprocess::spell_out(&caps[0]))
+                .into_owned();
+        }
+        Ok(text)
+    }
+}
+
+/// Pronunciations of words flite gets wrong, e.g. names
+///
+/// The lexicon file holds one `<word> => <pronunciation>` line per word,
+/// with the pronunciation spelled the way flite reads it right, e.g.
+/// `Nguyen => win`. Words are matched whole and regardless of case.
+#[derive(Debug)]
+pub struct Lexicon {
+    words: HashMap<String, String>,
+    pattern: Option<Regex>,
+}
+
+impl Lexicon {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let words: HashMap<_, _> = preprocess::read_mappings(path, "lexicon")?
+            .into_iter()
+            .map(|(_, word, pronunciation)| (word.to_lowercase(), pronunciation))
+            .collect();
+
+        log::info!("Loaded {} words from '{}'", words.len(), path.display());
+
+        let alternatives: Vec<_> = words.keys().map(|word| regex::escape(word)).collect();
+        let pattern = if alternatives.is_empty() {
+            None
+        } else {
+            Some(
+                Regex::new(&format!(r"(?i)\b(?:{})\b", alternatives.join("|")))
+                    .expect("valid lexicon regex"),
+            )
+        };
+
+        Ok(Self { words, pattern })
+    }
+}
+
+impl TextFilter for Lexicon {
+    fn name(&self) -> &str {
+        "lexicon"
+    }
+
+    fn apply(&self, text: &str) -> Result<String> {
+        Ok(match &self.pattern {
+            Some(pattern) => pattern
+                .replace_all(text, |caps: &Captures| {
+                    self.words
+                        .get(&caps[0].to_lowercase())
+                        .cloned()
+                        .unwrap_or_else(|| caps[0].to_owned())
+                })
+                .into_owned(),
+            None => text.to_owned(),
+        })
+    }
+}
+
+/// Strips Markdown syntax, e.g. from text embedded in other formats
+#[derive(Debug)]
+struct Markdown;
+
+impl TextFilter for Markdown {
+    fn name(&self) -> &str {
+        "markdown"
+    }
+
+    fn apply(&self, text: &str) -> Result<String> {
+        Ok(markdown::sections(text).join("\n\n"))
+    }
+}
+
+#[derive(Debug)]
+struct Emoji;
+
+impl TextFilter for Emoji {
+    fn name(&self) -> &str {
+        "emoji"
+    }
+
+    fn apply(&self, text: &str) -> Result<String> {
+        Ok(preprocess::verbalize_emoji(text))
+    }
+}
@@ -0,0 +1,331 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Queries of the Golem node beyond what gwasm-api exposes
+///
+/// gwasm-api only lets us submit a task and wait for its results, so any
+/// further interaction with the node goes through `golemcli`, which is
+/// installed alongside it and speaks the node's RPC for us.
+#[derive(Debug, Clone)]
+pub struct Golemcli {
+    program: PathBuf,
+    datadir: PathBuf,
+    address: String,
+    port: u16,
+    mainnet: bool,
+    call_timeout: Option<Duration>,
+}
+
+/// Task as reported by the node
+#[derive(Debug, Clone, Deserialize)]
+pub struct TaskInfo {
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub status: String,
+    /// Computation progress, in percent
+    #[serde(default, deserialize_with = "deserialize_amount")]
+    pub progress: Option<f64>,
+    /// Estimated cost in wei
+    #[serde(default, deserialize_with = "deserialize_amount")]
+    pub estimated_cost: Option<f64>,
+    /// Estimated transaction fee in wei
+    #[serde(default, deserialize_with = "deserialize_amount")]
+    pub estimated_fee: Option<f64>,
+}
+
+/// Subtask as reported by the node
+#[derive(Debug, Clone, Deserialize)]
+pub struct SubtaskInfo {
+    pub subtask_id: String,
+    #[serde(default)]
+    pub node_name: Option<String>,
+    #[serde(default)]
+    pub node_id: Option<String>,
+    #[serde(default)]
+    pub status: String,
+    /// Computation progress, in percent
+    #[serde(default, deserialize_with = "deserialize_amount")]
+    pub progress: Option<f64>,
+    /// SHA-256 checksums of the result files by their paths relative to the
+    /// task's output dir, e.g. `subtask_00000/out.wav`, for nodes which
+    /// report them
+    #[serde(default)]
+    pub checksums: BTreeMap<String, String>,
+    /// Data the subtask was defined with in the task
+    #[serde(default)]
+    pub extra_data: SubtaskData,
+}
+
+/// Subtask's definition as reported by the node
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SubtaskData {
+    /// Name the subtask was defined under, e.g. `subtask_00000`
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+impl SubtaskInfo {
+    /// Returns name the subtask was defined under in the task, which its
+    /// input and output dirs are named after
+    ///
+    /// The node lists subtasks as they get assigned, and a resent or
+    /// restarted subtask comes back as a new entry with an ID of its own, so
+    /// the name is what tells which of the described subtasks an entry is.
+    /// Nodes which don't report it are still known to have saved results to
+    /// the dir named after the subtask.
+    pub fn name(&self) -> Option<&str> {
+        self.extra_data
+            .name
+            .as_deref()
+            .or_else(|| self.checksums.keys().next()?.split('/').next())
+    }
+
+    /// Returns human readable name of the provider computing the subtask
+    pub fn provider(&self) -> Option<&str> {
+        self.node_name
+            .as_ref()
+            .filter(|name| !name.is_empty())
+            .or(self.node_id.as_ref())
+            .map(String::as_str)
+    }
+}
+
+/// Gas used by a batch payment transaction, regardless of the number of payees
+const BATCH_PAYMENT_GAS: u64 = 30_000;
+/// Gas used by a batch payment transaction per each payee
+const PAYMENT_GAS: u64 = 30_000;
+/// Both ETH and GNT have 18 decimals
+pub const WEI_PER_ETH: f64 = 1e18;
+
+/// Estimates ETH spent on transaction fees when paying for given number of subtasks
+///
+/// Providers are paid in batches, so this is an upper bound assuming every
+/// subtask is paid to a different provider in a batch of its own.
+pub fn estimate_fee(gas_price_wei: f64, subtasks: u64) -> f64 {
+    let gas = subtasks * (BATCH_PAYMENT_GAS + PAYMENT_GAS);
+    gas as f64 * gas_price_wei / WEI_PER_ETH
+}
+
+/// Funds available to the node for paying for tasks
+#[derive(Debug, Clone, Copy)]
+pub struct Balance {
+    pub gnt: f64,
+    pub eth: f64,
+}
+
+impl fmt::Display for Balance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} GNT, {} ETH", self.gnt, self.eth)
+    }
+}
+
+/// Parses amount formatted by golemcli, e.g. `1000.000000 GNT`
+fn parse_amount(value: &serde_json::Value) -> Option<f64> {
+    match value {
+        serde_json::Value::Number(number) => number.as_f64(),
+        serde_json::Value::String(amount) => amount.split_whitespace().next()?.parse().ok(),
+        _ => None,
+    }
+}
+
+fn deserialize_amount<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    serde_json::Value::deserialize(deserializer).map(|value| parse_amount(&value))
+}
+
+impl Golemcli {
+    pub fn new<P: AsRef<Path>, S: Into<String>>(
+        program: Option<PathBuf>,
+        datadir: P,
+        address: S,
+        port: u16,
+        mainnet: bool,
+    ) -> Self {
+        Self {
+            program: program.unwrap_or_else(|| PathBuf::from("golemcli")),
+            datadir: datadir.as_ref().to_path_buf(),
+            address: address.into(),
+            port,
+            mainnet,
+            call_timeout: None,
+        }
+    }
+
+    /// Sets time for each call to return, after which golemcli is killed
+    pub fn call_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.call_timeout = timeout;
+        self
+    }
+
+    /// Runs golemcli command returning its output
+    fn run(&self, args: &[&str]) -> Result<Vec<u8>> {
+        let mut command = Command::new(&self.program);
+        command
+            .arg("--datadir")
+            .arg(&self.datadir)
+            .arg("--address")
+            .arg(&self.address)
+            .arg("--port")
+            .arg(self.port.to_string())
+            .arg("--json");
+        if self.mainnet {
+            command.arg("--mainnet");
+        }
+        command.args(args);
+
+        log::debug!("Running {:?}", command);
+        let output = match self.call_timeout {
+            Some(timeout) => output_within(&mut command, timeout),
+            None => command.output().map_err(anyhow::Error::from),
+        }
+        .with_context(|| format!("running '{} {}'", self.program.display(), args.join(" ")))?;
+        if !output.status.success() {
+            bail!(
+                "'golemcli {}' exited with {}: {}",
+                args.join(" "),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        log::trace!(
+            "'golemcli {}' returned {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stdout).trim()
+        );
+        Ok(output.stdout)
+    }
+
+    /// Runs golemcli command parsing its JSON output
+    fn call<T: DeserializeOwned>(&self, args: &[&str]) -> Result<T> {
+        serde_json::from_slice(&self.run(args)?)
+            .with_context(|| format!("parsing output of 'golemcli {}'", args.join(" ")))
+    }
+
+    pub fn tasks(&self) -> Result<Vec<TaskInfo>> {
+        self.call(&["tasks", "show"])
+    }
+
+    /// Finds the most recently listed task of the given name
+    pub fn find_task(&self, name: &str) -> Result<TaskInfo> {
+        self.tasks()?
+            .into_iter()
+            .rev()
+            .find(|task| task.name == name)
+            .ok_or_else(|| anyhow!("no task named '{}' found on the node", name))
+    }
+
+    pub fn subtasks(&self, task_id: &str) -> Result<Vec<SubtaskInfo>> {
+        self.call(&["tasks", "subtasks", task_id])
+    }
+
+    /// Finds task of the given ID
+    pub fn task(&self, task_id: &str) -> Result<TaskInfo> {
+        self.tasks()?
+            .into_iter()
+            .find(|task| task.id == task_id)
+            .ok_or_else(|| anyhow!("no task with ID '{}' found on the node", task_id))
+    }
+
+    /// Deletes the task along with its results from the node
+    pub fn delete_task(&self, task_id: &str) -> Result<()> {
+        self.run(&["tasks", "delete", task_id]).map(|_| ())
+    }
+
+    pub fn abort_task(&self, task_id: &str) -> Result<()> {
+        self.run(&["tasks", "abort", task_id]).map(|_| ())
+    }
+
+    pub fn restart_subtask(&self, subtask_id: &str) -> Result<()> {
+        self.run(&["subtasks", "restart", subtask_id]).map(|_| ())
+    }
+
+    /// Returns version of Golem the node runs
+    pub fn node_version(&self) -> Result<serde_json::Value> {
+        self.call(&["debug", "rpc", "golem.version"])
+    }
+
+    /// Returns number of peers the node is connected to
+    pub fn peers(&self) -> Result<usize> {
+        let peers: Vec<serde_json::Value> = self.call(&["network", "show"])?;
+        Ok(peers.len())
+    }
+
+    /// Returns funds available to the node
+    pub fn balance(&self) -> Result<Balance> {
+        let info: serde_json::Value = self.call(&["account", "info"])?;
+        let finances = &info["finances"];
+        let amount = |key: &str| {
+            parse_amount(&finances[key])
+                .ok_or_else(|| anyhow!("no '{}' in 'golemcli account info' output", key))
+        };
+        Ok(Balance {
+            gnt: amount("gnt_available")?,
+            eth: amount("eth_available")?,
+        })
+    }
+
+    /// Returns gas price in wei the node currently pays for transactions
+    pub fn gas_price(&self) -> Result<f64> {
+        let prices: serde_json::Value = self.call(&["debug", "rpc", "pay.gas_price"])?;
+        parse_amount(&prices["current_gas_price"])
+            .ok_or_else(|| anyhow!("no 'current_gas_price' in 'pay.gas_price' response"))
+    }
+
+    /// Returns the node's raw payment records
+    pub fn payments(&self) -> Result<Vec<serde_json::Value>> {
+        self.call(&["payments"])
+    }
+}
+
+/// Runs the command collecting its output like `Command::output`, but kills
+/// it if it doesn't exit within the timeout
+fn output_within(command: &mut Command, timeout: Duration) -> Result<Output> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // drained from threads of their own so that the child can't block on a
+    // full pipe while it's being waited on
+    let drain = |pipe: Option<Box<dyn Read + Send>>| {
+        thread::spawn(move || {
+            let mut output = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut output);
+            }
+            output
+        })
+    };
+    let stdout = drain(child.stdout.take().map(|pipe| Box::new(pipe) as _));
+    let stderr = drain(child.stderr.take().map(|pipe| Box::new(pipe) as _));
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("no answer within {}", humantime::format_duration(timeout));
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
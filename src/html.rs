@@ -0,0 +1,189 @@
+use regex::{Captures, Regex};
+
+/// Elements dropped along with their content wherever they appear
+const DROPPED: &[&str] = &[
+    "head", "script", "style", "noscript", "template", "svg", "iframe", "form", "button", "select",
+];
+/// Page furniture dropped unless the page marks up its main content
+const FURNITURE: &[&str] = &["nav", "header", "footer", "aside"];
+/// Elements starting a new paragraph
+const BLOCKS: &[&str] = &[
+    "p",
+    "div",
+    "section",
+    "article",
+    "main",
+    "blockquote",
+    "pre",
+    "ul",
+    "ol",
+    "dl",
+    "dd",
+    "dt",
+    "table",
+    "tr",
+    "figure",
+    "figcaption",
+    "hr",
+];
+
+/// Extracts readable text from HTML page
+///
+/// Scripts, styles and the like are dropped along with all tags. If the
+/// page marks up its `<article>` or `<main>` content, only that is kept;
+/// otherwise navigation, headers, footers and asides are dropped. Headings
+/// are kept as paragraphs of their own, ending with a full stop so that
+/// flite pauses after them.
+pub fn extract(contents: &str) -> String {
+    extract_text(contents, true)
+}
+
+/// Extracts text from HTML document making up a chapter of a book, e.g. of
+/// an EPUB one, which is all content, so no page furniture is dropped
+pub fn extract_chapter(contents: &str) -> String {
+    extract_text(contents, false)
+}
+
+fn extract_text(contents: &str, page: bool) -> String {
+    let comments =
+        Regex::new(r"(?s)<!--.*?-->|<!\[CDATA\[.*?\]\]>|<![^>]*>").expect("valid comment regex");
+    let mut html = comments.replace_all(contents, " ").into_owned();
+
+    for name in DROPPED {
+        html = drop_element(&html, name);
+    }
+
+    let main = if page { main_content(&html) } else { None };
+    html = match main {
+        Some(main) => main,
+        None => {
+            let body = Regex::new(r"(?is)<body\b[^>]*>(.*)</body\s*>").expect("valid body regex");
+            let mut html = body
+                .captures(&html)
+                .map(|caps| caps[1].to_owned())
+                .unwrap_or(html);
+            if page {
+                for name in FURNITURE {
+                    html = drop_element(&html, name);
+                }
+            }
+            html
+        }
+    };
+
+    let headings =
+        Regex::new(r"(?is)<h[1-6]\b[^>]*>(.*?)</h[1-6]\s*>").expect("valid heading regex");
+    let html = headings.replace_all(&html, |caps: &Captures| {
+        let heading = strip_tags(&caps[1]);
+        let heading = heading.trim();
+        if heading.is_empty() || heading.ends_with(|c: char| c.is_ascii_punctuation()) {
+            format!("\n\n{}\n\n", heading)
+        } else {
+            format!("\n\n{}.\n\n", heading)
+        }
+    });
+
+    let blocks = Regex::new(&format!(r"(?i)</?(?:{})\b[^>]*>", BLOCKS.join("|")))
+        .expect("valid block regex");
+    let html = blocks.replace_all(&html, "\n\n");
+    let lines = Regex::new(r"(?i)<(?:br|li)\b[^>]*>").expect("valid line break regex");
+    let html = lines.replace_all(&html, "\n");
+    let cells = Regex::new(r"(?i)</t[dh]\s*>").expect("valid cell regex");
+    let html = cells.replace_all(&html, ", ");
+
+    // soft hyphens would only get in the way of flite
+    let text = decode_entities(&strip_tags(&html)).replace('\u{ad}', "");
+
+    // collapse the whitespace left behind by the markup
+    let spaces = Regex::new(r"[ \t\u{a0}]+").expect("valid spaces regex");
+    let text = spaces.replace_all(&text, " ");
+    let text = text.lines().map(str::trim).collect::<Vec<_>>().join("\n");
+    let paragraphs = Regex::new(r"\n{3,}").expect("valid paragraphs regex");
+    paragraphs.replace_all(&text, "\n\n").trim().to_owned()
+}
+
+/// Removes all occurrences of the element along with its content
+fn drop_element(html: &str, name: &str) -> String {
+    let element = Regex::new(&format!(
+        r"(?is)<{0}\b[^>]*?/>|<{0}\b[^>]*>.*?</{0}\s*>",
+        name
+    ))
+    .expect("valid element regex");
+    element.replace_all(html, " ").into_owned()
+}
+
+/// Returns content of the page's articles, or its main element
+fn main_content(html: &str) -> Option<String> {
+    let articles =
+        Regex::new(r"(?is)<article\b[^>]*>(.*?)</article\s*>").expect("valid article regex");
+    let content: Vec<_> = articles
+        .captures_iter(html)
+        .map(|caps| caps[1].to_owned())
+        .collect();
+    if !content.is_empty() {
+        return Some(content.join("\n\n"));
+    }
+
+    let main = Regex::new(r"(?is)<main\b[^>]*>(.*)</main\s*>").expect("valid main regex");
+    main.captures(html).map(|caps| caps[1].to_owned())
+}
+
+fn strip_tags(html: &str) -> String {
+    let tags = Regex::new(r"<[^>]*>").expect("valid tag regex");
+    tags.replace_all(html, "").into_owned()
+}
+
+/// Decodes character references and the named entities common in text
+fn decode_entities(text: &str) -> String {
+    let entity =
+        Regex::new(r"&(#[xX][0-9A-Fa-f]+|#\d+|[A-Za-z]+\d*);").expect("valid entity regex");
+    entity
+        .replace_all(text, |caps: &Captures| {
+            let reference = &caps[1];
+            let code = if let Some(hex) = reference
+                .strip_prefix("#x")
+                .or(reference.strip_prefix("#X"))
+            {
+                u32::from_str_radix(hex, 16).ok()
+            } else if let Some(decimal) = reference.strip_prefix('#') {
+                decimal.parse().ok()
+            } else {
+                named_entity(reference).map(u32::from)
+            };
+            code.and_then(std::char::from_u32)
+                .map_or_else(|| caps[0].to_owned(), String::from)
+        })
+        .into_owned()
+}
+
+fn named_entity(name: &str) -> Option<char> {
+    let c = match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "ndash" => '–',
+        "mdash" => '—',
+        "hellip" => '…',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "laquo" => '«',
+        "raquo" => '»',
+        "shy" => '\u{ad}',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        "deg" => '°',
+        "euro" => '€',
+        "pound" => '£',
+        "times" => '×',
+        "middot" => '·',
+        "bull" => '•',
+        _ => return None,
+    };
+    Some(c)
+}
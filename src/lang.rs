@@ -0,0 +1,83 @@
+/// Most frequent function words of the languages recognized by `detect`
+const STOPWORDS: &[(&str, &[&str])] = &[
+    (
+        "en",
+        &[
+            "the", "and", "of", "to", "in", "is", "that", "it", "was", "for", "with", "as", "his",
+            "he", "be", "at", "by", "this", "had", "not",
+        ],
+    ),
+    (
+        "es",
+        &[
+            "de", "la", "que", "el", "en", "y", "los", "se", "del", "las", "un", "por", "con",
+            "no", "una", "su", "para", "es", "al", "lo",
+        ],
+    ),
+    (
+        "de",
+        &[
+            "der", "die", "und", "in", "den", "von", "zu", "das", "mit", "sich", "des", "auf",
+            "für", "ist", "im", "dem", "nicht", "ein", "eine", "als",
+        ],
+    ),
+    (
+        "fr",
+        &[
+            "de", "la", "le", "et", "les", "des", "en", "un", "du", "une", "que", "est", "pour",
+            "qui", "dans", "par", "plus", "pas", "au", "sur",
+        ],
+    ),
+    (
+        "it",
+        &[
+            "di", "e", "il", "la", "che", "in", "a", "per", "un", "è", "del", "non", "della", "le",
+            "si", "una", "con", "dei", "gli", "da",
+        ],
+    ),
+    (
+        "pt",
+        &[
+            "de", "a", "o", "que", "e", "do", "da", "em", "um", "para", "é", "com", "não", "uma",
+            "os", "no", "se", "na", "por", "mais",
+        ],
+    ),
+];
+
+/// Minimum share of stopwords among all words for the detection to be trusted
+const MIN_STOPWORD_SHARE: f64 = 0.05;
+
+/// Detects language of the text returning its ISO 639-1 code
+///
+/// The detection is based on frequencies of the most common function words,
+/// which is crude but works well enough on chunks of prose. Returns `None`
+/// if the text doesn't resemble any of the recognized languages.
+pub fn detect(text: &str) -> Option<&'static str> {
+    let words: Vec<String> = text
+        .split_whitespace()
+        .map(|word| {
+            word.trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase()
+        })
+        .collect();
+    if words.is_empty() {
+        return None;
+    }
+
+    let (language, hits) = STOPWORDS
+        .iter()
+        .map(|(language, stopwords)| {
+            let hits = words
+                .iter()
+                .filter(|word| stopwords.contains(&word.as_str()))
+                .count();
+            (*language, hits)
+        })
+        .max_by_key(|&(_, hits)| hits)?;
+
+    if (hits as f64 / words.len() as f64) < MIN_STOPWORD_SHARE {
+        return None;
+    }
+
+    Some(language)
+}
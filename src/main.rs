@@ -1,30 +1,955 @@
 mod app;
+mod archive;
+mod assets;
+mod atomic;
+mod audio;
+mod batch;
+mod cache;
+mod chunks;
+mod combine;
+mod compress;
+mod config;
+mod currency;
+mod encode;
+mod encoding;
+mod epub;
+mod events;
+mod failure;
+mod filter;
+mod golem;
+mod html;
+mod lang;
+mod markdown;
+mod notify;
+mod plugin;
+mod postprocess;
+mod preprocess;
+mod prompt;
+mod qa;
+mod quarantine;
+mod recombine;
+mod report;
+mod rpclog;
+mod run;
+mod session;
+mod split;
+mod ssml;
+mod submit;
+mod task;
+mod tasks;
+mod telemetry;
+mod timing;
+mod toc;
+mod usage;
+mod verify;
+mod version;
+mod voice;
+mod watch;
+mod watchdog;
 
 use app::App;
-use colored::Colorize;
+use audio::{BitDepth, Layout};
+use cache::Size;
+use encode::{Format, Quality};
+use encoding::Encoding;
 use env_logger::{Builder, Env};
 use gwasm_api::prelude::Timeout;
-use std::{convert::TryInto, path::PathBuf};
+use preprocess::{Locale, SpeakLocale};
+use quarantine::Placeholder;
+use split::{InputFormat, LineNames, SplitBy};
+use std::{convert::TryInto, path::PathBuf, process, time::Duration};
 use structopt::StructOpt;
+use toc::TocMode;
+use voice::{Voice, VoiceRoute};
 
-#[derive(Debug, StructOpt)]
+#[derive(Debug, Clone, StructOpt)]
 #[structopt(
     name = "g_flite",
     author = "Golem RnD Team <contact@golem.network>",
     about = "flite, a text-to-speech program, distributed over Golem network"
 )]
 struct Opt {
-    /// Input text file
+    /// Input text file or URL, or `-` to read the text from stdin
     #[structopt(parse(from_os_str))]
-    input: PathBuf,
+    input: Option<PathBuf>,
 
     /// Output WAV file
     #[structopt(parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// Sets output formats, e.g. `wav,mp3,ogg,opus`
+    ///
+    /// All requested formats are produced in one pass over the synthesized
+    /// audio. Each file is named after the output file with the extension
+    /// swapped for the format's one. `ogg` holds Vorbis and `opus` Opus, both
+    /// in an Ogg container, while `m4b` is an audiobook holding AAC with
+    /// chapter markers of the input's chapters. Formats other than WAV are
+    /// encoded with `ffmpeg` which therefore needs to be installed and in
+    /// your `PATH`.
+    #[structopt(
+        long = "format",
+        parse(try_from_str),
+        raw(use_delimiter = "true"),
+        default_value = "wav"
+    )]
+    formats: Vec<Format>,
+
+    /// Sets quality of the lossy formats from 0, the smallest files, to 10
+    ///
+    /// Mapped onto each encoder's own scale: Vorbis quality as is, LAME's
+    /// VBR presets for MP3, bitrates from 8 to 96 kbit/s for Opus and from 16
+    /// to 128 kbit/s for AAC. The encoders' defaults are used unless set.
+    #[structopt(long = "quality")]
+    quality: Option<Quality>,
+
+    /// Splits the output into files of at most given duration, e.g. `30m`
+    ///
+    /// The files are numbered after the output, e.g. `book_001.wav`,
+    /// `book_002.wav`, and cut only between chunks, so that no sentence is
+    /// split unless it spans two chunks. With chapters, the chapter
+    /// manifest lists the file each chapter starts in.
+    #[structopt(
+        long = "max-output-duration",
+        parse(try_from_str = "humantime::parse_duration"),
+        raw(
+            conflicts_with_all = r#"&["per_line", "per_chapter", "lrc", "timings", "srt", "qa_report", "append"]"#
+        )
+    )]
+    max_output_duration: Option<Duration>,
+
+    /// Trims silence at the start and the end of each chunk
+    ///
+    /// Chunks are trimmed down to 50 ms of silence around the speech, so
+    /// that the pauses at the joins are even. Use the `trim` audio filter in
+    /// the config file to set the threshold and the silence kept.
+    #[structopt(long = "trim")]
+    trim: bool,
+
+    /// Crossfades consecutive chunks over given duration, e.g. `20ms`
+    ///
+    /// Chunks followed or preceded by silence, e.g. with --gap, are faded
+    /// out or in instead, so that the joins don't click.
+    #[structopt(long = "crossfade", parse(try_from_str = "humantime::parse_duration"))]
+    crossfade: Option<Duration>,
+
+    /// Resamples the output to given rate in Hz, e.g. `44100`
+    ///
+    /// Each chunk is resampled after the audio filters set in the config
+    /// file, so that the output is of this rate whatever flite produced.
+    #[structopt(long = "sample-rate")]
+    sample_rate: Option<u32>,
+
+    /// Sets bit depth of WAV output (8, 16, 24 or 32), 16 unless set
+    ///
+    /// flite synthesizes 16-bit audio, so more bits only add room for
+    /// processing it further, e.g. in a DAW.
+    #[structopt(long = "bit-depth")]
+    bit_depth: Option<BitDepth>,
+
+    /// Sets channel layout of the output (mono, stereo), the synthesized one unless set
+    ///
+    /// flite synthesizes mono audio, which `stereo` duplicates into both
+    /// channels. When chunks are read with different voices, e.g. set by
+    /// directives, each voice is panned to a place of its own instead.
+    #[structopt(long = "channels")]
+    channels: Option<Layout>,
+
+    /// Normalizes loudness of each chunk to given level in dBFS, e.g. `-20`
+    ///
+    /// Chunks computed by different providers may come out at slightly
+    /// different levels. Loudness is measured as RMS level of the speech,
+    /// leaving out pauses, and the gain is limited so that peaks stay below
+    /// -1 dBFS.
+    #[structopt(long = "loudness", raw(allow_hyphen_values = "true"))]
+    loudness: Option<f64>,
+
+    /// Appends the synthesized audio to the output file if it exists
+    ///
+    /// The existing file must have the same sample rate and channels as the
+    /// synthesized audio. Only WAV output can be appended to.
+    #[structopt(long = "append", raw(conflicts_with = r#""per_line""#))]
+    append: bool,
+
+    /// Writes additionally a preview clip of given length, e.g. `30s`
+    ///
+    /// The clip is saved next to the output file with `.preview.wav`
+    /// extension.
+    #[structopt(long = "preview", parse(try_from_str = "humantime::parse_duration"))]
+    preview: Option<Duration>,
+
+    /// Sets offset of the preview clip from the beginning of the audio
+    #[structopt(
+        long = "preview-offset",
+        parse(try_from_str = "humantime::parse_duration"),
+        default_value = "0s"
+    )]
+    preview_offset: Duration,
+
+    /// Writes LRC lyrics file synchronized with the output audio
+    #[structopt(long = "lrc", parse(from_os_str))]
+    lrc: Option<PathBuf>,
+
+    /// Annotates LRC lines with estimated per-word timestamps
+    #[structopt(long = "lrc-words")]
+    lrc_words: bool,
+
+    /// Writes JSON map of where each chunk, and each of its words, starts
+    /// and ends in the output audio
+    ///
+    /// flite doesn't report word timings, so those are estimated from the
+    /// chunk's span the same way as with --lrc-words.
+    #[structopt(long = "timings", parse(from_os_str))]
+    timings: Option<PathBuf>,
+
+    /// Writes SRT subtitles synchronized with the output audio, one cue per
+    /// sentence
+    ///
+    /// Sentences spanning two chunks are split into two cues, and their
+    /// timings within the chunks are estimated as with --lrc-words.
+    #[structopt(long = "srt", parse(from_os_str))]
+    srt: Option<PathBuf>,
+
+    /// Writes QA report listing long silences and clipped sections
+    ///
+    /// Each reported section carries its timestamps in the output audio as
+    /// well as the index of the subtask chunk it originates from, so that
+    /// defective chunks can be found without listening to the entire output.
+    #[structopt(long = "qa-report", parse(from_os_str))]
+    qa_report: Option<PathBuf>,
+
+    /// Sets longest silence not reported as a defect by QA
+    #[structopt(
+        long = "qa-max-silence",
+        parse(try_from_str = "humantime::parse_duration"),
+        default_value = "2s"
+    )]
+    qa_max_silence: Duration,
+
+    /// Sets how the input is split into subtasks (words, sentence)
+    ///
+    /// By default, subtasks are of equal word count, even if that cuts
+    /// sentences in half; `sentence` only splits at sentence boundaries,
+    /// keeping subtasks roughly balanced.
+    #[structopt(long = "split-by", default_value = "words")]
+    split_by: SplitBy,
+
+    /// Sets regular expression matching chapter headings, e.g. `CHAPTER`
+    ///
+    /// The pattern is matched against the beginning of each line of the
+    /// input. Subtasks never span chapter boundaries, so the actual number of
+    /// subtasks may exceed the requested one by up to the number of chapters.
+    #[structopt(long = "chapter-pattern")]
+    chapter_pattern: Option<String>,
+
+    /// Pads each chapter's audio with silence to a multiple of given length, e.g. `1s`
+    #[structopt(
+        long = "chapter-pad",
+        parse(try_from_str = "humantime::parse_duration")
+    )]
+    chapter_pad: Option<Duration>,
+
+    /// Synthesizes spoken table of contents listing where each chapter starts
+    ///
+    /// Either `prepend` the table of contents to the output, or write it as a
+    /// separate `track` named after the output file. Requires chapter
+    /// headings to be detected with `--chapter-pattern`.
+    #[structopt(
+        long = "toc",
+        parse(try_from_str),
+        raw(requires = r#""chapter_pattern""#)
+    )]
+    toc: Option<TocMode>,
+
+    /// Applies normalization rules from file to the input, e.g. to spell out units
+    ///
+    /// Each line of the file holds a single rule in the form
+    /// `<regex> => <spoken form>`, where the spoken form may refer to the
+    /// regex's capture groups, e.g. `v(\d+)\.(\d+)\.(\d+) => version $1 point $2 point $3`.
+    /// Empty lines and lines starting with `#` are ignored.
+    #[structopt(long = "rules", parse(from_os_str))]
+    rules: Option<PathBuf>,
+
+    /// Expands roman numerals, ordinals and year ranges, e.g. `Chapter XIV`
+    #[structopt(long = "expand-numerals")]
+    expand_numerals: bool,
+
+    /// Normalizes the input text before splitting it, implies --expand-numerals
+    ///
+    /// Strips control characters, applies Unicode NFC and expands common
+    /// abbreviations such as `Dr.` or `e.g.`. For English input, numbers are
+    /// spelled out as well, e.g. `1984` as `nineteen eighty-four`.
+    #[structopt(long = "normalize")]
+    normalize: bool,
+
+    /// Sets language of the input text wording is adapted to (en, de, fr, es)
+    #[structopt(long = "locale", default_value = "en")]
+    locale: Locale,
+
+    /// Sets regional conventions numbers and dates are read in, e.g. `de-DE` or `en-GB`
+    ///
+    /// Decides whether decimals are set off with a comma or a point, e.g.
+    /// `3,14` read as `three comma one four`, and whether numeric dates such
+    /// as `04/03/2020` put the day or the month first. Only the reading
+    /// changes, not the voice. Takes effect with --normalize for English
+    /// input, which also reads out numeric dates with this set.
+    #[structopt(long = "speak-locale")]
+    speak_locale: Option<SpeakLocale>,
+
+    /// Expands acronyms found in dictionary file, e.g. `HTTP => hypertext transfer protocol`
+    #[structopt(long = "acronyms", parse(from_os_str))]
+    acronyms: Option<PathBuf>,
+
+    /// Spells all-caps acronyms letter by letter unless found in the dictionary
+    #[structopt(long = "spell-acronyms")]
+    spell_acronyms: bool,
+
+    /// Spells out tokens matching the regex character by character, e.g. `[A-Z]{2}\d{6}`
+    ///
+    /// Meant for serial numbers, postcodes and the like, which are read
+    /// letter by letter and digit by digit, with a short pause after each,
+    /// and with symbols such as `-` or `/` read by name. May be given more
+    /// than once. Single tokens can be marked for spelling in the input with
+    /// `[spell:<token>]` as well, e.g. `[spell:SW1A 2AA]`.
+    #[structopt(long = "spell", raw(number_of_values = "1"))]
+    spell: Vec<String>,
+
+    /// Keeps citations, footnote markers and page numbers in the input
+    ///
+    /// By default, bracketed citations such as `[12]` or `(Smith et al., 2010)`,
+    /// footnote markers and lines holding nothing but a page number are
+    /// removed before synthesis.
+    #[structopt(long = "keep-citations")]
+    keep_citations: bool,
+
+    /// Keeps words hyphenated across line breaks split in the input
+    ///
+    /// By default, words wrapped with a hyphen, as is common in text
+    /// extracted from PDFs, are rejoined before splitting the input.
+    #[structopt(long = "keep-hyphenation")]
+    keep_hyphenation: bool,
+
+    /// Lengthens pauses at dashes set off between words by given duration, e.g. `300ms`
+    ///
+    /// Dashes are read as commas anyway; this inserts extra silence at the
+    /// quietest point near where each dash is estimated to fall in the audio.
+    #[structopt(long = "dash-pause", parse(try_from_str = "humantime::parse_duration"))]
+    dash_pause: Option<Duration>,
+
+    /// Inserts given silence between chunks, e.g. `200ms`
+    ///
+    /// flite clips the trailing breath at the end of each chunk, so joins
+    /// may sound abrupt. Chunks followed by a longer pause anyway, e.g. an
+    /// SSML `<break>`, are left as they are.
+    #[structopt(long = "gap", parse(try_from_str = "humantime::parse_duration"))]
+    gap: Option<Duration>,
+
+    /// Synthesizes every non-empty input line into a file of its own
+    ///
+    /// The output is then treated as a dir the files are written to, named
+    /// after the `--line-names` scheme. Each line is computed in a subtask of
+    /// its own, regardless of `--subtasks`.
+    #[structopt(
+        long = "per-line",
+        raw(
+            conflicts_with_all = r#"&["toc", "lrc", "timings", "srt", "chapter_pattern", "preview", "qa_report"]"#
+        )
+    )]
+    per_line: bool,
+
+    /// Sets naming scheme of files written in per-line mode (counter, words)
+    #[structopt(long = "line-names", default_value = "counter")]
+    line_names: LineNames,
+
+    /// Writes every chapter into a file of its own
+    ///
+    /// The files are written next to the output, named after its stem and
+    /// the chapter's index and title, e.g. `book_03_the_last_voyage.wav`,
+    /// and listed in a `.chapters.json` manifest. Needs EPUB or Markdown
+    /// input, or `--chapter-pattern`.
+    #[structopt(
+        long = "per-chapter",
+        raw(
+            conflicts_with_all = r#"&["per_line", "toc", "lrc", "timings", "srt", "preview", "qa_report", "append", "dash_pause"]"#
+        )
+    )]
+    per_chapter: bool,
+
+    /// Reads each sentence given number of times, e.g. for language drills
+    ///
+    /// Each sentence is computed in a subtask of its own, regardless of
+    /// `--subtasks`, and every reading of it is followed by `--repeat-gap`
+    /// of silence, leaving time to repeat it. SSML input is repeated by
+    /// element rather than by sentence.
+    #[structopt(
+        long = "repeat",
+        raw(conflicts_with_all = r#"&["per_line", "per_chapter"]"#)
+    )]
+    repeat: Option<u32>,
+
+    /// Sets silence following each reading of a sentence with --repeat
+    #[structopt(
+        long = "repeat-gap",
+        parse(try_from_str = "humantime::parse_duration"),
+        default_value = "2s"
+    )]
+    repeat_gap: Duration,
+
+    /// Interleaves the input with its translation from given file, e.g. for language learning
+    ///
+    /// Each non-empty line of the input is followed by the same line of the
+    /// translation, so both files need to have as many lines, usually a
+    /// sentence each. Every line is computed in a subtask of its own.
+    #[structopt(
+        long = "interleave",
+        parse(from_os_str),
+        raw(
+            conflicts_with_all = r#"&["per_line", "per_chapter", "repeat", "chapter_pattern", "watch"]"#
+        )
+    )]
+    interleave: Option<PathBuf>,
+
+    /// Interleaves pairs read from the input, one `<source> TAB <translation>` line each
+    #[structopt(
+        long = "pairs",
+        raw(
+            conflicts_with_all = r#"&["interleave", "per_line", "per_chapter", "repeat", "chapter_pattern", "watch"]"#
+        )
+    )]
+    pairs: bool,
+
+    /// Sets voice the translations are read with when interleaving
+    #[structopt(long = "translation-voice")]
+    translation_voice: Option<Voice>,
+
+    /// Sets silence between a line and its translation when interleaving
+    #[structopt(
+        long = "translation-gap",
+        parse(try_from_str = "humantime::parse_duration"),
+        default_value = "1s"
+    )]
+    translation_gap: Duration,
+
+    /// Sets silence following each pair when interleaving
+    #[structopt(
+        long = "pair-gap",
+        parse(try_from_str = "humantime::parse_duration"),
+        default_value = "2s"
+    )]
+    pair_gap: Duration,
+
+    /// Sets format of the input (text, ssml, markdown, html, epub)
+    ///
+    /// With SSML, `<break>` elements are rendered as silence, `<prosody>`
+    /// rates are passed on to flite and `<say-as>` characters, digits and
+    /// ordinals are spelled out; other markup is dropped, keeping its text.
+    /// With Markdown, its syntax and link URLs are stripped and each header
+    /// starts a chapter. With HTML, the page's readable text is extracted,
+    /// dropping scripts, navigation and the like. With EPUB, each document
+    /// of the book's reading order makes up a chapter, titled after the
+    /// book's table of contents. Inputs ending in `.html` or `.htm` are taken
+    /// for HTML and ones ending in `.epub` for EPUB unless set otherwise,
+    /// others for text.
+    #[structopt(long = "input-format")]
+    input_format: Option<InputFormat>,
+
+    /// Sets encoding of the input (utf-8, latin-1, windows-1252, utf-16, auto)
+    ///
+    /// Inputs are read as UTF-8 unless downloaded with another charset. With
+    /// `auto`, UTF-16 is told by its byte order mark, and text which isn't
+    /// valid UTF-8 is read as Windows-1252.
+    #[structopt(long = "encoding")]
+    encoding: Option<Encoding>,
+
+    /// Aborts before submitting the task if the estimated spoken duration exceeds given one, e.g. `20h`
+    #[structopt(
+        long = "max-duration",
+        parse(try_from_str = "humantime::parse_duration")
+    )]
+    max_duration: Option<Duration>,
+
+    /// Watches given dir, synthesizing every new or changed text file in it
+    ///
+    /// Each `.txt` file is synthesized in a Golem task of its own, with the
+    /// WAVE saved next to it. Runs until killed.
+    #[structopt(
+        long = "watch",
+        parse(from_os_str),
+        raw(conflicts_with_all = r#"&["input", "output", "per_line", "append"]"#)
+    )]
+    watch: Option<PathBuf>,
+
+    /// Records the task's events on the node to `events.log` in the workspace
+    ///
+    /// Subtask assignments, status changes and payments are polled from the
+    /// node using `golemcli`, which has to be installed.
+    #[structopt(long = "event-log")]
+    event_log: bool,
+
+    /// Prepares the task in the workspace without submitting it
+    ///
+    /// The prepared task can be reviewed and then submitted as it is with
+    /// `g_flite submit`.
+    #[structopt(long = "dry-run", raw(requires = r#""workspace""#))]
+    dry_run: bool,
+
+    /// Detects language of each subtask chunk
+    #[structopt(long = "detect-language")]
+    detect_language: bool,
+
+    /// Routes chunks detected as given language to a voice, e.g. `en=slt`
+    ///
+    /// Chunks in languages without a route are synthesized with the default
+    /// voice. The voice may be prefixed with the engine, e.g. `en=flite:slt`,
+    /// although flite is currently the only engine available.
+    #[structopt(
+        long = "voice-route",
+        parse(try_from_str),
+        raw(use_delimiter = "true", requires = r#""detect_language""#)
+    )]
+    voice_routes: Vec<VoiceRoute>,
+
+    #[structopt(flatten)]
+    task: TaskOpt,
+
+    #[structopt(flatten)]
+    node: NodeOpt,
+
+    /// Turns verbose logging on
+    #[structopt(short = "v", long = "verbose")]
+    verbose: bool,
+
+    /// Traces all communication with the Golem node to given file
+    ///
+    /// Logs every RPC call and response, with secrets redacted, which
+    /// helps diagnosing tasks rejected by the node.
+    #[structopt(long = "rpc-debug", parse(from_os_str))]
+    rpc_debug: Option<PathBuf>,
+
+    /// Prints errors on stderr as JSON objects instead of prose
+    ///
+    /// Each error is printed on a single line, holding its `code`, the
+    /// `stage` of the run it occurred at, its `message`, the `context`
+    /// chain of its causes and `advice` on what to do about it.
+    #[structopt(long = "json")]
+    json: bool,
+
+    /// Answers all confirmations, such as the mainnet spend guard, with yes
+    #[structopt(short = "y", long = "yes")]
+    yes: bool,
+
+    /// Fails instead of prompting whenever an answer is needed
+    ///
+    /// Meant for unattended and scripted use; combine with --yes to go
+    /// ahead where a confirmation would be asked.
+    #[structopt(long = "non-interactive")]
+    non_interactive: bool,
+
+    /// Skips checking GitHub for a newer release on startup
+    ///
+    /// The check is made at most once a day; it can be turned off for good
+    /// with `version_check = false` in the config file.
+    #[structopt(long = "no-version-check")]
+    no_version_check: bool,
+
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+enum Command {
+    /// Synthesizes input with several voices for comparison
+    ///
+    /// The input is split only once and the chunks for all the voices are
+    /// computed within a single Golem task. Each voice's output is saved as
+    /// `<input name>.<voice>.wav` in the output dir.
+    #[structopt(name = "compare")]
+    Compare(CompareOpt),
+
+    /// Synthesizes several input files, each into a WAVE file of its own
+    ///
+    /// The chunks of all the inputs are computed within a single Golem task,
+    /// so that the task setup is paid only once, unless listed in a manifest
+    /// with different bids. Each input's output is saved as
+    /// `<input name>.wav` in the output dir.
+    #[structopt(name = "batch")]
+    Batch(BatchOpt),
+
+    /// Lists tasks created by g_flite on the node
+    ///
+    /// Shows their status, progress and estimated cost, as reported by
+    /// `golemcli`, which has to be installed.
+    #[structopt(name = "tasks")]
+    Tasks(TasksOpt),
+
+    /// Removes results of a finished task from the node and the workspace
+    ///
+    /// Only purges tasks whose run's output files are all in place, so that
+    /// nothing is lost. Uses `golemcli`, which has to be installed.
+    #[structopt(name = "purge")]
+    Purge(PurgeOpt),
+
+    /// Combines already computed chunks into the output without synthesizing
+    ///
+    /// Takes a task workspace, an `attempt-<n>` dir within one, or a plain
+    /// dir of WAVE files combined in the order of their names, which lets
+    /// you recover a run manually or mix chunks from several runs.
+    #[structopt(name = "combine")]
+    Combine(CombineOpt),
+
+    /// Submits task prepared with --dry-run as it is and combines its results
+    ///
+    /// Takes the workspace the task was prepared in, or the `attempt-<n>` dir
+    /// holding it.
+    #[structopt(name = "submit")]
+    Submit(SubmitOpt),
+
+    /// Splits input into chunks without synthesizing them
+    ///
+    /// Each chunk is written into a text file of its own, along with a
+    /// `chunks.json` manifest, so that they can be fed into other pipelines.
+    #[structopt(name = "split")]
+    Split(SplitOpt),
+
+    /// Manages assets and run history kept in g_flite's state dir
+    #[structopt(name = "cache")]
+    Cache(CacheCommand),
+
+    /// Bundles a finished run into a single `.tar.zst` archive
+    ///
+    /// The archive holds the run and task manifests, the event log, the text
+    /// of each chunk and the run's output files, which makes it handy for
+    /// bug reports or handing over the result. Archiving the same run twice
+    /// gives the same archive.
+    #[structopt(name = "archive")]
+    Archive(ArchiveOpt),
+
+    /// Verifies that a file is unmodified output of a run
+    ///
+    /// The file is checked against the SHA-256 checksums and chromaprint
+    /// fingerprints of the outputs recorded in the run manifests. A file
+    /// which was re-encoded or remuxed is told apart from one which holds
+    /// different audio, which fails. Fingerprints are computed with `fpcalc`,
+    /// which has to be installed.
+    #[structopt(name = "verify")]
+    Verify(VerifyOpt),
+
+    /// Bundles what's needed to diagnose a run into an archive for a bug report
+    ///
+    /// The archive holds the run's manifest and the error it failed with,
+    /// the task's manifest and event log, versions of g_flite and the node,
+    /// and the config file with URLs, addresses and plugin arguments
+    /// redacted. Neither the text nor the audio of the run are included.
+    #[structopt(name = "report")]
+    Report(ReportOpt),
+
+    /// Shows whether anonymous usage metrics are reported
+    ///
+    /// Reporting is off unless enabled in the `[telemetry]` section of the
+    /// config file. Reports only hold the version, OS, outcome and failure
+    /// class of each run and how many documents, voices and subtasks it
+    /// had, never the text or file names.
+    #[structopt(name = "telemetry")]
+    Telemetry(TelemetryCommand),
+}
+
+#[derive(Debug, Clone, StructOpt)]
+enum TelemetryCommand {
+    /// Shows whether usage metrics are enabled and the last report sent
+    #[structopt(name = "status")]
+    Status,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+enum CacheCommand {
+    /// Lists stored assets and runs, least recently used first
+    ///
+    /// Runs are shown along with the voices they synthesized with and the
+    /// number of subtasks they computed.
+    #[structopt(name = "ls")]
+    Ls(CacheListOpt),
+
+    /// Summarizes what's stored against the size cap
+    #[structopt(name = "stats")]
+    Stats(CacheListOpt),
+
+    /// Evicts least recently used assets and runs over the size cap
+    ///
+    /// The cap is set with `max_size` in the `[cache]` section of the config
+    /// file, 1G unless set. Runs evict over the cap on their own as well.
+    #[structopt(name = "prune")]
+    Prune(PruneOpt),
+
+    /// Seeds the chunk cache with audio synthesized elsewhere or by older runs
+    ///
+    /// The files are matched with the chunks listed in the `chunks.json`
+    /// manifest written by `g_flite split`, by name or else in order. Runs
+    /// synthesizing the same chunks with the same voice reuse them instead
+    /// of sending them to Golem.
+    #[structopt(name = "import")]
+    Import(ImportOpt),
+}
+
+#[derive(Debug, Clone, StructOpt)]
+struct CompareOpt {
+    /// Input text file, or `-` to read the text from stdin
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// Sets voices to compare, e.g. `kal,slt,awb`
+    #[structopt(
+        long = "voices",
+        parse(try_from_str),
+        raw(use_delimiter = "true", required = "true")
+    )]
+    voices: Vec<Voice>,
+
+    /// Sets dir the outputs are saved to
+    #[structopt(long = "out-dir", parse(from_os_str), default_value = ".")]
+    out_dir: PathBuf,
+
+    #[structopt(flatten)]
+    task: TaskOpt,
+
+    #[structopt(flatten)]
+    node: NodeOpt,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+struct BatchOpt {
+    /// Input text files, or `-` to read one of them from stdin
+    #[structopt(parse(from_os_str), raw(required_unless = r#""manifest""#))]
+    inputs: Vec<PathBuf>,
+
+    /// Reads the inputs from a JSON manifest, each with its own bid and budget
+    ///
+    /// The manifest lists jobs such as `{ "input": "a.txt", "bid": 0.5,
+    /// "budget": 3.0 }`, inputs being relative to the manifest. A job's bid
+    /// overrides `--bid`, and its budget caps what its own subtasks may cost.
+    /// Both are checked against the ceilings set in the config file. Jobs
+    /// with different bids are computed in separate Golem tasks, one after
+    /// another.
+    #[structopt(
+        long = "manifest",
+        parse(from_os_str),
+        raw(conflicts_with = r#""inputs""#)
+    )]
+    manifest: Option<PathBuf>,
+
+    /// Sets dir the outputs are saved to
+    #[structopt(long = "out-dir", parse(from_os_str), default_value = ".")]
+    out_dir: PathBuf,
+
+    #[structopt(flatten)]
+    task: TaskOpt,
+
+    #[structopt(flatten)]
+    node: NodeOpt,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+struct TasksOpt {
+    #[structopt(flatten)]
+    node: NodeOpt,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+struct PurgeOpt {
+    /// ID of the task as shown by `g_flite tasks`
+    task_id: String,
+
+    #[structopt(flatten)]
+    node: NodeOpt,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+struct ImportOpt {
+    /// WAVE files of the chunks
+    #[structopt(parse(from_os_str), raw(required = "true"))]
+    files: Vec<PathBuf>,
+
+    /// Chunks manifest written by `g_flite split`
+    #[structopt(long = "manifest", parse(from_os_str))]
+    manifest: PathBuf,
+
+    /// Sets voice the chunks were synthesized with, otherwise the one set by
+    /// their voice directive or flite's default
+    #[structopt(long = "voice")]
+    voice: Option<Voice>,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+struct CacheListOpt {
+    /// Prints JSON instead of a table
+    #[structopt(long = "json")]
+    json: bool,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+struct PruneOpt {
+    /// Overrides the size cap set in the config file, e.g. `200M`
+    #[structopt(long = "max-size")]
+    max_size: Option<Size>,
+
+    /// Lists what would be removed without removing it
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+struct CombineOpt {
+    /// Workspace or dir holding the chunks to combine
+    #[structopt(parse(from_os_str))]
+    dir: PathBuf,
+
+    /// Output file
+    #[structopt(parse(from_os_str))]
+    output: PathBuf,
+
+    /// Sets output formats, e.g. `wav,mp3,ogg,opus`
+    #[structopt(
+        long = "format",
+        parse(try_from_str),
+        raw(use_delimiter = "true"),
+        default_value = "wav"
+    )]
+    formats: Vec<Format>,
+
+    /// Sets quality of the lossy formats from 0, the smallest files, to 10
+    #[structopt(long = "quality")]
+    quality: Option<Quality>,
+
+    /// Inserts given silence between chunks, e.g. `200ms`
+    #[structopt(long = "gap", parse(try_from_str = "humantime::parse_duration"))]
+    gap: Option<Duration>,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+struct ArchiveOpt {
+    /// ID of the run, as listed by `g_flite cache ls`
+    run_id: String,
+
+    /// Archive file, e.g. `run.tar.zst`
+    #[structopt(parse(from_os_str))]
     output: PathBuf,
 
-    /// Sets number of Golem subtasks
-    #[structopt(long = "subtasks", default_value = "6")]
-    subtasks: u64,
+    /// Archives the audio of each chunk as well
+    #[structopt(long = "audio")]
+    audio: bool,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+struct VerifyOpt {
+    /// File to verify
+    #[structopt(parse(from_os_str))]
+    file: PathBuf,
+
+    /// Only checks against the outputs of the run, as listed by `g_flite cache ls`
+    #[structopt(long = "run")]
+    run: Option<String>,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+struct ReportOpt {
+    /// ID of the run, as listed by `g_flite cache ls`
+    run_id: String,
+
+    /// Archive file, `g_flite_report_<run id>.tar.zst` unless set
+    #[structopt(parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// Includes the trace written with --rpc-debug during the run
+    #[structopt(long = "rpc-log", parse(from_os_str))]
+    rpc_log: Option<PathBuf>,
+
+    #[structopt(flatten)]
+    node: NodeOpt,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+struct SubmitOpt {
+    /// Workspace or attempt dir holding the prepared task
+    #[structopt(parse(from_os_str))]
+    dir: PathBuf,
+
+    /// Output file
+    #[structopt(parse(from_os_str))]
+    output: PathBuf,
+
+    /// Sets output formats, e.g. `wav,mp3,ogg,opus`
+    #[structopt(
+        long = "format",
+        parse(try_from_str),
+        raw(use_delimiter = "true"),
+        default_value = "wav"
+    )]
+    formats: Vec<Format>,
+
+    /// Sets quality of the lossy formats from 0, the smallest files, to 10
+    #[structopt(long = "quality")]
+    quality: Option<Quality>,
+
+    /// Inserts given silence between chunks, e.g. `200ms`
+    #[structopt(long = "gap", parse(try_from_str = "humantime::parse_duration"))]
+    gap: Option<Duration>,
+
+    #[structopt(flatten)]
+    node: NodeOpt,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+struct SplitOpt {
+    /// Input text file, or `-` to read the text from stdin
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// Sets number of chunks, picked from the input length unless set
+    #[structopt(long = "subtasks")]
+    subtasks: Option<u64>,
+
+    /// Sets number of words per chunk instead of the number of chunks
+    #[structopt(long = "words-per-subtask", raw(conflicts_with = r#""subtasks""#))]
+    words_per_subtask: Option<u64>,
+
+    /// Sets how the input is split into chunks (words, sentence)
+    #[structopt(long = "split-by", default_value = "words")]
+    split_by: SplitBy,
+
+    /// Sets dir the chunks are written to
+    #[structopt(long = "out-dir", parse(from_os_str), default_value = ".")]
+    out_dir: PathBuf,
+
+    /// Sets regular expression matching chapter headings, e.g. `CHAPTER`
+    #[structopt(long = "chapter-pattern")]
+    chapter_pattern: Option<String>,
+
+    /// Keeps citations, footnote markers and page numbers in the input
+    #[structopt(long = "keep-citations")]
+    keep_citations: bool,
+
+    /// Keeps words hyphenated across line breaks split in the input
+    #[structopt(long = "keep-hyphenation")]
+    keep_hyphenation: bool,
+
+    /// Sets encoding of the input (utf-8, latin-1, windows-1252, utf-16, auto)
+    #[structopt(long = "encoding")]
+    encoding: Option<Encoding>,
+}
+
+/// Options of the Golem task
+#[derive(Debug, Clone, StructOpt)]
+struct TaskOpt {
+    /// Sets number of Golem subtasks, picked from the input length and subtask timeout unless set
+    #[structopt(long = "subtasks")]
+    subtasks: Option<u64>,
+
+    /// Sets number of words per Golem subtask instead of the number of subtasks
+    #[structopt(long = "words-per-subtask", raw(conflicts_with = r#""subtasks""#))]
+    words_per_subtask: Option<u64>,
+
+    /// Sets voice for chunks whose voice isn't set by a directive or a
+    /// language route, e.g. `slt`, otherwise flite's default is used
+    #[structopt(long = "voice")]
+    voice: Option<Voice>,
 
     /// Sets bid value for Golem task
     #[structopt(long = "bid", default_value = "1.0")]
@@ -34,6 +959,35 @@ struct Opt {
     #[structopt(long = "budget")]
     budget: Option<f64>,
 
+    /// Shows costs also in given fiat currency, e.g. `usd`
+    ///
+    /// The exchange rate is fetched from CoinGecko and cached for an hour.
+    /// When it can't be fetched, the last cached rate is used.
+    #[structopt(long = "currency")]
+    currency: Option<String>,
+
+    /// Submits chunks in random order
+    ///
+    /// Subtasks are named and handed out to providers out of the text's
+    /// order, so that no provider can piece together long passages of a
+    /// confidential text. The chunks are combined in their original order
+    /// regardless.
+    #[structopt(long = "shuffle")]
+    shuffle: bool,
+
+    /// Lowers the odds of adjacent chunks reaching the same provider
+    ///
+    /// Golem can't restrict single subtasks to some providers, so subtasks
+    /// assigned to a provider which already got an adjacent chunk are
+    /// restarted instead, for another provider to pick them up. By then the
+    /// provider has received the chunk's text though, so this doesn't keep
+    /// it from seeing adjacent chunks, and any that it did are reported. The
+    /// task is aborted if a subtask keeps landing on such providers.
+    /// Progress is polled from the node using `golemcli`, which has to be
+    /// installed.
+    #[structopt(long = "scatter")]
+    scatter: bool,
+
     /// Sets Golem's task timeout value
     #[structopt(long = "task_timeout", parse(try_from_str), default_value = "00:10:00")]
     task_timeout: Timeout,
@@ -46,17 +1000,66 @@ struct Opt {
     )]
     subtask_timeout: Timeout,
 
-    /// Sets path to Golem datadir
-    #[structopt(long = "datadir", parse(from_os_str))]
-    datadir: Option<PathBuf>,
+    /// Aborts the task if no provider picks up any subtask within given time, e.g. `5m`
+    ///
+    /// Otherwise a task nobody wants at its bid waits for the full task
+    /// timeout. Progress is polled from the node using `golemcli`, which has
+    /// to be installed.
+    #[structopt(
+        long = "assignment-timeout",
+        parse(try_from_str = "humantime::parse_duration")
+    )]
+    assignment_timeout: Option<Duration>,
 
-    /// Sets RPC address to Golem instance
-    #[structopt(long = "address", default_value = "127.0.0.1")]
-    address: String,
+    /// Restarts subtasks which report no progress within given time, e.g. `2m`
+    ///
+    /// This lets another provider pick up a subtask stuck on a misbehaving
+    /// one well before the subtask timeout. Progress is polled from the
+    /// node using `golemcli`, which has to be installed.
+    #[structopt(long = "heartbeat", parse(try_from_str = "humantime::parse_duration"))]
+    heartbeat: Option<Duration>,
 
-    /// Sets RPC port to Golem instance
-    #[structopt(long = "port", default_value = "61000")]
-    port: u16,
+    /// Gives up on subtasks which fail or stall given number of times
+    ///
+    /// Once all other subtasks finish, the task is aborted and the chunks
+    /// given up on are replaced with silence in the output. They are listed
+    /// along with their text and providers in `quarantine.json` in the
+    /// workspace. Progress is polled from the node using `golemcli`, which
+    /// has to be installed.
+    #[structopt(long = "max-failures")]
+    max_failures: Option<u32>,
+
+    /// Waits for given number of distinct providers computing subtasks at once
+    ///
+    /// The task is considered healthy once as many providers are computing
+    /// it, which is noted in the log; a warning is shown if it never gets
+    /// there. Progress is polled from the node using `golemcli`, which has
+    /// to be installed.
+    #[structopt(long = "min-providers")]
+    min_providers: Option<usize>,
+
+    /// Aborts the task if fewer than --min-providers compute it at once within given time, e.g. `10m`
+    #[structopt(
+        long = "providers-timeout",
+        parse(try_from_str = "humantime::parse_duration"),
+        raw(requires = r#""min_providers""#)
+    )]
+    providers_timeout: Option<Duration>,
+
+    /// Sets what replaces chunks given up on in the output (silence, beep, notice)
+    ///
+    /// A notice reading "section missing" is synthesized with flite
+    /// installed locally, falling back to a beep if it isn't. Either is
+    /// padded with silence to the chunk's estimated length.
+    #[structopt(long = "placeholder", default_value = "silence")]
+    placeholder: Placeholder,
+
+    /// Synthesizes all chunks anew instead of reusing cached ones
+    ///
+    /// Chunks synthesized by earlier runs or imported with `g_flite cache
+    /// import` are otherwise reused whenever their text and voice match.
+    #[structopt(long = "no-cache")]
+    no_cache: bool,
 
     /// Sets workspace dir
     ///
@@ -68,23 +1071,116 @@ struct Opt {
     #[structopt(long = "workspace", parse(from_os_str))]
     workspace: Option<PathBuf>,
 
-    /// Turns verbose logging on
-    #[structopt(short = "v", long = "verbose")]
-    verbose: bool,
+    /// Compresses chunk inputs and outputs kept in the workspace with zstd
+    ///
+    /// Once the output is written, the workspace's text and WAVE files are
+    /// replaced with their compressed copies, which `g_flite combine` reads
+    /// just the same.
+    #[structopt(long = "compress-workspace", raw(requires = r#""workspace""#))]
+    compress_workspace: bool,
+}
+
+/// Options of the connection to Golem instance
+#[derive(Debug, Clone, StructOpt)]
+struct NodeOpt {
+    /// Sets path to Golem datadir
+    #[structopt(long = "datadir", parse(from_os_str))]
+    datadir: Option<PathBuf>,
+
+    /// Sets RPC address to Golem instance
+    #[structopt(long = "address", default_value = "127.0.0.1")]
+    address: String,
+
+    /// Sets RPC port to Golem instance
+    #[structopt(long = "port", default_value = "61000")]
+    port: u16,
 
     /// Configures golem-client to use mainnet datadir
     #[structopt(long)]
     mainnet: bool,
+    /// Waits for testnet funds before submitting the task if the node is short of them
+    ///
+    /// Golem nodes running on testnet top themselves up with tGNT and tETH
+    /// from the faucet, which takes a few minutes after the node's first
+    /// start. The balance is checked using `golemcli`, which has to be
+    /// installed.
+    #[structopt(long = "wait-for-funds", raw(conflicts_with = r#""mainnet""#))]
+    wait_for_funds: bool,
 }
 
 fn main() {
-    let opt = Opt::from_args();
+    let mut opt = Opt::from_args();
 
-    if opt.verbose {
-        Builder::from_env(Env::default().default_filter_or("info")).init();
+    let logger = if opt.verbose {
+        Some(Builder::from_env(Env::default().default_filter_or("info")).build())
+    } else {
+        None
+    };
+    match &opt.rpc_debug {
+        Some(path) => {
+            if let Err(e) = rpclog::init(path, logger) {
+                failure::report(&e, opt.json);
+                return;
+            }
+        }
+        None => {
+            if let Some(logger) = logger {
+                log::set_max_level(logger.filter());
+                // can only fail if a logger has already been set
+                log::set_boxed_logger(Box::new(logger)).unwrap();
+            }
+        }
+    }
+
+    if opt.yes {
+        prompt::assume_yes();
+    }
+    if opt.non_interactive {
+        prompt::disable();
     }
+    // nothing gets dropped on Ctrl-C, so outputs being written are cleaned
+    // up here
+    if let Err(e) = ctrlc::set_handler(|| {
+        atomic::remove_pending();
+        process::exit(130);
+    }) {
+        log::debug!("Couldn't set Ctrl-C handler: {}", e);
+    }
+
+    let json = opt.json;
+    // the notice would get in the way of errors printed as JSON
+    if !opt.no_version_check
+        && !json
+        && config::Config::load().map_or(true, |config| config.version_check != Some(false))
+    {
+        version::check();
+    }
+
+    let result = match opt.cmd.take() {
+        Some(Command::Compare(opt)) => opt.try_into().and_then(|app: App| app.run()),
+        Some(Command::Batch(opt)) => {
+            App::batch(opt).and_then(|apps| apps.iter().try_for_each(App::run))
+        }
+        Some(Command::Tasks(opt)) => tasks::list(opt),
+        Some(Command::Purge(opt)) => tasks::purge(opt),
+        Some(Command::Combine(opt)) => recombine::combine(opt),
+        Some(Command::Submit(opt)) => submit::submit(opt),
+        Some(Command::Split(opt)) => chunks::split(opt),
+        Some(Command::Archive(opt)) => archive::archive(opt),
+        Some(Command::Verify(opt)) => verify::verify(opt),
+        Some(Command::Report(opt)) => report::report(opt),
+        Some(Command::Telemetry(TelemetryCommand::Status)) => usage::status(),
+        Some(Command::Cache(CacheCommand::Ls(opt))) => cache::list(opt),
+        Some(Command::Cache(CacheCommand::Stats(opt))) => cache::stats(opt),
+        Some(Command::Cache(CacheCommand::Prune(opt))) => cache::prune_cmd(opt),
+        Some(Command::Cache(CacheCommand::Import(opt))) => cache::import(opt),
+        None => match opt.watch.clone() {
+            Some(dir) => watch::watch(&dir, opt),
+            None => opt.try_into().and_then(|app: App| app.run()),
+        },
+    };
 
-    if let Err(e) = opt.try_into().and_then(|app: App| app.run()) {
-        eprintln!("{}", format!("An error occurred: {}", e).red())
+    if let Err(e) = result {
+        failure::report(&e, json);
     }
 }
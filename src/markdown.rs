@@ -0,0 +1,131 @@
+use regex::Regex;
+
+/// Splits Markdown document into sections starting at headers, stripping
+/// their Markdown syntax
+///
+/// Headers are kept as the first line of their section, ending with a full
+/// stop so that flite pauses after them. Code fences are removed while the
+/// code itself is kept, and links are reduced to their text.
+pub fn sections(contents: &str) -> Vec<String> {
+    let atx_header =
+        Regex::new(r"^\s{0,3}#{1,6}\s+(.*?)(?:\s+#+)?\s*$").expect("valid header regex");
+    let setext_underline = Regex::new(r"^\s{0,3}(?:=+|-+)\s*$").expect("valid underline regex");
+    let fence = Regex::new(r"^\s{0,3}(?:```|~~~)").expect("valid fence regex");
+    let rule = Regex::new(r"^\s{0,3}(?:(?:\*\s*){3,}|(?:-\s*){3,}|(?:_\s*){3,})$")
+        .expect("valid rule regex");
+    let reference = Regex::new(r"^\s{0,3}\[[^\]]+\]:\s+\S+.*$").expect("valid reference regex");
+    let table_separator =
+        Regex::new(r"^\s*\|?(?:\s*:?-+:?\s*\|)+\s*:?-*:?\s*$").expect("valid table regex");
+
+    let lines: Vec<&str> = contents.lines().collect();
+    let mut sections = Vec::new();
+    let mut section: Vec<String> = Vec::new();
+    let mut in_fence = false;
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = lines[i];
+        i += 1;
+
+        if fence.is_match(line) {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            section.push(line.to_owned());
+            continue;
+        }
+
+        let setext = !line.trim().is_empty()
+            && lines
+                .get(i)
+                .filter(|next| setext_underline.is_match(next))
+                .is_some();
+        let header = if setext {
+            i += 1;
+            Some(line.trim())
+        } else {
+            atx_header
+                .captures(line)
+                .map(|caps| caps.get(1).map_or("", |m| m.as_str()))
+        };
+
+        if let Some(header) = header {
+            push_section(&mut sections, &section);
+            section.clear();
+            let header = strip_inline(header);
+            if header.ends_with(|c: char| c.is_ascii_punctuation()) {
+                section.push(header);
+            } else {
+                section.push(format!("{}.", header));
+            }
+            continue;
+        }
+
+        if rule.is_match(line) || reference.is_match(line) || table_separator.is_match(line) {
+            section.push(String::new());
+            continue;
+        }
+        section.push(strip_inline(&strip_block(line)));
+    }
+    push_section(&mut sections, &section);
+
+    log::info!("Markdown input has {} sections", sections.len());
+
+    sections
+}
+
+fn push_section(sections: &mut Vec<String>, lines: &[String]) {
+    let section = lines.join("\n");
+    if section.split_whitespace().next().is_some() {
+        sections.push(section.trim().to_owned());
+    }
+}
+
+/// Strips block markers from the start of the line, i.e. quotes, list items
+/// and table cell borders
+fn strip_block(line: &str) -> String {
+    let quote = Regex::new(r"^\s{0,3}(?:>\s?)+").expect("valid quote regex");
+    let item =
+        Regex::new(r"^\s*(?:[-*+]|\d{1,9}[.)])\s+(?:\[[ xX]\]\s+)?").expect("valid list regex");
+    let line = quote.replace(line, "");
+    let line = item.replace(&line, "");
+
+    let trimmed = line.trim();
+    if trimmed.starts_with('|') || trimmed.ends_with('|') {
+        trimmed
+            .trim_matches('|')
+            .split('|')
+            .map(str::trim)
+            .collect::<Vec<_>>()
+            .join(", ")
+    } else {
+        line.into_owned()
+    }
+}
+
+/// Strips inline syntax, i.e. emphasis, code spans, images, links and HTML
+fn strip_inline(text: &str) -> String {
+    let replacements = [
+        // images and links keep their text only
+        (r"!\[([^\]]*)\]\([^)]*\)", "$1"),
+        (r"!\[([^\]]*)\]\[[^\]]*\]", "$1"),
+        (r"\[([^\]]+)\]\([^)]*\)", "$1"),
+        (r"\[([^\]]+)\]\[[^\]]*\]", "$1"),
+        (r"<(?:https?|ftp|mailto):[^>]*>", ""),
+        (r"</?[A-Za-z][^>]*>", ""),
+        (r"`+([^`]*)`+", "$1"),
+        (r"(\*\*|__)(\S(?:.*?\S)?)(?:\*\*|__)", "$2"),
+        (r"~~(\S(?:.*?\S)?)~~", "$1"),
+        (r"\*(\S(?:[^*]*?\S)?)\*", "$1"),
+        (r"\b_(\S(?:[^_]*?\S)?)_\b", "$1"),
+        (r"\\([\\`*_{}\[\]()#+\-.!|~>])", "$1"),
+    ];
+
+    let mut text = text.to_owned();
+    for (pattern, replacement) in replacements.iter() {
+        let regex = Regex::new(pattern).expect("valid Markdown regex");
+        text = regex.replace_all(&text, *replacement).into_owned();
+    }
+    text
+}
@@ -0,0 +1,367 @@
+use crate::config::{NotifierConfig, WebhookFormat};
+use crate::currency::Rate;
+use anyhow::{anyhow, bail, Context, Result};
+use lettre::smtp::authentication::Credentials;
+use lettre::{ClientSecurity, ClientTlsParameters, SmtpClient, Transport};
+use lettre_email::EmailBuilder;
+use native_tls::TlsConnector;
+use serde::Serialize;
+use std::env;
+use std::fmt;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
+
+/// Environment variable holding the SMTP user name
+const SMTP_USER_ENV: &str = "G_FLITE_SMTP_USER";
+/// Environment variable holding the SMTP password
+const SMTP_PASSWORD_ENV: &str = "G_FLITE_SMTP_PASSWORD";
+/// Implicit TLS SMTP port used unless configured otherwise
+const SMTPS_PORT: u16 = 465;
+
+/// How the run ended
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Success,
+    Failure,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Success => write!(f, "success"),
+            Outcome::Failure => write!(f, "failure"),
+        }
+    }
+}
+
+/// Notification sent when a run ends
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    pub outcome: Outcome,
+    pub run_id: String,
+    pub message: String,
+    pub summary: Summary,
+}
+
+/// Details of the run worth knowing when it ends unattended
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    pub duration_secs: u64,
+    pub bid: f64,
+    pub budget: Option<f64>,
+    pub subtasks: u64,
+    pub estimated_fee_eth: Option<f64>,
+    pub rate: Option<Rate>,
+    pub output_dir: PathBuf,
+    pub outputs: Vec<PathBuf>,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Duration: {}",
+            humantime::format_duration(Duration::from_secs(self.duration_secs))
+        )?;
+        match (self.budget, self.subtasks) {
+            (Some(budget), _) => {
+                writeln!(f, "Cost: at most {} GNT (bid {} GNT)", budget, self.bid)?
+            }
+            (None, 0) => writeln!(f, "Cost: bid {} GNT", self.bid)?,
+            (None, subtasks) => {
+                writeln!(f, "Cost: bid {} GNT for {} subtasks", self.bid, subtasks)?
+            }
+        }
+        if let Some(fee) = self.estimated_fee_eth {
+            writeln!(f, "Transaction fees: at most {:.6} ETH", fee)?;
+        }
+        if let Some(rate) = &self.rate {
+            let cost = self.budget.unwrap_or(self.bid * self.subtasks as f64);
+            let fee = self.estimated_fee_eth.map(|fee| rate.eth(fee));
+            writeln!(
+                f,
+                "Cost in {}: at most {}{} (1 GNT = {} {} as of {})",
+                rate.currency.to_uppercase(),
+                rate.gnt(cost),
+                fee.map(|fee| format!(" plus {} in fees", fee))
+                    .unwrap_or_default(),
+                rate.gnt,
+                rate.currency.to_uppercase(),
+                rate.fetched
+            )?;
+        }
+        writeln!(f, "Output location: {}", self.output_dir.display())?;
+        for output in &self.outputs {
+            writeln!(f, "  {}", output.display())?;
+        }
+        Ok(())
+    }
+}
+
+impl Notification {
+    pub fn title(&self) -> String {
+        match self.outcome {
+            Outcome::Success => format!("g_flite run {} finished", self.run_id),
+            Outcome::Failure => format!("g_flite run {} failed", self.run_id),
+        }
+    }
+
+    /// Renders the template substituting `{title}`, `{outcome}`, `{run_id}`,
+    /// `{message}`, `{duration}`, `{output_dir}` and `{outputs}` placeholders
+    pub fn render(&self, template: &str) -> String {
+        let outputs = self
+            .summary
+            .outputs
+            .iter()
+            .map(|output| output.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let duration =
+            humantime::format_duration(Duration::from_secs(self.summary.duration_secs)).to_string();
+
+        template
+            .replace("{title}", &self.title())
+            .replace("{outcome}", &self.outcome.to_string())
+            .replace("{run_id}", &self.run_id)
+            .replace("{message}", &self.message)
+            .replace("{duration}", &duration)
+            .replace(
+                "{output_dir}",
+                &self.summary.output_dir.display().to_string(),
+            )
+            .replace("{outputs}", &outputs)
+    }
+}
+
+/// Mechanism delivering completion and failure notifications
+pub trait Notifier: fmt::Debug {
+    /// Returns short name of the notifier used in logs
+    fn name(&self) -> &'static str;
+
+    fn notify(&self, notification: &Notification) -> Result<()>;
+}
+
+/// Creates notifier as described by its config
+pub fn from_config(config: NotifierConfig) -> Box<dyn Notifier> {
+    match config {
+        NotifierConfig::Webhook {
+            url,
+            format,
+            template,
+        } => Box::new(WebhookNotifier {
+            url,
+            format,
+            template,
+        }),
+        NotifierConfig::Desktop => Box::new(DesktopNotifier),
+        NotifierConfig::Command { command, args } => Box::new(CommandNotifier { command, args }),
+        NotifierConfig::Email {
+            host,
+            port,
+            from,
+            to,
+        } => Box::new(EmailNotifier {
+            host,
+            port: port.unwrap_or(SMTPS_PORT),
+            from,
+            to,
+        }),
+    }
+}
+
+/// Sends the notification to all notifiers, reporting rather than failing on errors
+pub fn notify_all(notifiers: &[Box<dyn Notifier>], notification: &Notification) {
+    for notifier in notifiers {
+        match notifier.notify(notification) {
+            Ok(()) => log::info!("Sent {} notification", notifier.name()),
+            Err(e) => eprintln!("Couldn't send {} notification: {:#}", notifier.name(), e),
+        }
+    }
+}
+
+/// Default message template of chat webhooks, in Slack's mrkdwn
+const SLACK_TEMPLATE: &str = "*{title}*\n{message} in {duration}\nOutput: `{output_dir}`";
+/// Default message template of chat webhooks, in Markdown
+const MATRIX_TEMPLATE: &str = "**{title}**\n\n{message} in {duration}\n\nOutput: `{output_dir}`";
+
+/// POSTs the notification as JSON, either as is or shaped for a chat service
+#[derive(Debug)]
+pub struct WebhookNotifier {
+    url: String,
+    format: WebhookFormat,
+    template: Option<String>,
+}
+
+impl WebhookNotifier {
+    fn payload(&self, notification: &Notification) -> Result<serde_json::Value> {
+        let template = self.template.as_deref();
+        let payload = match self.format {
+            WebhookFormat::Json => {
+                let mut payload =
+                    serde_json::to_value(notification).context("serializing notification")?;
+                if let Some(template) = template {
+                    payload["text"] = notification.render(template).into();
+                }
+                payload
+            }
+            WebhookFormat::Slack => serde_json::json!({
+                "text": notification.render(template.unwrap_or(SLACK_TEMPLATE)),
+            }),
+            WebhookFormat::Matrix => serde_json::json!({
+                "text": notification.render(template.unwrap_or(MATRIX_TEMPLATE)),
+                "username": "g_flite",
+            }),
+        };
+        Ok(payload)
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn notify(&self, notification: &Notification) -> Result<()> {
+        let body = self.payload(notification)?;
+        let response = ureq::post(&self.url)
+            .timeout(Duration::from_secs(30))
+            .send_json(body);
+        if let Some(e) = response.synthetic_error() {
+            bail!("posting to '{}': {}", self.url, e);
+        }
+        if !response.ok() {
+            bail!(
+                "posting to '{}': {} {}",
+                self.url,
+                response.status(),
+                response.status_text()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Shows the notification using the platform's notification tool
+#[derive(Debug)]
+pub struct DesktopNotifier;
+
+impl Notifier for DesktopNotifier {
+    fn name(&self) -> &'static str {
+        "desktop"
+    }
+
+    fn notify(&self, notification: &Notification) -> Result<()> {
+        let title = notification.title();
+        let mut command = if cfg!(target_os = "macos") {
+            let mut command = Command::new("osascript");
+            command.arg("-e").arg(format!(
+                "display notification {:?} with title {:?}",
+                notification.message, title
+            ));
+            command
+        } else if cfg!(unix) {
+            let mut command = Command::new("notify-send");
+            command.arg(title).arg(&notification.message);
+            command
+        } else {
+            bail!("desktop notifications are not supported on this platform");
+        };
+
+        let status = command
+            .status()
+            .context("running desktop notification tool")?;
+        if !status.success() {
+            bail!("desktop notification tool exited with {}", status);
+        }
+        Ok(())
+    }
+}
+
+/// Runs a user command with the notification in `G_FLITE_*` environment variables
+#[derive(Debug)]
+pub struct CommandNotifier {
+    command: String,
+    args: Vec<String>,
+}
+
+impl Notifier for CommandNotifier {
+    fn name(&self) -> &'static str {
+        "command"
+    }
+
+    fn notify(&self, notification: &Notification) -> Result<()> {
+        let status = Command::new(&self.command)
+            .args(&self.args)
+            .env("G_FLITE_OUTCOME", notification.outcome.to_string())
+            .env("G_FLITE_RUN_ID", &notification.run_id)
+            .env("G_FLITE_MESSAGE", &notification.message)
+            .status()
+            .with_context(|| format!("running '{}'", self.command))?;
+        if !status.success() {
+            bail!("'{}' exited with {}", self.command, status);
+        }
+        Ok(())
+    }
+}
+
+/// Emails summary of the run over SMTP with TLS
+///
+/// Credentials, if the server requires them, are read from the
+/// `G_FLITE_SMTP_USER` and `G_FLITE_SMTP_PASSWORD` environment variables so
+/// that they don't have to be stored in the config file.
+#[derive(Debug)]
+pub struct EmailNotifier {
+    host: String,
+    port: u16,
+    from: String,
+    to: Vec<String>,
+}
+
+impl Notifier for EmailNotifier {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    fn notify(&self, notification: &Notification) -> Result<()> {
+        let mut body = String::new();
+        writeln!(body, "{}", notification.message)?;
+        writeln!(body)?;
+        write!(body, "{}", notification.summary)?;
+        writeln!(body)?;
+        writeln!(body, "Run: {}", notification.run_id)?;
+
+        let mut builder = EmailBuilder::new()
+            .from(self.from.as_str())
+            .subject(notification.title())
+            .text(body);
+        for to in &self.to {
+            builder = builder.to(to.as_str());
+        }
+        let email = builder
+            .build()
+            .map_err(|e| anyhow!("building email: {}", e))?;
+
+        let connector = TlsConnector::new().context("creating TLS connector")?;
+        let tls = ClientTlsParameters::new(self.host.clone(), connector);
+        // port 465 expects TLS right away, any other one is upgraded with STARTTLS
+        let security = if self.port == SMTPS_PORT {
+            ClientSecurity::Wrapper(tls)
+        } else {
+            ClientSecurity::Required(tls)
+        };
+        let mut client = SmtpClient::new((self.host.as_str(), self.port), security)
+            .with_context(|| format!("connecting to SMTP server '{}'", self.host))?;
+        if let (Ok(user), Ok(password)) = (env::var(SMTP_USER_ENV), env::var(SMTP_PASSWORD_ENV)) {
+            client = client.credentials(Credentials::new(user, password));
+        }
+
+        client
+            .transport()
+            .send(email.into())
+            .with_context(|| format!("sending email via '{}'", self.host))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+use crate::audio;
+use crate::filter::TextFilter;
+use crate::postprocess::AudioFilter;
+use anyhow::{anyhow, bail, Context, Result};
+use std::io::{self, Cursor, Read, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Filter run as an external executable, which reads the text or the WAVE
+/// audio on its stdin and writes the filtered one to its stdout
+///
+/// Anything the plugin writes to stderr is passed through, and it fails the
+/// run by exiting with non-zero status.
+#[derive(Debug)]
+pub struct Plugin {
+    command: String,
+    args: Vec<String>,
+}
+
+impl Plugin {
+    pub fn new(command: String, args: Vec<String>) -> Self {
+        Self { command, args }
+    }
+
+    /// Runs the plugin, feeding it the input and returning its output
+    fn run(&self, input: Vec<u8>) -> Result<Vec<u8>> {
+        let mut child = Command::new(&self.command)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("spawning filter plugin '{}'", self.command))?;
+
+        // written from a thread of its own, so that the plugin can't block
+        // on a full stdout while still being fed
+        let mut stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow!("opening stdin of filter plugin '{}'", self.command))?;
+        let writer = thread::spawn(move || stdin.write_all(&input));
+
+        let mut output = Vec::new();
+        child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow!("opening stdout of filter plugin '{}'", self.command))?
+            .read_to_end(&mut output)
+            .with_context(|| format!("reading output of filter plugin '{}'", self.command))?;
+
+        let status = child
+            .wait()
+            .with_context(|| format!("waiting for filter plugin '{}'", self.command))?;
+        if !status.success() {
+            bail!("filter plugin '{}' exited with {}", self.command, status);
+        }
+        match writer
+            .join()
+            .map_err(|_| anyhow!("feeding filter plugin '{}' panicked", self.command))?
+        {
+            // the plugin doesn't have to read all of its input
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => {
+                return Err(e)
+                    .with_context(|| format!("feeding input to filter plugin '{}'", self.command));
+            }
+            _ => {}
+        }
+
+        Ok(output)
+    }
+}
+
+impl TextFilter for Plugin {
+    fn name(&self) -> &str {
+        &self.command
+    }
+
+    fn apply(&self, text: &str) -> Result<String> {
+        let output = self.run(text.as_bytes().to_vec())?;
+        String::from_utf8(output)
+            .with_context(|| format!("filter plugin '{}' wrote invalid UTF-8", self.command))
+    }
+}
+
+impl AudioFilter for Plugin {
+    fn name(&self) -> &str {
+        &self.command
+    }
+
+    fn apply(&self, spec: hound::WavSpec, samples: Vec<i16>) -> Result<(hound::WavSpec, Vec<i16>)> {
+        let mut input = Cursor::new(Vec::new());
+        {
+            let mut writer = hound::WavWriter::new(&mut input, spec).with_context(|| {
+                format!("writing WAVE input of filter plugin '{}'", self.command)
+            })?;
+            for sample in samples {
+                writer.write_sample(sample)?;
+            }
+            writer.finalize().with_context(|| {
+                format!("writing WAVE input of filter plugin '{}'", self.command)
+            })?;
+        }
+
+        let output = self.run(input.into_inner())?;
+        audio::read_wav(
+            Cursor::new(output),
+            &format!("filter plugin '{}'", self.command),
+        )
+    }
+}
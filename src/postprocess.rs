@@ -0,0 +1,327 @@
+use crate::audio;
+use crate::config::AudioFilterConfig;
+use crate::plugin::Plugin;
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::fmt;
+use std::time::Duration;
+
+/// Level below which audio counts as silence when trimming, unless set
+const DEFAULT_TRIM_THRESHOLD_DB: f64 = -50.0;
+/// Level peaks are kept below when normalizing loudness
+const LOUDNESS_CEILING_DB: f64 = -1.0;
+/// Silence kept around the speech when trimming, unless set, so that soft
+/// consonants at its edges aren't clipped
+const DEFAULT_TRIM_PAD_MS: u64 = 50;
+
+/// Where in the pipeline an audio filter runs
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    /// On each chunk's audio before it's combined
+    #[default]
+    Chunk,
+    /// On each combined output
+    Output,
+}
+
+/// Step of the postprocessing chain rewriting the synthesized audio
+///
+/// Filters take the samples along with their spec and may change both.
+pub trait AudioFilter: fmt::Debug {
+    /// Name of the filter as logged
+    fn name(&self) -> &str;
+
+    fn apply(&self, spec: hound::WavSpec, samples: Vec<i16>) -> Result<(hound::WavSpec, Vec<i16>)>;
+}
+
+/// Ordered chain of audio filters, each fed the output of the previous one
+#[derive(Debug, Default)]
+pub struct Chain {
+    filters: Vec<Box<dyn AudioFilter>>,
+}
+
+impl Chain {
+    pub fn push<F: AudioFilter + 'static>(&mut self, filter: F) {
+        self.filters.push(Box::new(filter));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    pub fn apply(
+        &self,
+        spec: hound::WavSpec,
+        samples: Vec<i16>,
+    ) -> Result<(hound::WavSpec, Vec<i16>)> {
+        self.filters
+            .iter()
+            .try_fold((spec, samples), |(spec, samples), filter| {
+                log::debug!("Applying audio filter '{}'", filter.name());
+                filter.apply(spec, samples)
+            })
+    }
+}
+
+/// Builds the chain of audio filters configured for the stage, in the order
+/// they're listed
+///
+/// Filters changing length of the audio or its spec, i.e. trimming and
+/// resampling, only run on chunks, so that the timings of the chunks within
+/// the output stay right. Plugins run on the output must keep them too.
+pub fn chain(configured: &[AudioFilterConfig], stage: Stage) -> Result<Chain> {
+    let mut chain = Chain::default();
+    for filter in configured {
+        match *filter {
+            AudioFilterConfig::Trim { threshold, pad_ms } => {
+                if stage == Stage::Chunk {
+                    chain.push(Trim::new(
+                        threshold.unwrap_or(DEFAULT_TRIM_THRESHOLD_DB),
+                        Duration::from_millis(pad_ms.unwrap_or(DEFAULT_TRIM_PAD_MS)),
+                    ));
+                }
+            }
+            AudioFilterConfig::Gain {
+                db,
+                stage: filter_stage,
+            } => {
+                if filter_stage == stage {
+                    chain.push(Gain(10f64.powf(db / 20.0) as f32));
+                }
+            }
+            AudioFilterConfig::Resample { sample_rate } => {
+                let resample = Resample::new(sample_rate)?;
+                if stage == Stage::Chunk {
+                    chain.push(resample);
+                }
+            }
+            AudioFilterConfig::Fade {
+                in_ms,
+                out_ms,
+                stage: filter_stage,
+            } => {
+                if filter_stage == stage {
+                    chain.push(Fade {
+                        fade_in: Duration::from_millis(in_ms),
+                        fade_out: Duration::from_millis(out_ms),
+                    });
+                }
+            }
+            AudioFilterConfig::Command {
+                ref command,
+                ref args,
+                stage: filter_stage,
+            } => {
+                if filter_stage == stage {
+                    chain.push(Plugin::new(command.clone(), args.clone()));
+                }
+            }
+        }
+    }
+    Ok(chain)
+}
+
+/// Converts level in dBFS into 16-bit sample amplitude
+fn amplitude(db: f64) -> i32 {
+    (32768.0 * 10f64.powf(db / 20.0)).round() as i32
+}
+
+/// Trims silence at the start and the end of the audio
+#[derive(Debug)]
+pub struct Trim {
+    threshold: i32,
+    pad: Duration,
+}
+
+impl Trim {
+    /// Creates filter trimming audio below the threshold in dBFS, keeping
+    /// `pad` of it around the speech
+    pub fn new(threshold: f64, pad: Duration) -> Self {
+        Self {
+            threshold: amplitude(threshold),
+            pad,
+        }
+    }
+}
+
+impl Default for Trim {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_TRIM_THRESHOLD_DB,
+            Duration::from_millis(DEFAULT_TRIM_PAD_MS),
+        )
+    }
+}
+
+impl AudioFilter for Trim {
+    fn name(&self) -> &str {
+        "trim"
+    }
+
+    fn apply(&self, spec: hound::WavSpec, samples: Vec<i16>) -> Result<(hound::WavSpec, Vec<i16>)> {
+        let channels = usize::from(spec.channels).max(1);
+        let loud = |frame: &[i16]| {
+            frame
+                .iter()
+                .any(|&sample| i32::from(sample).abs() > self.threshold)
+        };
+        let frames: Vec<_> = samples.chunks(channels).collect();
+        let (first, last) = match (
+            frames.iter().position(|frame| loud(frame)),
+            frames.iter().rposition(|frame| loud(frame)),
+        ) {
+            (Some(first), Some(last)) => (first, last),
+            // all silent, leave as is rather than dropping the chunk
+            _ => return Ok((spec, samples)),
+        };
+
+        let pad = audio::duration_to_frames(self.pad, spec.sample_rate) as usize;
+        let from = first.saturating_sub(pad) * channels;
+        let to = ((last + 1 + pad) * channels).min(samples.len());
+        Ok((spec, samples[from..to].to_vec()))
+    }
+}
+
+/// Amplifies or attenuates the audio by the factor, clipping what overflows
+#[derive(Debug)]
+struct Gain(f32);
+
+impl AudioFilter for Gain {
+    fn name(&self) -> &str {
+        "gain"
+    }
+
+    fn apply(
+        &self,
+        spec: hound::WavSpec,
+        mut samples: Vec<i16>,
+    ) -> Result<(hound::WavSpec, Vec<i16>)> {
+        audio::apply_gain(&mut samples, self.0);
+        Ok((spec, samples))
+    }
+}
+
+/// Converts the audio to the sample rate
+#[derive(Debug)]
+pub struct Resample(u32);
+
+impl Resample {
+    pub fn new(sample_rate: u32) -> Result<Self> {
+        if sample_rate == 0 {
+            bail!("can't resample audio to 0 Hz");
+        }
+        Ok(Resample(sample_rate))
+    }
+}
+
+impl AudioFilter for Resample {
+    fn name(&self) -> &str {
+        "resample"
+    }
+
+    fn apply(&self, spec: hound::WavSpec, samples: Vec<i16>) -> Result<(hound::WavSpec, Vec<i16>)> {
+        if spec.sample_rate == self.0 {
+            return Ok((spec, samples));
+        }
+        let to = hound::WavSpec {
+            sample_rate: self.0,
+            ..spec
+        };
+        Ok((to, audio::convert(&samples, spec, to)))
+    }
+}
+
+/// Converts the audio to the number of channels, duplicating mono audio
+/// into each of them
+#[derive(Debug)]
+pub struct Remix(pub u16);
+
+impl AudioFilter for Remix {
+    fn name(&self) -> &str {
+        "remix"
+    }
+
+    fn apply(&self, spec: hound::WavSpec, samples: Vec<i16>) -> Result<(hound::WavSpec, Vec<i16>)> {
+        if spec.channels == self.0 {
+            return Ok((spec, samples));
+        }
+        let to = hound::WavSpec {
+            channels: self.0,
+            ..spec
+        };
+        Ok((to, audio::convert(&samples, spec, to)))
+    }
+}
+
+/// Brings the audio to the target loudness in dBFS, as measured by
+/// `audio::loudness`, while keeping its peaks below `LOUDNESS_CEILING_DB`
+#[derive(Debug)]
+pub struct Loudness(pub f64);
+
+impl AudioFilter for Loudness {
+    fn name(&self) -> &str {
+        "loudness"
+    }
+
+    fn apply(
+        &self,
+        spec: hound::WavSpec,
+        mut samples: Vec<i16>,
+    ) -> Result<(hound::WavSpec, Vec<i16>)> {
+        let level = match audio::loudness(&samples, spec) {
+            Some(level) => level,
+            None => return Ok((spec, samples)),
+        };
+        let gain = (self.0 - level).min(LOUDNESS_CEILING_DB - audio::peak(&samples));
+        log::debug!(
+            "Applying {:.1} dB of gain to audio at {:.1} dBFS",
+            gain,
+            level
+        );
+        audio::apply_gain(&mut samples, 10f64.powf(gain / 20.0) as f32);
+        Ok((spec, samples))
+    }
+}
+
+/// Fades the audio in at the start and out at the end, linearly
+#[derive(Debug)]
+struct Fade {
+    fade_in: Duration,
+    fade_out: Duration,
+}
+
+impl AudioFilter for Fade {
+    fn name(&self) -> &str {
+        "fade"
+    }
+
+    fn apply(
+        &self,
+        spec: hound::WavSpec,
+        mut samples: Vec<i16>,
+    ) -> Result<(hound::WavSpec, Vec<i16>)> {
+        let channels = usize::from(spec.channels).max(1);
+        let frames = samples.len() / channels;
+        let fade_in =
+            (audio::duration_to_frames(self.fade_in, spec.sample_rate) as usize).min(frames);
+        let fade_out =
+            (audio::duration_to_frames(self.fade_out, spec.sample_rate) as usize).min(frames);
+
+        for (i, frame) in samples.chunks_exact_mut(channels).enumerate() {
+            let mut factor = 1.0;
+            if i < fade_in {
+                factor *= i as f64 / fade_in as f64;
+            }
+            if frames - i <= fade_out {
+                factor *= (frames - i - 1) as f64 / fade_out as f64;
+            }
+            if factor < 1.0 {
+                for sample in frame {
+                    *sample = (f64::from(*sample) * factor).round() as i16;
+                }
+            }
+        }
+        Ok((spec, samples))
+    }
+}
@@ -0,0 +1,1168 @@
+use anyhow::{bail, Context, Result};
+use regex::{Captures, Regex};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::path::Path;
+use std::time::Duration;
+use std::{fmt, fs, str::FromStr};
+use unicode_normalization::UnicodeNormalization;
+
+/// Rewrite of text matching `pattern` into its spoken form
+#[derive(Debug)]
+struct Rule {
+    pattern: Regex,
+    replacement: String,
+}
+
+/// User-supplied normalization rules applied to the input before splitting
+///
+/// The rules file holds one rule per line in the form
+/// `<regex> => <spoken form>`, where the spoken form may refer to capture
+/// groups of the regex, e.g. `v(\d+)\.(\d+) => version $1 point $2`. Empty
+/// lines and lines starting with `#` are ignored. Rules are applied in the
+/// order they appear in the file.
+#[derive(Debug)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+/// Reads file of `<left> => <right>` lines, skipping empty ones and comments
+///
+/// Returns line numbers along with both sides of each line.
+pub fn read_mappings(path: &Path, kind: &str) -> Result<Vec<(usize, String, String)>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("reading {} file '{}'", kind, path.display()))?;
+
+    let mut mappings = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut parts = line.rsplitn(2, "=>");
+        match (parts.next(), parts.next()) {
+            (Some(right), Some(left)) => {
+                mappings.push((i + 1, left.trim().to_owned(), right.trim().to_owned()))
+            }
+            _ => bail!(
+                "parsing {} file '{}': line {}: expected <{}> => <spoken form>",
+                kind,
+                path.display(),
+                i + 1,
+                if kind == "rules" { "regex" } else { "word" }
+            ),
+        }
+    }
+
+    Ok(mappings)
+}
+
+impl Rules {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+
+        let mut rules = Vec::new();
+        for (line, pattern, replacement) in read_mappings(path, "rules")? {
+            let pattern = Regex::new(&pattern).with_context(|| {
+                format!(
+                    "parsing rules file '{}': line {}: invalid regex '{}'",
+                    path.display(),
+                    line,
+                    pattern
+                )
+            })?;
+
+            rules.push(Rule {
+                pattern,
+                replacement,
+            });
+        }
+
+        log::info!("Loaded {} rules from '{}'", rules.len(), path.display());
+
+        Ok(Self { rules })
+    }
+
+    /// Creates rule set of a single rule, e.g. one given in the config file
+    pub fn from_rule(pattern: &str, replacement: &str) -> Result<Self> {
+        let pattern =
+            Regex::new(pattern).with_context(|| format!("parsing rule regex '{}'", pattern))?;
+        Ok(Self {
+            rules: vec![Rule {
+                pattern,
+                replacement: replacement.to_owned(),
+            }],
+        })
+    }
+
+    pub fn apply(&self, text: &str) -> String {
+        let mut text = text.to_owned();
+        for rule in &self.rules {
+            text = rule
+                .pattern
+                .replace_all(&text, rule.replacement.as_str())
+                .into_owned();
+        }
+        text
+    }
+}
+
+/// Language whose wording is used when expanding numerals
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Locale {
+    En,
+    De,
+    Fr,
+    Es,
+}
+
+impl Locale {
+    /// Words introducing a roman numeral, e.g. `Chapter XIV`
+    fn numeral_context(self) -> &'static [&'static str] {
+        match self {
+            Locale::En => &[
+                "Chapter", "Part", "Book", "Volume", "Act", "Scene", "Section", "Appendix",
+            ],
+            Locale::De => &[
+                "Kapitel",
+                "Teil",
+                "Buch",
+                "Band",
+                "Akt",
+                "Szene",
+                "Abschnitt",
+            ],
+            Locale::Fr => &[
+                "Chapitre", "Partie", "Livre", "Tome", "Acte", "Scène", "Section",
+            ],
+            Locale::Es => &[
+                "Capítulo",
+                "Parte",
+                "Libro",
+                "Tomo",
+                "Acto",
+                "Escena",
+                "Sección",
+            ],
+        }
+    }
+
+    /// Abbreviations along with their expansion and whether they may end a
+    /// sentence, in which case the full stop is kept if a sentence follows
+    fn abbreviations(self) -> &'static [(&'static str, &'static str, bool)] {
+        match self {
+            Locale::En => &[
+                ("Mr.", "Mister", false),
+                ("Mrs.", "Missus", false),
+                ("Ms.", "Miz", false),
+                ("Dr.", "Doctor", false),
+                ("Prof.", "Professor", false),
+                ("Jr.", "Junior", true),
+                ("Sr.", "Senior", true),
+                ("e.g.", "for example", false),
+                ("i.e.", "that is", false),
+                ("cf.", "compare", false),
+                ("vs.", "versus", false),
+                ("approx.", "approximately", false),
+                ("incl.", "including", false),
+                ("etc.", "et cetera", true),
+            ],
+            Locale::De => &[
+                ("Hr.", "Herr", false),
+                ("Fr.", "Frau", false),
+                ("Dr.", "Doktor", false),
+                ("Prof.", "Professor", false),
+                ("z.B.", "zum Beispiel", false),
+                ("z. B.", "zum Beispiel", false),
+                ("d.h.", "das heißt", false),
+                ("d. h.", "das heißt", false),
+                ("bzw.", "beziehungsweise", false),
+                ("ca.", "circa", false),
+                ("usw.", "und so weiter", true),
+            ],
+            Locale::Fr => &[
+                ("M.", "Monsieur", false),
+                ("Mme", "Madame", false),
+                ("Mlle", "Mademoiselle", false),
+                ("Dr", "Docteur", false),
+                ("p. ex.", "par exemple", false),
+                ("c.-à-d.", "c'est-à-dire", false),
+                ("etc.", "et cetera", true),
+            ],
+            Locale::Es => &[
+                ("Sr.", "Señor", false),
+                ("Sra.", "Señora", false),
+                ("Srta.", "Señorita", false),
+                ("Dr.", "Doctor", false),
+                ("Ud.", "usted", false),
+                ("p. ej.", "por ejemplo", false),
+                ("etc.", "etcétera", true),
+            ],
+        }
+    }
+
+    /// Word joining both ends of a range, e.g. `1914 to 1918`
+    fn range_word(self) -> &'static str {
+        match self {
+            Locale::En => "to",
+            Locale::De => "bis",
+            Locale::Fr => "à",
+            Locale::Es => "a",
+        }
+    }
+}
+
+impl FromStr for Locale {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "en" => Ok(Locale::En),
+            "de" => Ok(Locale::De),
+            "fr" => Ok(Locale::Fr),
+            "es" => Ok(Locale::Es),
+            _ => bail!("unknown locale '{}'; expected one of: en, de, fr, es", s),
+        }
+    }
+}
+
+impl TryFrom<String> for Locale {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Locale::En => "en",
+            Locale::De => "de",
+            Locale::Fr => "fr",
+            Locale::Es => "es",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Regional conventions numbers and dates are written and read in, given as
+/// a language tag such as `de-DE` or `en-GB`
+///
+/// Unlike `Locale`, this doesn't change the words numbers are spelled out
+/// with, only how they're read: whether decimals are set off with a comma or
+/// a point, and whether numeric dates put the day or the month first. The
+/// default ones are those of US English.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct SpeakLocale {
+    /// Decimals are set off with a comma and thousands with a point
+    decimal_comma: bool,
+    /// Numeric dates put the day before the month
+    day_first: bool,
+}
+
+impl SpeakLocale {
+    /// Separators of thousands and of decimals, and the word read for the
+    /// latter
+    fn separators(self) -> (char, char, &'static str) {
+        if self.decimal_comma {
+            ('.', ',', "comma")
+        } else {
+            (',', '.', "point")
+        }
+    }
+}
+
+impl FromStr for SpeakLocale {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(2, &['-', '_'][..]);
+        let language = parts.next().unwrap_or("").to_lowercase();
+        let region = parts.next().map(str::to_uppercase);
+        if let Some(region) = &region {
+            if !(2..=3).contains(&region.len())
+                || !region.chars().all(|c| c.is_ascii_alphanumeric())
+            {
+                bail!(
+                    "invalid region in speak locale '{}'; expected a tag such as en-US or de-DE",
+                    s
+                );
+            }
+        }
+
+        let decimal_comma = match language.as_str() {
+            "en" => false,
+            "de" | "fr" | "es" | "it" | "nl" | "pt" | "pl" | "ru" | "sv" | "da" | "nb" | "fi"
+            | "cs" => true,
+            _ => bail!(
+                "unknown language in speak locale '{}'; expected one of: en, de, fr, es, it, nl, \
+                 pt, pl, ru, sv, da, nb, fi, cs, optionally followed by a region, e.g. de-DE",
+                s
+            ),
+        };
+        // a few regions use a decimal point regardless of the language
+        let decimal_comma = match region.as_deref() {
+            Some("CH") | Some("MX") => false,
+            Some("ZA") => true,
+            _ => decimal_comma,
+        };
+        // month first is an American habit, and English without a region
+        // is read the American way
+        let day_first = !(language == "en" && matches!(region.as_deref(), None | Some("US")));
+
+        Ok(Self {
+            decimal_comma,
+            day_first,
+        })
+    }
+}
+
+impl TryFrom<String> for SpeakLocale {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+/// Parses roman numeral, rejecting malformed ones such as `IIII` or `VX`
+fn parse_roman(numeral: &str) -> Option<u32> {
+    let value = |c| match c {
+        'I' => Some(1),
+        'V' => Some(5),
+        'X' => Some(10),
+        'L' => Some(50),
+        'C' => Some(100),
+        'D' => Some(500),
+        'M' => Some(1000),
+        _ => None,
+    };
+
+    let values = numeral.chars().map(value).collect::<Option<Vec<u32>>>()?;
+    let mut total = 0;
+    for (i, &current) in values.iter().enumerate() {
+        match values.get(i + 1) {
+            Some(&next) if next > current => total -= current as i64,
+            _ => total += current as i64,
+        }
+    }
+    if total <= 0 {
+        return None;
+    }
+    let total = total as u32;
+
+    // only canonical numerals round-trip, which rules out the malformed ones
+    if to_roman(total) == numeral {
+        Some(total)
+    } else {
+        None
+    }
+}
+
+fn to_roman(mut value: u32) -> String {
+    const NUMERALS: &[(u32, &str)] = &[
+        (1000, "M"),
+        (900, "CM"),
+        (500, "D"),
+        (400, "CD"),
+        (100, "C"),
+        (90, "XC"),
+        (50, "L"),
+        (40, "XL"),
+        (10, "X"),
+        (9, "IX"),
+        (5, "V"),
+        (4, "IV"),
+        (1, "I"),
+    ];
+
+    let mut numeral = String::new();
+    for &(step, symbol) in NUMERALS {
+        while value >= step {
+            numeral.push_str(symbol);
+            value -= step;
+        }
+    }
+    numeral
+}
+
+/// Spells out English cardinal number below one million
+fn cardinal_words(n: u32) -> String {
+    const UNITS: &[&str] = &[
+        "zero",
+        "one",
+        "two",
+        "three",
+        "four",
+        "five",
+        "six",
+        "seven",
+        "eight",
+        "nine",
+        "ten",
+        "eleven",
+        "twelve",
+        "thirteen",
+        "fourteen",
+        "fifteen",
+        "sixteen",
+        "seventeen",
+        "eighteen",
+        "nineteen",
+    ];
+    const TENS: &[&str] = &[
+        "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+    ];
+
+    match n {
+        0..=19 => UNITS[n as usize].to_owned(),
+        20..=99 => match n % 10 {
+            0 => TENS[(n / 10) as usize].to_owned(),
+            units => format!("{}-{}", TENS[(n / 10) as usize], UNITS[units as usize]),
+        },
+        100..=999 => match n % 100 {
+            0 => format!("{} hundred", UNITS[(n / 100) as usize]),
+            rest => format!(
+                "{} hundred {}",
+                UNITS[(n / 100) as usize],
+                cardinal_words(rest)
+            ),
+        },
+        _ => match n % 1000 {
+            0 => format!("{} thousand", cardinal_words(n / 1000)),
+            rest => format!(
+                "{} thousand {}",
+                cardinal_words(n / 1000),
+                cardinal_words(rest)
+            ),
+        },
+    }
+}
+
+/// Spells out English ordinal number below one million, e.g. `twenty-first`
+pub fn ordinal_words(n: u32) -> String {
+    let cardinal = cardinal_words(n);
+    let split = cardinal.rfind(&[' ', '-'][..]).map_or(0, |i| i + 1);
+    let (head, last) = cardinal.split_at(split);
+    let last = match last {
+        "one" => "first".to_owned(),
+        "two" => "second".to_owned(),
+        "three" => "third".to_owned(),
+        "five" => "fifth".to_owned(),
+        "eight" => "eighth".to_owned(),
+        "nine" => "ninth".to_owned(),
+        "twelve" => "twelfth".to_owned(),
+        last if last.ends_with('y') => format!("{}ieth", &last[..last.len() - 1]),
+        last => format!("{}th", last),
+    };
+    format!("{}{}", head, last)
+}
+
+/// Spells out English cardinal number below one trillion
+fn number_words(n: u64) -> String {
+    const SCALES: &[(u64, &str)] = &[(1_000_000_000, "billion"), (1_000_000, "million")];
+
+    for &(scale, name) in SCALES {
+        if n >= scale {
+            return match n % scale {
+                0 => format!("{} {}", number_words(n / scale), name),
+                rest => format!(
+                    "{} {} {}",
+                    number_words(n / scale),
+                    name,
+                    number_words(rest)
+                ),
+            };
+        }
+    }
+    cardinal_words(n as u32)
+}
+
+/// Spells out year the way it's read, e.g. `nineteen oh five`
+fn year_words(year: u32) -> String {
+    let (century, rest) = (year / 100, year % 100);
+    match rest {
+        _ if (2000..2010).contains(&year) => cardinal_words(year),
+        0 => format!("{} hundred", cardinal_words(century)),
+        1..=9 => format!("{} oh {}", cardinal_words(century), cardinal_words(rest)),
+        _ => format!("{} {}", cardinal_words(century), cardinal_words(rest)),
+    }
+}
+
+/// Strips control characters and applies Unicode NFC
+///
+/// Line breaks and tabs are kept, with Windows and old Mac line endings
+/// turned into plain ones, while zero-width characters are dropped along
+/// with the control ones.
+pub fn normalize_unicode(text: &str) -> String {
+    text.replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .nfc()
+        .filter(|&c| match c {
+            '\n' | '\t' => true,
+            '\u{200b}' | '\u{2060}' | '\u{feff}' => false,
+            c => !c.is_control(),
+        })
+        .collect()
+}
+
+/// Expands common abbreviations such as `Dr.` or `e.g.` into full words
+pub fn expand_abbreviations(text: &str, locale: Locale) -> String {
+    let abbreviations = locale.abbreviations();
+    let alternatives: Vec<_> = abbreviations
+        .iter()
+        .map(|(abbreviation, _, _)| {
+            if abbreviation.ends_with('.') {
+                regex::escape(abbreviation)
+            } else {
+                format!(r"{}\b", regex::escape(abbreviation))
+            }
+        })
+        .collect();
+    let abbreviation = Regex::new(&format!(r"\b(?:{})", alternatives.join("|")))
+        .expect("valid abbreviation regex");
+    let sentence = Regex::new(r"^(?:[ \t]*(?:\n|$)|\s+[[:upper:]])").expect("valid sentence regex");
+
+    abbreviation
+        .replace_all(text, |caps: &Captures| {
+            let found = caps.get(0).expect("whole match");
+            let (expansion, may_end) = abbreviations
+                .iter()
+                .find(|(abbreviation, _, _)| *abbreviation == found.as_str())
+                .map(|&(_, expansion, may_end)| (expansion, may_end))
+                .expect("matched abbreviation");
+            if may_end && sentence.is_match(&text[found.end()..]) {
+                format!("{}.", expansion)
+            } else {
+                expansion.to_owned()
+            }
+        })
+        .into_owned()
+}
+
+/// Spells out English numbers, e.g. `1,024` or `3.14`, with the separators
+/// of the speak locale, e.g. `1.024` or `3,14` for `de-DE`
+///
+/// Four-digit numbers between 1100 and 2099 are read as years. Numbers with
+/// leading zeros, such as codes, and ones too long to be read as a whole are
+/// read digit by digit, as are decimal places.
+pub fn expand_numbers(text: &str, speak_locale: SpeakLocale) -> String {
+    const DIGITS: &[&str] = &[
+        "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine",
+    ];
+    let digits = |digits: &str| {
+        digits
+            .bytes()
+            .map(|digit| DIGITS[usize::from(digit - b'0')])
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    let (thousands, decimal, decimal_word) = speak_locale.separators();
+    let number = Regex::new(&format!(
+        r"\b([0-9]{{1,3}}(?:{thousands}[0-9]{{3}})+|[0-9]+)(?:{decimal}([0-9]+))?\b",
+        thousands = regex::escape(&thousands.to_string()),
+        decimal = regex::escape(&decimal.to_string())
+    ))
+    .expect("valid number regex");
+    number
+        .replace_all(text, |caps: &Captures| {
+            let integer = &caps[1];
+            let grouped = integer.contains(thousands);
+            let integer = integer.replace(thousands, "");
+            let padded = integer.len() > 1 && integer.starts_with('0');
+            let mut words = match integer.parse::<u64>() {
+                Ok(year @ 1100..=2099) if !grouped && caps.get(2).is_none() => {
+                    year_words(year as u32)
+                }
+                Ok(value) if !padded && integer.len() <= 12 => number_words(value),
+                _ => digits(&integer),
+            };
+            if let Some(decimals) = caps.get(2) {
+                words = format!("{} {} {}", words, decimal_word, digits(decimals.as_str()));
+            }
+            words
+        })
+        .into_owned()
+}
+
+/// Spells out numeric dates in English, e.g. `04/03/2020` or `2020-03-04`,
+/// in the order of the speak locale
+///
+/// Dates put the day first read as `the fourth of March twenty twenty`, the
+/// others as `March fourth, twenty twenty`. Only dates with a four-digit
+/// year are read, so that fractions and version numbers are left intact, as
+/// are ones which don't exist.
+pub fn expand_dates(text: &str, speak_locale: SpeakLocale) -> String {
+    const MONTHS: &[&str] = &[
+        "January",
+        "February",
+        "March",
+        "April",
+        "May",
+        "June",
+        "July",
+        "August",
+        "September",
+        "October",
+        "November",
+        "December",
+    ];
+    const DAYS: &[u32] = &[31, 29, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+
+    let read = |year: &str, month: &str, day: &str| -> Option<String> {
+        let (year, month, day) = (
+            year.parse::<u32>().ok()?,
+            month.parse::<usize>().ok()?,
+            day.parse::<u32>().ok()?,
+        );
+        if !(1..=12).contains(&month) || day == 0 || day > DAYS[month - 1] {
+            return None;
+        }
+        let year = match year {
+            1100..=2099 => year_words(year),
+            year => cardinal_words(year),
+        };
+        let month = MONTHS[month - 1];
+        Some(if speak_locale.day_first {
+            format!("the {} of {} {}", ordinal_words(day), month, year)
+        } else {
+            format!("{} {}, {}", month, ordinal_words(day), year)
+        })
+    };
+
+    let iso = Regex::new(r"\b([0-9]{4})-([0-9]{2})-([0-9]{2})\b").expect("valid date regex");
+    let text = iso.replace_all(text, |caps: &Captures| {
+        read(&caps[1], &caps[2], &caps[3]).unwrap_or_else(|| caps[0].to_owned())
+    });
+
+    let numeric = Regex::new(r"\b([0-9]{1,2})([./-])([0-9]{1,2})([./-])([0-9]{4})\b")
+        .expect("valid date regex");
+    numeric
+        .replace_all(&text, |caps: &Captures| {
+            let (day, month) = if speak_locale.day_first {
+                (&caps[1], &caps[3])
+            } else {
+                (&caps[3], &caps[1])
+            };
+            if caps[2] != caps[4] {
+                return caps[0].to_owned();
+            }
+            read(&caps[5], month, day).unwrap_or_else(|| caps[0].to_owned())
+        })
+        .into_owned()
+}
+
+/// Expands roman numerals, ordinals and year ranges into their spoken form
+///
+/// Roman numerals are only expanded after words such as `Chapter` or `Part`
+/// to avoid mangling ordinary capitalized words. Ordinals such as `21st` are
+/// spelled out for English only, as other locales don't mark them uniformly.
+/// ISO dates such as `2020-03-04` aren't taken for year ranges.
+pub fn expand_numerals(text: &str, locale: Locale) -> String {
+    let roman = Regex::new(&format!(
+        r"\b({})(\s+)([IVXLCDM]+)\b",
+        locale.numeral_context().join("|")
+    ))
+    .expect("valid roman numeral regex");
+    let text = roman.replace_all(text, |caps: &Captures| match parse_roman(&caps[3]) {
+        Some(value) => format!("{}{}{}", &caps[1], &caps[2], value),
+        None => caps[0].to_owned(),
+    });
+
+    let years = Regex::new(r"\b(1\d{3}|20\d{2})\s*[–—-]\s*(1\d{3}|20\d{2}|\d{2})\b")
+        .expect("valid year range regex");
+    let text = years.replace_all(&text, |caps: &Captures| {
+        // `2020-03` followed by `-04` is an ISO date rather than a range
+        let rest = &text[caps.get(0).map_or(0, |m| m.end())..];
+        let mut rest = rest.chars();
+        if let (Some('-'), Some(next)) = (rest.next(), rest.next()) {
+            if next.is_ascii_digit() {
+                return caps[0].to_owned();
+            }
+        }
+        format!("{} {} {}", &caps[1], locale.range_word(), &caps[2])
+    });
+
+    if locale != Locale::En {
+        return text.into_owned();
+    }
+
+    let ordinals = Regex::new(r"\b(\d{1,6})(st|nd|rd|th)\b").expect("valid ordinal regex");
+    ordinals
+        .replace_all(&text, |caps: &Captures| match caps[1].parse() {
+            Ok(value) => ordinal_words(value),
+            Err(_) => caps[0].to_owned(),
+        })
+        .into_owned()
+}
+
+/// Symbols read out by name when spelling tokens
+const SYMBOLS: &[(char, &str)] = &[
+    ('-', "dash"),
+    ('/', "slash"),
+    ('.', "dot"),
+    ('_', "underscore"),
+    ('+', "plus"),
+    ('#', "hash"),
+    ('@', "at"),
+    ('&', "and"),
+    ('*', "star"),
+    (':', "colon"),
+];
+
+/// Spells out token character by character, e.g. `SW1A-2` as
+/// `S, W, 1, A, dash, 2`, the commas being read by flite as short pauses
+///
+/// Letters are capitalized and whitespace is dropped.
+pub fn spell_out(token: &str) -> String {
+    let characters: Vec<_> = token
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| match SYMBOLS.iter().find(|(symbol, _)| *symbol == c) {
+            Some((_, name)) => (*name).to_owned(),
+            None => c.to_uppercase().collect(),
+        })
+        .collect();
+    characters.join(", ")
+}
+
+/// Spells out tokens marked with `[spell:<token>]`, e.g. `[spell:AB-1234]`
+pub fn expand_spell_directives(text: &str) -> String {
+    let directive =
+        Regex::new(r"(?i)\[spell:[ \t]*([^\]\n]+?)[ \t]*\]").expect("valid spell directive regex");
+    directive
+        .replace_all(text, |caps: &Captures| spell_out(&caps[1]))
+        .into_owned()
+}
+
+/// Handling of all-caps acronyms flite would otherwise try to pronounce as words
+///
+/// Acronyms found in the dictionary are replaced with their expansion, while
+/// the remaining ones are optionally spelled letter by letter. Lines written
+/// entirely in capitals, such as headings, are left intact.
+#[derive(Debug, Default)]
+pub struct Acronyms {
+    dictionary: HashMap<String, String>,
+    spell: bool,
+}
+
+impl Acronyms {
+    /// Loads dictionary of `<acronym> => <expansion>` lines
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let dictionary: HashMap<_, _> = read_mappings(path, "acronyms")?
+            .into_iter()
+            .map(|(_, acronym, expansion)| (acronym, expansion))
+            .collect();
+
+        log::info!(
+            "Loaded {} acronyms from '{}'",
+            dictionary.len(),
+            path.display()
+        );
+
+        Ok(Self {
+            dictionary,
+            spell: false,
+        })
+    }
+
+    pub fn set_spell(&mut self, spell: bool) {
+        self.spell = spell;
+    }
+
+    fn is_shouted(line: &str) -> bool {
+        !line.chars().any(char::is_lowercase)
+            && line
+                .split_whitespace()
+                .filter(|word| word.chars().any(char::is_alphabetic))
+                .count()
+                > 1
+    }
+
+    pub fn apply(&self, text: &str) -> String {
+        let acronym = Regex::new(r"\b([A-Z][A-Z0-9]{1,5})(s?)\b").expect("valid acronym regex");
+
+        let lines: Vec<_> = text
+            .split('\n')
+            .map(|line| {
+                if Self::is_shouted(line) {
+                    return line.to_owned();
+                }
+
+                acronym
+                    .replace_all(line, |caps: &Captures| {
+                        if let Some(expansion) = self.dictionary.get(&caps[1]) {
+                            format!("{}{}", expansion, &caps[2])
+                        } else if self.spell {
+                            let letters: Vec<_> = caps[1].chars().map(String::from).collect();
+                            format!("{}{}", letters.join(" "), &caps[2])
+                        } else {
+                            caps[0].to_owned()
+                        }
+                    })
+                    .into_owned()
+            })
+            .collect();
+
+        lines.join("\n")
+    }
+}
+
+/// Removes bracketed citations, footnote markers and page numbers
+///
+/// These are common in text extracted from academic papers and PDFs, and
+/// only break the flow when read aloud.
+pub fn strip_citations(text: &str) -> String {
+    // numeric citations, e.g. `[12]`, `[3, 4]` or `[5–7]`
+    let numeric = Regex::new(r"\s*\[\d+(?:\s*[,–-]\s*\d+)*\]").expect("valid citation regex");
+    // author-year citations, e.g. `(Smith et al., 2010; Jones 2011)`
+    let author_year = Regex::new(
+        r"\s*\((?:see |cf\. )?[A-Z][\w-]+(?: et al\.| (?:and|&) [A-Z][\w-]+)?,? \d{4}[a-z]?(?:[;,] [A-Z][\w-]+(?: et al\.| (?:and|&) [A-Z][\w-]+)?,? \d{4}[a-z]?)*\)",
+    )
+    .expect("valid citation regex");
+    // superscript footnote markers, and numeric ones glued to punctuation
+    let superscript =
+        Regex::new(r"[\x{00B9}\x{00B2}\x{00B3}\x{2070}-\x{2079}]+").expect("valid footnote regex");
+    let glued = Regex::new(r"([[:alpha:]][.,;:])\d{1,3}(\s)").expect("valid footnote regex");
+    // lines holding nothing but a page number, e.g. `- 12 -` or `Page 12`
+    let page_number =
+        Regex::new(r"(?mi)^[ \t]*(?:[-–—][ \t]*)?(?:page[ \t]+)?\d{1,4}(?:[ \t]*[-–—])?[ \t]*$")
+            .expect("valid page number regex");
+
+    let text = numeric.replace_all(text, "");
+    let text = author_year.replace_all(&text, "");
+    let text = superscript.replace_all(&text, "");
+    let text = glued.replace_all(&text, "$1$2");
+    page_number.replace_all(&text, "").into_owned()
+}
+
+/// Rejoins words hyphenated across line breaks, e.g. `dis-\ntributed`
+///
+/// Whether the hyphen is dropped or kept, as in a wrapped `well-\nknown`, is
+/// decided by which of the two spellings occurs elsewhere in the text,
+/// preferring to drop it if neither does.
+pub fn dehyphenate(text: &str) -> String {
+    let wrapped = Regex::new(r"([[:alpha:]]+)-[ \t]*\r?\n[ \t]*([[:lower:]][[:alpha:]]*)")
+        .expect("valid hyphenation regex");
+
+    let words: HashSet<String> = text
+        .split_whitespace()
+        .map(|word| {
+            word.trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase()
+        })
+        .collect();
+
+    wrapped
+        .replace_all(text, |caps: &Captures| {
+            let joined = format!("{}{}", &caps[1], &caps[2]);
+            let hyphenated = format!("{}-{}", &caps[1], &caps[2]);
+            let word = if words.contains(&hyphenated.to_lowercase())
+                && !words.contains(&joined.to_lowercase())
+            {
+                hyphenated
+            } else {
+                joined
+            };
+            format!("{}\n", word)
+        })
+        .into_owned()
+}
+
+/// Emoji read out in words, others are dropped
+const EMOJI: &[(char, &str)] = &[
+    ('😀', "grinning face"),
+    ('😂', "face with tears of joy"),
+    ('😊', "smiling face"),
+    ('😉', "winking face"),
+    ('😍', "heart eyes"),
+    ('😎', "cool"),
+    ('😢', "crying face"),
+    ('😡', "angry face"),
+    ('😮', "surprised face"),
+    ('🤔', "thinking face"),
+    ('🙂', "slightly smiling face"),
+    ('🙁', "slightly frowning face"),
+    ('👍', "thumbs up"),
+    ('👎', "thumbs down"),
+    ('👏', "applause"),
+    ('🙏', "please"),
+    ('👋', "waving hand"),
+    ('💪', "flexed biceps"),
+    ('❤', "heart"),
+    ('💔', "broken heart"),
+    ('🔥', "fire"),
+    ('⭐', "star"),
+    ('✨', "sparkles"),
+    ('🎉', "party popper"),
+    ('✅', "check mark"),
+    ('✔', "check mark"),
+    ('❌', "cross mark"),
+    ('⚠', "warning"),
+    ('💡', "light bulb"),
+    ('🚀', "rocket"),
+    ('☀', "sun"),
+    ('🌧', "rain"),
+    ('☕', "coffee"),
+    ('🍕', "pizza"),
+    ('🐶', "dog"),
+    ('🐱', "cat"),
+];
+
+/// Reads out common emoji in words, set off with commas, and drops the rest
+///
+/// Emoji sequences, e.g. ones with skin tone modifiers, are read by their
+/// first known emoji.
+pub fn verbalize_emoji(text: &str) -> String {
+    let sequence = Regex::new(
+        r"[ \t]*[\x{1f000}-\x{1faff}\x{2600}-\x{27bf}\x{2b00}-\x{2bff}\x{fe0e}\x{fe0f}\x{200d}\x{20e3}\x{e0020}-\x{e007f}]+",
+    )
+    .expect("valid emoji regex");
+    sequence
+        .replace_all(text, |caps: &Captures| {
+            let known = caps[0]
+                .chars()
+                .find_map(|c| EMOJI.iter().find(|(emoji, _)| *emoji == c));
+            match known {
+                Some((_, words)) => format!(", {},", words),
+                None => " ".to_owned(),
+            }
+        })
+        .into_owned()
+}
+
+/// Dash marking a pause in normalized text, read by flite as a comma
+pub const DASH: &str = "—";
+
+/// Normalizes typographic quotes, dashes and ellipses
+///
+/// Curly quotes become straight ones and ellipses three dots. Dashes set off
+/// between words are unified into `DASH`, which `to_flite` turns into a
+/// comma, while dashes within words and numbers become plain hyphens.
+pub fn normalize_typography(text: &str) -> String {
+    let text = text
+        .replace(
+            &[
+                '\u{201C}', '\u{201D}', '\u{201E}', '\u{201F}', '\u{00AB}', '\u{00BB}',
+            ][..],
+            "\"",
+        )
+        .replace(&['\u{2018}', '\u{2019}', '\u{201A}', '\u{201B}'][..], "'")
+        .replace('\u{2026}', "...");
+
+    let spaced = Regex::new(r"[ \t]*(?:[—―]|--+|[ \t]–[ \t])[ \t]*").expect("valid dash regex");
+    let text = spaced.replace_all(&text, format!(" {} ", DASH).as_str());
+    text.replace(&['\u{2010}', '\u{2011}', '\u{2012}', '\u{2013}'][..], "-")
+}
+
+/// Returns text as passed to flite, with dashes read as short pauses and
+/// pause markers left out
+pub fn to_flite(text: &str) -> String {
+    strip_pause_markers(text)
+        .replace(&format!(" {} ", DASH), ", ")
+        .replace(DASH, ", ")
+}
+
+/// Inline markup inserting silence, e.g. `[pause 800ms]` or `[pause 2s]`
+fn pause_marker() -> Regex {
+    Regex::new(r"(?i)\[pause[ \t:]+([0-9]+)[ \t]*(ms|s)\]").expect("valid pause marker regex")
+}
+
+/// Joins each pause marker into a single word, e.g. `[pause:800ms]`, so
+/// that splitting the text into words keeps it intact
+pub fn join_pause_markers(text: &str) -> String {
+    pause_marker()
+        .replace_all(text, "[pause:$1$2]")
+        .into_owned()
+}
+
+/// Removes pause markers, e.g. from text read out or shown to the user
+pub fn strip_pause_markers(text: &str) -> String {
+    let marker = Regex::new(&format!(r"[ \t]*{}", pause_marker().as_str()))
+        .expect("valid pause marker regex");
+    marker.replace_all(text, "").trim_start().to_owned()
+}
+
+/// Returns pause markers in the text along with their positions relative to
+/// the length of the text without them
+pub fn pause_positions(text: &str) -> Vec<(f64, Duration)> {
+    let marker = pause_marker();
+    let length = strip_pause_markers(text).chars().count().max(1) as f64;
+
+    let mut pauses = Vec::new();
+    let mut stripped = 0;
+    let mut last = 0;
+    for caps in marker.captures_iter(text) {
+        let whole = caps.get(0).unwrap();
+        stripped += text[last..whole.start()].chars().count();
+        last = whole.end();
+
+        let value: u64 = caps[1].parse().unwrap_or(0);
+        let pause = if caps[2].eq_ignore_ascii_case("s") {
+            Duration::from_secs(value)
+        } else {
+            Duration::from_millis(value)
+        };
+        pauses.push(((stripped as f64 / length).min(1.0), pause));
+    }
+    pauses
+}
+
+/// Returns positions of dashes relative to the length of the text
+pub fn dash_positions(text: &str) -> Vec<f64> {
+    let length = text.chars().count() as f64;
+    text.chars()
+        .enumerate()
+        .filter(|&(_, c)| DASH.starts_with(c))
+        .map(|(i, _)| i as f64 / length)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_canonical_roman_numerals_only() {
+        let cases = [
+            ("I", Some(1)),
+            ("IV", Some(4)),
+            ("XIV", Some(14)),
+            ("XL", Some(40)),
+            ("MCMXCIV", Some(1994)),
+            ("IIII", None),
+            ("VX", None),
+            ("IC", None),
+            ("XIIII", None),
+            ("", None),
+            ("ABC", None),
+        ];
+        for &(numeral, expected) in &cases {
+            assert_eq!(parse_roman(numeral), expected, "{:?}", numeral);
+        }
+    }
+
+    #[test]
+    fn spells_out_ordinals() {
+        let cases = [
+            (1, "first"),
+            (2, "second"),
+            (3, "third"),
+            (5, "fifth"),
+            (8, "eighth"),
+            (9, "ninth"),
+            (11, "eleventh"),
+            (12, "twelfth"),
+            (20, "twentieth"),
+            (21, "twenty-first"),
+            (42, "forty-second"),
+            (100, "one hundredth"),
+            (112, "one hundred twelfth"),
+            (1000, "one thousandth"),
+        ];
+        for &(n, expected) in &cases {
+            assert_eq!(ordinal_words(n), expected, "{}", n);
+        }
+    }
+
+    #[test]
+    fn spells_out_years() {
+        let cases = [
+            (1066, "ten sixty-six"),
+            (1900, "nineteen hundred"),
+            (1905, "nineteen oh five"),
+            (1984, "nineteen eighty-four"),
+            (2000, "two thousand"),
+            (2009, "two thousand nine"),
+            (2010, "twenty ten"),
+        ];
+        for &(year, expected) in &cases {
+            assert_eq!(year_words(year), expected, "{}", year);
+        }
+    }
+
+    #[test]
+    fn expands_numerals() {
+        let cases = [
+            (Locale::En, "Chapter XIV", "Chapter 14"),
+            (Locale::En, "Chapter IIII", "Chapter IIII"),
+            (Locale::En, "I saw XIV", "I saw XIV"),
+            (Locale::De, "Kapitel IX", "Kapitel 9"),
+            (Locale::En, "the 21st time", "the twenty-first time"),
+            (Locale::De, "der 21st", "der 21st"),
+            (Locale::En, "1914-1918", "1914 to 1918"),
+            (Locale::En, "1939 – 45", "1939 to 45"),
+            (Locale::Fr, "1914-1918", "1914 à 1918"),
+            (Locale::En, "on 2020-03-04", "on 2020-03-04"),
+        ];
+        for &(locale, text, expected) in &cases {
+            assert_eq!(expand_numerals(text, locale), expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn expands_numbers() {
+        let en_us = SpeakLocale::default();
+        let de_de: SpeakLocale = "de-DE".parse().unwrap();
+        let cases = [
+            (en_us, "7 days", "seven days"),
+            (en_us, "1,024 bytes", "one thousand twenty-four bytes"),
+            (en_us, "3.14", "three point one four"),
+            (en_us, "in 1905", "in nineteen oh five"),
+            (
+                en_us,
+                "1,905 people",
+                "one thousand nine hundred five people",
+            ),
+            (en_us, "2,500,000", "two million five hundred thousand"),
+            (en_us, "code 007", "code zero zero seven"),
+            (
+                en_us,
+                "1234567890123",
+                "one two three four five six seven eight nine zero one two three",
+            ),
+            (de_de, "1.024", "one thousand twenty-four"),
+            (de_de, "3,14", "three comma one four"),
+            (de_de, "1,024", "one comma zero two four"),
+        ];
+        for &(speak_locale, text, expected) in &cases {
+            assert_eq!(expand_numbers(text, speak_locale), expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn expands_abbreviations() {
+        let cases = [
+            (Locale::En, "Dr. Watson", "Doctor Watson"),
+            (Locale::En, "Mr. and Mrs. Smith", "Mister and Missus Smith"),
+            (Locale::En, "e.g. this", "for example this"),
+            (
+                Locale::En,
+                "apples, pears etc. Then",
+                "apples, pears et cetera. Then",
+            ),
+            (
+                Locale::En,
+                "apples, pears etc. and",
+                "apples, pears et cetera and",
+            ),
+            (Locale::En, "apples, pears etc.", "apples, pears et cetera."),
+            (Locale::En, "John Smith Jr. went", "John Smith Junior went"),
+            (Locale::En, "Drake", "Drake"),
+            (Locale::De, "z.B. das", "zum Beispiel das"),
+            (Locale::De, "Hr. Müller", "Herr Müller"),
+            (Locale::Fr, "Mme Curie", "Madame Curie"),
+            (Locale::Fr, "Mmes", "Mmes"),
+        ];
+        for &(locale, text, expected) in &cases {
+            assert_eq!(expand_abbreviations(text, locale), expected, "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use anyhow::{bail, Context, Result};
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether questions are answered yes without asking, set with --yes
+static ASSUME_YES: AtomicBool = AtomicBool::new(false);
+/// Whether questions may be asked at all, unset with --non-interactive
+static INTERACTIVE: AtomicBool = AtomicBool::new(true);
+
+/// Answers all further questions yes without asking
+pub fn assume_yes() {
+    ASSUME_YES.store(true, Ordering::SeqCst);
+}
+
+/// Makes all further questions fail rather than wait for an answer
+pub fn disable() {
+    INTERACTIVE.store(false, Ordering::SeqCst);
+}
+
+/// Asks the user a yes/no question, defaulting to no
+pub fn confirm(question: &str) -> Result<bool> {
+    if ASSUME_YES.load(Ordering::SeqCst) {
+        eprintln!("{} [y/N] y (--yes)", question);
+        return Ok(true);
+    }
+    if !INTERACTIVE.load(Ordering::SeqCst) {
+        bail!(
+            "'{}' needs an answer, but prompts are disabled with --non-interactive; pass --yes to confirm",
+            question
+        );
+    }
+
+    // stdout may be taken by the output audio
+    eprint!("{} [y/N] ", question);
+    io::stderr().flush().context("flushing stderr")?;
+
+    let mut answer = String::new();
+    let read = io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .context("reading answer from stdin")?;
+    if read == 0 {
+        bail!("no answer to '{}', stdin is closed", question);
+    }
+    let answer = answer.trim().to_lowercase();
+    Ok(answer == "y" || answer == "yes")
+}
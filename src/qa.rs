@@ -0,0 +1,170 @@
+use crate::timing::Timeline;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Amplitude at or below which a frame is considered silent (about -50 dBFS)
+const SILENCE_THRESHOLD: i32 = 100;
+/// Amplitude at or above which a frame is considered clipped
+const CLIPPING_THRESHOLD: i32 = 32_767;
+/// Minimum number of consecutive clipped frames reported as a defect
+const MIN_CLIPPED_FRAMES: u64 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IssueKind {
+    Silence,
+    Clipping,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Issue {
+    pub kind: IssueKind,
+    pub start: String,
+    pub end: String,
+    pub chunk: Option<usize>,
+    #[serde(skip)]
+    start_frame: u64,
+    #[serde(skip)]
+    end_frame: u64,
+}
+
+/// QA report listing suspicious sections of the combined audio
+#[derive(Debug, Serialize)]
+pub struct QaReport {
+    pub max_silence: String,
+    pub issues: Vec<Issue>,
+}
+
+impl QaReport {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let contents = serde_json::to_vec_pretty(self).context("serializing QA report")?;
+        fs::write(path, contents)
+            .with_context(|| format!("writing QA report to '{}'", path.display()))
+    }
+}
+
+fn format_timestamp(timestamp: Duration) -> String {
+    let millis = timestamp.as_millis();
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// Streaming analyzer detecting long silences and clipped sections
+#[derive(Debug)]
+pub struct QaAnalyzer {
+    channels: u64,
+    max_silence: Duration,
+    max_silence_frames: u64,
+    channel: u64,
+    amplitude: i32,
+    frame: u64,
+    silence_start: Option<u64>,
+    clipping_start: Option<u64>,
+    issues: Vec<Issue>,
+}
+
+impl QaAnalyzer {
+    pub fn new(spec: hound::WavSpec, max_silence: Duration) -> Self {
+        Self {
+            channels: u64::from(spec.channels),
+            max_silence,
+            max_silence_frames: crate::audio::duration_to_frames(max_silence, spec.sample_rate),
+            channel: 0,
+            amplitude: 0,
+            frame: 0,
+            silence_start: None,
+            clipping_start: None,
+            issues: Vec::new(),
+        }
+    }
+
+    pub fn write_samples(&mut self, samples: &[i16]) {
+        for &sample in samples {
+            self.amplitude = self.amplitude.max(i32::from(sample).abs());
+            self.channel += 1;
+
+            if self.channel == self.channels {
+                self.push_frame();
+            }
+        }
+    }
+
+    fn push_frame(&mut self) {
+        let amplitude = self.amplitude;
+        let frame = self.frame;
+
+        if amplitude <= SILENCE_THRESHOLD {
+            self.silence_start.get_or_insert(frame);
+        } else {
+            self.close_silence(frame);
+        }
+
+        if amplitude >= CLIPPING_THRESHOLD {
+            self.clipping_start.get_or_insert(frame);
+        } else {
+            self.close_clipping(frame);
+        }
+
+        self.channel = 0;
+        self.amplitude = 0;
+        self.frame += 1;
+    }
+
+    fn close_silence(&mut self, end: u64) {
+        if let Some(start) = self.silence_start.take() {
+            if end - start > self.max_silence_frames {
+                self.push_issue(IssueKind::Silence, start, end);
+            }
+        }
+    }
+
+    fn close_clipping(&mut self, end: u64) {
+        if let Some(start) = self.clipping_start.take() {
+            if end - start >= MIN_CLIPPED_FRAMES {
+                self.push_issue(IssueKind::Clipping, start, end);
+            }
+        }
+    }
+
+    fn push_issue(&mut self, kind: IssueKind, start_frame: u64, end_frame: u64) {
+        self.issues.push(Issue {
+            kind,
+            start: String::new(),
+            end: String::new(),
+            chunk: None,
+            start_frame,
+            end_frame,
+        });
+    }
+
+    /// Finishes the analysis, resolving issues' timestamps and chunks
+    pub fn finish(mut self, timeline: &Timeline) -> QaReport {
+        let frame = self.frame;
+        self.close_silence(frame);
+        self.close_clipping(frame);
+
+        for issue in &mut self.issues {
+            issue.start = format_timestamp(timeline.frames_to_duration(issue.start_frame));
+            issue.end = format_timestamp(timeline.frames_to_duration(issue.end_frame));
+            issue.chunk = timeline
+                .spans()
+                .iter()
+                .find(|span| span.start <= issue.start_frame && issue.start_frame < span.end)
+                .map(|span| span.chunk);
+        }
+
+        QaReport {
+            max_silence: format_timestamp(self.max_silence),
+            issues: self.issues,
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use crate::audio;
+use crate::task::SubtaskDescriptor;
+use crate::voice::Voice;
+use anyhow::{anyhow, bail, Context, Result};
+use gwasm_api::prelude::ComputedSubtask;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use std::{fmt, str::FromStr};
+
+/// Name of the WAVE file standing in for a quarantined subtask's result
+const PLACEHOLDER_NAME: &str = "placeholder.wav";
+/// Name of the notice synthesized locally for `Placeholder::Notice`
+const NOTICE_NAME: &str = "notice.wav";
+/// Notice read out in place of a missing chunk
+const NOTICE: &str = "Section missing.";
+/// flite executable used to synthesize the notice locally
+const FLITE: &str = "flite";
+
+/// Frequency of the beep, in Hz
+const BEEP_FREQUENCY: f64 = 880.0;
+const BEEP_LENGTH: Duration = Duration::from_millis(400);
+/// Fade in and out keeping the beep from clicking
+const BEEP_FADE: Duration = Duration::from_millis(20);
+/// Amplitude of the beep relative to full scale
+const BEEP_VOLUME: f64 = 0.3;
+
+/// Audio standing in for a chunk left out of the output
+///
+/// Each placeholder is padded with silence to the chunk's estimated length,
+/// keeping the timing of what follows roughly intact.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Placeholder {
+    Silence,
+    /// Short tone at the start of the gap
+    Beep,
+    /// "Section missing" notice synthesized with a local flite
+    Notice,
+}
+
+impl FromStr for Placeholder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "silence" => Ok(Placeholder::Silence),
+            "beep" => Ok(Placeholder::Beep),
+            "notice" => Ok(Placeholder::Notice),
+            _ => bail!(
+                "unknown placeholder '{}'; expected one of: silence, beep, notice",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Placeholder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Placeholder::Silence => write!(f, "silence"),
+            Placeholder::Beep => write!(f, "beep"),
+            Placeholder::Notice => write!(f, "notice"),
+        }
+    }
+}
+
+/// Chunk given up on after its subtask failed repeatedly
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    pub subtask: String,
+    /// ID of the subtask on the node
+    pub subtask_id: String,
+    pub input: PathBuf,
+    /// Index of the chunk within its input
+    pub chunk: usize,
+    pub voice: Option<Voice>,
+    pub text: String,
+    pub failures: u32,
+    /// Providers the subtask was assigned to, in order
+    pub providers: Vec<String>,
+    /// Length of the placeholder substituted for the chunk, in seconds
+    pub placeholder_secs: f64,
+}
+
+/// Chunks left out of the output, saved to the workspace for inspection
+#[derive(Debug, Serialize)]
+pub struct Quarantine {
+    pub task_name: String,
+    pub entries: Vec<Entry>,
+}
+
+impl Quarantine {
+    pub const FILENAME: &'static str = "quarantine.json";
+
+    pub fn save<P: AsRef<Path>>(&self, workspace: P) -> Result<PathBuf> {
+        let path = workspace.as_ref().join(Self::FILENAME);
+        let json = serde_json::to_string_pretty(self).context("serializing quarantine list")?;
+        fs::write(&path, json)
+            .with_context(|| format!("writing quarantine list '{}'", path.display()))?;
+        Ok(path)
+    }
+}
+
+/// Collects results of the finished subtasks saved by Golem, substituting
+/// placeholders of given length for the quarantined ones
+///
+/// The subtasks are returned in the descriptors' order, the same way
+/// `task::order_subtasks` returns them.
+pub fn salvage(
+    descriptors: &[SubtaskDescriptor],
+    placeholders: &BTreeMap<usize, Duration>,
+    placeholder: Placeholder,
+) -> Result<Vec<ComputedSubtask>> {
+    // placeholders have to match the format of the actual results
+    let spec = descriptors
+        .iter()
+        .enumerate()
+        .filter(|(i, descriptor)| !placeholders.contains_key(i) && descriptor.output.is_file())
+        .map(|(_, descriptor)| {
+            hound::WavReader::open(&descriptor.output)
+                .map(|reader| reader.spec())
+                .with_context(|| format!("reading result '{}'", descriptor.output.display()))
+        })
+        .next()
+        .ok_or_else(|| anyhow!("no subtask finished, so there are no results to salvage"))??;
+
+    let sound = match placeholder {
+        Placeholder::Silence => Vec::new(),
+        Placeholder::Beep => beep(spec),
+        Placeholder::Notice if placeholders.is_empty() => Vec::new(),
+        Placeholder::Notice => {
+            let dir = descriptors
+                .first()
+                .and_then(|descriptor| descriptor.output.parent())
+                .and_then(Path::parent)
+                .ok_or_else(|| anyhow!("no subtasks to salvage"))?;
+            notice(&dir.join(NOTICE_NAME), spec).unwrap_or_else(|e| {
+                log::warn!(
+                    "Couldn't synthesize notice locally, beeping instead: {:#}",
+                    e
+                );
+                beep(spec)
+            })
+        }
+    };
+
+    let mut subtasks = Vec::with_capacity(descriptors.len());
+    for (i, descriptor) in descriptors.iter().enumerate() {
+        let path = match placeholders.get(&i) {
+            Some(&duration) => write_placeholder(descriptor, spec, &sound, duration)?,
+            None if descriptor.output.is_file() => descriptor.output.clone(),
+            None => bail!(
+                "results of subtask '{}' are missing and it wasn't quarantined",
+                descriptor.id
+            ),
+        };
+        let file =
+            File::open(&path).with_context(|| format!("opening result '{}'", path.display()))?;
+        let mut data = BTreeMap::new();
+        data.insert(path, BufReader::new(file));
+        subtasks.push(ComputedSubtask { data });
+    }
+    Ok(subtasks)
+}
+
+/// Writes the sound padded with silence next to where the subtask's result
+/// would be, so that the subtask can still be identified by its output dir
+fn write_placeholder(
+    descriptor: &SubtaskDescriptor,
+    spec: hound::WavSpec,
+    sound: &[i16],
+    duration: Duration,
+) -> Result<PathBuf> {
+    let path = descriptor.output.with_file_name(PLACEHOLDER_NAME);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("creating dir '{}'", dir.display()))?;
+    }
+
+    let samples = audio::duration_to_frames(duration, spec.sample_rate) * u64::from(spec.channels);
+    let padding = samples.saturating_sub(sound.len() as u64);
+    let mut writer = hound::WavWriter::create(&path, spec)
+        .with_context(|| format!("creating placeholder '{}'", path.display()))?;
+    for &sample in sound {
+        writer
+            .write_sample(sample)
+            .with_context(|| format!("writing placeholder '{}'", path.display()))?;
+    }
+    for _ in 0..padding {
+        writer
+            .write_sample(0i16)
+            .with_context(|| format!("writing placeholder '{}'", path.display()))?;
+    }
+    writer
+        .finalize()
+        .with_context(|| format!("writing placeholder '{}'", path.display()))?;
+    Ok(path)
+}
+
+/// Generates a short sine tone faded in and out
+fn beep(spec: hound::WavSpec) -> Vec<i16> {
+    let rate = f64::from(spec.sample_rate);
+    let frames = audio::duration_to_frames(BEEP_LENGTH, spec.sample_rate);
+    let fade = audio::duration_to_frames(BEEP_FADE, spec.sample_rate).max(1) as f64;
+
+    let mut samples = Vec::with_capacity(frames as usize * usize::from(spec.channels));
+    for frame in 0..frames {
+        let envelope = (frame as f64 / fade)
+            .min((frames - frame) as f64 / fade)
+            .min(1.0);
+        let value = (2.0 * PI * BEEP_FREQUENCY * frame as f64 / rate).sin();
+        let sample = (value * envelope * BEEP_VOLUME * f64::from(i16::MAX)) as i16;
+        for _ in 0..spec.channels {
+            samples.push(sample);
+        }
+    }
+    samples
+}
+
+/// Synthesizes the notice with flite installed locally, converted to the spec
+fn notice(path: &Path, spec: hound::WavSpec) -> Result<Vec<i16>> {
+    let status = Command::new(FLITE)
+        .arg("-t")
+        .arg(NOTICE)
+        .arg("-o")
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .with_context(|| format!("spawning '{}'; is it installed and in your PATH?", FLITE))?;
+    if !status.success() {
+        bail!("'{}' exited with {}", FLITE, status);
+    }
+
+    let file = File::open(path).with_context(|| format!("opening notice '{}'", path.display()))?;
+    let (notice_spec, samples) =
+        audio::read_wav(BufReader::new(file), &format!("'{}'", path.display()))?;
+    Ok(audio::convert(&samples, notice_spec, spec))
+}
@@ -0,0 +1,155 @@
+use super::CombineOpt;
+use crate::audio;
+use crate::combine::Combiner;
+use crate::compress;
+use crate::config::Config;
+use crate::encode::{self, Encoder, Format, Quality};
+use crate::postprocess::{self, Stage};
+use crate::task;
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Combines already computed chunks into the output, skipping synthesis
+///
+/// The dir is either a task workspace, in which case the results of its
+/// latest attempt are combined, a single attempt dir, or a plain dir of WAVE
+/// files combined in the order of their names.
+pub fn combine(opt: CombineOpt) -> Result<()> {
+    let mut chunks = chunk_paths(&opt.dir)?;
+    // don't combine output of an earlier combine into itself
+    if let Ok(output) = fs::canonicalize(&opt.output) {
+        chunks.retain(|path| fs::canonicalize(path).ok().as_ref() != Some(&output));
+    }
+    if chunks.is_empty() {
+        bail!("no chunks to combine found in '{}'", opt.dir.display());
+    }
+
+    let message = format!(
+        "Combining {} chunks from '{}' into '{}'...",
+        chunks.len(),
+        opt.dir.display(),
+        opt.output.display()
+    );
+    // stdout may be taken by the output audio
+    if streams(&opt.output) {
+        if opt.formats.iter().any(|&format| format != Format::Wav) {
+            bail!("only WAV output can be streamed to stdout");
+        }
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+
+    combine_files(&chunks, &opt.output, &opt.formats, opt.quality, opt.gap)
+}
+
+/// Combines the WAVE files in order into the output in all the formats,
+/// separated by `gap` of silence if set
+///
+/// Audio filters configured in the config file are applied as in a run.
+pub fn combine_files(
+    chunks: &[PathBuf],
+    output: &Path,
+    formats: &[Format],
+    quality: Option<Quality>,
+    gap: Option<Duration>,
+) -> Result<()> {
+    encode::check_formats(output, formats)?;
+    let audio_filters = Config::load()?.audio_filters;
+    let chunk_filters = postprocess::chain(&audio_filters, Stage::Chunk)?;
+
+    let mut combiner: Option<Combiner> = None;
+    for (i, path) in chunks.iter().enumerate() {
+        let file = compress::open(path).context("opening chunk")?;
+        let (spec, samples) = audio::read_wav(file, &format!("'{}'", path.display()))
+            .with_context(|| format!("reading chunk '{}'", path.display()))?;
+        let (spec, samples) = chunk_filters.apply(spec, samples)?;
+
+        if combiner.is_none() {
+            let mut created = create_combiner(spec, output, formats, quality)?;
+            created.set_filters(postprocess::chain(&audio_filters, Stage::Output)?);
+            combiner = Some(created);
+        }
+        let combiner = combiner.as_mut().unwrap();
+
+        // chunks mixed from several runs may differ in spec
+        if spec == combiner.spec() {
+            combiner.write_chunk(i, &samples)?;
+        } else {
+            log::info!(
+                "Converting '{}' from {:?} to {:?}",
+                path.display(),
+                spec,
+                combiner.spec()
+            );
+            let samples = combiner.convert(samples, spec);
+            combiner.write_chunk(i, &samples)?;
+        }
+        if let Some(gap) = gap.filter(|_| i + 1 < chunks.len()) {
+            combiner.write_silence(audio::duration_to_frames(gap, combiner.spec().sample_rate))?;
+        }
+    }
+
+    if let Some(combiner) = combiner {
+        combiner.finish()?;
+    }
+    Ok(())
+}
+
+/// Finds the chunks to combine in the dir, in order
+fn chunk_paths(dir: &Path) -> Result<Vec<PathBuf>> {
+    if dir.join(task::OUTPUT_DIR).is_dir() {
+        return task::subtask_outputs(dir);
+    }
+    if let Some(attempt_dir) = task::latest_attempt_dir(dir)? {
+        log::info!("Combining results from '{}'", attempt_dir.display());
+        return task::subtask_outputs(attempt_dir);
+    }
+
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("reading dir '{}'", dir.display()))? {
+        let path = entry
+            .with_context(|| format!("reading dir '{}'", dir.display()))?
+            .path();
+        let is_wav = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .filter(|extension| extension.to_lowercase() == "wav")
+            .is_some();
+        if is_wav && path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+fn create_combiner(
+    spec: hound::WavSpec,
+    output: &Path,
+    formats: &[Format],
+    quality: Option<Quality>,
+) -> Result<Combiner> {
+    let mut combiner = Combiner::new(spec);
+    for &format in formats {
+        match format {
+            Format::Wav if streams(output) => combiner.stream_output(io::stdout())?,
+            Format::Wav => combiner.set_output(output)?,
+            _ => combiner.add_encoder(Encoder::spawn(
+                format,
+                output.with_extension(format.extension()),
+                spec,
+                quality,
+            )?),
+        }
+    }
+    Ok(combiner)
+}
+
+/// Checks whether the output is `-`, standing for stdout
+fn streams(output: &Path) -> bool {
+    output == Path::new("-")
+}
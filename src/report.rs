@@ -0,0 +1,127 @@
+use super::ReportOpt;
+use crate::archive::Archive;
+use crate::config;
+use crate::run::{self, Run};
+use crate::tasks;
+use anyhow::{bail, Context, Result};
+use serde_json::json;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Keys of config values which may hold secrets or personal data, such as
+/// webhook URLs, email addresses or plugin arguments
+const REDACTED_KEYS: &[&str] = &[
+    "url", "endpoint", "host", "from", "to", "args", "template", "password", "token",
+];
+/// Where issues are reported
+const ISSUES_URL: &str = "https://github.com/golemfactory/g-flite/issues/new";
+
+/// Bundles what's needed to diagnose a run into a single archive to attach
+/// to a bug report
+///
+/// Unlike `g_flite archive`, the report holds neither the text nor the audio
+/// of the run; only its manifest, the error it failed with, the task's
+/// manifest, event log and quarantine list, versions of g_flite and the
+/// node, and the config file with anything resembling a secret redacted.
+pub fn report(opt: ReportOpt) -> Result<()> {
+    let run_dir = run::runs_dir()?.join(&opt.run_id);
+    if !run_dir.is_dir() {
+        bail!(
+            "no run '{}' in the run history; see `g_flite cache ls`",
+            opt.run_id
+        );
+    }
+    let output = opt
+        .output
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(format!("g_flite_report_{}.tar.zst", opt.run_id)));
+    let mut archive = Archive::create(&output, PathBuf::from(format!("report_{}", opt.run_id)))?;
+
+    let system = json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "os": env::consts::OS,
+        "arch": env::consts::ARCH,
+    });
+    archive.add_data(
+        Path::new("system.json"),
+        &serde_json::to_vec_pretty(&system)?,
+    )?;
+
+    for name in &[Run::MANIFEST, Run::ERROR] {
+        let path = run_dir.join(name);
+        if path.is_file() {
+            archive.add_file(Path::new(name), &path)?;
+        }
+    }
+
+    let manifest = fs::read(run_dir.join(Run::MANIFEST))
+        .ok()
+        .and_then(|contents| serde_json::from_slice::<serde_json::Value>(&contents).ok());
+    match manifest
+        .as_ref()
+        .and_then(|manifest| manifest["task_dir"].as_str())
+    {
+        Some(task_dir) if Path::new(task_dir).is_dir() => {
+            archive.add_task_records(Path::new(task_dir))?;
+        }
+        _ => log::warn!(
+            "Task dir of run '{}' is unknown or gone, so its event log isn't included",
+            opt.run_id
+        ),
+    }
+
+    if let Some(rpc_log) = &opt.rpc_log {
+        archive.add_file(Path::new("rpc.log"), rpc_log)?;
+    }
+
+    let config_path = config::config_path()?;
+    if config_path.is_file() {
+        let contents = fs::read_to_string(&config_path)
+            .with_context(|| format!("reading config file '{}'", config_path.display()))?;
+        archive.add_data(
+            Path::new("config.toml"),
+            redact_config(&contents)?.as_bytes(),
+        )?;
+    }
+
+    let node = match tasks::golemcli(opt.node).and_then(|golemcli| golemcli.node_version()) {
+        Ok(version) => json!({ "version": version }),
+        Err(e) => json!({ "error": format!("{:#}", e) }),
+    };
+    archive.add_data(Path::new("node.json"), &serde_json::to_vec_pretty(&node)?)?;
+
+    let files = archive.finish(&output)?;
+    println!(
+        "Wrote report of run '{}' with {} files into '{}'; attach it to your issue at {}",
+        opt.run_id,
+        files,
+        output.display(),
+        ISSUES_URL
+    );
+    Ok(())
+}
+
+/// Returns the config with values of the keys which may hold secrets
+/// replaced
+fn redact_config(contents: &str) -> Result<String> {
+    let mut config: toml::Value = toml::from_str(contents).context("parsing config file")?;
+    redact(&mut config);
+    toml::to_string_pretty(&config).context("serializing redacted config")
+}
+
+fn redact(value: &mut toml::Value) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table.iter_mut() {
+                if REDACTED_KEYS.contains(&key.as_str()) {
+                    *value = toml::Value::String("<redacted>".to_owned());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        toml::Value::Array(values) => values.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
@@ -0,0 +1,99 @@
+use anyhow::{Context, Result};
+use chrono::Local;
+use log::{LevelFilter, Log, Metadata, Record};
+use regex::Regex;
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Log targets of the code talking to the Golem node
+const RPC_TARGETS: &[&str] = &["gwasm_api", "golem_rpc_api", "actix_wamp", "g_flite::golem"];
+
+fn is_rpc(target: &str) -> bool {
+    RPC_TARGETS.iter().any(|rpc| target.starts_with(rpc))
+}
+
+/// Logger tracing all communication with the Golem node to a file
+///
+/// Records of the RPC crates are written to the file at all levels, with
+/// anything resembling a secret redacted; all other records go to the
+/// regular logger, if logging is turned on.
+struct RpcLogger {
+    file: Mutex<LineWriter<File>>,
+    secrets: Regex,
+    inner: Option<env_logger::Logger>,
+}
+
+impl RpcLogger {
+    fn redact(&self, message: &str) -> String {
+        self.secrets
+            .replace_all(message, "${1}\"<redacted>\"")
+            .into_owned()
+    }
+}
+
+impl Log for RpcLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        is_rpc(metadata.target())
+            || self
+                .inner
+                .as_ref()
+                .filter(|inner| inner.enabled(metadata))
+                .is_some()
+    }
+
+    fn log(&self, record: &Record) {
+        if let Some(inner) = &self.inner {
+            if inner.matches(record) {
+                inner.log(record);
+            }
+        }
+        if !is_rpc(record.target()) {
+            return;
+        }
+
+        let message = self.redact(&record.args().to_string());
+        if let Ok(mut file) = self.file.lock() {
+            // nowhere to report failing to write the log to
+            let _ = writeln!(
+                file,
+                "{} {} {} {}",
+                Local::now().to_rfc3339(),
+                record.level(),
+                record.target(),
+                message
+            );
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(inner) = &self.inner {
+            inner.flush();
+        }
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.flush();
+        }
+    }
+}
+
+/// Installs logger tracing RPC communication to the file, forwarding other
+/// records to `inner`
+pub fn init<P: AsRef<Path>>(path: P, inner: Option<env_logger::Logger>) -> Result<()> {
+    let path = path.as_ref();
+    let file =
+        File::create(path).with_context(|| format!("creating RPC log '{}'", path.display()))?;
+    let secrets = Regex::new(
+        r#"(?i)("?(?:secret|password|passwd|token|private_key|signature)"?\s*[:=]\s*)("[^"]*"|'[^']*'|[^\s,}\]]+)"#,
+    )
+    .context("compiling secrets pattern")?;
+
+    let logger = RpcLogger {
+        file: Mutex::new(LineWriter::new(file)),
+        secrets,
+        inner,
+    };
+    log::set_boxed_logger(Box::new(logger)).context("installing RPC logger")?;
+    log::set_max_level(LevelFilter::Trace);
+    Ok(())
+}
@@ -0,0 +1,133 @@
+use crate::currency::Rate;
+use crate::failure;
+use crate::telemetry::Telemetry;
+use crate::verify::OutputDigest;
+use crate::voice::Voice;
+use anyhow::{anyhow, Context, Result};
+use chrono::Local;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Returns path to g_flite's own state dir
+pub fn state_dir() -> Result<PathBuf> {
+    appdirs::user_data_dir(Some("g_flite"), Some("golem"), false)
+        .map_err(|_| anyhow!("no standard project app datadirs available"))
+}
+
+/// Returns path to the run history store
+pub fn runs_dir() -> Result<PathBuf> {
+    Ok(state_dir()?.join("runs"))
+}
+
+/// Single invocation of g_flite with its dir in the run history store
+#[derive(Debug)]
+pub struct Run {
+    id: String,
+    dir: PathBuf,
+    started: String,
+}
+
+impl Run {
+    pub const MANIFEST: &'static str = "run.json";
+    /// Report of the error the run failed with, if it did
+    pub const ERROR: &'static str = "error.json";
+
+    pub fn create() -> Result<Self> {
+        let runs_dir = runs_dir()?;
+        fs::create_dir_all(&runs_dir)
+            .with_context(|| format!("creating run history dir '{}'", runs_dir.display()))?;
+
+        let now = Local::now();
+        let base_id = now.format("%Y%m%d-%H%M%S").to_string();
+        let mut id = base_id.clone();
+        let mut suffix = 1;
+        while runs_dir.join(&id).exists() {
+            suffix += 1;
+            id = format!("{}-{}", base_id, suffix);
+        }
+
+        let dir = runs_dir.join(&id);
+        fs::create_dir(&dir).with_context(|| format!("creating run dir '{}'", dir.display()))?;
+
+        Ok(Self {
+            id,
+            dir,
+            started: now.to_rfc3339(),
+        })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn started(&self) -> &str {
+        &self.started
+    }
+
+    /// Records the error the run failed with
+    pub fn save_error(&self, error: &anyhow::Error) -> Result<PathBuf> {
+        let path = self.dir.join(Run::ERROR);
+        let contents = serde_json::to_vec_pretty(&failure::Report::new(error))
+            .context("serializing error report")?;
+        fs::write(&path, contents)
+            .with_context(|| format!("writing error report to '{}'", path.display()))?;
+        Ok(path)
+    }
+}
+
+/// Manifest summarizing a finished run
+#[derive(Debug, Serialize)]
+pub struct RunManifest {
+    pub id: String,
+    pub version: String,
+    pub started: String,
+    pub finished: String,
+    pub inputs: Vec<PathBuf>,
+    /// Name of the Golem task computing the run
+    pub task_name: String,
+    /// Workspace dir of the task's last attempt
+    pub task_dir: PathBuf,
+    pub outputs: Vec<RunOutput>,
+    pub subtasks: u64,
+    /// Transaction fees in ETH estimated before submitting on mainnet
+    pub estimated_fee_eth: Option<f64>,
+    /// Exchange rate costs were shown in with `--currency`
+    pub rate: Option<Rate>,
+    pub telemetry: Telemetry,
+}
+
+/// Output files synthesized with a single voice
+#[derive(Debug, Serialize)]
+pub struct RunOutput {
+    pub voice: Option<Voice>,
+    pub files: Vec<PathBuf>,
+    /// Checksums and fingerprints of the files, which `g_flite verify`
+    /// checks files against
+    pub digests: Vec<OutputDigest>,
+}
+
+impl RunOutput {
+    pub fn new(voice: Option<Voice>, files: Vec<PathBuf>) -> Result<Self> {
+        let digests = OutputDigest::of_files(&files)?;
+        Ok(Self {
+            voice,
+            files,
+            digests,
+        })
+    }
+}
+
+impl RunManifest {
+    pub fn save(&self, run: &Run) -> Result<PathBuf> {
+        let path = run.dir().join(Run::MANIFEST);
+        let contents = serde_json::to_vec_pretty(self).context("serializing run manifest")?;
+        fs::write(&path, contents)
+            .with_context(|| format!("writing run manifest to '{}'", path.display()))?;
+        Ok(path)
+    }
+}
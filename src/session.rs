@@ -0,0 +1,167 @@
+use crate::config::SessionConfig;
+use anyhow::{anyhow, bail, Context, Result};
+use gwasm_api::prelude::*;
+use native_tls::TlsConnector;
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the session is checked for going idle
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Computes the task on the node like gwasm-api's `compute`, within the
+/// session limits set in the config file
+///
+/// The node's RPC endpoint is checked for accepting a TLS connection first,
+/// so that an unreachable node fails the run rather than hanging it. While
+/// computing, gwasm-api polls the node for the task's progress every couple
+/// of seconds, so a session which stops reporting it for the idle timeout
+/// is given up on, leaving the task on the node.
+pub fn compute(
+    datadir: PathBuf,
+    address: String,
+    port: u16,
+    net: Net,
+    task: Task,
+    progress_handler: impl ProgressUpdate + Send + 'static,
+    config: SessionConfig,
+) -> Result<ComputedTask> {
+    if let Some(timeout) = config.handshake_timeout {
+        check_handshake(&address, port, timeout)?;
+    }
+
+    let idle_timeout = match config.idle_timeout {
+        Some(idle_timeout) => idle_timeout,
+        None => {
+            return gwasm_api::compute(datadir, address, port, net, task, progress_handler)
+                .map_err(anyhow::Error::from)
+        }
+    };
+
+    let heard = Arc::new(Mutex::new(Some(Instant::now())));
+    let heartbeat = Heartbeat {
+        inner: progress_handler,
+        heard: heard.clone(),
+    };
+    let (sender, receiver) = mpsc::channel();
+    // the thread is left blocked on a wedged session, which the process
+    // exiting takes care of
+    thread::spawn(move || {
+        let _ = sender.send(gwasm_api::compute(
+            datadir, address, port, net, task, heartbeat,
+        ));
+    });
+
+    loop {
+        match receiver.recv_timeout(IDLE_CHECK_INTERVAL) {
+            Ok(computed) => return computed.map_err(anyhow::Error::from),
+            Err(RecvTimeoutError::Disconnected) => {
+                bail!("RPC session with the node ended without a result")
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+
+        if let Some(heard) = heard.lock().ok().and_then(|heard| *heard) {
+            if heard.elapsed() >= idle_timeout {
+                bail!(
+                    "RPC session with the node went idle for {}; the task may still be \
+                     computed on the node, see `g_flite tasks`",
+                    humantime::format_duration(idle_timeout)
+                );
+            }
+        }
+    }
+}
+
+/// Checks that the node's RPC endpoint completes a TLS handshake within the
+/// timeout
+///
+/// The node's certificate is self-signed, so it isn't verified; this only
+/// tells whether anything answers.
+fn check_handshake(address: &str, port: u16, timeout: Duration) -> Result<()> {
+    let endpoint = format!("{}:{}", address, port);
+    let unanswered = || {
+        anyhow!(
+            "node's RPC endpoint at {} didn't complete the handshake within {}",
+            endpoint,
+            humantime::format_duration(timeout)
+        )
+    };
+    let is_timeout = |e: &io::Error| {
+        e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock
+    };
+
+    let deadline = Instant::now() + timeout;
+    let addr = (address, port)
+        .to_socket_addrs()
+        .with_context(|| format!("resolving node's RPC endpoint {}", endpoint))?
+        .next()
+        .ok_or_else(|| anyhow!("node's RPC endpoint {} resolved to no address", endpoint))?;
+    let stream = match TcpStream::connect_timeout(&addr, timeout) {
+        Ok(stream) => stream,
+        Err(ref e) if is_timeout(e) => return Err(unanswered()),
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("connecting to node's RPC endpoint {}", endpoint))
+        }
+    };
+    let remaining = deadline
+        .checked_duration_since(Instant::now())
+        .filter(|remaining| *remaining > Duration::from_millis(0))
+        .ok_or_else(unanswered)?;
+    stream.set_read_timeout(Some(remaining))?;
+    stream.set_write_timeout(Some(remaining))?;
+
+    let connector = TlsConnector::builder()
+        .danger_accept_invalid_certs(true)
+        .danger_accept_invalid_hostnames(true)
+        .build()
+        .context("setting up TLS")?;
+    match connector.connect(address, stream) {
+        Ok(_) => {
+            log::debug!("Node's RPC endpoint {} is up", endpoint);
+            Ok(())
+        }
+        Err(native_tls::HandshakeError::Failure(e)) => {
+            Err(e).with_context(|| format!("connecting to node's RPC endpoint {}", endpoint))
+        }
+        Err(native_tls::HandshakeError::WouldBlock(_)) => Err(unanswered()),
+    }
+}
+
+/// Progress handler noting when the session last reported anything
+struct Heartbeat<P> {
+    inner: P,
+    /// Cleared once the task finished, as downloading the results reports
+    /// nothing
+    heard: Arc<Mutex<Option<Instant>>>,
+}
+
+impl<P> Heartbeat<P> {
+    fn beat(&self, alive: bool) {
+        if let Ok(mut heard) = self.heard.lock() {
+            *heard = if alive { Some(Instant::now()) } else { None };
+        }
+    }
+}
+
+impl<P: ProgressUpdate> ProgressUpdate for Heartbeat<P> {
+    fn update(&self, progress: f64) {
+        self.beat(true);
+        self.inner.update(progress)
+    }
+
+    fn start(&self) {
+        self.beat(true);
+        self.inner.start()
+    }
+
+    fn stop(&self) {
+        self.beat(false);
+        self.inner.stop()
+    }
+}
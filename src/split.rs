@@ -0,0 +1,815 @@
+use crate::voice::Voice;
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use std::collections::HashSet;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{fmt, str::FromStr};
+
+/// Piece of input text synthesized within a single Golem subtask
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub text: String,
+    /// Index of the chapter the chunk belongs to
+    pub chapter: usize,
+    /// Voice overriding the default one for this chunk
+    pub voice: Option<Voice>,
+    /// Silence following the chunk's audio
+    pub pause: Option<Duration>,
+    /// Extra flite arguments, e.g. changing the speaking rate
+    pub args: Vec<String>,
+}
+
+/// Compiles chapter heading pattern matched against the start of each line
+pub fn chapter_pattern(pattern: &str) -> Result<Regex> {
+    Regex::new(&format!("(?m)^(?:{})", pattern))
+        .with_context(|| format!("parsing chapter pattern '{}'", pattern))
+}
+
+/// Splits text into chapters starting at lines matching `pattern`
+///
+/// Any text preceding the first chapter heading forms a chapter of its own.
+fn split_chapters<'a>(contents: &'a str, pattern: Option<&Regex>) -> Vec<&'a str> {
+    let pattern = match pattern {
+        Some(pattern) => pattern,
+        None => return vec![contents],
+    };
+
+    let mut chapters = Vec::new();
+    let mut start = 0;
+    for heading in pattern.find_iter(contents) {
+        chapters.push(&contents[start..heading.start()]);
+        start = heading.start();
+    }
+    chapters.push(&contents[start..]);
+
+    chapters
+        .into_iter()
+        .filter(|chapter| chapter.split_whitespace().next().is_some())
+        .collect()
+}
+
+/// Returns titles of chapters found in text, i.e. their first non-empty lines
+///
+/// The titles are indexed the same way as `Chunk::chapter`.
+pub fn chapter_titles(contents: &str, pattern: Option<&Regex>) -> Vec<String> {
+    split_chapters(contents, pattern)
+        .into_iter()
+        .map(first_line)
+        .collect()
+}
+
+/// Returns titles of the sections, i.e. their first non-empty lines
+pub fn section_titles(sections: &[String]) -> Vec<String> {
+    sections.iter().map(|section| first_line(section)).collect()
+}
+
+fn first_line(text: &str) -> String {
+    text.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or_default()
+        .to_owned()
+}
+
+/// Strategy of splitting text into chunks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SplitBy {
+    /// Chunks of at most equal word count, regardless of where they fall
+    Words,
+    /// Chunks of roughly equal word count, only ever ending with a sentence
+    Sentence,
+}
+
+impl FromStr for SplitBy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "words" => Ok(SplitBy::Words),
+            "sentence" => Ok(SplitBy::Sentence),
+            _ => bail!(
+                "unknown split strategy '{}'; expected one of: words, sentence",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for SplitBy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SplitBy::Words => write!(f, "words"),
+            SplitBy::Sentence => write!(f, "sentence"),
+        }
+    }
+}
+
+/// Upper bound on the number of subtasks picked automatically
+const MAX_AUTO_SUBTASKS: u64 = 100;
+
+/// Number of subtasks the input is split into
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Subtasks {
+    Count(u64),
+    /// As many subtasks as it takes to keep each at most this many words long
+    WordsPerSubtask(u64),
+    /// Like `WordsPerSubtask`, but picked by g_flite, so the number of
+    /// subtasks is capped
+    Auto(u64),
+}
+
+impl Subtasks {
+    /// Picks the number of subtasks or words per subtask, whichever is set,
+    /// or else `auto_words` per subtask
+    pub fn new(
+        count: Option<u64>,
+        words_per_subtask: Option<u64>,
+        auto_words: u64,
+    ) -> Result<Self> {
+        match (count, words_per_subtask) {
+            (_, Some(0)) => bail!("--words-per-subtask has to be at least 1"),
+            (_, Some(words)) => Ok(Subtasks::WordsPerSubtask(words)),
+            (Some(count), None) => Ok(Subtasks::Count(count)),
+            (None, None) => Ok(Subtasks::Auto(auto_words.max(1))),
+        }
+    }
+
+    /// Resolves the number of subtasks for text of given word count
+    pub fn count(self, word_count: usize) -> u64 {
+        let subtasks = |words| ((word_count as f64 / words as f64).ceil() as u64).max(1);
+        match self {
+            Subtasks::Count(count) => count,
+            Subtasks::WordsPerSubtask(words) => subtasks(words),
+            Subtasks::Auto(words) => {
+                let count = subtasks(words).min(MAX_AUTO_SUBTASKS);
+                log::info!(
+                    "Picked {} subtasks for input of {} words",
+                    count,
+                    word_count
+                );
+                count
+            }
+        }
+    }
+}
+
+/// Returns number of words in the texts
+pub fn word_count<S: AsRef<str>>(texts: &[S]) -> usize {
+    texts
+        .iter()
+        .map(|text| text.as_ref().split_whitespace().count())
+        .sum()
+}
+
+/// Abbreviations whose full stop doesn't end a sentence
+const ABBREVIATIONS: &[&str] = &[
+    "mr.", "mrs.", "ms.", "dr.", "prof.", "st.", "mt.", "no.", "vs.", "cf.", "e.g.", "i.e.",
+    "approx.", "fig.",
+];
+
+/// Checks whether the word ends a sentence, i.e. ends with a full stop,
+/// question or exclamation mark, possibly followed by closing quotes
+///
+/// Full stops of common abbreviations and initials such as `J.` are not
+/// taken for sentence ends.
+pub fn ends_sentence(word: &str) -> bool {
+    let word = word.trim_end_matches(&['"', '\'', ')', ']', '»', '”', '’'][..]);
+    if word.ends_with(&['!', '?', '…'][..]) {
+        return true;
+    }
+    if !word.ends_with('.') {
+        return false;
+    }
+
+    let stem = word.trim_start_matches(&['"', '\'', '(', '[', '«', '“', '‘'][..]);
+    let initial = stem.chars().count() == 2 && stem.starts_with(char::is_uppercase);
+    !initial && !ABBREVIATIONS.contains(&stem.to_lowercase().as_str())
+}
+
+/// Returns indices one past the last word of each sentence, the last one
+/// ending the words whether or not they end with a full stop
+fn sentence_ends(words: &[&str]) -> Vec<usize> {
+    let mut ends: Vec<_> = words
+        .iter()
+        .enumerate()
+        .filter(|(_, word)| ends_sentence(word))
+        .map(|(i, _)| i + 1)
+        .collect();
+    if ends.last() != Some(&words.len()) {
+        ends.push(words.len());
+    }
+    ends
+}
+
+/// Returns ranges of words making up `count` chunks of roughly equal length,
+/// each ending with a sentence
+///
+/// Each chunk ends at the sentence end nearest to where it would end if
+/// split by words. Sentences longer than a chunk are kept whole, which may
+/// leave fewer chunks than asked for.
+fn sentence_ranges(words: &[&str], count: usize) -> Vec<Range<usize>> {
+    let ends = sentence_ends(words);
+
+    let mut ranges = Vec::with_capacity(count);
+    let mut start = 0;
+    for i in 1..=count {
+        let ideal = i * words.len() / count;
+        let end = ends
+            .iter()
+            .cloned()
+            .filter(|&end| end > start)
+            .min_by_key(|&end| (end as i64 - ideal as i64).abs())
+            .unwrap_or(words.len());
+        if end > start {
+            ranges.push(start..end);
+            start = end;
+        }
+    }
+    ranges
+}
+
+/// Run of words to be read with the same voice
+struct VoiceRun<'a> {
+    voice: Option<Voice>,
+    words: Vec<&'a str>,
+}
+
+/// Compiles pattern of the `[voice:<name>]` directives, once per split
+fn voice_directive() -> Regex {
+    Regex::new(r"(?i)\[voice:[ \t]*([a-z0-9]+)[ \t]*\]").expect("valid voice directive regex")
+}
+
+/// Splits words of the text into runs at `[voice:<name>]` directives, each
+/// switching the voice of the words following it, `[voice:default]` back to
+/// the default one
+fn voice_runs<'a>(text: &'a str, directive: &Regex) -> Result<Vec<VoiceRun<'a>>> {
+    let mut runs = Vec::new();
+    let mut voice = None;
+    let mut last = 0;
+    for caps in directive.captures_iter(text) {
+        let whole = caps.get(0).unwrap();
+        runs.push(VoiceRun {
+            voice,
+            words: text[last..whole.start()].split_whitespace().collect(),
+        });
+        voice = match &caps[1] {
+            name if name.eq_ignore_ascii_case("default") => None,
+            name => Some(
+                name.parse()
+                    .with_context(|| format!("parsing voice directive '{}'", whole.as_str()))?,
+            ),
+        };
+        last = whole.end();
+    }
+    runs.push(VoiceRun {
+        voice,
+        words: text[last..].split_whitespace().collect(),
+    });
+
+    runs.retain(|run| !run.words.is_empty());
+    Ok(runs)
+}
+
+/// Splits text into chunks of at most equal word count, or ending with
+/// sentences if `split_by` says so
+///
+/// Chunks never span chapter boundaries, therefore the number of chunks may
+/// exceed `num_subtasks` by up to the number of chapters found. The same
+/// goes for switches of voice.
+pub fn split(
+    contents: &str,
+    num_subtasks: u64,
+    chapter_pattern: Option<&Regex>,
+    split_by: SplitBy,
+) -> Result<Vec<Chunk>> {
+    let chapters = split_chapters(contents, chapter_pattern);
+    if chapter_pattern.is_some() {
+        log::info!("Input text file has {} chapters", chapters.len());
+    }
+
+    split_chapter_texts(&chapters, num_subtasks, split_by)
+}
+
+/// Splits sections of text, e.g. ones under Markdown headers, into chunks,
+/// treating each section as a chapter
+pub fn split_sections(
+    sections: &[String],
+    num_subtasks: u64,
+    split_by: SplitBy,
+) -> Result<Vec<Chunk>> {
+    let chapters: Vec<_> = sections.iter().map(String::as_str).collect();
+    split_chapter_texts(&chapters, num_subtasks, split_by)
+}
+
+fn split_chapter_texts(
+    chapters: &[&str],
+    num_subtasks: u64,
+    split_by: SplitBy,
+) -> Result<Vec<Chunk>> {
+    let directive = voice_directive();
+    let chapters = chapters
+        .iter()
+        .map(|chapter| voice_runs(chapter, &directive))
+        .collect::<Result<Vec<_>>>()?;
+    let word_count: usize = chapters.iter().flatten().map(|run| run.words.len()).sum();
+
+    if (word_count as u64) < num_subtasks {
+        bail!(
+            "splitting input into Golem subtasks: cannot split input of {} words into {} subtasks",
+            word_count,
+            num_subtasks
+        );
+    }
+
+    log::info!("Input text file has {} words", word_count);
+
+    let mut chunks = Vec::with_capacity(num_subtasks as usize);
+    let num_words = (word_count as f64 / num_subtasks as f64).ceil() as usize;
+
+    log::info!("Each chunk will have max {} words", num_words);
+
+    for (chapter, runs) in chapters.iter().enumerate() {
+        for VoiceRun { voice, words } in runs {
+            if split_by == SplitBy::Sentence {
+                let count = (words.len() as f64 / num_words as f64).ceil() as usize;
+                for range in sentence_ranges(words, count) {
+                    chunks.push(Chunk {
+                        text: words[range].join(" "),
+                        chapter,
+                        voice: *voice,
+                        pause: None,
+                        args: Vec::new(),
+                    });
+                }
+                continue;
+            }
+
+            for words in words.chunks(num_words) {
+                chunks.push(Chunk {
+                    text: words.join(" "),
+                    chapter,
+                    voice: *voice,
+                    pause: None,
+                    args: Vec::new(),
+                });
+            }
+        }
+    }
+
+    if log::log_enabled!(log::Level::Info) {
+        for (i, chunk) in chunks.iter().enumerate() {
+            log::info!(
+                "Chunk {} has {} words",
+                i,
+                chunk.text.split_whitespace().count()
+            );
+        }
+    }
+
+    Ok(chunks)
+}
+
+/// Splits chapters of text into chunks of a single sentence each, e.g. to
+/// have each sentence repeated
+pub fn split_sentences(chapters: &[&str]) -> Result<Vec<Chunk>> {
+    let directive = voice_directive();
+    let mut chunks = Vec::new();
+    for (chapter, text) in chapters.iter().enumerate() {
+        for VoiceRun { voice, words } in voice_runs(text, &directive)? {
+            let mut start = 0;
+            for end in sentence_ends(&words) {
+                chunks.push(Chunk {
+                    text: words[start..end].join(" "),
+                    chapter,
+                    voice,
+                    pause: None,
+                    args: Vec::new(),
+                });
+                start = end;
+            }
+        }
+    }
+
+    if chunks.is_empty() {
+        bail!("splitting input into Golem subtasks: input has no sentences");
+    }
+
+    log::info!("Input text file has {} sentences", chunks.len());
+
+    Ok(chunks)
+}
+
+/// Splits text into chapters at lines matching the pattern and then into
+/// chunks of a single sentence each
+pub fn split_text_sentences(contents: &str, chapter_pattern: Option<&Regex>) -> Result<Vec<Chunk>> {
+    split_sentences(&split_chapters(contents, chapter_pattern))
+}
+
+/// Format of the input text
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputFormat {
+    /// Plain text, read as is
+    Text,
+    /// Speech Synthesis Markup Language document
+    Ssml,
+    /// Markdown document, its headers starting chapters
+    Markdown,
+    /// HTML page, e.g. a saved web page, its readable text extracted
+    Html,
+    /// EPUB book, its spine documents making up chapters
+    Epub,
+}
+
+impl InputFormat {
+    /// Guesses format of the input from its extension
+    pub fn detect(input: &Path) -> Self {
+        let extension = input
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_lowercase);
+        match extension.as_deref() {
+            Some("html") | Some("htm") | Some("xhtml") => InputFormat::Html,
+            Some("epub") => InputFormat::Epub,
+            _ => InputFormat::Text,
+        }
+    }
+
+    /// Returns content types input of the format is served with when
+    /// downloaded
+    ///
+    /// Markup which is read as text anyway, such as Markdown, is often served
+    /// as plain text.
+    pub fn content_types(self) -> &'static [&'static str] {
+        match self {
+            InputFormat::Text => &["text/plain"],
+            InputFormat::Ssml => &[
+                "application/ssml+xml",
+                "application/xml",
+                "text/xml",
+                "text/plain",
+            ],
+            InputFormat::Markdown => &["text/markdown", "text/x-markdown", "text/plain"],
+            InputFormat::Html => &["text/html", "application/xhtml+xml"],
+            InputFormat::Epub => &["application/epub+zip"],
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(InputFormat::Text),
+            "ssml" => Ok(InputFormat::Ssml),
+            "markdown" | "md" => Ok(InputFormat::Markdown),
+            "html" => Ok(InputFormat::Html),
+            "epub" => Ok(InputFormat::Epub),
+            _ => bail!(
+                "unknown input format '{}'; expected one of: text, ssml, markdown, html, epub",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputFormat::Text => write!(f, "text"),
+            InputFormat::Ssml => write!(f, "ssml"),
+            InputFormat::Markdown => write!(f, "markdown"),
+            InputFormat::Html => write!(f, "html"),
+            InputFormat::Epub => write!(f, "epub"),
+        }
+    }
+}
+
+/// Naming scheme of output files in per-line mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineNames {
+    /// Zero-padded line counter, e.g. `0042`
+    Counter,
+    /// First few words of the line, e.g. `please_hold_the_line`
+    Words,
+}
+
+impl FromStr for LineNames {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "counter" => Ok(LineNames::Counter),
+            "words" => Ok(LineNames::Words),
+            _ => bail!(
+                "unknown naming scheme '{}'; expected one of: counter, words",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for LineNames {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LineNames::Counter => write!(f, "counter"),
+            LineNames::Words => write!(f, "words"),
+        }
+    }
+}
+
+/// Maximum number of words making up a line's name
+const NAME_WORDS: usize = 5;
+
+/// Splits text into chunks of a single non-empty line each
+///
+/// Every line becomes a chapter of its own, so that lines are synthesized
+/// independently of each other.
+pub fn split_lines(contents: &str) -> Result<Vec<Chunk>> {
+    let chunks: Vec<_> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .enumerate()
+        .map(|(chapter, line)| Chunk {
+            text: line.to_owned(),
+            chapter,
+            voice: None,
+            pause: None,
+            args: Vec::new(),
+        })
+        .collect();
+
+    if chunks.is_empty() {
+        bail!("splitting input into Golem subtasks: input has no non-empty lines");
+    }
+
+    log::info!("Input text file has {} non-empty lines", chunks.len());
+
+    Ok(chunks)
+}
+
+/// Interleaving of the input with its translation, pair by pair, e.g. for
+/// language learning
+#[derive(Debug, Clone)]
+pub struct Interleave {
+    /// Translation of the input, line by line, unless the input holds
+    /// tab-separated pairs
+    pub translation: Option<PathBuf>,
+    /// Voice the translations are read with, otherwise the default one
+    pub voice: Option<Voice>,
+    /// Silence between the source and its translation
+    pub translation_gap: Duration,
+    /// Silence following each pair
+    pub pair_gap: Duration,
+}
+
+/// Returns non-empty lines of text, trimmed
+fn non_empty_lines(contents: &str) -> Vec<&str> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+/// Pairs non-empty lines of the input with the ones of its translation, in
+/// order
+pub fn parallel_pairs(contents: &str, translation: &str) -> Result<Vec<(String, String)>> {
+    let sources = non_empty_lines(contents);
+    let translations = non_empty_lines(translation);
+    if sources.len() != translations.len() {
+        bail!(
+            "pairing input with its translation: input has {} non-empty lines, while the translation has {}",
+            sources.len(),
+            translations.len()
+        );
+    }
+
+    Ok(sources
+        .into_iter()
+        .zip(translations)
+        .map(|(source, translation)| (source.to_owned(), translation.to_owned()))
+        .collect())
+}
+
+/// Reads pairs from tab-separated lines, e.g. `Good morning.<TAB>Guten Morgen.`
+pub fn tsv_pairs(contents: &str) -> Result<Vec<(String, String)>> {
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            let mut fields = line.splitn(2, '\t');
+            match (fields.next(), fields.next()) {
+                (Some(source), Some(translation))
+                    if !source.trim().is_empty() && !translation.trim().is_empty() =>
+                {
+                    Ok((source.trim().to_owned(), translation.trim().to_owned()))
+                }
+                _ => bail!(
+                    "pairing input: line {}: expected <source> TAB <translation>",
+                    i + 1
+                ),
+            }
+        })
+        .collect()
+}
+
+/// Turns pairs into chunks of the source followed by its translation, each
+/// chunk followed by its gap
+///
+/// All chunks make up a single chapter.
+pub fn interleave(pairs: &[(String, String)], interleave: &Interleave) -> Result<Vec<Chunk>> {
+    if pairs.is_empty() {
+        bail!("splitting input into Golem subtasks: input has no pairs");
+    }
+
+    log::info!("Input text file has {} pairs", pairs.len());
+
+    let mut chunks = Vec::with_capacity(pairs.len() * 2);
+    for (source, translation) in pairs {
+        chunks.push(Chunk {
+            text: source.clone(),
+            chapter: 0,
+            voice: None,
+            pause: Some(interleave.translation_gap),
+            args: Vec::new(),
+        });
+        chunks.push(Chunk {
+            text: translation.clone(),
+            chapter: 0,
+            voice: interleave.voice,
+            pause: Some(interleave.pair_gap),
+            args: Vec::new(),
+        });
+    }
+    Ok(chunks)
+}
+
+/// Follows each chunk but the last with at least `gap` of silence, so that
+/// the joins don't sound abrupt
+pub fn separate(chunks: &mut [Chunk], gap: Duration) {
+    if let Some((_, init)) = chunks.split_last_mut() {
+        for chunk in init {
+            chunk.pause = Some(chunk.pause.map_or(gap, |pause| pause.max(gap)));
+        }
+    }
+}
+
+/// Returns unique file stems for the lines
+pub fn line_names(chunks: &[Chunk], names: LineNames) -> Vec<String> {
+    let width = chunks.len().to_string().len().max(4);
+    let mut taken = HashSet::new();
+
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            let counter = format!("{:0width$}", i + 1, width = width);
+            let name = match names {
+                LineNames::Counter => counter.clone(),
+                LineNames::Words => slug(&chunk.text).unwrap_or_else(|| counter.clone()),
+            };
+
+            // lines starting with the same words are told apart by the counter
+            if taken.insert(name.clone()) {
+                name
+            } else {
+                let name = format!("{}_{}", name, counter);
+                taken.insert(name.clone());
+                name
+            }
+        })
+        .collect()
+}
+
+/// Returns file stems for the chapters, made of their index and first few
+/// words of their title, e.g. `03_the_last_voyage`
+pub fn chapter_names(titles: &[String]) -> Vec<String> {
+    let width = titles.len().to_string().len().max(2);
+    titles
+        .iter()
+        .enumerate()
+        .map(|(i, title)| {
+            let counter = format!("{:0width$}", i + 1, width = width);
+            match slug(title) {
+                Some(slug) => format!("{}_{}", counter, slug),
+                None => counter,
+            }
+        })
+        .collect()
+}
+
+/// Joins first few words of the text, lowercased and stripped of anything
+/// but letters and digits, with underscores
+fn slug(text: &str) -> Option<String> {
+    let words: Vec<_> = text
+        .split_whitespace()
+        .map(|word| {
+            word.chars()
+                .filter(|c| c.is_alphanumeric())
+                .collect::<String>()
+                .to_lowercase()
+        })
+        .filter(|word| !word.is_empty())
+        .take(NAME_WORDS)
+        .collect();
+    if words.is_empty() {
+        None
+    } else {
+        Some(words.join("_"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tells_sentence_ends() {
+        let cases = [
+            ("end.", true),
+            ("really?", true),
+            ("stop!", true),
+            ("so…", true),
+            ("said.\"", true),
+            ("(aside.)", true),
+            ("word", false),
+            ("word,", false),
+            ("Mr.", false),
+            ("Dr.", false),
+            ("e.g.", false),
+            ("(e.g.", false),
+            ("J.", false),
+            ("I.", false),
+            ("it.", true),
+        ];
+        for &(word, expected) in &cases {
+            assert_eq!(ends_sentence(word), expected, "{:?}", word);
+        }
+    }
+
+    #[test]
+    fn splits_at_sentence_ends() {
+        let cases: &[(&str, usize, &[&str])] = &[
+            ("One. Two. Three. Four.", 2, &["One. Two.", "Three. Four."]),
+            ("One two three. Four.", 2, &["One two three.", "Four."]),
+            (
+                "One. Two three four five six.",
+                2,
+                &["One.", "Two three four five six."],
+            ),
+            (
+                "A long sentence without end",
+                3,
+                &["A long sentence without end"],
+            ),
+            (
+                "Mr. Smith left. Dr. Jones stayed.",
+                2,
+                &["Mr. Smith left.", "Dr. Jones stayed."],
+            ),
+            ("One. Two.", 4, &["One.", "Two."]),
+        ];
+        for &(text, count, expected) in cases {
+            let words: Vec<_> = text.split_whitespace().collect();
+            let chunks: Vec<_> = sentence_ranges(&words, count)
+                .into_iter()
+                .map(|range| words[range].join(" "))
+                .collect();
+            assert_eq!(chunks, expected, "{:?} into {}", text, count);
+        }
+    }
+
+    #[test]
+    fn splits_into_single_sentences() {
+        let cases: &[(&str, &[(&str, usize)])] = &[
+            ("One. Two? Three", &[("One.", 0), ("Two?", 0), ("Three", 0)]),
+            (
+                "Preface.\nCHAPTER 1\nIt began. It ended.\nCHAPTER 2\nAgain.",
+                &[
+                    ("Preface.", 0),
+                    ("CHAPTER 1 It began.", 1),
+                    ("It ended.", 1),
+                    ("CHAPTER 2 Again.", 2),
+                ],
+            ),
+        ];
+        let pattern = chapter_pattern("CHAPTER").unwrap();
+        for &(text, expected) in cases {
+            let chunks: Vec<_> = split_text_sentences(text, Some(&pattern))
+                .unwrap()
+                .into_iter()
+                .map(|chunk| (chunk.text, chunk.chapter))
+                .collect();
+            let expected: Vec<_> = expected
+                .iter()
+                .map(|&(text, chapter)| (text.to_owned(), chapter))
+                .collect();
+            assert_eq!(chunks, expected, "{:?}", text);
+        }
+    }
+}
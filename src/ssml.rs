@@ -0,0 +1,452 @@
+use crate::preprocess;
+use crate::split::Chunk;
+use anyhow::{bail, Result};
+use regex::{Captures, Regex};
+use std::time::Duration;
+
+/// Run of SSML text between element boundaries
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub text: String,
+    /// Speaking rate relative to flite's default one
+    pub rate: Option<f32>,
+    /// Silence following the segment, as requested by `<break>`
+    pub pause: Option<Duration>,
+}
+
+/// Element opened and not yet closed while parsing
+struct Element {
+    name: String,
+    /// Rate set by `<prosody>`, already relative to the enclosing one
+    rate: Option<f32>,
+    /// Rendering of the content of `<say-as>` or `<sub>`
+    render: Option<Render>,
+    /// Start of the element's content within the current segment's text
+    start: usize,
+}
+
+enum Render {
+    SayAs(String),
+    Alias(String),
+}
+
+/// Parses SSML document into segments of plain text
+///
+/// Segments end at `<break>`, `<p>` and `<s>` elements and wherever the
+/// speaking rate changes. Of `<prosody>` only the rate is kept, as flite has
+/// no means of changing pitch or volume mid-utterance; other elements not
+/// understood are dropped, keeping their text.
+pub fn parse(contents: &str) -> Result<Vec<Segment>> {
+    let comments = Regex::new(r"(?s)<!--.*?-->").expect("valid comment regex");
+    let contents = comments.replace_all(contents, " ");
+    let tags = Regex::new(r"<[^>]*>").expect("valid tag regex");
+    let tag = Regex::new(r"^<(/?)([A-Za-z][\w:.-]*)((?:\s[^>]*?)?)\s*(/?)>$")
+        .expect("valid element regex");
+
+    let mut parser = Parser::default();
+    let mut last = 0;
+    for found in tags.find_iter(&contents) {
+        parser.text(&decode_entities(&contents[last..found.start()]));
+        last = found.end();
+
+        let found = found.as_str();
+        // XML declaration, doctype and processing instructions
+        if found.starts_with("<?") || found.starts_with("<!") {
+            continue;
+        }
+        let caps = match tag.captures(found) {
+            Some(caps) => caps,
+            None => bail!("malformed SSML tag '{}'", found),
+        };
+        let name = caps[2].to_lowercase();
+        if !caps[1].is_empty() {
+            parser.close(&name)?;
+        } else {
+            let attributes = Attributes::parse(&caps[3]);
+            parser.open(&name, &attributes)?;
+            if !caps[4].is_empty() {
+                parser.close(&name)?;
+            }
+        }
+    }
+    parser.text(&decode_entities(&contents[last..]));
+
+    if let Some(element) = parser.elements.last() {
+        bail!("SSML element <{}> is never closed", element.name);
+    }
+    parser.flush(None);
+
+    if parser.segments.is_empty() {
+        bail!("SSML input has no text to synthesize");
+    }
+
+    Ok(parser.segments)
+}
+
+#[derive(Default)]
+struct Parser {
+    segments: Vec<Segment>,
+    elements: Vec<Element>,
+    text: String,
+}
+
+impl Parser {
+    fn rate(&self) -> Option<f32> {
+        self.elements.iter().rev().filter_map(|e| e.rate).next()
+    }
+
+    fn text(&mut self, text: &str) {
+        self.text.push_str(text);
+    }
+
+    fn open(&mut self, name: &str, attributes: &Attributes) -> Result<()> {
+        let mut rate = None;
+        let mut render = None;
+        match name {
+            "break" => {
+                let pause = match (attributes.get("time"), attributes.get("strength")) {
+                    (Some(time), _) => parse_time(time)?,
+                    (None, Some(strength)) => parse_strength(strength)?,
+                    (None, None) => parse_strength("medium")?,
+                };
+                self.boundary(name, Some(pause))?;
+            }
+            "p" | "s" | "paragraph" | "sentence" => self.boundary(name, None)?,
+            "prosody" => {
+                if let Some(value) = attributes.get("rate") {
+                    let parent = self.rate().unwrap_or(1.0);
+                    rate = Some(parent * parse_rate(value)?);
+                    self.boundary(name, None)?;
+                }
+                for attribute in &["pitch", "range", "contour", "volume"] {
+                    if attributes.get(attribute).is_some() {
+                        log::warn!(
+                            "flite can't change {} within text; ignoring <prosody {}>",
+                            attribute,
+                            attribute
+                        );
+                    }
+                }
+            }
+            "say-as" => {
+                let interpret = attributes
+                    .get("interpret-as")
+                    .unwrap_or_default()
+                    .to_lowercase();
+                render = Some(Render::SayAs(interpret));
+            }
+            "sub" => {
+                render = attributes
+                    .get("alias")
+                    .map(|alias| Render::Alias(alias.to_owned()));
+            }
+            "speak" | "voice" | "emphasis" | "mark" | "lang" | "audio" | "phoneme" | "w"
+            | "token" => {}
+            _ => log::warn!("Unsupported SSML element <{}>; reading its text only", name),
+        }
+
+        if name != "break" {
+            if let (Some(_), Some(outer)) =
+                (&render, self.elements.iter().find(|e| e.render.is_some()))
+            {
+                bail!(
+                    "SSML element <{}> can't be used within <{}>",
+                    name,
+                    outer.name
+                );
+            }
+            self.elements.push(Element {
+                name: name.to_owned(),
+                rate,
+                render,
+                start: self.text.len(),
+            });
+        }
+        Ok(())
+    }
+
+    fn close(&mut self, name: &str) -> Result<()> {
+        if name == "break" {
+            return Ok(());
+        }
+        let ends_segment = match self.elements.last() {
+            Some(element) if element.name == name => {
+                element.rate.is_some() || name == "p" || name == "paragraph"
+            }
+            Some(element) => bail!("SSML element <{}> is closed by </{}>", element.name, name),
+            None => bail!("SSML element </{}> closes nothing", name),
+        };
+        // the text up to here is still spoken at the element's rate
+        if ends_segment {
+            self.boundary(name, None)?;
+        }
+
+        if let Some(element) = self.elements.pop() {
+            if let Some(render) = &element.render {
+                let content = self.text.split_off(element.start);
+                let rendered = match render {
+                    Render::SayAs(interpret) => say_as(&content, interpret),
+                    Render::Alias(alias) => alias.clone(),
+                };
+                self.text.push_str(&rendered);
+            }
+        }
+        Ok(())
+    }
+
+    /// Ends the current segment at an element boundary
+    fn boundary(&mut self, name: &str, pause: Option<Duration>) -> Result<()> {
+        if let Some(element) = self.elements.iter().find(|e| e.render.is_some()) {
+            bail!(
+                "SSML element <{}> can't be used within <{}>",
+                name,
+                element.name
+            );
+        }
+        self.flush(pause);
+        Ok(())
+    }
+
+    /// Ends the current segment, followed by given pause
+    fn flush(&mut self, pause: Option<Duration>) {
+        let text = self.text.trim().to_owned();
+        self.text.clear();
+
+        if text.is_empty() {
+            // consecutive breaks add up
+            if let (Some(pause), Some(last)) = (pause, self.segments.last_mut()) {
+                last.pause = Some(last.pause.unwrap_or_default() + pause);
+            } else if pause.is_some() {
+                log::info!("Ignoring SSML break preceding any text");
+            }
+            return;
+        }
+
+        self.segments.push(Segment {
+            text,
+            rate: self.rate(),
+            pause,
+        });
+    }
+}
+
+/// Attributes of an SSML element, e.g. `time="300ms"`
+struct Attributes(Vec<(String, String)>);
+
+impl Attributes {
+    fn parse(s: &str) -> Self {
+        let attribute = Regex::new(r#"([\w:.-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#)
+            .expect("valid attribute regex");
+        Attributes(
+            attribute
+                .captures_iter(s)
+                .map(|caps| {
+                    let value = caps.get(2).or(caps.get(3)).map_or("", |m| m.as_str());
+                    (caps[1].to_lowercase(), decode_entities(value))
+                })
+                .collect(),
+        )
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Parses duration of `<break time>`, e.g. `500ms` or `1.5s`
+fn parse_time(time: &str) -> Result<Duration> {
+    let time = time.trim();
+    let (value, scale) = if let Some(value) = time.strip_suffix("ms") {
+        (value, 1.0)
+    } else if let Some(value) = time.strip_suffix('s') {
+        (value, 1000.0)
+    } else {
+        bail!(
+            "invalid SSML break time '{}'; expected e.g. 500ms or 2s",
+            time
+        );
+    };
+    match value.trim().parse::<f64>() {
+        Ok(value) if value >= 0.0 => Ok(Duration::from_millis((value * scale).round() as u64)),
+        _ => bail!(
+            "invalid SSML break time '{}'; expected e.g. 500ms or 2s",
+            time
+        ),
+    }
+}
+
+/// Maps `<break strength>` to the length of the pause
+fn parse_strength(strength: &str) -> Result<Duration> {
+    let millis = match strength {
+        "none" => 0,
+        "x-weak" => 100,
+        "weak" => 250,
+        "medium" => 400,
+        "strong" => 750,
+        "x-strong" => 1200,
+        _ => bail!(
+            "invalid SSML break strength '{}'; expected one of: none, x-weak, weak, medium, strong, x-strong",
+            strength
+        ),
+    };
+    Ok(Duration::from_millis(millis))
+}
+
+/// Parses `<prosody rate>` relative to the enclosing rate, e.g. `slow` or `120%`
+fn parse_rate(rate: &str) -> Result<f32> {
+    let rate = rate.trim();
+    let value = match rate {
+        "x-slow" => 0.5,
+        "slow" => 0.75,
+        "medium" | "default" => 1.0,
+        "fast" => 1.25,
+        "x-fast" => 1.5,
+        _ if rate.ends_with('%') => match rate[..rate.len() - 1].parse::<f32>() {
+            // both `120%` and `+20%` are in use
+            Ok(value) if rate.starts_with('+') || rate.starts_with('-') => 1.0 + value / 100.0,
+            Ok(value) => value / 100.0,
+            Err(_) => 0.0,
+        },
+        _ => rate.parse().unwrap_or(0.0),
+    };
+    if value <= 0.0 {
+        bail!(
+            "invalid SSML prosody rate '{}'; expected e.g. slow, fast or 120%",
+            rate
+        );
+    }
+    Ok(value)
+}
+
+/// Renders content of `<say-as>` the way flite will read it as requested
+fn say_as(content: &str, interpret: &str) -> String {
+    let spaced = |filter: fn(&char) -> bool| {
+        content
+            .chars()
+            .filter(filter)
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    match interpret {
+        "characters" | "spell-out" | "verbatim" => spaced(|c| !c.is_whitespace()),
+        "digits" | "telephone" => spaced(char::is_ascii_digit),
+        "ordinal" => {
+            let ordinal = Regex::new(r"\d{1,6}").expect("valid ordinal regex");
+            ordinal
+                .replace_all(content, |caps: &Captures| match caps[0].parse() {
+                    Ok(value) => preprocess::ordinal_words(value),
+                    Err(_) => caps[0].to_owned(),
+                })
+                .into_owned()
+        }
+        // flite reads cardinals, dates and the like well enough on its own
+        _ => content.to_owned(),
+    }
+}
+
+/// Decodes XML character and predefined entity references
+fn decode_entities(text: &str) -> String {
+    let entity =
+        Regex::new(r"&(#x[0-9A-Fa-f]+|#\d+|amp|lt|gt|quot|apos);").expect("valid entity regex");
+    entity
+        .replace_all(text, |caps: &Captures| {
+            let reference = &caps[1];
+            let code = if let Some(hex) = reference.strip_prefix("#x") {
+                u32::from_str_radix(hex, 16).ok()
+            } else if let Some(decimal) = reference.strip_prefix('#') {
+                decimal.parse().ok()
+            } else {
+                None
+            };
+            match (code, reference) {
+                (Some(code), _) => std::char::from_u32(code).map_or(String::new(), String::from),
+                (None, "amp") => "&".to_owned(),
+                (None, "lt") => "<".to_owned(),
+                (None, "gt") => ">".to_owned(),
+                (None, "quot") => "\"".to_owned(),
+                (None, _) => "'".to_owned(),
+            }
+        })
+        .into_owned()
+}
+
+/// Splits segments into chunks of roughly equal word count
+///
+/// Chunks only ever end at segment boundaries, and always end at breaks and
+/// changes of speaking rate, so the number of chunks may differ from
+/// `num_subtasks` either way.
+pub fn split(segments: &[Segment], num_subtasks: u64) -> Result<Vec<Chunk>> {
+    let word_count: usize = segments
+        .iter()
+        .map(|segment| segment.text.split_whitespace().count())
+        .sum();
+
+    if (word_count as u64) < num_subtasks {
+        bail!(
+            "splitting input into Golem subtasks: cannot split input of {} words into {} subtasks",
+            word_count,
+            num_subtasks
+        );
+    }
+
+    log::info!(
+        "Input SSML has {} words in {} segments",
+        word_count,
+        segments.len()
+    );
+
+    let num_words = (word_count as f64 / num_subtasks as f64).ceil() as usize;
+    let mut chunks = Vec::with_capacity(num_subtasks as usize);
+    let mut acc: Vec<&str> = Vec::new();
+    let mut words = 0;
+
+    for (i, segment) in segments.iter().enumerate() {
+        acc.push(&segment.text);
+        words += segment.text.split_whitespace().count();
+
+        let rate_changes = segments
+            .get(i + 1)
+            .filter(|next| next.rate != segment.rate)
+            .is_some();
+        if words >= num_words || segment.pause.is_some() || rate_changes || i + 1 == segments.len()
+        {
+            chunks.push(Chunk {
+                text: acc.join(" "),
+                chapter: 0,
+                voice: None,
+                pause: segment.pause,
+                args: segment
+                    .rate
+                    .map(|rate| {
+                        vec![
+                            "--setf".to_owned(),
+                            format!("duration_stretch={}", 1.0 / rate),
+                        ]
+                    })
+                    .unwrap_or_default(),
+            });
+            acc.clear();
+            words = 0;
+        }
+    }
+
+    if log::log_enabled!(log::Level::Info) {
+        for (i, chunk) in chunks.iter().enumerate() {
+            log::info!(
+                "Chunk {} has {} words{}",
+                i,
+                chunk.text.split_whitespace().count(),
+                chunk.pause.map_or(String::new(), |pause| format!(
+                    ", followed by {:?} break",
+                    pause
+                ))
+            );
+        }
+    }
+
+    Ok(chunks)
+}
@@ -0,0 +1,126 @@
+use super::SubmitOpt;
+use crate::app::{self, ProgressUpdater};
+use crate::config::Config;
+use crate::prompt;
+use crate::recombine;
+use crate::session;
+use crate::task::{self, SubtaskDescriptor, TaskManifest};
+use anyhow::{anyhow, bail, Result};
+use gwasm_api::prelude::*;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Submits task prepared with --dry-run as it is and combines its results
+pub fn submit(opt: SubmitOpt) -> Result<()> {
+    if opt.output == Path::new("-") {
+        bail!("submit can't stream the output to stdout; use `g_flite combine` once it's computed");
+    }
+
+    let attempt_dir = if opt.dir.join(TaskManifest::FILENAME).is_file() {
+        opt.dir.clone()
+    } else {
+        task::latest_attempt_dir(&opt.dir)?
+            .ok_or_else(|| anyhow!("no prepared task found in '{}'", opt.dir.display()))?
+    };
+    let manifest = TaskManifest::load(&attempt_dir)?;
+    let task = manifest.task(&attempt_dir)?;
+
+    let subtasks = manifest.subtasks.len() as u64;
+    let cost = manifest.budget.unwrap_or(manifest.bid * subtasks as f64);
+    let config = Config::load()?;
+    let limits = config.limits;
+    if let Some(max_bid) = limits.max_bid {
+        if manifest.bid > max_bid {
+            bail!(
+                "bid of {} GNT exceeds the maximum of {} GNT set in the config file",
+                manifest.bid,
+                max_bid
+            );
+        }
+    }
+    if let Some(max_budget) = limits.max_budget {
+        if cost > max_budget {
+            bail!(
+                "task would cost up to {} GNT, exceeding the maximum of {} GNT set in the config file",
+                cost,
+                max_budget
+            );
+        }
+    }
+
+    let net = if opt.node.mainnet {
+        println!(
+            "Task will cost at most {} GNT for {} subtasks",
+            cost, subtasks
+        );
+        if !prompt::confirm("Submit the task on mainnet?")? {
+            bail!("task submission cancelled");
+        }
+        Net::MainNet
+    } else {
+        Net::TestNet
+    };
+
+    println!(
+        "Submitting task '{}' prepared in '{}'...",
+        manifest.name,
+        attempt_dir.display()
+    );
+    let datadir = app::golem_datadir(opt.node.datadir)?;
+    let computed_task = session::compute(
+        datadir,
+        opt.node.address,
+        opt.node.port,
+        net,
+        task,
+        ProgressUpdater::new(subtasks),
+        config.session,
+    )?;
+    // makes sure each subtask's result is in place
+    task::order_subtasks(computed_task.subtasks, &manifest.subtasks)?;
+
+    let groups = groups(&manifest.subtasks);
+    for (i, group) in groups.iter().enumerate() {
+        let output = if groups.len() == 1 {
+            opt.output.clone()
+        } else {
+            numbered(&opt.output, i)
+        };
+        println!("Combining output into '{}'...", output.display());
+
+        let chunks: Vec<PathBuf> = group
+            .iter()
+            .map(|descriptor| task::subtask_output(&attempt_dir, &descriptor.id))
+            .collect();
+        recombine::combine_files(&chunks, &output, &opt.formats, opt.quality, opt.gap)?;
+    }
+    Ok(())
+}
+
+/// Groups subtasks covering the whole input, e.g. one per voice or batched
+/// input, each group starting with the first chunk
+fn groups(descriptors: &[SubtaskDescriptor]) -> Vec<&[SubtaskDescriptor]> {
+    let mut groups = Vec::new();
+    let mut start = 0;
+    for (i, descriptor) in descriptors.iter().enumerate() {
+        if i > start && descriptor.chunk == Some(0) {
+            groups.push(&descriptors[start..i]);
+            start = i;
+        }
+    }
+    if start < descriptors.len() {
+        groups.push(&descriptors[start..]);
+    }
+    groups
+}
+
+/// Numbers the output file name, e.g. `out.1.wav`
+fn numbered(output: &Path, index: usize) -> PathBuf {
+    let mut filename: OsString = output.file_stem().unwrap_or(output.as_os_str()).into();
+    filename.push(format!(".{}", index + 1));
+    if let Some(extension) = output.extension() {
+        filename.push(".");
+        filename.push(extension);
+    }
+    output.with_file_name(filename)
+}
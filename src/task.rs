@@ -0,0 +1,554 @@
+use crate::assets;
+use crate::compress;
+use anyhow::{anyhow, bail, Context, Result};
+use gwasm_api::prelude::{ComputedSubtask, Options, Subtask, Task, Timeout};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+const FLITE_JS: &[u8] = include_bytes!("../assets/flite.js");
+const FLITE_WASM: &[u8] = include_bytes!("../assets/flite.wasm");
+
+const JS_NAME: &str = "flite.js";
+const WASM_NAME: &str = "flite.wasm";
+pub const INPUT_NAME: &str = "in.txt";
+pub const OUTPUT_NAME: &str = "out.wav";
+/// Workspace subdir holding the task inputs
+pub const INPUT_DIR: &str = "in";
+/// Workspace subdir holding the subtask results
+pub const OUTPUT_DIR: &str = "out";
+/// Prefix of the workspace subdirs holding each attempt at computing a task
+const ATTEMPT_PREFIX: &str = "attempt-";
+
+/// Creates workspace subdir for the next attempt at computing a task
+///
+/// Each submission gets a fresh `attempt-<n>` dir, so that retries and
+/// resubmissions into the same workspace never collide with the inputs and
+/// outputs of the earlier ones.
+pub fn create_attempt_dir<P: AsRef<Path>>(workspace: P) -> Result<PathBuf> {
+    let workspace = workspace.as_ref();
+    fs::create_dir_all(workspace)
+        .with_context(|| format!("creating dir '{}'", workspace.display()))?;
+
+    let mut attempt = 1;
+    loop {
+        let dir = workspace.join(format!("{}{}", ATTEMPT_PREFIX, attempt));
+        match fs::create_dir(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => attempt += 1,
+            Err(e) => return Err(e).with_context(|| format!("creating dir '{}'", dir.display())),
+        }
+    }
+}
+
+/// Returns the workspace holding the attempt dir, or the dir itself if it's
+/// no attempt dir
+pub fn attempt_workspace(dir: &Path) -> &Path {
+    let is_attempt = dir
+        .file_name()
+        .and_then(|name| name.to_str())
+        .filter(|name| name.starts_with(ATTEMPT_PREFIX))
+        .is_some();
+    match dir.parent() {
+        Some(workspace) if is_attempt => workspace,
+        _ => dir,
+    }
+}
+
+/// Returns the workspace subdir of the latest attempt at computing a task
+pub fn latest_attempt_dir<P: AsRef<Path>>(workspace: P) -> Result<Option<PathBuf>> {
+    let workspace = workspace.as_ref();
+    let mut latest: Option<(u64, PathBuf)> = None;
+    for entry in
+        fs::read_dir(workspace).with_context(|| format!("reading dir '{}'", workspace.display()))?
+    {
+        let path = entry
+            .with_context(|| format!("reading dir '{}'", workspace.display()))?
+            .path();
+        let attempt = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(ATTEMPT_PREFIX))
+            .and_then(|attempt| attempt.parse().ok());
+        if let Some(attempt) = attempt {
+            if latest.as_ref().filter(|(n, _)| *n > attempt).is_none() {
+                latest = Some((attempt, path));
+            }
+        }
+    }
+    Ok(latest.map(|(_, path)| path))
+}
+
+/// Returns path the subtask's result is saved to in the attempt dir
+pub fn subtask_output<P: AsRef<Path>>(attempt_dir: P, id: &str) -> PathBuf {
+    attempt_dir
+        .as_ref()
+        .join(OUTPUT_DIR)
+        .join(id)
+        .join(OUTPUT_NAME)
+}
+
+/// Returns paths of the subtask results saved in the attempt dir, in the
+/// order described by the task manifest, or in the subtasks' order if
+/// there's none
+///
+/// Subtasks whose results are missing are skipped, while compressed ones
+/// are kept, to be opened with `compress::open`.
+pub fn subtask_outputs<P: AsRef<Path>>(attempt_dir: P) -> Result<Vec<PathBuf>> {
+    let attempt_dir = attempt_dir.as_ref();
+    // subtasks of a shuffled task are named out of the chunks' order
+    if attempt_dir.join(TaskManifest::FILENAME).is_file() {
+        let manifest = TaskManifest::load(attempt_dir)?;
+        return Ok(manifest
+            .subtasks
+            .iter()
+            .map(|descriptor| subtask_output(attempt_dir, &descriptor.id))
+            .filter(|path| compress::exists(path))
+            .collect());
+    }
+
+    let output_dir = attempt_dir.join(OUTPUT_DIR);
+    let mut outputs = Vec::new();
+    for entry in fs::read_dir(&output_dir)
+        .with_context(|| format!("reading dir '{}'", output_dir.display()))?
+    {
+        let path = entry
+            .with_context(|| format!("reading dir '{}'", output_dir.display()))?
+            .path()
+            .join(OUTPUT_NAME);
+        if compress::exists(&path) {
+            outputs.push(path);
+        }
+    }
+    outputs.sort();
+    Ok(outputs)
+}
+
+/// Prefix of the subtask names, followed by the subtask's index
+const SUBTASK_PREFIX: &str = "subtask_";
+
+/// Returns name of the subtask at the index
+///
+/// The index is zero-padded, so that the names sort in the index order.
+pub fn subtask_name(index: usize) -> String {
+    format!("{}{:05}", SUBTASK_PREFIX, index)
+}
+
+/// Returns name of the subtask which computed the output at the path
+fn computed_by(output: &Path) -> Option<&str> {
+    output.parent()?.file_name()?.to_str()
+}
+
+/// Orders computed subtasks as described by the descriptors
+///
+/// Subtasks are identified by the dirs their outputs were saved to rather
+/// than relying on gwasm-api returning them in order. Fails unless each
+/// described subtask is present exactly once.
+pub fn order_subtasks(
+    subtasks: Vec<ComputedSubtask>,
+    descriptors: &[SubtaskDescriptor],
+) -> Result<Vec<ComputedSubtask>> {
+    let mut ordered: Vec<Option<ComputedSubtask>> = descriptors.iter().map(|_| None).collect();
+    for subtask in subtasks {
+        let id = subtask
+            .data
+            .keys()
+            .next()
+            .and_then(|path| computed_by(path))
+            .ok_or_else(|| anyhow!("couldn't identify subtask from its output paths"))?;
+        let index = descriptors
+            .iter()
+            .position(|descriptor| descriptor.id == id)
+            .ok_or_else(|| anyhow!("got results of unexpected subtask '{}'", id))?;
+        if ordered[index].is_some() {
+            bail!("got results of subtask '{}' twice", id);
+        }
+        ordered[index] = Some(subtask);
+    }
+
+    ordered
+        .into_iter()
+        .zip(descriptors)
+        .map(|(subtask, descriptor)| {
+            subtask.ok_or_else(|| anyhow!("results of subtask '{}' are missing", descriptor.id))
+        })
+        .collect()
+}
+
+/// Returns indices of the descriptors in the order their subtasks are
+/// defined in the gWasm task, i.e. by name
+pub fn definition_order(descriptors: &[SubtaskDescriptor]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..descriptors.len()).collect();
+    order.sort_by(|&a, &b| descriptors[a].id.cmp(&descriptors[b].id));
+    order
+}
+
+/// Checks that subtasks synthesize chunks in source order, i.e. the
+/// `expected` chunk indices, e.g. all chunks once per voice
+pub fn verify_chunk_order(descriptors: &[SubtaskDescriptor], expected: &[usize]) -> Result<()> {
+    if descriptors.len() != expected.len() {
+        bail!(
+            "task has {} subtasks where {} are expected",
+            descriptors.len(),
+            expected.len()
+        );
+    }
+    for (descriptor, &expected) in descriptors.iter().zip(expected) {
+        if descriptor.chunk != Some(expected) {
+            bail!(
+                "subtask '{}' synthesizes chunk {:?} where chunk {} is expected; refusing to combine chunks out of source order",
+                descriptor.id,
+                descriptor.chunk,
+                expected
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Description of a single subtask as laid out in the workspace
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubtaskDescriptor {
+    pub id: String,
+    /// Index of the chunk of input text the subtask synthesizes, if any
+    pub chunk: Option<usize>,
+    /// Extra flite arguments preceding the input and output filenames
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub input: PathBuf,
+    /// Path Golem saves the subtask's result to
+    pub output: PathBuf,
+    /// SHA-256 of the synthesized text
+    pub text_hash: String,
+}
+
+/// Task manifest saved alongside the gWasm task in the workspace
+///
+/// It describes the task fully, so that a prepared workspace can be
+/// submitted later on as it is.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TaskManifest {
+    pub name: String,
+    pub bid: f64,
+    pub budget: Option<f64>,
+    pub options: TaskOptions,
+    pub subtasks: Vec<SubtaskDescriptor>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TaskOptions {
+    pub subtasks: u64,
+    pub timeout: String,
+    pub subtask_timeout: String,
+}
+
+impl TaskManifest {
+    pub const FILENAME: &'static str = "task.json";
+
+    pub fn save<P: AsRef<Path>>(&self, dir: P) -> Result<()> {
+        let path = dir.as_ref().join(Self::FILENAME);
+        let contents = serde_json::to_vec_pretty(self).context("serializing task manifest")?;
+        fs::write(&path, contents)
+            .with_context(|| format!("writing task manifest to '{}'", path.display()))
+    }
+
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let path = dir.as_ref().join(Self::FILENAME);
+        let contents = fs::read(&path)
+            .with_context(|| format!("reading task manifest '{}'", path.display()))?;
+        serde_json::from_slice(&contents)
+            .with_context(|| format!("parsing task manifest '{}'", path.display()))
+    }
+
+    /// Recreates the gWasm task laid out in the attempt dir
+    pub fn task<P: AsRef<Path>>(&self, dir: P) -> Result<Task> {
+        let dir = dir.as_ref();
+        let timeout = self
+            .options
+            .timeout
+            .parse()
+            .map_err(|_| anyhow!("invalid task timeout '{}'", self.options.timeout))?;
+        let subtask_timeout =
+            self.options.subtask_timeout.parse().map_err(|_| {
+                anyhow!("invalid subtask timeout '{}'", self.options.subtask_timeout)
+            })?;
+
+        let mut options = Options::new(
+            JS_NAME,
+            WASM_NAME,
+            dir.join(INPUT_DIR),
+            dir.join(OUTPUT_DIR),
+        );
+        for i in definition_order(&self.subtasks) {
+            let descriptor = &self.subtasks[i];
+            let input = dir.join(INPUT_DIR).join(&descriptor.id).join(INPUT_NAME);
+            if !input.is_file() {
+                bail!(
+                    "input of subtask '{}' is missing from '{}'",
+                    descriptor.id,
+                    input.display()
+                );
+            }
+            options.add_subtask(descriptor.id.clone(), subtask(&descriptor.args));
+        }
+
+        Ok(Task::new(
+            self.name.clone(),
+            self.bid,
+            self.budget,
+            timeout,
+            subtask_timeout,
+            options,
+        ))
+    }
+}
+
+/// Creates gWasm subtask running flite on the subtask's input
+fn subtask(args: &[String]) -> Subtask {
+    let mut subtask = Subtask::new();
+    for arg in args {
+        subtask.push_exec_arg(arg.clone());
+    }
+    subtask.push_exec_arg(INPUT_NAME);
+    subtask.push_exec_arg(OUTPUT_NAME);
+    subtask.push_output_file_path(OUTPUT_NAME);
+    subtask
+}
+
+/// Input of a single flite invocation within the gWasm task
+#[derive(Debug, Clone)]
+pub struct SubtaskInput {
+    pub text: String,
+    /// Index of the chunk of input text, if the text is one
+    pub chunk: Option<usize>,
+    /// Extra flite arguments preceding the input and output filenames
+    pub args: Vec<String>,
+}
+
+/// Builder of the gWasm task running flite
+///
+/// Lays out the task in the attempt dir: the flite binary and one dir per
+/// subtask with its input text go under `in/`, while `out/` holds one dir per
+/// subtask where Golem saves the computed WAVs.
+#[derive(Debug)]
+pub struct TaskBuilder {
+    dir: PathBuf,
+    name: String,
+    bid: f64,
+    budget: Option<f64>,
+    timeout: Timeout,
+    subtask_timeout: Timeout,
+    shuffle: bool,
+    subtasks: Vec<SubtaskInput>,
+}
+
+impl TaskBuilder {
+    pub fn new<P: AsRef<Path>>(dir: P, timeout: Timeout, subtask_timeout: Timeout) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            name: "g_flite".to_owned(),
+            bid: 1.0,
+            budget: None,
+            timeout,
+            subtask_timeout,
+            shuffle: false,
+            subtasks: Vec::new(),
+        }
+    }
+
+    pub fn name<S: Into<String>>(mut self, name: S) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn bid(mut self, bid: f64) -> Self {
+        self.bid = bid;
+        self
+    }
+
+    pub fn budget(mut self, budget: Option<f64>) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Names and submits the subtasks in random order, so that providers
+    /// can't tell which parts of the text follow each other
+    ///
+    /// The descriptors still list the subtasks in the order they were
+    /// pushed in, which the results are combined in.
+    pub fn shuffle(mut self, shuffle: bool) -> Self {
+        self.shuffle = shuffle;
+        self
+    }
+
+    pub fn push_subtask(mut self, input: SubtaskInput) -> Self {
+        self.subtasks.push(input);
+        self
+    }
+
+    /// Lays out the task, returning it along with descriptors of its subtasks
+    pub fn build(self) -> Result<(Task, Vec<SubtaskDescriptor>)> {
+        let input_dir = self.dir.join(INPUT_DIR);
+        let output_dir = self.dir.join(OUTPUT_DIR);
+
+        for dir in &[&input_dir, &output_dir] {
+            fs::create_dir(dir).with_context(|| format!("creating dir '{}'", dir.display()))?;
+        }
+
+        for (name, contents) in &[(JS_NAME, FLITE_JS), (WASM_NAME, FLITE_WASM)] {
+            let path = input_dir.join(name);
+            assets::install(name, contents, &path)
+                .with_context(|| format!("writing flite binary to '{}'", path.display()))?;
+        }
+
+        let mut options = Options::new(JS_NAME, WASM_NAME, input_dir.clone(), output_dir.clone());
+        let mut descriptors = Vec::with_capacity(self.subtasks.len());
+
+        let mut names: Vec<String> = (0..self.subtasks.len()).map(subtask_name).collect();
+        if self.shuffle {
+            names.shuffle(&mut rand::thread_rng());
+        }
+
+        for (name, input) in names.into_iter().zip(self.subtasks) {
+            let subtask_input_dir = input_dir.join(&name);
+            fs::create_dir(&subtask_input_dir)
+                .with_context(|| format!("creating dir '{}'", subtask_input_dir.display()))?;
+            let path = subtask_input_dir.join(INPUT_NAME);
+            fs::write(&path, input.text.as_bytes())
+                .with_context(|| format!("writing subtask input to '{}'", path.display()))?;
+
+            let subtask_output_dir = output_dir.join(&name);
+            fs::create_dir(&subtask_output_dir)
+                .with_context(|| format!("creating dir '{}'", subtask_output_dir.display()))?;
+
+            descriptors.push(SubtaskDescriptor {
+                id: name,
+                chunk: input.chunk,
+                args: input.args,
+                input: path,
+                output: subtask_output_dir.join(OUTPUT_NAME),
+                text_hash: format!("{:x}", Sha256::digest(input.text.as_bytes())),
+            });
+        }
+
+        for i in definition_order(&descriptors) {
+            let descriptor = &descriptors[i];
+            options.add_subtask(descriptor.id.clone(), subtask(&descriptor.args));
+        }
+
+        let task = Task::new(
+            self.name,
+            self.bid,
+            self.budget,
+            self.timeout,
+            self.subtask_timeout,
+            options,
+        );
+        Ok((task, descriptors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::fs::File;
+    use std::io::BufReader;
+
+    fn descriptor(id: &str, chunk: Option<usize>) -> SubtaskDescriptor {
+        SubtaskDescriptor {
+            id: id.to_owned(),
+            chunk,
+            args: Vec::new(),
+            input: PathBuf::from(INPUT_DIR).join(id).join(INPUT_NAME),
+            output: PathBuf::from(OUTPUT_DIR).join(id),
+            text_hash: String::new(),
+        }
+    }
+
+    /// Subtask as computed by gwasm-api, its output saved under the path
+    fn computed(output: &Path) -> ComputedSubtask {
+        // the outputs aren't read, so any file will do
+        let file = File::open(std::env::current_exe().unwrap()).unwrap();
+        let mut data = BTreeMap::new();
+        data.insert(output.to_owned(), BufReader::new(file));
+        ComputedSubtask { data }
+    }
+
+    #[test]
+    fn orders_subtasks_by_output_dir() {
+        let descriptors: Vec<_> = ["b", "a", "c"]
+            .iter()
+            .map(|id| descriptor(id, None))
+            .collect();
+        // dirs the results were saved to, and the order or error expected
+        let cases: &[(&[&str], Result<&[&str], &str>)] = &[
+            (&["b", "a", "c"], Ok(&["b", "a", "c"])),
+            (&["c", "b", "a"], Ok(&["b", "a", "c"])),
+            (&["a", "c"], Err("results of subtask 'b' are missing")),
+            (
+                &["a", "b", "c", "a"],
+                Err("got results of subtask 'a' twice"),
+            ),
+            (
+                &["a", "b", "d"],
+                Err("got results of unexpected subtask 'd'"),
+            ),
+            (
+                &[""],
+                Err("couldn't identify subtask from its output paths"),
+            ),
+        ];
+        for &(dirs, expected) in cases {
+            let subtasks = dirs
+                .iter()
+                .map(|dir| computed(&Path::new(dir).join(OUTPUT_NAME)))
+                .collect();
+            let ordered = order_subtasks(subtasks, &descriptors).map(|ordered| {
+                ordered
+                    .iter()
+                    .map(|subtask| computed_by(subtask.data.keys().next().unwrap()).unwrap())
+                    .map(str::to_owned)
+                    .collect::<Vec<_>>()
+            });
+            match (ordered, expected) {
+                (Ok(ordered), Ok(expected)) => assert_eq!(ordered, expected, "{:?}", dirs),
+                (Err(e), Err(expected)) => assert_eq!(e.to_string(), expected, "{:?}", dirs),
+                (ordered, expected) => {
+                    panic!("{:?}: got {:?}, expected {:?}", dirs, ordered, expected)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn verifies_chunk_order() {
+        // chunks of the subtasks, the chunks expected and whether they match
+        type Case = (&'static [Option<usize>], &'static [usize], bool);
+        let cases: &[Case] = &[
+            (&[Some(0), Some(1), Some(2)], &[0, 1, 2], true),
+            (&[Some(0), Some(1), Some(0), Some(1)], &[0, 1, 0, 1], true),
+            (&[], &[], true),
+            (&[Some(1), Some(0)], &[0, 1], false),
+            (&[Some(0), None], &[0, 1], false),
+            (&[Some(0), Some(1)], &[0, 1, 2], false),
+            (&[Some(0), Some(1), Some(2)], &[0, 1], false),
+        ];
+        for &(chunks, expected, ok) in cases {
+            let descriptors: Vec<_> = chunks
+                .iter()
+                .enumerate()
+                .map(|(i, &chunk)| descriptor(&subtask_name(i), chunk))
+                .collect();
+            assert_eq!(
+                verify_chunk_order(&descriptors, expected).is_ok(),
+                ok,
+                "{:?} against {:?}",
+                chunks,
+                expected
+            );
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use super::{NodeOpt, PurgeOpt, TasksOpt};
+use crate::app;
+use crate::config::Config;
+use crate::golem::{Golemcli, TaskInfo, WEI_PER_ETH};
+use crate::run::{self, Run};
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Prefix of the names of tasks created by g_flite
+const TASK_NAME_PREFIX: &str = "g_flite";
+
+pub fn golemcli(node: NodeOpt) -> Result<Golemcli> {
+    let datadir = app::golem_datadir(node.datadir)?;
+    let config = Config::load()?;
+    Ok(Golemcli::new(
+        config.golemcli,
+        datadir,
+        node.address,
+        node.port,
+        node.mainnet,
+    )
+    .call_timeout(config.session.call_timeout))
+}
+
+/// Lists tasks created by g_flite on the node
+pub fn list(opt: TasksOpt) -> Result<()> {
+    let golemcli = golemcli(opt.node)?;
+
+    let tasks: Vec<TaskInfo> = golemcli
+        .tasks()?
+        .into_iter()
+        .filter(|task| task.name.starts_with(TASK_NAME_PREFIX))
+        .collect();
+    if tasks.is_empty() {
+        println!("No g_flite tasks found on the node");
+        return Ok(());
+    }
+
+    let rows: Vec<Vec<String>> = tasks
+        .iter()
+        .map(|task| {
+            vec![
+                task.id.clone(),
+                task.name.clone(),
+                task.status.clone(),
+                task.progress
+                    .map(|progress| format!("{:.0}%", progress))
+                    .unwrap_or_else(|| "-".to_owned()),
+                cost(task),
+            ]
+        })
+        .collect();
+    print_table(&["ID", "NAME", "STATUS", "PROGRESS", "EST. COST"], &rows);
+    Ok(())
+}
+
+fn cost(task: &TaskInfo) -> String {
+    match (task.estimated_cost, task.estimated_fee) {
+        (Some(cost), Some(fee)) => format!(
+            "{:.4} GNT + {:.6} ETH",
+            cost / WEI_PER_ETH,
+            fee / WEI_PER_ETH
+        ),
+        (Some(cost), None) => format!("{:.4} GNT", cost / WEI_PER_ETH),
+        _ => "-".to_owned(),
+    }
+}
+
+/// Removes results of the task from the node and the workspace, once its
+/// run's output is safely stored
+pub fn purge(opt: PurgeOpt) -> Result<()> {
+    let golemcli = golemcli(opt.node)?;
+    let task = golemcli.task(&opt.task_id)?;
+    let run_id = task
+        .name
+        .strip_prefix(TASK_NAME_PREFIX)
+        .and_then(|suffix| suffix.strip_prefix('_'))
+        .ok_or_else(|| anyhow!("task '{}' wasn't created by a g_flite run", task.id))?;
+
+    let path = run::runs_dir()?.join(run_id).join(Run::MANIFEST);
+    let contents = fs::read(&path).with_context(|| {
+        format!(
+            "reading manifest of run '{}' from '{}'; did the run finish?",
+            run_id,
+            path.display()
+        )
+    })?;
+    let manifest: serde_json::Value =
+        serde_json::from_slice(&contents).context("parsing run manifest")?;
+
+    let outputs = manifest["outputs"].as_array().into_iter().flatten();
+    for file in outputs.flat_map(|output| output["files"].as_array().into_iter().flatten()) {
+        let file = file.as_str().map(PathBuf::from).unwrap_or_default();
+        if !file.is_file() {
+            bail!(
+                "output '{}' of run '{}' is missing; refusing to purge the task's results",
+                file.display(),
+                run_id
+            );
+        }
+    }
+
+    golemcli.delete_task(&task.id)?;
+    println!("Removed task '{}' from the node", task.id);
+
+    if let Some(dir) = manifest["task_dir"].as_str().map(Path::new) {
+        if dir.is_dir() {
+            fs::remove_dir_all(dir)
+                .with_context(|| format!("removing task dir '{}'", dir.display()))?;
+            println!("Removed task dir '{}'", dir.display());
+        }
+    }
+    Ok(())
+}
+
+/// Prints rows in columns aligned to the widest cell
+pub fn print_table(header: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = header.iter().map(|cell| cell.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let print_row = |cells: Vec<&str>| {
+        let line: Vec<_> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    };
+    print_row(header.to_vec());
+    for row in rows {
+        print_row(row.iter().map(String::as_str).collect());
+    }
+}
@@ -0,0 +1,101 @@
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Wall-clock time spent in a single pipeline stage
+#[derive(Debug, Serialize)]
+pub struct Stage {
+    pub name: &'static str,
+    pub seconds: f64,
+}
+
+/// Resource usage of a run recorded in its manifest
+#[derive(Debug, Default, Serialize)]
+pub struct Telemetry {
+    /// Total size of the task workspace after computing it
+    pub workspace_bytes: u64,
+    /// Size of the task inputs handed over to Golem
+    pub uploaded_bytes: u64,
+    /// Size of the subtask results received from Golem
+    pub downloaded_bytes: u64,
+    /// Peak resident memory while combining the output, if the OS reports it
+    pub combine_peak_memory_bytes: Option<u64>,
+    pub stages: Vec<Stage>,
+}
+
+impl Telemetry {
+    /// Records stage which started at `start` and finished just now
+    pub fn record_stage(&mut self, name: &'static str, start: Instant) {
+        self.record_span(name, start, Instant::now());
+    }
+
+    /// Records stage which started at `start` and finished at `end`
+    pub fn record_span(&mut self, name: &'static str, start: Instant, end: Instant) {
+        let elapsed = end.saturating_duration_since(start);
+        log::info!("Stage '{}' took {:?}", name, elapsed);
+        self.stages.push(Stage {
+            name,
+            seconds: elapsed.as_secs() as f64 + f64::from(elapsed.subsec_millis()) / 1000.0,
+        });
+    }
+
+    /// Describes time spent in each stage, e.g. `split 0.2s, compute 3m 5s`
+    pub fn breakdown(&self) -> String {
+        self.stages
+            .iter()
+            .map(|stage| {
+                let time = if stage.seconds < 60.0 {
+                    format!("{:.1}s", stage.seconds)
+                } else {
+                    humantime::format_duration(Duration::from_secs(stage.seconds.round() as u64))
+                        .to_string()
+                };
+                format!("{} {}", stage.name, time)
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Returns total size of all files within the dir, recursively
+pub fn dir_size<P: AsRef<Path>>(path: P) -> u64 {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => dir_size(entry.path()),
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+/// Resets peak resident memory of the process, so that it only covers what follows
+///
+/// Only supported on Linux; elsewhere the peak covers the entire run.
+pub fn reset_peak_memory() {
+    if cfg!(target_os = "linux") {
+        if let Err(e) = fs::write("/proc/self/clear_refs", "5") {
+            log::debug!("Couldn't reset peak memory usage: {}", e);
+        }
+    }
+}
+
+/// Returns peak resident memory of the process, if the OS reports it
+pub fn peak_memory() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kilobytes: u64 = line
+        .trim_start_matches("VmHWM:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
@@ -0,0 +1,360 @@
+use crate::preprocess;
+use crate::split::{self, Chunk};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Average speaking rate of flite's voices used to estimate the duration
+const WORDS_PER_MINUTE: u64 = 160;
+
+/// Rate providers synthesize at, allowing for flite running as WASM being
+/// slower than native one
+const SYNTHESIS_WORDS_PER_MINUTE: u64 = 2000;
+/// Subtask length picked when neither the number of subtasks nor their
+/// length is set, small enough to spread the input over several providers
+/// while keeping the overhead of each subtask low
+const AUTO_WORDS_PER_SUBTASK: u64 = 1500;
+
+/// Estimates how long a provider takes to synthesize given number of words
+pub fn estimate_synthesis(words: usize) -> Duration {
+    Duration::from_secs(words as u64 * 60 / SYNTHESIS_WORDS_PER_MINUTE)
+}
+
+/// Returns number of words per subtask picked automatically, short enough
+/// for each subtask to be estimated to take at most half the subtask
+/// timeout, if known
+pub fn auto_words_per_subtask(subtask_timeout: Option<Duration>) -> u64 {
+    let fitting = subtask_timeout
+        .map(|timeout| timeout.as_secs() * SYNTHESIS_WORDS_PER_MINUTE / 60 / 2)
+        .filter(|&words| words > 0)
+        .unwrap_or(AUTO_WORDS_PER_SUBTASK);
+    fitting.min(AUTO_WORDS_PER_SUBTASK)
+}
+
+/// Estimates spoken duration of the chunks before synthesizing them
+pub fn estimate_duration(chunks: &[Chunk]) -> Duration {
+    let words: usize = chunks
+        .iter()
+        .map(|chunk| chunk.text.split_whitespace().count())
+        .sum();
+    Duration::from_secs(words as u64 * 60 / WORDS_PER_MINUTE)
+}
+
+/// Span of the combined audio stream synthesized from a single chunk
+#[derive(Debug, Clone, Copy)]
+pub struct Span {
+    pub chunk: usize,
+    /// Index of the first frame of the span
+    pub start: u64,
+    /// Index one past the last frame of the span
+    pub end: u64,
+}
+
+/// Chunk timing data collected while combining subtask outputs
+#[derive(Debug, Default)]
+pub struct Timeline {
+    sample_rate: u32,
+    spans: Vec<Span>,
+}
+
+impl Timeline {
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+    }
+
+    pub fn push(&mut self, chunk: usize, start: u64, end: u64) {
+        self.spans.push(Span { chunk, start, end });
+    }
+
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
+    pub fn frames_to_duration(&self, frames: u64) -> Duration {
+        if self.sample_rate == 0 {
+            return Duration::from_secs(0);
+        }
+        let sample_rate = u64::from(self.sample_rate);
+        Duration::from_secs(frames / sample_rate)
+            + Duration::from_nanos((frames % sample_rate) * 1_000_000_000 / sample_rate)
+    }
+}
+
+fn format_lrc_timestamp(timestamp: Duration) -> String {
+    let centis = timestamp.as_millis() / 10;
+    format!(
+        "{:02}:{:02}.{:02}",
+        centis / 6000,
+        centis / 100 % 60,
+        centis % 100
+    )
+}
+
+fn format_srt_timestamp(timestamp: Duration) -> String {
+    let millis = timestamp.as_millis();
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// Estimates start offsets of words within a span
+///
+/// flite doesn't report word timings, so the span's duration is distributed
+/// among the words proportionally to their length.
+fn estimate_word_offsets(text: &str, duration: Duration) -> Vec<(Duration, &str)> {
+    let words: Vec<_> = text.split_whitespace().collect();
+    let total: usize = words.iter().map(|word| word.chars().count()).sum();
+    let mut offsets = Vec::with_capacity(words.len());
+    let mut acc = 0;
+    for word in words {
+        let offset = if total == 0 {
+            Duration::from_secs(0)
+        } else {
+            duration * acc as u32 / total as u32
+        };
+        offsets.push((offset, word));
+        acc += word.chars().count();
+    }
+    offsets
+}
+
+/// Writes an LRC file with one line per chunk
+///
+/// If `words` is set, each line is additionally annotated with estimated
+/// per-word timestamps following the enhanced LRC format.
+pub fn write_lrc<P: AsRef<Path>>(
+    path: P,
+    timeline: &Timeline,
+    chunks: &[Chunk],
+    words: bool,
+) -> Result<()> {
+    let path = path.as_ref();
+    let mut contents = String::new();
+    contents.push_str("[re:g_flite]\n");
+
+    for span in timeline.spans() {
+        let text = match chunks.get(span.chunk) {
+            Some(chunk) => preprocess::strip_pause_markers(&chunk.text),
+            None => continue,
+        };
+        let start = timeline.frames_to_duration(span.start);
+        let _ = write!(contents, "[{}]", format_lrc_timestamp(start));
+
+        if words {
+            let duration = timeline.frames_to_duration(span.end - span.start);
+            let line: Vec<_> = estimate_word_offsets(&text, duration)
+                .into_iter()
+                .map(|(offset, word)| format!("<{}>{}", format_lrc_timestamp(start + offset), word))
+                .collect();
+            contents.push_str(&line.join(" "));
+        } else {
+            contents.push_str(&text);
+        }
+        contents.push('\n');
+    }
+
+    fs::write(path, contents).with_context(|| format!("writing LRC file '{}'", path.display()))
+}
+
+/// Writes SRT subtitles with one cue per sentence of each chunk
+///
+/// Cues of a chunk split its span between its sentences in proportion to
+/// their length, the same way as word timestamps are estimated.
+pub fn write_srt<P: AsRef<Path>>(path: P, timeline: &Timeline, chunks: &[Chunk]) -> Result<()> {
+    let path = path.as_ref();
+    let mut contents = String::new();
+    let mut index = 0;
+
+    for span in timeline.spans() {
+        let text = match chunks.get(span.chunk) {
+            Some(chunk) => preprocess::strip_pause_markers(&chunk.text),
+            None => continue,
+        };
+        let start = timeline.frames_to_duration(span.start);
+        let end = timeline.frames_to_duration(span.end);
+        let offsets = estimate_word_offsets(&text, end - start);
+
+        let mut sentence = Vec::new();
+        for (i, &(offset, word)) in offsets.iter().enumerate() {
+            sentence.push((offset, word));
+            let last = i + 1 == offsets.len();
+            if !split::ends_sentence(word) && !last {
+                continue;
+            }
+
+            let cue_start = start + sentence[0].0;
+            let cue_end = offsets.get(i + 1).map_or(end, |&(next, _)| start + next);
+            let words: Vec<_> = sentence.drain(..).map(|(_, word)| word).collect();
+            index += 1;
+            let _ = write!(
+                contents,
+                "{}\n{} --> {}\n{}\n\n",
+                index,
+                format_srt_timestamp(cue_start),
+                format_srt_timestamp(cue_end),
+                words.join(" ")
+            );
+        }
+    }
+
+    fs::write(path, contents).with_context(|| format!("writing SRT file '{}'", path.display()))
+}
+
+/// Map of where chunks and their words are in the output audio
+#[derive(Debug, Serialize)]
+struct Timings<'a> {
+    sample_rate: u32,
+    chunks: Vec<ChunkTiming<'a>>,
+}
+
+/// Where a chunk is in the output audio, in seconds and frames
+#[derive(Debug, Serialize)]
+struct ChunkTiming<'a> {
+    chunk: usize,
+    start: f64,
+    end: f64,
+    start_frame: u64,
+    end_frame: u64,
+    text: String,
+    /// Estimated from the chunk's span, as flite doesn't report them
+    words: Vec<WordTiming<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct WordTiming<'a> {
+    word: &'a str,
+    start: f64,
+    end: f64,
+}
+
+/// Returns the duration in seconds, rounded to milliseconds
+fn secs(duration: Duration) -> f64 {
+    (duration.as_secs_f64() * 1000.0).round() / 1000.0
+}
+
+/// Writes a JSON map of the chunks' spans in the output, each with estimated
+/// spans of its words
+pub fn write_timings<P: AsRef<Path>>(path: P, timeline: &Timeline, chunks: &[Chunk]) -> Result<()> {
+    let path = path.as_ref();
+    let texts: Vec<_> = timeline
+        .spans()
+        .iter()
+        .map(|span| {
+            chunks
+                .get(span.chunk)
+                .map(|chunk| preprocess::strip_pause_markers(&chunk.text))
+        })
+        .collect();
+
+    let mut timings = Timings {
+        sample_rate: timeline.sample_rate,
+        chunks: Vec::with_capacity(texts.len()),
+    };
+    for (span, text) in timeline.spans().iter().zip(&texts) {
+        let text = match text {
+            Some(text) => text,
+            None => continue,
+        };
+        let start = timeline.frames_to_duration(span.start);
+        let end = timeline.frames_to_duration(span.end);
+        let offsets = estimate_word_offsets(text, end - start);
+        let words = offsets
+            .iter()
+            .enumerate()
+            .map(|(i, &(offset, word))| {
+                let word_end = offsets.get(i + 1).map_or(end, |&(next, _)| start + next);
+                WordTiming {
+                    word,
+                    start: secs(start + offset),
+                    end: secs(word_end),
+                }
+            })
+            .collect();
+        timings.chunks.push(ChunkTiming {
+            chunk: span.chunk,
+            start: secs(start),
+            end: secs(end),
+            start_frame: span.start,
+            end_frame: span.end,
+            text: text.to_string(),
+            words,
+        });
+    }
+
+    let contents = serde_json::to_vec_pretty(&timings).context("serializing timing map")?;
+    fs::write(path, contents).with_context(|| format!("writing timing map '{}'", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_lrc_timestamps() {
+        let cases = [
+            (Duration::from_millis(0), "00:00.00"),
+            (Duration::from_millis(9), "00:00.00"),
+            (Duration::from_millis(1_234), "00:01.23"),
+            (Duration::from_millis(59_999), "00:59.99"),
+            (Duration::from_millis(60_000), "01:00.00"),
+            (Duration::from_millis(754_320), "12:34.32"),
+            (Duration::from_secs(100 * 60), "100:00.00"),
+        ];
+        for &(timestamp, expected) in &cases {
+            assert_eq!(format_lrc_timestamp(timestamp), expected, "{:?}", timestamp);
+        }
+    }
+
+    #[test]
+    fn formats_srt_timestamps() {
+        let cases = [
+            (Duration::from_millis(0), "00:00:00,000"),
+            (Duration::from_micros(1_999), "00:00:00,001"),
+            (Duration::from_millis(1_234), "00:00:01,234"),
+            (Duration::from_millis(59_999), "00:00:59,999"),
+            (Duration::from_millis(3_599_999), "00:59:59,999"),
+            (Duration::from_millis(3_723_004), "01:02:03,004"),
+            (Duration::from_secs(100 * 3600), "100:00:00,000"),
+        ];
+        for &(timestamp, expected) in &cases {
+            assert_eq!(format_srt_timestamp(timestamp), expected, "{:?}", timestamp);
+        }
+    }
+
+    #[test]
+    fn estimates_word_offsets() {
+        // text, its duration and the expected offsets of its words in ms
+        type Case = (&'static str, Duration, &'static [(u64, &'static str)]);
+        let second = Duration::from_secs(1);
+        let cases: &[Case] = &[
+            ("", second, &[]),
+            ("one", second, &[(0, "one")]),
+            ("ab cd", second, &[(0, "ab"), (500, "cd")]),
+            ("a  bcd", second, &[(0, "a"), (250, "bcd")]),
+            (
+                "ab cd ef gh",
+                2 * second,
+                &[(0, "ab"), (500, "cd"), (1000, "ef"), (1500, "gh")],
+            ),
+        ];
+        for &(text, duration, expected) in cases {
+            let expected: Vec<_> = expected
+                .iter()
+                .map(|&(millis, word)| (Duration::from_millis(millis), word))
+                .collect();
+            assert_eq!(
+                estimate_word_offsets(text, duration),
+                expected,
+                "{:?}",
+                text
+            );
+        }
+    }
+}
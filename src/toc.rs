@@ -0,0 +1,124 @@
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{fmt, str::FromStr};
+
+/// Where to put the spoken table of contents
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TocMode {
+    /// Prepends the table of contents to the output
+    Prepend,
+    /// Writes the table of contents as a separate track next to the output
+    Track,
+}
+
+impl FromStr for TocMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "prepend" => Ok(TocMode::Prepend),
+            "track" => Ok(TocMode::Track),
+            _ => bail!(
+                "unknown table of contents mode '{}'; expected one of: prepend, track",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for TocMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TocMode::Prepend => write!(f, "prepend"),
+            TocMode::Track => write!(f, "track"),
+        }
+    }
+}
+
+/// Chapter listed in the table of contents
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub title: String,
+    pub start: Duration,
+}
+
+/// Spells out the time offset the way it's meant to be read aloud
+fn spell_offset(offset: Duration) -> String {
+    let secs = offset.as_secs();
+    let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+
+    let plural = |count: u64, unit: &str| {
+        if count == 1 {
+            format!("{} {}", count, unit)
+        } else {
+            format!("{} {}s", count, unit)
+        }
+    };
+
+    match (hours, minutes) {
+        (0, 0) if seconds == 0 => "the beginning".to_owned(),
+        (0, 0) => plural(seconds, "second"),
+        (0, _) => plural(minutes, "minute"),
+        (_, 0) => plural(hours, "hour"),
+        _ => format!("{} {}", plural(hours, "hour"), plural(minutes, "minute")),
+    }
+}
+
+/// Generates text of the table of contents to be synthesized
+///
+/// When prepended to the output, the table of contents can't know its own
+/// length in advance, hence the offsets are announced as counted from its end.
+pub fn script(entries: &[Entry], mode: TocMode) -> String {
+    let mut script = vec!["Table of contents.".to_owned()];
+    if mode == TocMode::Prepend {
+        script.push("Times are counted from the end of this table of contents.".to_owned());
+    }
+
+    for entry in entries {
+        let title = entry
+            .title
+            .trim_end_matches(|c: char| c.is_ascii_punctuation() || c.is_whitespace());
+        script.push(format!(
+            "{}, starting at {}.",
+            title,
+            spell_offset(entry.start)
+        ));
+    }
+
+    script.join(" ")
+}
+
+/// Chapter of the output listed in the chapter manifest
+#[derive(Debug, Serialize)]
+pub struct ManifestEntry {
+    /// Index of the chapter, counted from 1
+    pub index: usize,
+    pub title: String,
+    /// Offset of the chapter in the combined output, or in the part of it
+    /// the chapter starts in, in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_secs: Option<f64>,
+    /// Files the chapter was written to in per-chapter mode, or the part of
+    /// the output it starts in when split by duration
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<PathBuf>,
+}
+
+/// Chapters of the output, saved next to it for players and tools to pick up
+#[derive(Debug, Serialize)]
+pub struct ChapterManifest {
+    pub input: PathBuf,
+    pub chapters: Vec<ManifestEntry>,
+}
+
+impl ChapterManifest {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let json = serde_json::to_string_pretty(self).context("serializing chapter manifest")?;
+        fs::write(path, json)
+            .with_context(|| format!("writing chapter manifest '{}'", path.display()))
+    }
+}
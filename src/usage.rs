@@ -0,0 +1,136 @@
+use crate::config::{self, Config, TelemetryConfig};
+use crate::failure::{Failure, Stage};
+use crate::notify::Outcome;
+use crate::run;
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Anonymous report of a single run sent when usage metrics are enabled
+///
+/// Holds nothing identifying the user, the node or what was synthesized,
+/// only what the run did and how it ended.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub version: String,
+    pub os: String,
+    pub outcome: Outcome,
+    /// Broad cause of the failure, if the run failed
+    pub failure: Option<Failure>,
+    /// Stage of the run which failed, if it's known
+    pub stage: Option<Stage>,
+    pub documents: usize,
+    pub voices: usize,
+    pub subtasks: u64,
+    /// Rounded down to whole minutes
+    pub duration_mins: u64,
+}
+
+impl Report {
+    pub fn new(
+        result: std::result::Result<u64, &anyhow::Error>,
+        documents: usize,
+        voices: usize,
+        duration: Duration,
+    ) -> Self {
+        let (outcome, failure, stage, subtasks) = match result {
+            Ok(subtasks) => (Outcome::Success, None, None, subtasks),
+            Err(e) => (
+                Outcome::Failure,
+                Some(Failure::classify(e)),
+                e.downcast_ref::<Stage>().cloned(),
+                0,
+            ),
+        };
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            os: env::consts::OS.to_owned(),
+            outcome,
+            failure,
+            stage,
+            documents,
+            voices,
+            subtasks,
+            duration_mins: duration.as_secs() / 60,
+        }
+    }
+}
+
+/// Sends the report if usage metrics are enabled, keeping it as the last
+/// one sent for `g_flite telemetry status`
+///
+/// Failing to send it is only logged.
+pub fn send(config: &TelemetryConfig, report: &Report) {
+    let endpoint = match (config.enabled, &config.endpoint) {
+        (true, Some(endpoint)) => endpoint,
+        _ => return,
+    };
+
+    if let Err(e) = post(endpoint, report) {
+        log::debug!("Couldn't send usage metrics: {:#}", e);
+        return;
+    }
+    if let Err(e) = save_last(report) {
+        log::warn!("Couldn't keep the usage report sent: {:#}", e);
+    }
+}
+
+fn post(endpoint: &str, report: &Report) -> Result<()> {
+    let body = serde_json::to_value(report).context("serializing usage report")?;
+    let response = ureq::post(endpoint)
+        .timeout(Duration::from_secs(5))
+        .send_json(body);
+    if let Some(e) = response.synthetic_error() {
+        bail!("posting to '{}': {}", endpoint, e);
+    }
+    if !response.ok() {
+        bail!(
+            "posting to '{}': {} {}",
+            endpoint,
+            response.status(),
+            response.status_text()
+        );
+    }
+    Ok(())
+}
+
+fn last_path() -> Result<PathBuf> {
+    Ok(run::state_dir()?.join("last_usage_report.json"))
+}
+
+fn save_last(report: &Report) -> Result<()> {
+    let path = last_path()?;
+    let contents = serde_json::to_vec_pretty(report).context("serializing usage report")?;
+    fs::create_dir_all(run::state_dir()?).context("creating state dir")?;
+    fs::write(&path, contents)
+        .with_context(|| format!("writing usage report to '{}'", path.display()))
+}
+
+/// Prints whether usage metrics are enabled and the last report sent
+pub fn status() -> Result<()> {
+    let config = Config::load()?.telemetry;
+    match (config.enabled, &config.endpoint) {
+        (true, Some(endpoint)) => println!(
+            "Usage metrics are enabled, reported to '{}' after each run",
+            endpoint
+        ),
+        (true, None) => println!(
+            "Usage metrics are enabled, but no endpoint is set in '{}', so nothing is reported",
+            config::config_path()?.display()
+        ),
+        (false, _) => println!(
+            "Usage metrics are disabled; to opt in, set `enabled = true` and an `endpoint` in \
+             the [telemetry] section of '{}'",
+            config::config_path()?.display()
+        ),
+    }
+
+    let last = last_path()?;
+    if let Ok(contents) = fs::read_to_string(&last) {
+        println!("\nLast report sent:\n{}", contents);
+    }
+    Ok(())
+}
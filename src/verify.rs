@@ -0,0 +1,269 @@
+use super::VerifyOpt;
+use crate::audio;
+use crate::encode::Format;
+use crate::run::{self, Run};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// External chromaprint binary computing acoustic fingerprints
+const FPCALC: &str = "fpcalc";
+/// Share of matching fingerprint bits from which a file sounds the same as
+/// an output, which re-encoding keeps well above
+const MATCH_SIMILARITY: f64 = 0.85;
+/// Number of fingerprint items by which a file may be shifted against an
+/// output, about a second, covering the padding encoders add
+const MAX_SHIFT: usize = 8;
+/// Difference in duration a file may have from an output it sounds the same
+/// as, in seconds
+const MAX_DURATION_DIFF: f64 = 1.0;
+
+/// Chromaprint acoustic fingerprint of an output file
+///
+/// Unlike the checksum, it survives re-encoding of the audio, so it tells
+/// whether a file holds the same speech even if its bytes differ.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fingerprint {
+    pub duration_secs: f64,
+    /// Raw chromaprint, one 32-bit item per about 124ms of audio
+    pub chromaprint: Vec<u32>,
+}
+
+/// Output of `fpcalc -json -raw`
+#[derive(Deserialize)]
+struct FpcalcOutput {
+    duration: f64,
+    // older fpcalc versions print the items as signed
+    fingerprint: Vec<i64>,
+}
+
+impl Fingerprint {
+    /// Fingerprints the whole of the audio file with `fpcalc`
+    pub fn of(path: &Path) -> Result<Self> {
+        let output = Command::new(FPCALC)
+            .args(["-json", "-raw", "-length", "0"])
+            .arg(path)
+            .stdin(Stdio::null())
+            .output()
+            .with_context(|| {
+                format!(
+                    "spawning '{}' to fingerprint '{}'; is chromaprint installed and in your PATH?",
+                    FPCALC,
+                    path.display()
+                )
+            })?;
+        if !output.status.success() {
+            bail!(
+                "fingerprinting '{}' failed with {}: {}",
+                path.display(),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let output: FpcalcOutput = serde_json::from_slice(&output.stdout)
+            .with_context(|| format!("parsing fingerprint of '{}'", path.display()))?;
+
+        Ok(Self {
+            duration_secs: output.duration,
+            chromaprint: output
+                .fingerprint
+                .into_iter()
+                .map(|item| item as u32)
+                .collect(),
+        })
+    }
+
+    /// Returns the share of bits matching between the two fingerprints,
+    /// shifted against each other as far as it takes them to match best
+    pub fn similarity(&self, other: &Fingerprint) -> f64 {
+        let (a, b) = (&self.chromaprint, &other.chromaprint);
+        let shifts = (0..=MAX_SHIFT.min(a.len())).map(|shift| (&a[shift..], &b[..]));
+        let shifts = shifts.chain((1..=MAX_SHIFT.min(b.len())).map(|shift| (&a[..], &b[shift..])));
+        shifts
+            .filter_map(|(a, b)| {
+                let len = a.len().min(b.len());
+                if len == 0 {
+                    return None;
+                }
+                let errors: u32 = a.iter().zip(b).map(|(a, b)| (a ^ b).count_ones()).sum();
+                Some(1.0 - f64::from(errors) / (len * 32) as f64)
+            })
+            .fold(0.0, f64::max)
+    }
+}
+
+/// Checksum and fingerprint of an output file recorded in the run manifest,
+/// so that the file can be verified later on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputDigest {
+    pub path: PathBuf,
+    pub sha256: String,
+    /// Fingerprint of audio files, missing if `fpcalc` isn't available
+    pub fingerprint: Option<Fingerprint>,
+}
+
+impl OutputDigest {
+    pub fn of(path: &Path) -> Result<Self> {
+        let is_audio = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| extension.parse::<Format>().is_ok());
+        let fingerprint = if is_audio {
+            Fingerprint::of(path)
+                .map_err(|e| log::warn!("Not fingerprinting output: {:#}", e))
+                .ok()
+        } else {
+            None
+        };
+
+        Ok(Self {
+            path: path.to_owned(),
+            sha256: audio::file_checksum(path)?,
+            fingerprint,
+        })
+    }
+
+    /// Computes digests of the files, leaving out those which aren't files,
+    /// such as audio streamed to stdout
+    pub fn of_files(files: &[PathBuf]) -> Result<Vec<Self>> {
+        files
+            .iter()
+            .filter(|file| file.is_file())
+            .map(|file| Self::of(file))
+            .collect()
+    }
+}
+
+/// Run manifest as far as verification reads it
+#[derive(Deserialize)]
+struct RecordedRun {
+    id: String,
+    #[serde(default)]
+    outputs: Vec<RecordedOutput>,
+}
+
+#[derive(Deserialize)]
+struct RecordedOutput {
+    // runs of older versions recorded no digests
+    #[serde(default)]
+    digests: Vec<OutputDigest>,
+}
+
+/// Verifies that a file is unmodified output of a run, against the checksums
+/// and fingerprints recorded in the run manifests
+///
+/// A file whose checksum matches is the output as it was written. Failing
+/// that, a file which sounds the same as an output, e.g. after re-encoding,
+/// is reported as such, while one which matches no output fails.
+pub fn verify(opt: VerifyOpt) -> Result<()> {
+    let runs = match &opt.run {
+        Some(id) => vec![read_run(&run::runs_dir()?.join(id))?],
+        None => recorded_runs()?,
+    };
+    let scope = match &opt.run {
+        Some(id) => format!("of run '{}'", id),
+        None => "in the run history".to_owned(),
+    };
+    let digests = runs.iter().flat_map(|run| {
+        run.outputs
+            .iter()
+            .flat_map(|output| &output.digests)
+            .map(move |digest| (run, digest))
+    });
+
+    let checksum = audio::file_checksum(&opt.file)?;
+    if let Some((run, digest)) = digests
+        .clone()
+        .find(|(_, digest)| digest.sha256.eq_ignore_ascii_case(&checksum))
+    {
+        println!(
+            "'{}' is output '{}' of run '{}', unmodified",
+            opt.file.display(),
+            digest.path.display(),
+            run.id
+        );
+        return Ok(());
+    }
+
+    let fingerprinted: Vec<_> = digests
+        .filter_map(|(run, digest)| Some((run, digest, digest.fingerprint.as_ref()?)))
+        .collect();
+    if fingerprinted.is_empty() {
+        bail!(
+            "'{}' matches the checksum of no output {}, and none of them has a fingerprint to \
+             compare it with",
+            opt.file.display(),
+            scope
+        );
+    }
+
+    let fingerprint = Fingerprint::of(&opt.file)?;
+    let mut closest: Option<(&RecordedRun, &OutputDigest, f64)> = None;
+    for (run, digest, other) in fingerprinted {
+        let similarity = fingerprint.similarity(other);
+        let duration_diff = (fingerprint.duration_secs - other.duration_secs).abs();
+        if similarity >= MATCH_SIMILARITY && duration_diff <= MAX_DURATION_DIFF {
+            println!(
+                "'{}' sounds the same as output '{}' of run '{}' ({:.0}% of its fingerprint \
+                 matches), but its bytes differ, so it was re-encoded or otherwise modified",
+                opt.file.display(),
+                digest.path.display(),
+                run.id,
+                similarity * 100.0
+            );
+            return Ok(());
+        }
+        if closest.is_none_or(|(_, _, closest)| similarity > closest) {
+            closest = Some((run, digest, similarity));
+        }
+    }
+
+    let (run, digest, similarity) = closest.expect("fingerprinted outputs were compared");
+    bail!(
+        "'{}' matches no output {}; the closest one, '{}' of run '{}', shares only {:.0}% of its \
+         fingerprint",
+        opt.file.display(),
+        scope,
+        digest.path.display(),
+        run.id,
+        similarity * 100.0
+    )
+}
+
+/// Reads manifests of all finished runs in the run history store
+fn recorded_runs() -> Result<Vec<RecordedRun>> {
+    let runs_dir = run::runs_dir()?;
+    let entries = match fs::read_dir(&runs_dir) {
+        Ok(entries) => entries,
+        Err(_) => bail!("no runs recorded in '{}'", runs_dir.display()),
+    };
+    let mut dirs: Vec<_> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|dir| dir.join(Run::MANIFEST).is_file())
+        .collect();
+    dirs.sort();
+
+    Ok(dirs
+        .iter()
+        .filter_map(|dir| {
+            read_run(dir)
+                .map_err(|e| log::debug!("Skipping run '{}': {:#}", dir.display(), e))
+                .ok()
+        })
+        .collect())
+}
+
+fn read_run(dir: &Path) -> Result<RecordedRun> {
+    let path = dir.join(Run::MANIFEST);
+    let contents = fs::read(&path).with_context(|| {
+        format!(
+            "reading run manifest from '{}'; did the run finish?",
+            path.display()
+        )
+    })?;
+    serde_json::from_slice(&contents)
+        .with_context(|| format!("parsing run manifest '{}'", path.display()))
+}
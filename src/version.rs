@@ -0,0 +1,136 @@
+use crate::run;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Local};
+use console::style;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Latest release as reported by GitHub's API
+const LATEST_RELEASE_URL: &str =
+    "https://api.github.com/repos/golemfactory/g-flite/releases/latest";
+/// Where releases are downloaded from
+const RELEASES_PAGE: &str = "https://github.com/golemfactory/g-flite/releases";
+/// How long the latest release is remembered before asking GitHub again
+const CHECK_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+/// Kept short, as the check holds up startup
+const FETCH_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Latest release seen, cached in the state dir
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Latest {
+    version: String,
+    /// When the release was looked up, in RFC 3339
+    checked: String,
+}
+
+impl Latest {
+    fn age(&self) -> Option<Duration> {
+        let checked = DateTime::parse_from_rfc3339(&self.checked).ok()?;
+        Local::now().signed_duration_since(checked).to_std().ok()
+    }
+}
+
+/// Prints a notice if a newer release than the running one is out
+///
+/// The latest release is looked up at most once a day. Failing to look it
+/// up, e.g. when offline, is only logged.
+pub fn check() {
+    let latest = match latest() {
+        Ok(latest) => latest,
+        Err(e) => {
+            log::debug!("Couldn't check for a newer release: {:#}", e);
+            return;
+        }
+    };
+
+    let running = env!("CARGO_PKG_VERSION");
+    if is_newer(&latest, running) {
+        eprintln!(
+            "{} g_flite {} is out (running {}); older versions may prepare tasks the node rejects, see {}",
+            style("!").bold().yellow(),
+            latest,
+            running,
+            RELEASES_PAGE
+        );
+    }
+}
+
+/// Returns version of the latest release, from the cache if it's recent enough
+fn latest() -> Result<String> {
+    if let Some(latest) = load_cache() {
+        if latest.age().filter(|age| *age < CHECK_MAX_AGE).is_some() {
+            return Ok(latest.version);
+        }
+    }
+
+    let latest = Latest {
+        version: fetch()?,
+        checked: Local::now().to_rfc3339(),
+    };
+    if let Err(e) = save_cache(&latest) {
+        log::warn!("Couldn't cache latest release: {:#}", e);
+    }
+    Ok(latest.version)
+}
+
+fn fetch() -> Result<String> {
+    let response = ureq::get(LATEST_RELEASE_URL)
+        .set("User-Agent", "g_flite")
+        .timeout(FETCH_TIMEOUT)
+        .call();
+    if let Some(e) = response.synthetic_error() {
+        bail!("fetching latest release: {}", e);
+    }
+    if !response.ok() {
+        bail!(
+            "fetching latest release: {} {}",
+            response.status(),
+            response.status_text()
+        );
+    }
+
+    let release = response
+        .into_json()
+        .context("parsing latest release response")?;
+    release["tag_name"]
+        .as_str()
+        .map(|tag| tag.trim_start_matches('v').to_owned())
+        .ok_or_else(|| anyhow!("no tag in latest release response"))
+}
+
+/// Tells whether the version is newer than the other one, comparing their
+/// dot-separated numbers and ignoring any pre-release suffix
+fn is_newer(version: &str, than: &str) -> bool {
+    let numbers = |version: &str| -> Vec<u64> {
+        version
+            .split(&['-', '+'][..])
+            .next()
+            .unwrap_or("")
+            .split('.')
+            .map(|number| number.parse().unwrap_or(0))
+            .collect()
+    };
+    numbers(version) > numbers(than)
+}
+
+fn cache_path() -> Result<PathBuf> {
+    Ok(run::state_dir()?.join("latest_release.json"))
+}
+
+/// Loads the cached latest release, ignoring a missing or broken cache
+fn load_cache() -> Option<Latest> {
+    cache_path()
+        .and_then(|path| Ok(fs::read(path)?))
+        .ok()
+        .and_then(|contents| serde_json::from_slice(&contents).ok())
+}
+
+fn save_cache(latest: &Latest) -> Result<()> {
+    let path = cache_path()?;
+    let contents = serde_json::to_vec_pretty(latest).context("serializing latest release")?;
+    fs::create_dir_all(run::state_dir()?).context("creating state dir")?;
+    fs::write(&path, contents)
+        .with_context(|| format!("writing latest release to '{}'", path.display()))
+}
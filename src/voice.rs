@@ -0,0 +1,103 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// flite voice compiled into the gWasm binary
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Voice {
+    Kal,
+    Kal16,
+    Awb,
+    Rms,
+    Slt,
+}
+
+impl Voice {
+    pub fn name(self) -> &'static str {
+        match self {
+            Voice::Kal => "kal",
+            Voice::Kal16 => "kal16",
+            Voice::Awb => "awb",
+            Voice::Rms => "rms",
+            Voice::Slt => "slt",
+        }
+    }
+
+    /// Returns flite arguments selecting the voice
+    pub fn exec_args(self) -> Vec<String> {
+        vec!["-voice".to_owned(), self.name().to_owned()]
+    }
+}
+
+impl FromStr for Voice {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "kal" => Ok(Voice::Kal),
+            "kal16" => Ok(Voice::Kal16),
+            "awb" => Ok(Voice::Awb),
+            "rms" => Ok(Voice::Rms),
+            "slt" => Ok(Voice::Slt),
+            _ => bail!(
+                "unknown voice '{}'; expected one of: kal, kal16, awb, rms, slt",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Voice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Entry of the language to voice routing table, e.g. `en=slt`
+///
+/// The voice may be prefixed with the synthesis engine, e.g. `en=flite:slt`,
+/// although flite is currently the only engine available in the gWasm binary.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoiceRoute {
+    pub language: String,
+    pub voice: Voice,
+}
+
+impl FromStr for VoiceRoute {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(2, '=');
+        let language = parts.next().unwrap_or("").trim().to_lowercase();
+        let target = match parts.next() {
+            Some(target) if !language.is_empty() => target.trim(),
+            _ => bail!("invalid voice route '{}'; expected <language>=<voice>", s),
+        };
+
+        let mut parts = target.rsplitn(2, ':');
+        let voice = parts.next().unwrap_or("");
+        if let Some(engine) = parts.next() {
+            if engine != "flite" {
+                bail!(
+                    "unsupported synthesis engine '{}' in voice route '{}'; only flite is available",
+                    engine,
+                    s
+                );
+            }
+        }
+
+        Ok(Self {
+            language,
+            voice: voice.parse()?,
+        })
+    }
+}
+
+/// Returns voice routed for the language, if any
+pub fn route(routes: &[VoiceRoute], language: &str) -> Option<Voice> {
+    routes
+        .iter()
+        .find(|route| route.language == language)
+        .map(|route| route.voice)
+}
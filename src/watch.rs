@@ -0,0 +1,105 @@
+use super::Opt;
+use crate::app::App;
+use crate::failure;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// How often the dir is scanned for new and changed files
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Extension of the text files picked up
+const TEXT_EXTENSION: &str = "txt";
+
+/// Synthesizes every text file appearing in or changing in the dir, saving
+/// the WAVE next to it, until the process is killed
+///
+/// Files are picked up only once they haven't changed for a whole poll
+/// interval, so that they aren't synthesized while still being written.
+/// Files present from the start are synthesized unless their WAVE is
+/// already newer than them.
+pub fn watch(dir: &Path, opt: Opt) -> Result<()> {
+    println!(
+        "Watching '{}' for new or changed text files...",
+        dir.display()
+    );
+
+    let mut done: HashMap<PathBuf, SystemTime> = HashMap::new();
+    let mut pending: HashMap<PathBuf, SystemTime> = HashMap::new();
+    for (path, modified) in scan(dir)? {
+        if is_synthesized(&path, modified) {
+            done.insert(path, modified);
+        }
+    }
+
+    loop {
+        for (path, modified) in scan(dir)? {
+            if done.get(&path) == Some(&modified) {
+                continue;
+            }
+            if pending.get(&path) != Some(&modified) {
+                pending.insert(path, modified);
+                continue;
+            }
+
+            pending.remove(&path);
+            done.insert(path.clone(), modified);
+            if let Err(e) = synthesize(&path, opt.clone()) {
+                failure::report(
+                    &e.context(format!("synthesizing '{}'", path.display())),
+                    opt.json,
+                );
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn synthesize(path: &Path, mut opt: Opt) -> Result<()> {
+    opt.watch = None;
+    opt.input = Some(path.to_path_buf());
+    opt.output = Some(output_path(path));
+    App::try_from(opt)?.run()
+}
+
+fn output_path(path: &Path) -> PathBuf {
+    path.with_extension("wav")
+}
+
+/// Checks whether the text file's WAVE is newer than the file itself
+fn is_synthesized(path: &Path, modified: SystemTime) -> bool {
+    fs::metadata(output_path(path))
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .filter(|synthesized| *synthesized >= modified)
+        .is_some()
+}
+
+/// Lists text files in the dir along with their modification times
+fn scan(dir: &Path) -> Result<Vec<(PathBuf, SystemTime)>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("reading dir '{}'", dir.display()))? {
+        let path = entry
+            .with_context(|| format!("reading dir '{}'", dir.display()))?
+            .path();
+        let is_text = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .filter(|extension| extension.to_lowercase() == TEXT_EXTENSION)
+            .is_some();
+        if !is_text {
+            continue;
+        }
+        // the file may be gone or not readable yet
+        if let Ok(modified) = fs::metadata(&path).and_then(|metadata| metadata.modified()) {
+            if path.is_file() {
+                files.push((path, modified));
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
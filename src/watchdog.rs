@@ -0,0 +1,538 @@
+use crate::golem::{Golemcli, SubtaskInfo};
+use crate::task::SubtaskDescriptor;
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How often the node is polled for the task's subtasks
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Status of subtasks being computed by a provider
+const COMPUTING: &str = "Starting";
+/// Status of subtasks whose results were accepted
+const FINISHED: &str = "Finished";
+/// Statuses of subtasks which a provider failed to compute
+const FAILED: &[&str] = &["Failure", "Timeout", "Failed - Resent"];
+/// Number of times a subtask is restarted to get it off a provider holding
+/// an adjacent chunk, before the task is aborted
+const MAX_SCATTER_RESTARTS: usize = 3;
+
+/// Reason the watchdog aborted the task
+#[derive(Debug, Clone)]
+pub enum Stall {
+    /// No provider picked up any subtask in time
+    Unassigned {
+        timeout: Duration,
+        peers: Option<usize>,
+    },
+    /// All subtasks finished but the quarantined ones, which kept failing
+    Quarantined(Vec<Quarantined>),
+    /// Fewer providers than required were computing subtasks at once in time
+    Understaffed {
+        timeout: Duration,
+        required: usize,
+        reached: usize,
+    },
+    /// A subtask kept getting assigned to providers holding an adjacent
+    /// chunk, which would otherwise have been let to compute it
+    Unscattered {
+        subtask_id: String,
+        provider: String,
+    },
+}
+
+/// Subtask given up on after it failed or stalled too many times
+#[derive(Debug, Clone, Serialize)]
+pub struct Quarantined {
+    /// Name the subtask was defined under, which its descriptor's ID
+    /// matches
+    pub subtask: String,
+    /// ID of the subtask's latest entry in the node's listing
+    pub subtask_id: String,
+    pub failures: u32,
+    /// Providers the subtask was assigned to, in order
+    pub providers: Vec<String>,
+}
+
+/// Limits on how long the task may go without making progress
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    /// Time for the first subtask to get assigned to a provider
+    pub assignment: Option<Duration>,
+    /// Time for a computed subtask to report progress
+    pub heartbeat: Option<Duration>,
+    /// Number of times a subtask may fail or stall before it's quarantined
+    pub max_failures: Option<u32>,
+    /// Number of distinct providers computing subtasks at once for the
+    /// task to be considered healthy
+    pub min_providers: Option<usize>,
+    /// Time for the task to become healthy
+    pub providers: Option<Duration>,
+}
+
+/// Subtasks synthesizing adjacent chunks of the text, which are kept off
+/// the same provider
+///
+/// Subtasks are identified by the names they were defined under, as the
+/// node's listing doesn't follow the order they were defined in.
+#[derive(Debug, Clone, Default)]
+pub struct Neighbours(BTreeMap<String, Vec<String>>);
+
+impl Neighbours {
+    /// Works out neighbours of the described subtasks, i.e. those
+    /// synthesizing consecutive chunks of the same document and voice
+    pub fn new(descriptors: &[SubtaskDescriptor]) -> Self {
+        let mut neighbours: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for pair in descriptors.windows(2) {
+            if pair[1].chunk.is_some() && pair[1].chunk == pair[0].chunk.map(|chunk| chunk + 1) {
+                let (a, b) = (&pair[0].id, &pair[1].id);
+                neighbours.entry(a.clone()).or_default().push(b.clone());
+                neighbours.entry(b.clone()).or_default().push(a.clone());
+            }
+        }
+        Neighbours(neighbours)
+    }
+
+    fn of(&self, name: &str) -> &[String] {
+        self.0.get(name).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// What the watchdog saw of the task by the time it stopped
+#[derive(Debug, Default)]
+pub struct Watched {
+    /// Why the task was aborted, if it was
+    pub stall: Option<Stall>,
+    /// When the first subtask was seen assigned to a provider
+    pub assigned: Option<Instant>,
+    /// When `Timeouts::min_providers` were first seen computing at once
+    pub healthy: Option<Instant>,
+    /// Most distinct providers seen computing at once
+    pub peak_providers: usize,
+    /// Checksums the node reported for subtasks' result files, keyed by the
+    /// files' paths relative to the task's output dir
+    pub checksums: BTreeMap<String, String>,
+    /// Names of adjacent subtasks sent to the same provider, which got to
+    /// see the text of both
+    pub exposed: Vec<(String, String, String)>,
+}
+
+/// Background thread acting on the task when it stops making progress
+///
+/// gwasm-api's `compute` keeps waiting until the task's timeout, so the
+/// watchdog polls the node via golemcli instead. Subtasks which stall are
+/// restarted so that another provider can pick them up, and a task which
+/// nobody picks up is aborted, which makes `compute` return early. So is a
+/// task whose only unfinished subtasks are ones which failed too many times,
+/// rather than retrying them for good, and one which doesn't get enough
+/// providers computing it at once. Given neighbours to scatter, subtasks
+/// assigned to a provider holding an adjacent chunk are restarted as well.
+/// Without any of that, it only notes when the task got assigned and
+/// collects the checksums the node reports for results.
+pub struct Watchdog {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<Watched>,
+}
+
+impl Watchdog {
+    pub fn spawn(
+        golemcli: Golemcli,
+        task_name: String,
+        timeouts: Timeouts,
+        scatter: Option<Neighbours>,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+
+        let handle = thread::spawn(move || {
+            let mut state = State {
+                golemcli,
+                task_name,
+                timeouts,
+                scatter,
+                started: Instant::now(),
+                assigned: None,
+                healthy: None,
+                peak_providers: 0,
+                checksums: BTreeMap::new(),
+                progress: HashMap::new(),
+                listed: HashMap::new(),
+                attempts: HashMap::new(),
+            };
+            while !stopped.load(Ordering::SeqCst) {
+                match state.check() {
+                    Ok(Some(stall)) => return state.watched(Some(stall)),
+                    Ok(None) => {}
+                    Err(e) => log::debug!("Polling task progress failed: {:#}", e),
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+            // the last subtasks may have finished since the last poll
+            if let Err(e) = state.collect_checksums() {
+                log::debug!("Polling result checksums failed: {:#}", e);
+            }
+            state.watched(None)
+        });
+
+        Self { stop, handle }
+    }
+
+    /// Stops watching the task, returning why it was aborted if it was
+    pub fn stop(self) -> Watched {
+        self.stop.store(true, Ordering::SeqCst);
+        self.handle.join().unwrap_or_default()
+    }
+}
+
+struct State {
+    golemcli: Golemcli,
+    task_name: String,
+    timeouts: Timeouts,
+    scatter: Option<Neighbours>,
+    started: Instant,
+    /// When the first subtask was seen assigned
+    assigned: Option<Instant>,
+    /// When enough providers were first seen computing at once
+    healthy: Option<Instant>,
+    peak_providers: usize,
+    checksums: BTreeMap<String, String>,
+    /// Last reported progress of each subtask and when it changed
+    progress: HashMap<String, (Option<f64>, Instant)>,
+    /// Last seen status and provider of each entry in the node's listing,
+    /// by the entry's subtask ID
+    listed: HashMap<String, (String, Option<String>)>,
+    /// Attempts of each subtask, by its name
+    attempts: HashMap<String, Attempts>,
+}
+
+/// History of a subtask's attempts at being computed, across all its
+/// entries in the node's listing
+#[derive(Default)]
+struct Attempts {
+    failures: u32,
+    providers: Vec<String>,
+    /// Providers the subtask was taken away from for holding an adjacent
+    /// chunk
+    revoked: Vec<String>,
+}
+
+impl Attempts {
+    /// Returns whether the provider got to compute the subtask
+    fn held_by(&self, provider: &str) -> bool {
+        self.providers.iter().any(|held| held == provider)
+            && !self.revoked.iter().any(|revoked| revoked == provider)
+    }
+}
+
+/// Returns key the subtask's attempts are recorded under, i.e. its name, so
+/// that entries of a resent or restarted subtask add up
+fn attempts_key(subtask: &SubtaskInfo) -> &str {
+    subtask.name().unwrap_or(&subtask.subtask_id)
+}
+
+impl State {
+    fn watched(&self, stall: Option<Stall>) -> Watched {
+        Watched {
+            stall,
+            assigned: self.assigned,
+            healthy: self.healthy,
+            peak_providers: self.peak_providers,
+            checksums: self.checksums.clone(),
+            exposed: self.exposed(),
+        }
+    }
+
+    /// Returns adjacent subtasks which were ever assigned to the same
+    /// provider, whether they were restarted or not
+    fn exposed(&self) -> Vec<(String, String, String)> {
+        let neighbours = match &self.scatter {
+            Some(neighbours) => neighbours,
+            None => return Vec::new(),
+        };
+        let providers = |name: &str| {
+            self.attempts
+                .get(name)
+                .map_or(&[][..], |attempts| attempts.providers.as_slice())
+        };
+
+        let mut exposed = Vec::new();
+        for (a, adjacent) in &neighbours.0 {
+            for b in adjacent.iter().filter(|&b| b > a) {
+                for provider in providers(a) {
+                    if providers(b).contains(provider) {
+                        exposed.push((a.clone(), b.clone(), provider.clone()));
+                    }
+                }
+            }
+        }
+        exposed
+    }
+
+    fn check(&mut self) -> Result<Option<Stall>> {
+        let task = self.golemcli.find_task(&self.task_name)?;
+        let subtasks = self.golemcli.subtasks(&task.id)?;
+        for subtask in &subtasks {
+            self.record(subtask);
+        }
+        self.record_checksums(&subtasks);
+
+        if self.assigned.is_none() && subtasks.iter().any(|subtask| subtask.provider().is_some()) {
+            self.assigned = Some(Instant::now());
+        }
+        if let Some(timeout) = self.timeouts.assignment {
+            if self.assigned.is_none() && self.started.elapsed() >= timeout {
+                log::info!(
+                    "No subtask assigned within {:?}, aborting task {}",
+                    timeout,
+                    task.id
+                );
+                self.golemcli.abort_task(&task.id)?;
+                let peers = self
+                    .golemcli
+                    .peers()
+                    .map_err(|e| log::debug!("Couldn't query peers: {:#}", e))
+                    .ok();
+                return Ok(Some(Stall::Unassigned { timeout, peers }));
+            }
+        }
+
+        if let Some(required) = self.timeouts.min_providers {
+            let computing: HashSet<_> = subtasks
+                .iter()
+                .filter(|subtask| subtask.status == COMPUTING)
+                .filter_map(SubtaskInfo::provider)
+                .collect();
+            self.peak_providers = self.peak_providers.max(computing.len());
+            if self.healthy.is_none() && computing.len() >= required {
+                log::info!("{} providers computing task {}", computing.len(), task.id);
+                self.healthy = Some(Instant::now());
+            }
+            if let Some(timeout) = self.timeouts.providers {
+                if self.healthy.is_none() && self.started.elapsed() >= timeout {
+                    log::info!(
+                        "At most {} of {} providers computing within {:?}, aborting task {}",
+                        self.peak_providers,
+                        required,
+                        timeout,
+                        task.id
+                    );
+                    self.golemcli.abort_task(&task.id)?;
+                    return Ok(Some(Stall::Understaffed {
+                        timeout,
+                        required,
+                        reached: self.peak_providers,
+                    }));
+                }
+            }
+        }
+
+        if let Some(stall) = self.scatter(&subtasks)? {
+            self.golemcli.abort_task(&task.id)?;
+            return Ok(Some(stall));
+        }
+
+        if let Some(heartbeat) = self.timeouts.heartbeat {
+            // quarantined subtasks aren't worth restarting anymore
+            let computing: Vec<_> = subtasks
+                .iter()
+                .filter(|subtask| subtask.status == COMPUTING)
+                .filter(|subtask| !self.is_quarantined(attempts_key(subtask)))
+                .cloned()
+                .collect();
+            for subtask in &computing {
+                self.check_heartbeat(subtask, heartbeat)?;
+            }
+        }
+
+        if let Some(quarantined) = self.quarantined(&subtasks) {
+            log::info!(
+                "Only quarantined subtasks left unfinished, aborting task {}",
+                task.id
+            );
+            self.golemcli.abort_task(&task.id)?;
+            return Ok(Some(Stall::Quarantined(quarantined)));
+        }
+        Ok(None)
+    }
+
+    fn collect_checksums(&mut self) -> Result<()> {
+        let task = self.golemcli.find_task(&self.task_name)?;
+        let subtasks = self.golemcli.subtasks(&task.id)?;
+        self.record_checksums(&subtasks);
+        Ok(())
+    }
+
+    fn record_checksums(&mut self, subtasks: &[SubtaskInfo]) {
+        for subtask in subtasks {
+            self.checksums.extend(subtask.checksums.clone());
+        }
+    }
+
+    /// Notes changes of the subtask's provider and failures since last seen
+    fn record(&mut self, subtask: &SubtaskInfo) {
+        let (status, provider) = self.listed.entry(subtask.subtask_id.clone()).or_default();
+        let assigned = subtask
+            .provider()
+            .filter(|assigned| provider.as_deref() != Some(assigned))
+            .map(str::to_owned);
+        let failed = *status != subtask.status && FAILED.contains(&subtask.status.as_str());
+        *status = subtask.status.clone();
+        if assigned.is_some() {
+            *provider = assigned.clone();
+        }
+
+        let name = attempts_key(subtask);
+        let attempts = self.attempts.entry(name.to_owned()).or_default();
+        attempts.providers.extend(assigned);
+        if failed {
+            attempts.failures += 1;
+            if self.timeouts.max_failures == Some(attempts.failures) {
+                log::warn!(
+                    "Subtask {} failed {} times, quarantining it",
+                    name,
+                    attempts.failures
+                );
+            }
+        }
+    }
+
+    fn is_quarantined(&self, name: &str) -> bool {
+        match (self.timeouts.max_failures, self.attempts.get(name)) {
+            (Some(max_failures), Some(attempts)) => attempts.failures >= max_failures,
+            _ => false,
+        }
+    }
+
+    /// Returns the quarantined subtasks once all others have finished
+    fn quarantined(&self, subtasks: &[SubtaskInfo]) -> Option<Vec<Quarantined>> {
+        let finished: HashSet<_> = subtasks
+            .iter()
+            .filter(|subtask| subtask.status == FINISHED)
+            .map(attempts_key)
+            .collect();
+        // later entries of a subtask are the newer ones
+        let mut unfinished = BTreeMap::new();
+        for subtask in subtasks {
+            let name = attempts_key(subtask);
+            if !finished.contains(name) {
+                unfinished.insert(name, &subtask.subtask_id);
+            }
+        }
+        if unfinished.is_empty() {
+            return None;
+        }
+
+        let mut quarantined = Vec::with_capacity(unfinished.len());
+        for (name, subtask_id) in unfinished {
+            if !self.is_quarantined(name) {
+                return None;
+            }
+            let attempts = &self.attempts[name];
+            quarantined.push(Quarantined {
+                subtask: name.to_owned(),
+                subtask_id: subtask_id.clone(),
+                failures: attempts.failures,
+                providers: attempts.providers.clone(),
+            });
+        }
+        Some(quarantined)
+    }
+
+    /// Restarts subtasks assigned to a provider holding an adjacent chunk,
+    /// returning why the task has to be aborted if one keeps coming back
+    ///
+    /// The provider has already received the chunk's text by then, so this
+    /// only lowers the odds of it getting adjacent chunks.
+    fn scatter(&mut self, subtasks: &[SubtaskInfo]) -> Result<Option<Stall>> {
+        let neighbours = match &self.scatter {
+            Some(neighbours) => neighbours,
+            None => return Ok(None),
+        };
+
+        // the node lists subtasks as they get assigned, so the one which came
+        // last is restarted rather than its neighbours already computing
+        for subtask in subtasks.iter().rev() {
+            let provider = match subtask.provider() {
+                Some(provider) if subtask.status == COMPUTING => provider,
+                _ => continue,
+            };
+            let name = match subtask.name() {
+                Some(name) => name,
+                None => {
+                    log::debug!(
+                        "Can't tell which chunk subtask {} synthesizes, leaving it be",
+                        subtask.subtask_id
+                    );
+                    continue;
+                }
+            };
+            let attempts = &self.attempts[name];
+            if !attempts.held_by(provider) {
+                continue;
+            }
+            let shared = neighbours
+                .of(name)
+                .iter()
+                .filter_map(|neighbour| self.attempts.get(neighbour))
+                .any(|neighbour| neighbour.held_by(provider));
+            if !shared {
+                continue;
+            }
+            if attempts.revoked.len() >= MAX_SCATTER_RESTARTS {
+                log::info!(
+                    "Subtask {} restarted {} times to keep adjacent chunks apart, aborting task",
+                    subtask.subtask_id,
+                    MAX_SCATTER_RESTARTS
+                );
+                return Ok(Some(Stall::Unscattered {
+                    subtask_id: subtask.subtask_id.clone(),
+                    provider: provider.to_owned(),
+                }));
+            }
+
+            log::info!(
+                "Subtask {} assigned to provider {} holding an adjacent chunk, restarting it",
+                subtask.subtask_id,
+                provider
+            );
+            self.golemcli.restart_subtask(&subtask.subtask_id)?;
+            self.progress.remove(&subtask.subtask_id);
+            // revoked right away, so that its neighbours stay where they are
+            let attempts = self.attempts.entry(name.to_owned()).or_default();
+            attempts.revoked.push(provider.to_owned());
+        }
+        Ok(None)
+    }
+
+    /// Restarts the subtask if it hasn't advanced within the heartbeat window
+    fn check_heartbeat(&mut self, subtask: &SubtaskInfo, heartbeat: Duration) -> Result<()> {
+        let now = Instant::now();
+        let (progress, changed) = self
+            .progress
+            .entry(subtask.subtask_id.clone())
+            .or_insert((subtask.progress, now));
+        if *progress != subtask.progress {
+            *progress = subtask.progress;
+            *changed = now;
+            return Ok(());
+        }
+        if now.duration_since(*changed) < heartbeat {
+            return Ok(());
+        }
+
+        log::info!(
+            "Subtask {} made no progress within {:?}, restarting it",
+            subtask.subtask_id,
+            heartbeat
+        );
+        self.golemcli.restart_subtask(&subtask.subtask_id)?;
+        self.progress.remove(&subtask.subtask_id);
+        if let Some(attempts) = self.attempts.get_mut(attempts_key(subtask)) {
+            attempts.failures += 1;
+        }
+        Ok(())
+    }
+}